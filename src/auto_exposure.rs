@@ -0,0 +1,113 @@
+use std::time::Instant;
+
+use crate::settings::AutoExposureConfiguration;
+
+/// Average luminance which the exposure is normalized towards, on the same 0-255 scale as
+/// the sample averages.
+const TARGET_LUMINANCE: f64 = 128.0;
+
+/// Darkest average luminance we'll try to correct, so a black screen doesn't get boosted
+/// all the way up to the `MAX_GAIN`.
+const MIN_LUMINANCE: f64 = 4.0;
+
+/// Limit on how much the exposure can brighten (or the inverse, dim) the samples.
+const MAX_GAIN: f64 = 8.0;
+
+/// Track the rolling average luminance of the samples and calculate the gain which moves
+/// it towards the `TARGET_LUMINANCE`.
+pub struct AutoExposure {
+    /// Exponential moving average of the luminance, or [None] before the first update.
+    luminance: Option<f64>,
+
+    /// When the `luminance` was last updated.
+    last_update: Option<Instant>,
+}
+
+impl AutoExposure {
+    /// Create a new [AutoExposure] which starts with a gain of 1.
+    pub fn new() -> Self {
+        Self {
+            luminance: None,
+            last_update: None,
+        }
+    }
+
+    /// Get the gain to multiply each sample average by for the current exposure.
+    pub fn gain(&self, parameters: &AutoExposureConfiguration) -> f64 {
+        let luminance = match self.luminance {
+            Some(luminance) => luminance.max(MIN_LUMINANCE),
+            None => return 1.0,
+        };
+        let strength = parameters.strength.clamp(0.0, 1.0);
+
+        (TARGET_LUMINANCE / luminance)
+            .powf(strength)
+            .clamp(1.0 / MAX_GAIN, MAX_GAIN)
+    }
+
+    /// Blend the `luminance` of the latest frame into the rolling average. The weight of the
+    /// new frame depends on how long it's been since the last update, so the `time_constant`
+    /// means the same thing at any frame rate.
+    pub fn update(&mut self, parameters: &AutoExposureConfiguration, luminance: f64, now: Instant) {
+        let weight = match (self.luminance, self.last_update) {
+            (Some(_), Some(last_update)) if parameters.time_constant > 0.0 => {
+                let elapsed = now.saturating_duration_since(last_update).as_secs_f64();
+                1.0 - (-elapsed / parameters.time_constant).exp()
+            }
+            _ => 1.0,
+        };
+
+        self.luminance = Some(match self.luminance {
+            Some(previous) => previous + (luminance - previous) * weight,
+            None => luminance,
+        });
+        self.last_update = Some(now);
+    }
+
+    /// Get the Rec. 709 luminance of an RGB sample average.
+    pub fn luminance((r, g, b): (f64, f64, f64)) -> f64 {
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    const PARAMETERS: AutoExposureConfiguration = AutoExposureConfiguration {
+        strength: 1.0,
+        time_constant: 1.0,
+    };
+
+    #[test]
+    fn start_with_unity_gain() {
+        let exposure = AutoExposure::new();
+        assert_eq!(exposure.gain(&PARAMETERS), 1.0);
+    }
+
+    #[test]
+    fn normalize_luminance() {
+        let mut exposure = AutoExposure::new();
+        exposure.update(&PARAMETERS, 32.0, Instant::now());
+        assert_eq!(exposure.gain(&PARAMETERS), 4.0);
+
+        let half = AutoExposureConfiguration {
+            strength: 0.5,
+            time_constant: 0.0,
+        };
+        exposure.update(&half, 512.0, Instant::now());
+        assert_eq!(exposure.gain(&half), 0.5);
+    }
+
+    #[test]
+    fn follow_time_constant() {
+        let start = Instant::now();
+        let mut exposure = AutoExposure::new();
+        exposure.update(&PARAMETERS, 128.0, start);
+        exposure.update(&PARAMETERS, 0.0, start + Duration::from_secs(1));
+        let luminance = exposure.luminance.expect("updated the luminance");
+        assert!((luminance - 128.0 / std::f64::consts::E).abs() < 0.001);
+    }
+}
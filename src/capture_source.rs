@@ -0,0 +1,179 @@
+use std::mem;
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::SIZE,
+        Graphics::Dxgi::Common::{
+            DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R10G10B10A2_UNORM,
+            DXGI_MODE_ROTATION, DXGI_MODE_ROTATION_IDENTITY, DXGI_MODE_ROTATION_ROTATE180,
+            DXGI_MODE_ROTATION_ROTATE270, DXGI_MODE_ROTATION_ROTATE90,
+        },
+    },
+};
+
+/// A backend which captures frames for [crate::screen_samples::ScreenSamples], e.g. DXGI
+/// desktop duplication, Windows.Graphics.Capture, or a [crate::test_source::TestSource].
+/// The sample blocks, averaging, fading and minimum brightness are all handled the same
+/// way for every [CaptureSource], so a new backend only needs to provide the pixels.
+pub trait CaptureSource {
+    /// Create or recreate any resources the source needs before it can acquire a frame, e.g.
+    /// after they were lost. Sources which acquire everything up front can use the default.
+    fn create(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Acquire the next frame. Returns `false` if nothing changed since the last frame, in
+    /// which case the LEDs just keep fading towards the last samples.
+    fn acquire_frame(&mut self) -> Result<bool>;
+
+    /// Get the size of the frames in desktop coordinates.
+    fn bounds(&self) -> SIZE;
+
+    /// Get the [DXGI_MODE_ROTATION] from desktop coordinates to the pixels in the frame.
+    fn rotation(&self) -> DXGI_MODE_ROTATION {
+        DXGI_MODE_ROTATION_IDENTITY
+    }
+
+    /// Map the pixels of the last frame from `acquire_frame`. Call `release_pixels` when
+    /// finished with the [MappedSurface].
+    fn read_pixels(&self) -> Result<MappedSurface<'_>>;
+
+    /// Release the pixels after a successful call to `read_pixels`.
+    fn release_pixels(&self) {}
+}
+
+/// Position of a sample pixel in an evenly spaced 16x16 grid for each sample block.
+#[derive(Copy)]
+pub struct PixelOffset {
+    pub x: usize,
+    pub y: usize,
+}
+
+impl Clone for PixelOffset {
+    fn clone(&self) -> Self {
+        Self {
+            x: self.x,
+            y: self.y,
+        }
+    }
+}
+
+impl PixelOffset {
+    /// Map a position in desktop coordinates within `bounds` to the corresponding position
+    /// in the duplicated texture, which is not rotated along with the desktop.
+    pub fn from_desktop(x: usize, y: usize, bounds: &SIZE, rotation: DXGI_MODE_ROTATION) -> Self {
+        let width = bounds.cx as usize;
+        let height = bounds.cy as usize;
+
+        match rotation {
+            DXGI_MODE_ROTATION_ROTATE90 => Self {
+                x: y,
+                y: width - x - 1,
+            },
+            DXGI_MODE_ROTATION_ROTATE180 => Self {
+                x: width - x - 1,
+                y: height - y - 1,
+            },
+            DXGI_MODE_ROTATION_ROTATE270 => Self {
+                x: height - y - 1,
+                y: x,
+            },
+            _ => Self { x, y },
+        }
+    }
+}
+
+/// Supported layouts of the 32-bit pixels in a duplicated desktop surface.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceFormat {
+    /// 8 bits per channel in BGRA order, i.e. `DXGI_FORMAT_B8G8R8A8_UNORM`.
+    Bgra8,
+
+    /// 10 bits per color channel and 2 bits of alpha packed into a little-endian 32-bit value
+    /// starting with red in the low bits, i.e. `DXGI_FORMAT_R10G10B10A2_UNORM`.
+    Rgb10A2,
+}
+
+impl SurfaceFormat {
+    /// Match a [DXGI_FORMAT] to one of the [SurfaceFormat] values we know how to decode.
+    pub fn from_dxgi(format: DXGI_FORMAT) -> Option<Self> {
+        match format {
+            DXGI_FORMAT_B8G8R8A8_UNORM => Some(Self::Bgra8),
+            DXGI_FORMAT_R10G10B10A2_UNORM => Some(Self::Rgb10A2),
+            _ => None,
+        }
+    }
+
+    /// Get the [DXGI_FORMAT] for a `staging` texture with this [SurfaceFormat].
+    pub fn to_dxgi(self) -> DXGI_FORMAT {
+        match self {
+            Self::Bgra8 => DXGI_FORMAT_B8G8R8A8_UNORM,
+            Self::Rgb10A2 => DXGI_FORMAT_R10G10B10A2_UNORM,
+        }
+    }
+}
+
+/// The `pixels` of a mapped surface with rows that are `pitch` bytes long in a [SurfaceFormat].
+/// If only some of the rows were copied, `rows` maps each row of the frame to its row in the
+/// `pixels`.
+pub struct MappedSurface<'a> {
+    pub pixels: &'a [u8],
+    pub pitch: usize,
+    pub format: SurfaceFormat,
+    pub rows: Option<&'a [u32]>,
+}
+
+impl<'a> MappedSurface<'a> {
+    /// Get the bytes of the pixel at the [PixelOffset].
+    fn pixel(&self, offset: &PixelOffset) -> &[u8] {
+        let y = match self.rows {
+            Some(rows) => rows[offset.y] as usize,
+            None => offset.y,
+        };
+        let row = &self.pixels[y * self.pitch..];
+        &row[offset.x * mem::size_of::<u32>()..][..mem::size_of::<u32>()]
+    }
+
+    /// Read the RGB channels of the pixel at the [PixelOffset] with 10 bits of precision, so
+    /// [SurfaceFormat::Rgb10A2] samples can be averaged before they're reduced to 8 bits. The
+    /// channels of [SurfaceFormat::Bgra8] are shifted up by 2 bits, so an average of them
+    /// divided by 4 is the same as averaging the 8-bit channels.
+    pub fn rgb10(&self, offset: &PixelOffset) -> (u16, u16, u16) {
+        let pixel = self.pixel(offset);
+
+        match self.format {
+            SurfaceFormat::Bgra8 => (
+                u16::from(pixel[2]) << 2,
+                u16::from(pixel[1]) << 2,
+                u16::from(pixel[0]) << 2,
+            ),
+            SurfaceFormat::Rgb10A2 => {
+                let value = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                (
+                    (value & 0x3FF) as u16,
+                    ((value >> 10) & 0x3FF) as u16,
+                    ((value >> 20) & 0x3FF) as u16,
+                )
+            }
+        }
+    }
+
+    /// Read the 8-bit RGB channels of the pixel at the [PixelOffset].
+    pub fn rgb(&self, offset: &PixelOffset) -> (u8, u8, u8) {
+        let pixel = self.pixel(offset);
+
+        match self.format {
+            SurfaceFormat::Bgra8 => (pixel[2], pixel[1], pixel[0]),
+            SurfaceFormat::Rgb10A2 => {
+                // Keep the 8 most significant bits of each 10-bit channel.
+                let value = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                (
+                    ((value >> 2) & 0xFF) as u8,
+                    ((value >> 12) & 0xFF) as u8,
+                    ((value >> 22) & 0xFF) as u8,
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    http_client,
+    output_sink::OutputSink,
+    pixel_buffer::PixelBuffer,
+    screen_samples::ScreenSamples,
+    settings::{ChromaConfiguration, ChromaDeviceType},
+};
+
+/// Port which the Chroma SDK REST API listens on to start a session.
+const CHROMA_SDK_PORT: u16 = 54235;
+
+/// Give up on each request to the Chroma SDK after this long.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The Chroma SDK ends the session if it doesn't hear from us for 15 seconds, so send a
+/// heartbeat if we haven't updated the devices for this long.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Description of the application which we register with the Chroma SDK.
+const CHROMA_APP: &str = r#"{"title":"AdaLight","description":"Ambient lighting which follows the screen","author":{"name":"AdaLight","contact":"https://github.com/wravery/adalight-rs"},"device_supported":[{devices}],"category":"application"}"#;
+
+/// Response from the Chroma SDK when we start a session.
+#[derive(Deserialize)]
+struct JsonChromaSession {
+    uri: String,
+}
+
+/// Get the name of the REST resource for a [ChromaDeviceType].
+fn device_name(device: ChromaDeviceType) -> &'static str {
+    match device {
+        ChromaDeviceType::Keyboard => "keyboard",
+        ChromaDeviceType::Mouse => "mouse",
+        ChromaDeviceType::Mousepad => "mousepad",
+        ChromaDeviceType::Headset => "headset",
+        ChromaDeviceType::Keypad => "keypad",
+        ChromaDeviceType::Chromalink => "chromalink",
+    }
+}
+
+/// Encode a static effect with an RGBA pixel, which the Chroma SDK expects in BGR order.
+fn static_effect(rgba_pixel: u32) -> String {
+    let bgr = ((rgba_pixel & 0xFF00) << 8)
+        | ((rgba_pixel & 0xFF0000) >> 8)
+        | ((rgba_pixel & 0xFF000000) >> 24);
+    format!(
+        r#"{{"effect":"CHROMA_STATIC","param":{{"color":{}}}}}"#,
+        bgr
+    )
+}
+
+/// Representation of a Chroma SDK session. The session belongs to a separate thread, which
+/// updates the devices with the latest frame every [ChromaConfiguration] `interval`, so the
+/// HTTP requests never hold up the worker thread.
+pub struct ChromaConnection<'a> {
+    configuration: &'a ChromaConfiguration,
+    tx: Option<mpsc::Sender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+    failed: Arc<AtomicBool>,
+}
+
+impl<'a> ChromaConnection<'a> {
+    /// Allocate a new [ChromaConnection] without starting a session.
+    pub fn new(configuration: &'a ChromaConfiguration) -> Self {
+        Self {
+            configuration,
+            tx: None,
+            thread: None,
+            failed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Start a session with the Chroma SDK, and return the `uri` for the rest of the requests.
+    fn start_session(&self) -> Option<String> {
+        let devices = self
+            .configuration
+            .devices
+            .iter()
+            .map(|device| format!(r#""{}""#, device_name(device.device)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let (status, body) = http_client::request(
+            "localhost",
+            CHROMA_SDK_PORT,
+            "POST",
+            "/razer/chromasdk",
+            &CHROMA_APP.replace("{devices}", &devices),
+            REQUEST_TIMEOUT,
+        )?;
+        if !http_client::is_success(status) {
+            return None;
+        }
+
+        let session: JsonChromaSession = serde_json::from_str(&body).ok()?;
+        Some(session.uri)
+    }
+}
+
+/// Update each of the `devices` with the latest colors from the channel every `interval`,
+/// until the [ChromaConnection] closes the channel. Then end the session at the `uri`.
+fn run_session(
+    uri: String,
+    devices: Vec<ChromaDeviceType>,
+    interval: Duration,
+    rx: mpsc::Receiver<Vec<u8>>,
+    failed: Arc<AtomicBool>,
+) {
+    let (host, port, path) = match http_client::parse_url(&uri) {
+        Some(url) => url,
+        None => {
+            failed.store(true, Ordering::Relaxed);
+            return;
+        }
+    };
+    let request = |method: &str, resource: &str, body: &str| {
+        matches!(
+            http_client::request(
+                host,
+                port,
+                method,
+                &format!("{}{}", path, resource),
+                body,
+                REQUEST_TIMEOUT
+            ),
+            Some((status, _)) if http_client::is_success(status)
+        )
+    };
+
+    let mut colors: Option<Vec<u8>> = None;
+    let mut last_update = Instant::now();
+    loop {
+        let mut latest = None;
+        let deadline = Instant::now() + interval;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(remaining) {
+                Ok(frame) => latest = Some(frame),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    request("DELETE", "", "");
+                    return;
+                }
+            }
+        }
+
+        let succeeded = match latest {
+            Some(latest) if colors.as_ref() != Some(&latest) => {
+                let succeeded = devices.iter().zip(latest.chunks(3)).all(|(device, color)| {
+                    let rgba_pixel = (u32::from(color[0]) << 24)
+                        | (u32::from(color[1]) << 16)
+                        | (u32::from(color[2]) << 8);
+                    request(
+                        "PUT",
+                        &format!("/{}", device_name(*device)),
+                        &static_effect(rgba_pixel),
+                    )
+                });
+                colors = Some(latest);
+                last_update = Instant::now();
+                succeeded
+            }
+            _ if last_update.elapsed() >= HEARTBEAT_INTERVAL => {
+                last_update = Instant::now();
+                request("PUT", "/heartbeat", "")
+            }
+            _ => true,
+        };
+
+        if !succeeded {
+            failed.store(true, Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+impl<'a> OutputSink for ChromaConnection<'a> {
+    /// Start a session with the Chroma SDK and the thread which updates the devices, if it
+    /// isn't running yet.
+    fn open(&mut self) -> bool {
+        if self.tx.is_none() {
+            if let Some(uri) = self.start_session() {
+                let (tx, rx) = mpsc::channel();
+                let devices = self
+                    .configuration
+                    .devices
+                    .iter()
+                    .map(|device| device.device)
+                    .collect();
+                let interval = Duration::from_millis(u64::from(self.configuration.interval));
+                let failed = self.failed.clone();
+                failed.store(false, Ordering::Relaxed);
+                self.thread = Some(thread::spawn(move || {
+                    run_session(uri, devices, interval, rx, failed)
+                }));
+                self.tx = Some(tx);
+            }
+        }
+
+        self.healthy()
+    }
+
+    /// Render the average color of the LEDs for each of the devices.
+    fn render(&self, samples: &ScreenSamples) -> Vec<PixelBuffer> {
+        let mut pixels =
+            PixelBuffer::new_image_buffer(Vec::new(), self.configuration.devices.len(), 0);
+        for device in self.configuration.devices.iter() {
+            pixels.add(samples.average_leds(&device.leds));
+        }
+        vec![pixels]
+    }
+
+    /// Hand the colors to the thread, which picks up the latest ones every `interval`. Returns
+    /// `false` if the thread stopped because one of the requests failed.
+    fn send(&mut self, pixels: Vec<PixelBuffer>) -> bool {
+        if self.failed.load(Ordering::Relaxed) {
+            self.close();
+            return false;
+        }
+
+        match (self.tx.as_ref(), pixels.first()) {
+            (Some(tx), Some(pixels)) => tx.send(pixels.data().to_vec()).is_ok(),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Close the channel, which tells the thread to end the session, and wait for it.
+    fn close(&mut self) {
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Test if the session is running, or if it still needs to report that it failed.
+    fn healthy(&self) -> bool {
+        self.tx.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn static_effects() {
+        assert_eq!(
+            static_effect(0x102030FF),
+            r#"{"effect":"CHROMA_STATIC","param":{"color":3153936}}"#
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use std::{fs, ptr};
+
+use windows::Win32::{
+    Foundation::{MAX_PATH, PWSTR},
+    Graphics::Gdi::{CreateDCW, DeleteDC, HDC},
+    UI::ColorSystem::GetICMProfileW,
+};
+
+use crate::gamma_correction::{LinearLookup, LINEAR_MAX};
+
+/// Matrix which converts D50 adapted XYZ values in the ICC profile connection space to
+/// linear sRGB, using the Bradford transform for the D50 to D65 white point.
+const SRGB_FROM_D50: [[f64; 3]; 3] = [
+    [3.133_856_1, -1.616_866_7, -0.490_614_6],
+    [-0.978_768_4, 1.916_141_5, 0.033_454_0],
+    [0.071_945_3, -0.228_991_4, 1.405_242_7],
+];
+
+/// Size of the fixed header at the beginning of every ICC profile.
+const HEADER_SIZE: usize = 128;
+
+/// Tone reproduction curve for one of the channels in an ICC profile, which converts the
+/// encoded channel values to linear light.
+enum ToneCurve {
+    /// Simple power function, including the identity curve with a `1.0` exponent.
+    Gamma(f64),
+
+    /// Table of evenly spaced samples, which are interpolated in between.
+    Table(Vec<u16>),
+
+    /// Parametric curve with the function type and up to 7 parameters.
+    Parametric(u16, [f64; 7]),
+}
+
+impl ToneCurve {
+    /// Convert an encoded channel value in the range `0.0..=1.0` to linear light.
+    fn apply(&self, x: f64) -> f64 {
+        match self {
+            Self::Gamma(gamma) => x.powf(*gamma),
+            Self::Table(table) => {
+                let position = x * (table.len() - 1) as f64;
+                let index = (position.floor() as usize).min(table.len() - 1);
+                let next = (index + 1).min(table.len() - 1);
+                let fraction = position - index as f64;
+                let (low, high) = (f64::from(table[index]), f64::from(table[next]));
+                (low + (high - low) * fraction) / f64::from(u16::MAX)
+            }
+            Self::Parametric(function, [g, a, b, c, d, e, f]) => match function {
+                0 => x.powf(*g),
+                1 if x >= -b / a => (a * x + b).powf(*g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(*g) + c,
+                2 => *c,
+                3 if x >= *d => (a * x + b).powf(*g),
+                3 => c * x,
+                4 if x >= *d => (a * x + b).powf(*g) + e,
+                _ => c * x + f,
+            },
+        }
+        .clamp(0.0, 1.0)
+    }
+}
+
+/// Transform from the colors of a monitor with an ICC profile to sRGB, so that monitors which
+/// are calibrated differently produce matching LED colors for the same content. Only the
+/// matrix/TRC profiles which are typical for displays are supported.
+pub struct ColorProfile {
+    /// Contribution of each encoded value of the red, green and blue channels to the linear
+    /// sRGB channels, in the range `0..=LINEAR_MAX` used by [LinearLookup].
+    contributions: [Vec<[i32; 3]>; 3],
+
+    /// [LinearLookup] tables to convert the transformed averages back to sRGB.
+    srgb: LinearLookup,
+}
+
+impl ColorProfile {
+    /// Load the active ICC profile for the display with this `DeviceName`. Returns [None] if
+    /// there is no profile or it isn't supported.
+    pub fn for_device(device_name: &[u16; 32]) -> Option<Self> {
+        let mut path = [0_u16; MAX_PATH as usize];
+
+        unsafe {
+            let dc = CreateDCW(
+                "DISPLAY",
+                PWSTR(device_name.as_ptr() as *mut u16),
+                PWSTR::default(),
+                ptr::null(),
+            );
+            if dc == HDC::default() {
+                return None;
+            }
+
+            let mut size = path.len() as u32;
+            let found = GetICMProfileW(dc, &mut size, PWSTR(path.as_mut_ptr())).as_bool();
+            DeleteDC(dc);
+
+            if !found {
+                return None;
+            }
+        }
+
+        let length = path.iter().position(|c| *c == 0).unwrap_or(path.len());
+        let data = fs::read(String::from_utf16_lossy(&path[..length])).ok()?;
+        Self::from_bytes(&data)
+    }
+
+    /// Parse the colorant and tone reproduction curve tags from the `data` in an ICC profile.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE + 4 || &data[36..40] != b"acsp" || &data[16..20] != b"RGB " {
+            return None;
+        }
+
+        let colorants = [
+            Self::read_xyz(Self::find_tag(data, b"rXYZ")?)?,
+            Self::read_xyz(Self::find_tag(data, b"gXYZ")?)?,
+            Self::read_xyz(Self::find_tag(data, b"bXYZ")?)?,
+        ];
+        let curves = [
+            Self::read_curve(Self::find_tag(data, b"rTRC")?)?,
+            Self::read_curve(Self::find_tag(data, b"gTRC")?)?,
+            Self::read_curve(Self::find_tag(data, b"bTRC")?)?,
+        ];
+
+        let contribution = |channel: usize, value: usize| {
+            let linear = curves[channel].apply(value as f64 / 255.0) * LINEAR_MAX as f64;
+            let mut result = [0; 3];
+            for (output, row) in result.iter_mut().zip(SRGB_FROM_D50.iter()) {
+                let weight = row
+                    .iter()
+                    .zip(colorants[channel].iter())
+                    .map(|(m, xyz)| m * xyz)
+                    .sum::<f64>();
+                *output = (weight * linear).round() as i32;
+            }
+            result
+        };
+
+        Some(Self {
+            contributions: [0, 1, 2].map(|channel| {
+                (0..=255)
+                    .map(|value| contribution(channel, value))
+                    .collect()
+            }),
+            srgb: LinearLookup::new(),
+        })
+    }
+
+    /// Transform a 10-bit encoded pixel from [crate::capture_source::MappedSurface::rgb10] to
+    /// linear sRGB in the range `0..=4 * LINEAR_MAX`, interpolating between the contributions of
+    /// the 8-bit values and clipping any colors which are outside of the sRGB gamut. Divide the
+    /// result, or an average of them, by 4 to get the range of [LinearLookup].
+    pub fn to_linear(&self, r: u16, g: u16, b: u16) -> (u32, u32, u32) {
+        let contribution = |contributions: &[[i32; 3]], value: u16| {
+            let (index, fraction) = (usize::from(value >> 2), i32::from(value & 3));
+            let (low, high) = (
+                contributions[index],
+                contributions[(index + 1).min(contributions.len() - 1)],
+            );
+            [0, 1, 2].map(|i| low[i] * (4 - fraction) + high[i] * fraction)
+        };
+        let [red, green, blue] = &self.contributions;
+        let (r, g, b) = (
+            contribution(red, r),
+            contribution(green, g),
+            contribution(blue, b),
+        );
+        let channel = |i: usize| (r[i] + g[i] + b[i]).clamp(0, 4 * LINEAR_MAX as i32) as u32;
+
+        (channel(0), channel(1), channel(2))
+    }
+
+    /// Convert an average in linear light back to an sRGB encoded channel in the range
+    /// `0.0..=255.0`.
+    pub fn to_srgb(&self, linear: f64) -> f64 {
+        self.srgb.to_srgb(linear)
+    }
+
+    /// Find the data for a tag with this `signature` in the tag table.
+    fn find_tag<'a>(data: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+        let count = read_u32(data, HEADER_SIZE)? as usize;
+        (0..count).find_map(|index| {
+            let entry = HEADER_SIZE + 4 + index * 12;
+            if data.get(entry..entry + 4)? != signature {
+                return None;
+            }
+
+            let offset = read_u32(data, entry + 4)? as usize;
+            let size = read_u32(data, entry + 8)? as usize;
+            data.get(offset..offset.checked_add(size)?)
+        })
+    }
+
+    /// Read the XYZ values from an `XYZType` tag.
+    fn read_xyz(tag: &[u8]) -> Option<[f64; 3]> {
+        if tag.get(0..4)? != b"XYZ " {
+            return None;
+        }
+
+        Some([
+            read_s15_fixed16(tag, 8)?,
+            read_s15_fixed16(tag, 12)?,
+            read_s15_fixed16(tag, 16)?,
+        ])
+    }
+
+    /// Read a [ToneCurve] from a `curveType` or `parametricCurveType` tag.
+    fn read_curve(tag: &[u8]) -> Option<ToneCurve> {
+        match tag.get(0..4)? {
+            b"curv" => {
+                let count = read_u32(tag, 8)? as usize;
+                match count {
+                    0 => Some(ToneCurve::Gamma(1.0)),
+                    1 => Some(ToneCurve::Gamma(f64::from(read_u16(tag, 12)?) / 256.0)),
+                    _ => Some(ToneCurve::Table(
+                        (0..count)
+                            .map(|index| read_u16(tag, 12 + index * 2))
+                            .collect::<Option<Vec<_>>>()?,
+                    )),
+                }
+            }
+            b"para" => {
+                let function = read_u16(tag, 8)?;
+                let count = match function {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return None,
+                };
+                let mut parameters = [0.0; 7];
+                for (index, parameter) in parameters.iter_mut().take(count).enumerate() {
+                    *parameter = read_s15_fixed16(tag, 12 + index * 4)?;
+                }
+                Some(ToneCurve::Parametric(function, parameters))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Read a big-endian `u16` at `offset`.
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+/// Read a big-endian `u32` at `offset`.
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Read a big-endian `s15Fixed16Number` at `offset`.
+fn read_s15_fixed16(data: &[u8], offset: usize) -> Option<f64> {
+    Some(read_u32(data, offset)? as i32 as f64 / 65536.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Primaries of sRGB adapted to D50, which should make the transform an identity.
+    const SRGB_PRIMARIES: [[f64; 3]; 3] = [
+        [0.436_074_7, 0.222_504_5, 0.013_932_2],
+        [0.385_064_9, 0.716_878_6, 0.097_104_5],
+        [0.143_080_4, 0.060_616_9, 0.714_173_3],
+    ];
+
+    fn fixed(value: f64) -> [u8; 4] {
+        ((value * 65536.0).round() as i32).to_be_bytes()
+    }
+
+    fn build_profile(primaries: &[[f64; 3]; 3]) -> Vec<u8> {
+        let mut tags: Vec<(&[u8; 4], Vec<u8>)> = Vec::new();
+        for (signature, primary) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().zip(primaries) {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            for value in primary {
+                tag.extend_from_slice(&fixed(*value));
+            }
+            tags.push((signature, tag));
+        }
+        for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+            // The sRGB transfer function.
+            let mut tag = b"para\0\0\0\0\0\x03\0\0".to_vec();
+            for value in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+                tag.extend_from_slice(&fixed(value));
+            }
+            tags.push((signature, tag));
+        }
+
+        let mut data = vec![0; HEADER_SIZE];
+        data[16..20].copy_from_slice(b"RGB ");
+        data[36..40].copy_from_slice(b"acsp");
+        data.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        let mut offset = data.len() + tags.len() * 12;
+        for (signature, tag) in tags.iter() {
+            data.extend_from_slice(*signature);
+            data.extend_from_slice(&(offset as u32).to_be_bytes());
+            data.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+            offset += tag.len();
+        }
+        for (_, tag) in tags.iter() {
+            data.extend_from_slice(tag);
+        }
+        data
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        let profile =
+            ColorProfile::from_bytes(&build_profile(&SRGB_PRIMARIES)).expect("parse the profile");
+        for value in [0_u8, 32, 128, 200, 255] {
+            let value10 = u16::from(value) << 2;
+            let (r, g, b) = profile.to_linear(value10, value10, value10);
+            for channel in [r, g, b] {
+                let srgb = profile.to_srgb(f64::from(channel) / 4.0);
+                assert!((srgb - value as f64).abs() < 2.0, "{value} -> {srgb}");
+            }
+        }
+    }
+
+    #[test]
+    fn wide_gamut_red_is_clipped() {
+        // A display with a more saturated red primary than sRGB.
+        let mut primaries = SRGB_PRIMARIES;
+        primaries[0] = [0.515_1, 0.241_2, -0.001_1];
+        let profile =
+            ColorProfile::from_bytes(&build_profile(&primaries)).expect("parse the profile");
+        let (r, g, b) = profile.to_linear(1023, 0, 0);
+        assert_eq!(r, 4 * LINEAR_MAX as u32);
+        assert_eq!(g, 0);
+        assert!(b < 4 * 10);
+    }
+
+    #[test]
+    fn reject_invalid_profile() {
+        assert!(ColorProfile::from_bytes(&[0; HEADER_SIZE + 4]).is_none());
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    mem,
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, Stdio},
+};
+
+use windows::{
+    core::Result,
+    Win32::Foundation::{E_FAIL, SIZE},
+};
+
+use crate::{
+    capture_source::{CaptureSource, MappedSurface, SurfaceFormat},
+    settings::FileSourceConfiguration,
+};
+
+/// Width in pixels of the frames decoded from a video file.
+const VIDEO_WIDTH: i32 = 640;
+
+/// Height in pixels of the frames decoded from a video file.
+const VIDEO_HEIGHT: i32 = 360;
+
+/// The `CREATE_NO_WINDOW` process creation flag, which keeps `ffmpeg` from opening a
+/// console window.
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+/// An `ffmpeg` child process which decodes a video file to raw BGRA frames on `stdout`.
+struct VideoDecoder {
+    process: Child,
+    stdout: ChildStdout,
+}
+
+impl VideoDecoder {
+    /// Start decoding the video file at `path` from the beginning, scaled to
+    /// `VIDEO_WIDTH` x `VIDEO_HEIGHT`.
+    pub fn start(path: &Path) -> io::Result<Self> {
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-loglevel", "error", "-i"])
+            .arg(path)
+            .args([
+                "-vf",
+                &format!("scale={VIDEO_WIDTH}:{VIDEO_HEIGHT}"),
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "bgra",
+                "-",
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            command.creation_flags(CREATE_NO_WINDOW);
+        }
+
+        let mut process = command.spawn()?;
+        let stdout = match process.stdout.take() {
+            Some(stdout) => stdout,
+            None => return Err(io::ErrorKind::BrokenPipe.into()),
+        };
+
+        Ok(Self { process, stdout })
+    }
+}
+
+impl Drop for VideoDecoder {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Where the frames of a [FileSource] come from.
+enum Frames {
+    /// A sorted list of PNG images, and the index of the next one to read.
+    Images { paths: Vec<PathBuf>, next: usize },
+
+    /// A video file which is being decoded by a [VideoDecoder].
+    Video(VideoDecoder),
+}
+
+/// Read BGRA frames from a PNG image, a directory full of PNG images, or a video file instead
+/// of capturing the desktop, so a configuration can be previewed against known content and
+/// the sampling code can be tested end-to-end. Video files are decoded with `ffmpeg`, which
+/// needs to be on the `PATH`.
+pub struct FileSource {
+    /// The path of the video file, which we need to restart decoding when it repeats.
+    path: PathBuf,
+
+    /// Start over from the first frame after reaching the end.
+    repeat: bool,
+
+    /// The [Frames] we are reading.
+    frames: Frames,
+
+    /// The size of every frame. Images in a sequence must all be the same size.
+    bounds: SIZE,
+
+    /// The BGRA pixels of the last frame, in rows that are `bounds.cx * 4` bytes long.
+    pixels: Vec<u8>,
+}
+
+impl FileSource {
+    /// Open the file or directory in the [FileSourceConfiguration].
+    pub fn new(configuration: &FileSourceConfiguration) -> io::Result<Self> {
+        let path = PathBuf::from(&configuration.path);
+        let (frames, bounds) = if path.is_dir() || Self::is_image(&path) {
+            let paths = if path.is_dir() {
+                let mut paths: Vec<PathBuf> = fs::read_dir(&path)?
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| Self::is_image(path))
+                    .collect();
+                paths.sort();
+                paths
+            } else {
+                vec![path.clone()]
+            };
+
+            // Read the first image to find out how big the frames are.
+            let (_, bounds) = match paths.first() {
+                Some(first) => Self::read_image(first)?,
+                None => return Err(io::ErrorKind::NotFound.into()),
+            };
+
+            (Frames::Images { paths, next: 0 }, bounds)
+        } else {
+            (
+                Frames::Video(VideoDecoder::start(&path)?),
+                SIZE {
+                    cx: VIDEO_WIDTH,
+                    cy: VIDEO_HEIGHT,
+                },
+            )
+        };
+
+        Ok(Self {
+            path,
+            repeat: configuration.repeat,
+            frames,
+            bounds,
+            pixels: vec![0; (bounds.cx * bounds.cy) as usize * mem::size_of::<u32>()],
+        })
+    }
+
+    /// Read the next frame and return the BGRA pixels along with the row pitch in bytes. Returns
+    /// an [Err] value if we reached the end and we're not repeating, or the frame couldn't be
+    /// read.
+    pub fn next_frame(&mut self) -> io::Result<(&[u8], usize)> {
+        let pitch = self.pitch();
+
+        match &mut self.frames {
+            Frames::Images { paths, next } => {
+                if *next >= paths.len() {
+                    if !self.repeat {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    *next = 0;
+                }
+
+                let (pixels, bounds) = Self::read_image(&paths[*next])?;
+                *next += 1;
+
+                if bounds != self.bounds {
+                    return Err(io::ErrorKind::InvalidData.into());
+                }
+
+                self.pixels = pixels;
+            }
+            Frames::Video(decoder) => {
+                if let Err(error) = decoder.stdout.read_exact(&mut self.pixels) {
+                    if error.kind() != io::ErrorKind::UnexpectedEof || !self.repeat {
+                        return Err(error);
+                    }
+
+                    // Start decoding the video again from the beginning.
+                    let mut decoder = VideoDecoder::start(&self.path)?;
+                    decoder.stdout.read_exact(&mut self.pixels)?;
+                    self.frames = Frames::Video(decoder);
+                }
+            }
+        }
+
+        Ok((&self.pixels, pitch))
+    }
+
+    /// Get the row pitch in bytes of the frames.
+    fn pitch(&self) -> usize {
+        self.bounds.cx as usize * mem::size_of::<u32>()
+    }
+
+    /// Test if the `path` looks like a PNG image.
+    fn is_image(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+    }
+
+    /// Decode the PNG image at `path` to BGRA pixels.
+    fn read_image(path: &Path) -> io::Result<(Vec<u8>, SIZE)> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer)?;
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => return Err(io::ErrorKind::InvalidData.into()),
+        };
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut pixels = Vec::with_capacity(width * height * mem::size_of::<u32>());
+
+        for row in buffer.chunks_exact(info.line_size).take(height) {
+            for pixel in row.chunks_exact(channels).take(width) {
+                let (r, g, b) = if channels < 3 {
+                    (pixel[0], pixel[0], pixel[0])
+                } else {
+                    (pixel[0], pixel[1], pixel[2])
+                };
+                pixels.extend_from_slice(&[b, g, r, 0xFF]);
+            }
+        }
+
+        Ok((
+            pixels,
+            SIZE {
+                cx: width as i32,
+                cy: height as i32,
+            },
+        ))
+    }
+}
+
+impl CaptureSource for FileSource {
+    /// Read the next frame. Returns an [Err] value if we reached the end and we're not
+    /// repeating, or the frame couldn't be read.
+    fn acquire_frame(&mut self) -> Result<bool> {
+        match self.next_frame() {
+            Ok(_) => Ok(true),
+            Err(_) => Err(E_FAIL.into()),
+        }
+    }
+
+    /// Get the size of the frames.
+    fn bounds(&self) -> SIZE {
+        self.bounds
+    }
+
+    /// Read the BGRA pixels of the last frame.
+    fn read_pixels(&self) -> Result<MappedSurface<'_>> {
+        Ok(MappedSurface {
+            pixels: &self.pixels,
+            pitch: self.pitch(),
+            format: SurfaceFormat::Bgra8,
+            rows: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::{env, io::BufWriter};
+
+    fn write_image(path: &Path, rgb: [u8; 3]) {
+        let file = BufWriter::new(File::create(path).expect("create the image"));
+        let mut encoder = png::Encoder::new(file, 2, 2);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().expect("write the header");
+        writer
+            .write_image_data(&rgb.repeat(4))
+            .expect("write the pixels");
+    }
+
+    #[test]
+    fn image_sequence() {
+        let directory = env::temp_dir().join(format!("adalight-frames-{}", std::process::id()));
+        fs::create_dir_all(&directory).expect("create the directory");
+        write_image(&directory.join("frame-0.png"), [255, 0, 0]);
+        write_image(&directory.join("frame-1.png"), [0, 0, 255]);
+
+        let mut source = FileSource::new(&FileSourceConfiguration {
+            path: directory.to_string_lossy().into_owned(),
+            repeat: false,
+        })
+        .expect("open the images");
+        assert_eq!(source.bounds(), SIZE { cx: 2, cy: 2 });
+
+        let (pixels, pitch) = source.next_frame().expect("read the first frame");
+        assert_eq!(pitch, 8);
+        assert_eq!(&pixels[..4], &[0, 0, 255, 0xFF]);
+        let (pixels, _) = source.next_frame().expect("read the second frame");
+        assert_eq!(&pixels[..4], &[255, 0, 0, 0xFF]);
+        assert!(source.next_frame().is_err());
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -1,3 +1,15 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+};
+
+use serde_json::Value;
+
+use crate::settings::{GammaConfiguration, GammaCurve};
+
+/// Number of entries in each channel of a [GammaLookup] table.
+const TABLE_SIZE: usize = 256;
+
 #[doc(hidden)]
 struct GammaValues {
     pub r: u8,
@@ -13,22 +25,132 @@ pub struct GammaLookup {
 
 impl GammaLookup {
     /// Create a new [GammaLookup] instance to perform gamma correction on the RGB
-    /// channels for each LED color.
-    pub fn new() -> Self {
+    /// channels for each LED color, with the [GammaCurve] for each channel in the
+    /// [GammaConfiguration]. If it has a `table`, load that instead. [crate::settings::Settings]
+    /// already checked that it loads, so it only falls back to the curves if the file was
+    /// removed since then.
+    pub fn new(configuration: &GammaConfiguration) -> Self {
+        if let Some(lookup) = configuration
+            .table
+            .as_ref()
+            .and_then(|path| Self::load(path).ok())
+        {
+            return lookup;
+        }
+
         Self {
             table: (0_u8..=255)
-                .map(|index| {
-                    let f = ((index as f64) / 255.0).powf(2.8);
-                    GammaValues {
-                        r: (f * 255.0) as u8,
-                        g: (f * 240.0) as u8,
-                        b: (f * 220.0) as u8,
-                    }
+                .map(|index| GammaValues {
+                    r: Self::correct(&configuration.red, index),
+                    g: Self::correct(&configuration.green, index),
+                    b: Self::correct(&configuration.blue, index),
                 })
                 .collect(),
         }
     }
 
+    /// Load a measured lookup table from the file at `path`. A `.json` file holds an array of
+    /// 256 values shared by all of the channels, or an array of 3 arrays with 256 values for
+    /// red, green and blue. Anything else is read as CSV, with 256 rows of either 1 shared
+    /// value or 3 values for red, green and blue. Every value must be from 0 to 255.
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let channels = if path.to_ascii_lowercase().ends_with(".json") {
+            Self::parse_json(&contents)
+        } else {
+            Self::parse_csv(&contents)
+        }
+        .ok_or_else(|| Error::from(ErrorKind::InvalidData))?;
+        Ok(Self::from_channels(&channels))
+    }
+
+    /// Build the table from the values for each of the red, green and blue `channels`.
+    fn from_channels(channels: &[Vec<u8>; 3]) -> Self {
+        Self {
+            table: (0..TABLE_SIZE)
+                .map(|index| GammaValues {
+                    r: channels[0][index],
+                    g: channels[1][index],
+                    b: channels[2][index],
+                })
+                .collect(),
+        }
+    }
+
+    /// Parse the values for each channel from a JSON array of numbers, or an array of 3 of
+    /// them. Returns `None` if it's any other shape.
+    fn parse_json(contents: &str) -> Option<[Vec<u8>; 3]> {
+        let parse_channel = |value: &Value| -> Option<Vec<u8>> {
+            let values = value.as_array()?;
+            if values.len() != TABLE_SIZE {
+                return None;
+            }
+            values
+                .iter()
+                .map(|value| u8::try_from(value.as_u64()?).ok())
+                .collect()
+        };
+
+        let value: Value = serde_json::from_str(contents).ok()?;
+        match value.as_array()?.as_slice() {
+            [red, green, blue] => Some([
+                parse_channel(red)?,
+                parse_channel(green)?,
+                parse_channel(blue)?,
+            ]),
+            _ => {
+                let shared = parse_channel(&value)?;
+                Some([shared.clone(), shared.clone(), shared])
+            }
+        }
+    }
+
+    /// Parse the values for each channel from CSV rows with 1 or 3 columns. Blank lines and a
+    /// header row which doesn't start with a number are skipped. Returns `None` if there aren't
+    /// exactly 256 rows or the rows don't all have the same number of columns.
+    fn parse_csv(contents: &str) -> Option<[Vec<u8>; 3]> {
+        let rows: Vec<Vec<u8>> = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .filter(|(index, line)| *index > 0 || line.starts_with(|c: char| c.is_ascii_digit()))
+            .map(|(_, line)| {
+                line.split(',')
+                    .map(|value| value.trim().parse().ok())
+                    .collect::<Option<Vec<u8>>>()
+            })
+            .collect::<Option<_>>()?;
+        if rows.len() != TABLE_SIZE {
+            return None;
+        }
+
+        let columns = rows[0].len();
+        let mut channels = [Vec::new(), Vec::new(), Vec::new()];
+        for row in rows.iter().filter(|row| row.len() == columns) {
+            match row.as_slice() {
+                [shared] => {
+                    for channel in channels.iter_mut() {
+                        channel.push(*shared);
+                    }
+                }
+                [red, green, blue] => {
+                    channels[0].push(*red);
+                    channels[1].push(*green);
+                    channels[2].push(*blue);
+                }
+                _ => return None,
+            }
+        }
+        Some(channels).filter(|channels| channels[0].len() == TABLE_SIZE)
+    }
+
+    /// Apply the [GammaCurve] to a single channel `value`.
+    fn correct(curve: &GammaCurve, value: u8) -> u8 {
+        let f = ((value as f64) / 255.0).powf(curve.exponent);
+        (f * f64::from(curve.max)) as u8
+    }
+
     /// Get a gamma corrected value for the red channel.
     pub fn red(&self, r: u8) -> u8 {
         self.table[usize::from(r)].r
@@ -43,6 +165,88 @@ impl GammaLookup {
     pub fn blue(&self, b: u8) -> u8 {
         self.table[usize::from(b)].b
     }
+
+    /// Get gamma corrected values for a color with channels in the range `0.0..=255.0`,
+    /// interpolating between the entries in the table, so the result keeps the fraction which
+    /// would otherwise be lost to the 8-bit input, e.g. for dithering.
+    pub fn interpolate(&self, (r, g, b): (f64, f64, f64)) -> (f64, f64, f64) {
+        let lerp = |value: f64, channel: fn(&GammaValues) -> u8| {
+            let value = value.clamp(0.0, 255.0);
+            let index = value.floor() as usize;
+            let low = f64::from(channel(&self.table[index]));
+            let high = f64::from(channel(&self.table[(index + 1).min(TABLE_SIZE - 1)]));
+            low + (high - low) * (value - index as f64)
+        };
+        (
+            lerp(r, |values| values.r),
+            lerp(g, |values| values.g),
+            lerp(b, |values| values.b),
+        )
+    }
+}
+
+/// Maximum value of a channel in linear light, which determines the precision of the
+/// [LinearLookup] tables.
+pub const LINEAR_MAX: usize = 4095;
+
+/// Lookup tables to convert sRGB encoded channels to linear light and back again, so that
+/// averaging a block of samples doesn't bias the result towards the darker colors.
+pub struct LinearLookup {
+    #[doc(hidden)]
+    to_linear: Vec<u16>,
+    #[doc(hidden)]
+    to_srgb: Vec<f64>,
+}
+
+impl LinearLookup {
+    /// Create a new [LinearLookup] instance with the sRGB transfer functions.
+    pub fn new() -> Self {
+        Self {
+            to_linear: (0_u8..=255)
+                .map(|index| {
+                    let c = (index as f64) / 255.0;
+                    let linear = if c <= 0.04045 {
+                        c / 12.92
+                    } else {
+                        ((c + 0.055) / 1.055).powf(2.4)
+                    };
+                    (linear * LINEAR_MAX as f64).round() as u16
+                })
+                .collect(),
+            to_srgb: (0..=LINEAR_MAX)
+                .map(|index| {
+                    let linear = (index as f64) / LINEAR_MAX as f64;
+                    let c = if linear <= 0.0031308 {
+                        linear * 12.92
+                    } else {
+                        1.055 * linear.powf(1.0 / 2.4) - 0.055
+                    };
+                    c * 255.0
+                })
+                .collect(),
+        }
+    }
+
+    /// Convert an sRGB encoded channel to linear light in the range `0..=4095`.
+    pub fn to_linear(&self, value: u8) -> u16 {
+        self.to_linear[usize::from(value)]
+    }
+
+    /// Convert a 10-bit sRGB encoded channel from [crate::capture_source::MappedSurface::rgb10]
+    /// to linear light in the range `0..=4 * 4095`, interpolating between the 8-bit entries.
+    /// Divide the result, or an average of them, by 4 to get the range of `to_linear`.
+    pub fn to_linear10(&self, value: u16) -> u32 {
+        let (index, fraction) = ((value >> 2) as u8, u32::from(value & 3));
+        let low = u32::from(self.to_linear(index));
+        let high = u32::from(self.to_linear(index.saturating_add(1)));
+        low * (4 - fraction) + high * fraction
+    }
+
+    /// Convert an average in linear light back to an sRGB encoded channel in the range
+    /// `0.0..=255.0`.
+    pub fn to_srgb(&self, linear: f64) -> f64 {
+        self.to_srgb[(linear.round() as usize).min(LINEAR_MAX)]
+    }
 }
 
 #[cfg(test)]
@@ -51,19 +255,132 @@ mod test {
 
     #[test]
     fn new_gamma_lookup() {
-        let gamma_lookup = GammaLookup::new();
+        let gamma_lookup = GammaLookup::new(&GammaConfiguration::default());
         assert_eq!(gamma_lookup.table.len(), 256);
     }
 
+    #[test]
+    fn interpolate_between_entries() {
+        let gamma_lookup = GammaLookup::new(&GammaConfiguration::default());
+        let (r, g, b) = gamma_lookup.interpolate((200.5, 255.0, 300.0));
+        let (low, high) = (gamma_lookup.red(200), gamma_lookup.red(201));
+        assert_eq!(r, (f64::from(low) + f64::from(high)) / 2.0);
+        assert_eq!(g, f64::from(gamma_lookup.green(255)));
+        assert_eq!(b, f64::from(gamma_lookup.blue(255)));
+    }
+
     #[test]
     fn red_greater_than_green() {
-        let gamma_lookup = GammaLookup::new();
+        let gamma_lookup = GammaLookup::new(&GammaConfiguration::default());
         assert!(gamma_lookup.red(255) > gamma_lookup.green(255));
     }
 
     #[test]
     fn green_greater_than_blue() {
-        let gamma_lookup = GammaLookup::new();
+        let gamma_lookup = GammaLookup::new(&GammaConfiguration::default());
         assert!(gamma_lookup.green(255) > gamma_lookup.blue(255));
     }
+
+    #[test]
+    fn separate_channel_curves() {
+        let gamma_lookup = GammaLookup::new(&GammaConfiguration {
+            red: GammaCurve {
+                exponent: 1.0,
+                max: 255,
+            },
+            green: GammaCurve {
+                exponent: 2.0,
+                max: 255,
+            },
+            blue: GammaCurve {
+                exponent: 1.0,
+                max: 128,
+            },
+            table: None,
+        });
+        assert_eq!(gamma_lookup.red(128), 128);
+        assert_eq!(gamma_lookup.green(128), 64);
+        assert_eq!(gamma_lookup.blue(255), 128);
+        assert_eq!(gamma_lookup.blue(0), 0);
+    }
+
+    #[test]
+    fn parse_csv_table() {
+        let shared: String = (0..=255)
+            .map(|value| format!("{}\n", 255 - value))
+            .collect();
+        let channels =
+            GammaLookup::parse_csv(&format!("value\n{}", shared)).expect("parse 1 column");
+        let gamma_lookup = GammaLookup::from_channels(&channels);
+        assert_eq!(gamma_lookup.red(0), 255);
+        assert_eq!(gamma_lookup.blue(255), 0);
+
+        let separate: String = (0..=255)
+            .map(|value| format!("{}, {}, {}\n", value, value / 2, value / 4))
+            .collect();
+        let channels = GammaLookup::parse_csv(&separate).expect("parse 3 columns");
+        let gamma_lookup = GammaLookup::from_channels(&channels);
+        assert_eq!(gamma_lookup.red(200), 200);
+        assert_eq!(gamma_lookup.green(200), 100);
+        assert_eq!(gamma_lookup.blue(200), 50);
+
+        assert!(GammaLookup::parse_csv(&separate.replacen("0, 0, 0", "0", 1)).is_none());
+        assert!(GammaLookup::parse_csv(&separate.replacen("0, 0, 0", "256, 0, 0", 1)).is_none());
+        assert!(GammaLookup::parse_csv("0\n1\n2\n").is_none());
+    }
+
+    #[test]
+    fn parse_json_table() {
+        let encode = |divisor: u32| {
+            let values: Vec<String> = (0..=255)
+                .map(|value| (value / divisor).to_string())
+                .collect();
+            format!("[{}]", values.join(","))
+        };
+        let channels = GammaLookup::parse_json(&encode(1)).expect("parse 1 array");
+        assert_eq!(channels[2][128], 128);
+
+        let channels =
+            GammaLookup::parse_json(&format!("[{},{},{}]", encode(1), encode(2), encode(1)))
+                .expect("parse 3 arrays");
+        assert_eq!(channels[0][128], 128);
+        assert_eq!(channels[1][128], 64);
+
+        assert!(GammaLookup::parse_json("[1, 2, 3]").is_none());
+        assert!(GammaLookup::parse_json(r#"{ "red": [] }"#).is_none());
+    }
+
+    #[test]
+    fn linear_round_trip() {
+        let linear_lookup = LinearLookup::new();
+        for value in [0_u8, 1, 64, 128, 200, 255] {
+            let linear = f64::from(linear_lookup.to_linear(value));
+            assert!((linear_lookup.to_srgb(linear) - value as f64).abs() < 1.0);
+        }
+    }
+
+    #[test]
+    fn interpolate_10_bit_linear() {
+        let linear_lookup = LinearLookup::new();
+        for value in [0_u8, 64, 255] {
+            assert_eq!(
+                linear_lookup.to_linear10(u16::from(value) << 2),
+                u32::from(linear_lookup.to_linear(value)) * 4
+            );
+        }
+        let (low, high) = (
+            u32::from(linear_lookup.to_linear(128)),
+            u32::from(linear_lookup.to_linear(129)),
+        );
+        assert_eq!(linear_lookup.to_linear10(514), low * 2 + high * 2);
+        assert_eq!(linear_lookup.to_linear10(1023), 4 * LINEAR_MAX as u32);
+    }
+
+    #[test]
+    fn linear_average_brighter() {
+        let linear_lookup = LinearLookup::new();
+        let average =
+            (f64::from(linear_lookup.to_linear(0)) + f64::from(linear_lookup.to_linear(255))) / 2.0;
+        assert!(linear_lookup.to_srgb(average) > 180.0);
+    }
 }
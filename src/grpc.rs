@@ -0,0 +1,164 @@
+use std::{
+    net::SocketAddr,
+    thread::{self, JoinHandle},
+};
+
+use tokio::{runtime::Builder, sync::oneshot, task};
+use tonic::{transport::Server, Request, Response, Status};
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, WPARAM},
+    UI::WindowsAndMessaging::PostMessageA,
+};
+
+use crate::{
+    health::HealthEvent,
+    hidden_window::{WM_HEALTH_EVENT, WM_SET_BRIGHTNESS, WM_SET_ENABLED, WM_SET_PROFILE},
+    rest_api,
+    settings::GrpcConfiguration,
+};
+
+/// Types and the `Control` service generated from `proto/adalight.proto` by `build.rs`.
+mod proto {
+    tonic::include_proto!("adalight.v1");
+}
+
+use proto::{
+    control_server::{Control, ControlServer},
+    GetStatsRequest, GetStatsResponse, SetBrightnessRequest, SetBrightnessResponse,
+    SetProfileRequest, SetProfileResponse, StartRequest, StartResponse, StopRequest, StopResponse,
+};
+
+/// Implements the `Control` service by posting commands to the
+/// [crate::hidden_window::HiddenWindow], which owns the [crate::update_timer::UpdateTimer], the
+/// same way the [rest_api::RestServer] does.
+struct ControlService {
+    h_wnd: HWND,
+    profiles: Vec<String>,
+}
+
+impl ControlService {
+    /// Post a command to the [crate::hidden_window::HiddenWindow].
+    fn post(&self, message: u32, w_param: usize) -> Result<(), Status> {
+        if unsafe { PostMessageA(self.h_wnd, message, WPARAM(w_param), LPARAM(0)) }.as_bool() {
+            Ok(())
+        } else {
+            Err(Status::unavailable("the window is not accepting commands"))
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Control for ControlService {
+    async fn start(&self, _: Request<StartRequest>) -> Result<Response<StartResponse>, Status> {
+        self.post(WM_SET_ENABLED, 1)?;
+        Ok(Response::new(StartResponse {}))
+    }
+
+    async fn stop(&self, _: Request<StopRequest>) -> Result<Response<StopResponse>, Status> {
+        self.post(WM_SET_ENABLED, 0)?;
+        Ok(Response::new(StopResponse {}))
+    }
+
+    async fn set_brightness(
+        &self,
+        request: Request<SetBrightnessRequest>,
+    ) -> Result<Response<SetBrightnessResponse>, Status> {
+        let brightness = u8::try_from(request.into_inner().brightness)
+            .map_err(|_| Status::invalid_argument("brightness must be from 0 to 255"))?;
+        self.post(WM_SET_BRIGHTNESS, usize::from(brightness))?;
+        Ok(Response::new(SetBrightnessResponse {}))
+    }
+
+    async fn set_profile(
+        &self,
+        request: Request<SetProfileRequest>,
+    ) -> Result<Response<SetProfileResponse>, Status> {
+        let name = request.into_inner().name;
+        let profile = self
+            .profiles
+            .iter()
+            .position(|profile| *profile == name)
+            .ok_or_else(|| Status::not_found(format!("no profile named {:?}", name)))?;
+        self.post(WM_SET_PROFILE, profile)?;
+        Ok(Response::new(SetProfileResponse {}))
+    }
+
+    async fn get_stats(
+        &self,
+        _: Request<GetStatsRequest>,
+    ) -> Result<Response<GetStatsResponse>, Status> {
+        let h_wnd = self.h_wnd;
+        let status = task::spawn_blocking(move || rest_api::get_status(h_wnd))
+            .await
+            .ok()
+            .flatten()
+            .ok_or_else(|| Status::unavailable("the window did not return the status"))?;
+
+        Ok(Response::new(GetStatsResponse {
+            enabled: status.state.enabled,
+            brightness: u32::from(status.state.brightness),
+            frame_rate: status.state.frame_rate,
+            average_color: status.state.average_color >> 8,
+            profile: status
+                .profile
+                .and_then(|profile| self.profiles.get(profile))
+                .cloned()
+                .unwrap_or_default(),
+            frames_captured: status.stats.frames_captured as u64,
+            frames_skipped: status.stats.frames_skipped as u64,
+            output_failures: status.stats.output_failures as u64,
+        }))
+    }
+}
+
+/// Serves the `Control` service on its own thread with a single threaded runtime, until the
+/// [GrpcServer] is dropped.
+pub struct GrpcServer {
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GrpcServer {
+    /// Start serving on the `bind` address in the [GrpcConfiguration], and post any commands
+    /// to `h_wnd`. Returns `None` if the address is invalid. If the server fails later, e.g.
+    /// because the address is in use, it posts a [HealthEvent::GrpcFailed] to `h_wnd`.
+    pub fn start(
+        configuration: &GrpcConfiguration,
+        profiles: Vec<String>,
+        h_wnd: HWND,
+    ) -> Option<Self> {
+        let address: SocketAddr = configuration.bind.parse().ok()?;
+        let runtime = Builder::new_current_thread().enable_all().build().ok()?;
+        let (shutdown, rx) = oneshot::channel();
+        let service = ControlService { h_wnd, profiles };
+        let thread = thread::spawn(move || {
+            let server = Server::builder()
+                .add_service(ControlServer::new(service))
+                .serve_with_shutdown(address, async {
+                    let _ = rx.await;
+                });
+            if runtime.block_on(server).is_err() {
+                let (w_param, l_param) = HealthEvent::GrpcFailed.to_params();
+                unsafe {
+                    PostMessageA(h_wnd, WM_HEALTH_EVENT, WPARAM(w_param), LPARAM(l_param));
+                }
+            }
+        });
+
+        Some(Self {
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for GrpcServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
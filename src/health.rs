@@ -0,0 +1,242 @@
+use std::{fmt, sync::mpsc};
+
+/// Changes in the health of the outputs and the screen capture, which used to be swallowed as
+/// `bool` return values. The [crate::update_timer::UpdateTimer] collects them in a channel, and
+/// the [crate::hidden_window::HiddenWindow] logs them and keeps track of the [HealthStatus].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HealthEvent {
+    /// The [crate::serial_port::SerialPort] at this index in the `serial_outputs` was opened.
+    SerialConnected(usize),
+
+    /// A write to the [crate::serial_port::SerialPort] at this index failed, and it's trying
+    /// to reconnect.
+    SerialLost(usize),
+
+    /// The [crate::opc_pool::OpcConnection] to the server at this index in the `servers` is
+    /// connected.
+    OpcServerUp(usize),
+
+    /// The [crate::opc_pool::OpcConnection] to the server at this index lost its connection
+    /// or couldn't connect, and it's trying to reconnect.
+    OpcServerDown(usize),
+
+    /// The screen capture is throttled, e.g. because the D3D11 or DXGI resources were lost or
+    /// none of the outputs are listening.
+    CaptureThrottled,
+
+    /// The screen capture is running at the full frame rate again.
+    CaptureResumed,
+
+    /// One of the enabled displays is masking out protected content, so the LEDs are showing
+    /// the [crate::settings::ProtectedContentFallback] instead of the desktop.
+    ContentProtected,
+
+    /// None of the enabled displays are masking out protected content anymore.
+    ContentUnprotected,
+
+    /// The [crate::grpc::GrpcServer] couldn't bind to its address, or stopped with an error.
+    GrpcFailed,
+}
+
+impl HealthEvent {
+    /// Encode the [HealthEvent] as the `WPARAM` and `LPARAM` of a window message. The first
+    /// value is the kind of event, and the second is the index of the output, if it has one.
+    pub fn to_params(self) -> (usize, isize) {
+        match self {
+            Self::SerialConnected(index) => (0, index as isize),
+            Self::SerialLost(index) => (1, index as isize),
+            Self::OpcServerUp(index) => (2, index as isize),
+            Self::OpcServerDown(index) => (3, index as isize),
+            Self::CaptureThrottled => (4, 0),
+            Self::CaptureResumed => (5, 0),
+            Self::ContentProtected => (6, 0),
+            Self::ContentUnprotected => (7, 0),
+            Self::GrpcFailed => (8, 0),
+        }
+    }
+
+    /// Decode a [HealthEvent] from the `WPARAM` and `LPARAM` of a window message. Returns
+    /// `None` if it's not one of the kinds of events from [HealthEvent::to_params].
+    pub fn from_params(kind: usize, index: isize) -> Option<Self> {
+        let index = usize::try_from(index).ok()?;
+        match kind {
+            0 => Some(Self::SerialConnected(index)),
+            1 => Some(Self::SerialLost(index)),
+            2 => Some(Self::OpcServerUp(index)),
+            3 => Some(Self::OpcServerDown(index)),
+            4 => Some(Self::CaptureThrottled),
+            5 => Some(Self::CaptureResumed),
+            6 => Some(Self::ContentProtected),
+            7 => Some(Self::ContentUnprotected),
+            8 => Some(Self::GrpcFailed),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for HealthEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SerialConnected(index) => write!(f, "Serial Output {}: Connected", index),
+            Self::SerialLost(index) => write!(f, "Serial Output {}: Lost", index),
+            Self::OpcServerUp(index) => write!(f, "OPC Server {}: Up", index),
+            Self::OpcServerDown(index) => write!(f, "OPC Server {}: Down", index),
+            Self::CaptureThrottled => write!(f, "Capture: Throttled"),
+            Self::CaptureResumed => write!(f, "Capture: Resumed"),
+            Self::ContentProtected => write!(f, "Content Protected: true"),
+            Self::ContentUnprotected => write!(f, "Content Protected: false"),
+            Self::GrpcFailed => write!(f, "gRPC: Failed"),
+        }
+    }
+}
+
+/// Cloneable handle which each of the outputs and the timer use to report a [HealthEvent].
+/// The default one doesn't report anything, e.g. for a self-test. Nobody has to be listening,
+/// so the events are dropped if the receiver is gone.
+#[derive(Clone, Default)]
+pub struct HealthSender(Option<mpsc::Sender<HealthEvent>>);
+
+impl HealthSender {
+    /// Report each [HealthEvent] to the `tx` channel.
+    pub fn new(tx: mpsc::Sender<HealthEvent>) -> Self {
+        Self(Some(tx))
+    }
+
+    /// Report a [HealthEvent], if anyone is listening.
+    pub fn send(&self, event: HealthEvent) {
+        if let Some(tx) = self.0.as_ref() {
+            let _ = tx.send(event);
+        }
+    }
+}
+
+/// The latest health of each output and the screen capture, built up from the [HealthEvent]
+/// messages. Outputs which haven't reported anything yet aren't included.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HealthStatus {
+    /// Whether each serial output is connected, by index.
+    pub serial: Vec<Option<bool>>,
+
+    /// Whether each OPC server is up, by index.
+    pub servers: Vec<Option<bool>>,
+
+    /// True if the screen capture is throttled.
+    pub capture_throttled: bool,
+
+    /// True if one of the enabled displays is masking out protected content.
+    pub content_protected: bool,
+
+    /// True if the gRPC server failed.
+    pub grpc_failed: bool,
+}
+
+impl HealthStatus {
+    /// Update the [HealthStatus] with the latest [HealthEvent].
+    pub fn apply(&mut self, event: HealthEvent) {
+        match event {
+            HealthEvent::SerialConnected(index) => Self::set(&mut self.serial, index, true),
+            HealthEvent::SerialLost(index) => Self::set(&mut self.serial, index, false),
+            HealthEvent::OpcServerUp(index) => Self::set(&mut self.servers, index, true),
+            HealthEvent::OpcServerDown(index) => Self::set(&mut self.servers, index, false),
+            HealthEvent::CaptureThrottled => self.capture_throttled = true,
+            HealthEvent::CaptureResumed => self.capture_throttled = false,
+            HealthEvent::ContentProtected => self.content_protected = true,
+            HealthEvent::ContentUnprotected => self.content_protected = false,
+            HealthEvent::GrpcFailed => self.grpc_failed = true,
+        }
+    }
+
+    /// Set the value at `index`, growing the list if it hasn't seen that index yet.
+    fn set(values: &mut Vec<Option<bool>>, index: usize, value: bool) {
+        if values.len() <= index {
+            values.resize(index + 1, None);
+        }
+        values[index] = Some(value);
+    }
+
+    /// Count how many of the `values` which have reported anything are `true`.
+    fn count(values: &[Option<bool>]) -> (usize, usize) {
+        values
+            .iter()
+            .fold((0, 0), |(up, total), value| match value {
+                Some(true) => (up + 1, total + 1),
+                Some(false) => (up, total + 1),
+                None => (up, total),
+            })
+    }
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (serial_connected, serial_total) = Self::count(&self.serial);
+        let (servers_up, servers_total) = Self::count(&self.servers);
+        write!(
+            f,
+            "Serial Outputs Connected: {}/{}, OPC Servers Up: {}/{}, Capture Throttled: {}, \
+             Content Protected: {}, gRPC Failed: {}",
+            serial_connected,
+            serial_total,
+            servers_up,
+            servers_total,
+            self.capture_throttled,
+            self.content_protected,
+            self.grpc_failed
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_params() {
+        for event in [
+            HealthEvent::SerialConnected(1),
+            HealthEvent::SerialLost(0),
+            HealthEvent::OpcServerUp(3),
+            HealthEvent::OpcServerDown(2),
+            HealthEvent::CaptureThrottled,
+            HealthEvent::CaptureResumed,
+            HealthEvent::ContentProtected,
+            HealthEvent::ContentUnprotected,
+            HealthEvent::GrpcFailed,
+        ] {
+            let (kind, index) = event.to_params();
+            assert_eq!(HealthEvent::from_params(kind, index), Some(event));
+        }
+        assert_eq!(HealthEvent::from_params(9, 0), None);
+        assert_eq!(HealthEvent::from_params(0, -1), None);
+    }
+
+    #[test]
+    fn apply_events() {
+        let mut status = HealthStatus::default();
+        status.apply(HealthEvent::SerialConnected(1));
+        status.apply(HealthEvent::OpcServerUp(0));
+        status.apply(HealthEvent::OpcServerDown(0));
+        status.apply(HealthEvent::CaptureThrottled);
+        status.apply(HealthEvent::ContentProtected);
+        status.apply(HealthEvent::GrpcFailed);
+        assert_eq!(
+            status,
+            HealthStatus {
+                serial: vec![None, Some(true)],
+                servers: vec![Some(false)],
+                capture_throttled: true,
+                content_protected: true,
+                grpc_failed: true,
+            }
+        );
+        assert_eq!(
+            status.to_string(),
+            "Serial Outputs Connected: 1/1, OPC Servers Up: 0/1, Capture Throttled: true, \
+             Content Protected: true, gRPC Failed: true"
+        );
+
+        status.apply(HealthEvent::CaptureResumed);
+        assert!(!status.capture_throttled);
+        status.apply(HealthEvent::ContentUnprotected);
+        assert!(!status.content_protected);
+    }
+}
@@ -1,9 +1,9 @@
-use std::{cell::RefCell, mem, ptr, rc::Rc};
+use std::{cell::RefCell, mem, ptr, rc::Rc, thread};
 
 use windows::{
-    core::Error,
+    core::{Error, GUID},
     Win32::{
-        Foundation::{HWND, LPARAM, LRESULT, PSTR, PWSTR, WPARAM},
+        Foundation::{HANDLE, HWND, LPARAM, LRESULT, PSTR, PWSTR, WPARAM},
         System::{
             Diagnostics::Debug::{
                 FormatMessageW, FORMAT_MESSAGE_ALLOCATE_BUFFER, FORMAT_MESSAGE_FROM_SYSTEM,
@@ -15,29 +15,166 @@ use windows::{
                 NOTIFY_FOR_THIS_SESSION,
             },
         },
+        UI::HiDpi::{SetProcessDpiAwarenessContext, DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2},
         UI::WindowsAndMessaging::{
             self, CreateWindowExA, DefWindowProcA, DestroyWindow, GetSystemMetrics, MessageBoxW,
-            PostQuitMessage, RegisterClassExA, GWLP_USERDATA, HMENU, HWND_DESKTOP, MB_ICONERROR,
-            SM_REMOTESESSION, WINDOW_LONG_PTR_INDEX, WNDCLASSEXA,
+            PostMessageA, PostQuitMessage, RegisterClassExA, RegisterDeviceNotificationW, SetTimer,
+            UnregisterDeviceNotification, DBT_DEVICEARRIVAL, DBT_DEVTYP_DEVICEINTERFACE,
+            DEVICE_NOTIFY_WINDOW_HANDLE, DEV_BROADCAST_DEVICEINTERFACE_W, DEV_BROADCAST_HDR,
+            GWLP_USERDATA, HDEVNOTIFY, HMENU, HWND_DESKTOP, MB_ICONERROR, SM_REMOTESESSION,
+            WINDOW_LONG_PTR_INDEX, WNDCLASSEXA,
         },
     },
 };
 
-use crate::update_timer::UpdateTimer;
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcServer;
+use crate::{
+    health::{HealthEvent, HealthStatus},
+    mqtt::{MqttClient, MqttState},
+    rest_api::{ControlStatus, RestServer, StatusReply},
+    settings::{GrpcConfiguration, MqttConfiguration, RestConfiguration},
+    update_timer::UpdateTimer,
+};
+
+/// Message which another process can post to the `AdaLightListener` window to enable or
+/// disable one of the configured displays at runtime. The `WPARAM` is the index of the display
+/// in the settings, and the `LPARAM` is non-zero to enable it or 0 to disable it.
+const WM_SET_DISPLAY_ENABLED: u32 = WindowsAndMessaging::WM_APP + 1;
+
+/// Message which another process can post to the `AdaLightListener` window to change the
+/// global brightness of every output at runtime. The `WPARAM` is the brightness (0-255).
+pub const WM_SET_BRIGHTNESS: u32 = WindowsAndMessaging::WM_APP + 2;
+
+/// Message which another process can post to the `AdaLightListener` window to turn the LEDs on
+/// or off at runtime. The `WPARAM` is non-zero to turn them on or 0 to turn them off.
+pub const WM_SET_ENABLED: u32 = WindowsAndMessaging::WM_APP + 3;
+
+/// Message which another process can post to the `AdaLightListener` window to pause or resume
+/// one of the configured OPC servers at runtime. The `WPARAM` is the index of the server in the
+/// settings, and the `LPARAM` is non-zero to resume it or 0 to pause it.
+const WM_SET_SERVER_ENABLED: u32 = WindowsAndMessaging::WM_APP + 4;
+
+/// Message which another process can post to the `AdaLightListener` window to switch to one of
+/// the configured profiles at runtime. The `WPARAM` is the index of the profile in the settings.
+pub const WM_SET_PROFILE: u32 = WindowsAndMessaging::WM_APP + 5;
+
+/// Message which the [RestServer] posts to the `AdaLightListener` window to get the current
+/// [ControlStatus]. The `WPARAM` is the size of the [StatusReply], and the `LPARAM` is a boxed
+/// [StatusReply], which the window takes ownership of and sends the [ControlStatus] to.
+pub const WM_GET_STATUS: u32 = WindowsAndMessaging::WM_APP + 6;
+
+/// Message which the [WindowState] posts to the `AdaLightListener` window for each
+/// [HealthEvent] from the [UpdateTimer]. The `WPARAM` and `LPARAM` are encoded with
+/// [HealthEvent::to_params].
+pub const WM_HEALTH_EVENT: u32 = WindowsAndMessaging::WM_APP + 7;
+
+/// Timer ID for publishing the [MqttState] every [MqttConfiguration] `interval`.
+const MQTT_TIMER_ID: usize = 1;
+
+/// Device interface class for COM ports, which USB serial adapters like the Arduino register
+/// when they're plugged in.
+const GUID_DEVINTERFACE_COMPORT: GUID = GUID::from_u128(0x86e0d1e0_8089_11d0_9ce4_08003e301f73);
 
 /// Boxed state for the [HiddenWindow] stored in the [WindowsAndMessaging::GWLP_USERDATA]
 /// data slot.
 struct WindowState {
     pub connected_to_console: bool,
+    pub enabled: bool,
     pub timer: UpdateTimer,
+    pub serial_notification: HDEVNOTIFY,
+    pub mqtt: Option<MqttClient>,
+    pub health: HealthStatus,
+    pub _rest: Option<RestServer>,
+    #[cfg(feature = "grpc")]
+    pub _grpc: Option<GrpcServer>,
 }
 
 impl WindowState {
     /// Allocate a new instance of [WindowState] and pass it ownership of the [UpdateTimer].
-    pub fn new(timer: UpdateTimer) -> Self {
+    /// This also registers `h_wnd` for [DBT_DEVICEARRIVAL] notifications when a COM port
+    /// is plugged in, and starts the [MqttClient], the [RestServer], and the gRPC server if
+    /// they're configured. Each [HealthEvent] from the [UpdateTimer] is relayed to `h_wnd` as
+    /// a [WM_HEALTH_EVENT] message by another thread, which exits when the [UpdateTimer] is
+    /// dropped.
+    pub fn new(
+        h_wnd: HWND,
+        timer: UpdateTimer,
+        mqtt: Option<MqttConfiguration>,
+        rest: Option<RestConfiguration>,
+        grpc: Option<GrpcConfiguration>,
+    ) -> Self {
+        let filter = DEV_BROADCAST_DEVICEINTERFACE_W {
+            dbcc_size: mem::size_of::<DEV_BROADCAST_DEVICEINTERFACE_W>() as u32,
+            dbcc_devicetype: DBT_DEVTYP_DEVICEINTERFACE,
+            dbcc_classguid: GUID_DEVINTERFACE_COMPORT,
+            ..Default::default()
+        };
+
+        let mqtt = mqtt.map(|mqtt| {
+            unsafe {
+                SetTimer(h_wnd, MQTT_TIMER_ID, mqtt.interval, None);
+            }
+            MqttClient::start(mqtt, h_wnd)
+        });
+        if let Some(events) = timer.health_events() {
+            thread::spawn(move || {
+                for event in events {
+                    let (w_param, l_param) = event.to_params();
+                    unsafe {
+                        PostMessageA(h_wnd, WM_HEALTH_EVENT, WPARAM(w_param), LPARAM(l_param));
+                    }
+                }
+            });
+        }
+
+        let rest = rest.and_then(|rest| RestServer::start(&rest, timer.profile_names(), h_wnd));
+        #[cfg(feature = "grpc")]
+        let grpc = grpc.and_then(|grpc| GrpcServer::start(&grpc, timer.profile_names(), h_wnd));
+        // Settings::from_str rejects a gRPC configuration without the grpc feature.
+        #[cfg(not(feature = "grpc"))]
+        let _ = grpc;
+
         Self {
             connected_to_console: unsafe { GetSystemMetrics(SM_REMOTESESSION) } == 0,
+            enabled: true,
             timer,
+            serial_notification: unsafe {
+                RegisterDeviceNotificationW(
+                    HANDLE(h_wnd.0),
+                    &filter as *const _ as *const _,
+                    DEVICE_NOTIFY_WINDOW_HANDLE,
+                )
+            },
+            mqtt,
+            health: HealthStatus::default(),
+            _rest: rest,
+            #[cfg(feature = "grpc")]
+            _grpc: grpc,
+        }
+    }
+
+    /// Get the current [MqttState], which is also part of the [ControlStatus] for the
+    /// [RestServer]. The frame rate and average color are 0 unless the timer is running.
+    fn current_state(&self) -> MqttState {
+        let running = self.connected_to_console && self.enabled;
+        let stats = self.timer.stats();
+        MqttState {
+            enabled: self.enabled,
+            brightness: self.timer.brightness().unwrap_or(255),
+            frame_rate: if running { stats.frame_rate } else { 0.0 },
+            average_color: if running { stats.average_color } else { 0 },
+        }
+    }
+}
+
+impl Drop for WindowState {
+    fn drop(&mut self) {
+        if self.serial_notification != HDEVNOTIFY::default() {
+            unsafe {
+                UnregisterDeviceNotification(self.serial_notification);
+            }
+            self.serial_notification = HDEVNOTIFY::default();
         }
     }
 }
@@ -48,9 +185,21 @@ pub struct HiddenWindow(HWND);
 
 impl HiddenWindow {
     /// Allocate a new instance of [HiddenWindow] and create the new [HWND]. The [UpdateTimer]
-    /// in `timer` is passed to the [WindowState], which takes ownership of it.
-    pub fn new(timer: UpdateTimer) -> Self {
+    /// in `timer`, the optional [MqttConfiguration], the optional [RestConfiguration], and the
+    /// optional [GrpcConfiguration] are passed to the [WindowState], which takes ownership of
+    /// them.
+    pub fn new(
+        timer: UpdateTimer,
+        mqtt: Option<MqttConfiguration>,
+        rest: Option<RestConfiguration>,
+        grpc: Option<GrpcConfiguration>,
+    ) -> Self {
         let h_wnd = unsafe {
+            // Opt in to per-monitor DPI awareness before creating the window, so DXGI and
+            // WM_DISPLAYCHANGE report physical pixels on every monitor instead of coordinates
+            // scaled for the DPI of the primary monitor.
+            SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2);
+
             let class_name = Self::get_window_class();
             let exe_instance = GetModuleHandleA(PSTR::default());
             let window_class = WNDCLASSEXA {
@@ -78,7 +227,9 @@ impl HiddenWindow {
                     exe_instance,
                     ptr::null(),
                 );
-                let state = Box::new(Rc::new(RefCell::new(Some(WindowState::new(timer)))));
+                let state = Box::new(Rc::new(RefCell::new(Some(WindowState::new(
+                    h_wnd, timer, mqtt, rest, grpc,
+                )))));
                 Self::set_window_long(h_wnd, GWLP_USERDATA, Box::into_raw(state) as isize);
                 Self::attach_to_console(h_wnd);
                 h_wnd
@@ -156,7 +307,7 @@ impl HiddenWindow {
     fn attach_to_console(h_wnd: HWND) {
         if let Some(state) = Self::get_window_state(h_wnd) {
             let state = state.borrow();
-            if state.connected_to_console {
+            if state.connected_to_console && state.enabled {
                 state.timer.resume();
                 state.timer.start();
             }
@@ -173,6 +324,112 @@ impl HiddenWindow {
         }
     }
 
+    /// Handle monitors being plugged in or removed, changing their display mode, or changing
+    /// their DPI scaling. [WindowsAndMessaging::WM_DEVICECHANGE] fires for every device node,
+    /// e.g. a mouse, so it's only used for the serial ports.
+    fn refresh_displays(h_wnd: HWND) {
+        if let Some(state) = Self::get_window_state(h_wnd) {
+            let state = state.borrow();
+            if state.connected_to_console {
+                state.timer.refresh_displays();
+            }
+        }
+    }
+
+    /// Handle a [DBT_DEVICEARRIVAL] notification for a COM port.
+    fn serial_port_arrived(h_wnd: HWND) {
+        if let Some(state) = Self::get_window_state(h_wnd) {
+            let state = state.borrow();
+            if state.connected_to_console {
+                state.timer.serial_port_arrived();
+            }
+        }
+    }
+
+    /// Handle a [WM_SET_DISPLAY_ENABLED] message.
+    fn set_display_enabled(h_wnd: HWND, display_index: usize, enabled: bool) -> bool {
+        match Self::get_window_state(h_wnd) {
+            Some(state) => state
+                .borrow()
+                .timer
+                .set_display_enabled(display_index, enabled),
+            None => false,
+        }
+    }
+
+    /// Handle a [WM_SET_SERVER_ENABLED] message.
+    fn set_server_enabled(h_wnd: HWND, server_index: usize, enabled: bool) -> bool {
+        match Self::get_window_state(h_wnd) {
+            Some(state) => state
+                .borrow()
+                .timer
+                .set_server_enabled(server_index, enabled),
+            None => false,
+        }
+    }
+
+    /// Handle a [WM_SET_BRIGHTNESS] message.
+    fn set_brightness(h_wnd: HWND, brightness: u8) {
+        if let Some(state) = Self::get_window_state(h_wnd) {
+            state.borrow().timer.set_brightness(brightness);
+        }
+    }
+
+    /// Handle a [WM_SET_ENABLED] message.
+    fn set_enabled(h_wnd: HWND, enabled: bool) {
+        if let Some(state) = Self::get_window_state(h_wnd) {
+            if state.borrow().enabled == enabled {
+                return;
+            }
+
+            if enabled {
+                state.borrow_mut().enabled = true;
+                Self::attach_to_console(h_wnd);
+            } else {
+                Self::detach_from_console(h_wnd);
+                state.borrow_mut().enabled = false;
+            }
+        }
+    }
+
+    /// Publish the [MqttState] every [MqttConfiguration] `interval`.
+    fn publish_state(h_wnd: HWND) {
+        if let Some(state) = Self::get_window_state(h_wnd) {
+            let state = state.borrow();
+            if let Some(mqtt) = state.mqtt.as_ref() {
+                mqtt.publish(state.current_state());
+            }
+        }
+    }
+
+    /// Handle a [WM_SET_PROFILE] message.
+    fn set_profile(h_wnd: HWND, profile_index: usize) -> bool {
+        match Self::get_window_state(h_wnd) {
+            Some(state) => state.borrow().timer.apply_profile(profile_index),
+            None => false,
+        }
+    }
+
+    /// Handle a [WM_HEALTH_EVENT] message by updating the [HealthStatus], which the REST API
+    /// and gRPC clients can read with [WM_GET_STATUS].
+    fn health_event(h_wnd: HWND, event: HealthEvent) {
+        if let Some(state) = Self::get_window_state(h_wnd) {
+            state.borrow_mut().health.apply(event);
+        }
+    }
+
+    /// Handle a [WM_GET_STATUS] message.
+    fn get_status(h_wnd: HWND) -> Option<ControlStatus> {
+        let state = Self::get_window_state(h_wnd)?;
+        let state = state.borrow();
+        Some(ControlStatus {
+            state: state.current_state(),
+            profile: state.timer.active_profile(),
+            stats: state.timer.stats(),
+            health: state.health.clone(),
+        })
+    }
+
     /// Implement the [HiddenWindow] [WindowsAndMessaging::WNDPROC].
     unsafe extern "system" fn window_proc(
         h_wnd: HWND,
@@ -213,9 +470,51 @@ impl HiddenWindow {
                 };
                 Default::default()
             }
-            WindowsAndMessaging::WM_DISPLAYCHANGE => {
-                Self::detach_from_console(h_wnd);
-                Self::attach_to_console(h_wnd);
+            WindowsAndMessaging::WM_DISPLAYCHANGE | WindowsAndMessaging::WM_DPICHANGED => {
+                Self::refresh_displays(h_wnd);
+                Default::default()
+            }
+            WindowsAndMessaging::WM_DEVICECHANGE => {
+                if w_param.0 as u32 == DBT_DEVICEARRIVAL {
+                    let header = l_param.0 as *const DEV_BROADCAST_HDR;
+                    if !header.is_null() && (*header).dbch_devicetype == DBT_DEVTYP_DEVICEINTERFACE
+                    {
+                        Self::serial_port_arrived(h_wnd);
+                    }
+                }
+                Default::default()
+            }
+            WM_SET_DISPLAY_ENABLED => {
+                LRESULT(Self::set_display_enabled(h_wnd, w_param.0, l_param.0 != 0) as isize)
+            }
+            WM_SET_BRIGHTNESS => {
+                Self::set_brightness(h_wnd, w_param.0.min(255) as u8);
+                Default::default()
+            }
+            WM_SET_ENABLED => {
+                Self::set_enabled(h_wnd, w_param.0 != 0);
+                Default::default()
+            }
+            WM_SET_SERVER_ENABLED => {
+                LRESULT(Self::set_server_enabled(h_wnd, w_param.0, l_param.0 != 0) as isize)
+            }
+            WM_SET_PROFILE => LRESULT(Self::set_profile(h_wnd, w_param.0) as isize),
+            WM_GET_STATUS if w_param.0 == mem::size_of::<StatusReply>() && l_param.0 != 0 => {
+                let reply = Box::from_raw(l_param.0 as *mut StatusReply);
+                if let Some(current) = Self::get_status(h_wnd) {
+                    // The request may have stopped waiting, so ignore a closed channel.
+                    let _ = reply.try_send(current);
+                }
+                Default::default()
+            }
+            WM_HEALTH_EVENT => {
+                if let Some(event) = HealthEvent::from_params(w_param.0, l_param.0) {
+                    Self::health_event(h_wnd, event);
+                }
+                Default::default()
+            }
+            WindowsAndMessaging::WM_TIMER if w_param.0 == MQTT_TIMER_ID => {
+                Self::publish_state(h_wnd);
                 Default::default()
             }
             _ => DefWindowProcA(h_wnd, message, w_param, l_param),
@@ -0,0 +1,187 @@
+use crate::settings::HsvConfiguration;
+
+/// Convert an RGB color with channels in the range `0.0..=255.0` to HSV, with the hue in
+/// degrees (`0.0..360.0`), and the saturation and value in the range `0.0..=1.0`.
+fn rgb_to_hsv((r, g, b): (f64, f64, f64)) -> (f64, f64, f64) {
+    let (r, g, b) = (r / 255.0, g / 255.0, b / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta <= f64::EPSILON {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max <= f64::EPSILON {
+        0.0
+    } else {
+        delta / max
+    };
+
+    (hue, saturation, max)
+}
+
+/// Convert an HSV color from [rgb_to_hsv] back to RGB, with channels in the range
+/// `0.0..=255.0`.
+fn hsv_to_rgb((hue, saturation, value): (f64, f64, f64)) -> (f64, f64, f64) {
+    let chroma = value * saturation;
+    let sector = (hue / 60.0).rem_euclid(6.0);
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = value - chroma;
+
+    ((r + m) * 255.0, (g + m) * 255.0, (b + m) * 255.0)
+}
+
+/// Multiply the saturation of an averaged `color` by the `saturation` gain in the
+/// [HsvConfiguration], and raise its value to the `value_gamma`. Neither of them can go past
+/// full saturation or brightness, so none of the channels clip.
+pub fn adjust(configuration: &HsvConfiguration, color: (f64, f64, f64)) -> (f64, f64, f64) {
+    let (hue, saturation, value) = rgb_to_hsv(color);
+    hsv_to_rgb((
+        hue,
+        (saturation * configuration.saturation).clamp(0.0, 1.0),
+        value.powf(configuration.value_gamma).clamp(0.0, 1.0),
+    ))
+}
+
+/// Blend the `amount` (from `0.0` to `1.0`) of the `to` color into the `from` color in HSV,
+/// turning the hue the short way around the color wheel, so a fade keeps its saturation.
+pub fn blend(from: (f64, f64, f64), to: (f64, f64, f64), amount: f64) -> (f64, f64, f64) {
+    let (from_hue, mut from_saturation, from_value) = rgb_to_hsv(from);
+    let (to_hue, mut to_saturation, to_value) = rgb_to_hsv(to);
+
+    // A gray doesn't have a hue, so it takes the hue of the other color.
+    let from_hue = if from_saturation <= f64::EPSILON {
+        to_hue
+    } else {
+        from_hue
+    };
+    let to_hue = if to_saturation <= f64::EPSILON {
+        from_hue
+    } else {
+        to_hue
+    };
+
+    // Black doesn't have a saturation, so fading in from or out to black only changes the
+    // value of the other color.
+    if from_value <= f64::EPSILON {
+        from_saturation = to_saturation;
+    } else if to_value <= f64::EPSILON {
+        to_saturation = from_saturation;
+    }
+
+    let turn = (to_hue - from_hue + 540.0).rem_euclid(360.0) - 180.0;
+    let mix = |from: f64, to: f64| from + (to - from) * amount;
+
+    hsv_to_rgb((
+        (from_hue + turn * amount).rem_euclid(360.0),
+        mix(from_saturation, to_saturation),
+        mix(from_value, to_value),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close((r1, g1, b1): (f64, f64, f64), (r2, g2, b2): (f64, f64, f64)) {
+        assert!(
+            (r1 - r2).abs() < 0.01 && (g1 - g2).abs() < 0.01 && (b1 - b2).abs() < 0.01,
+            "({}, {}, {}) != ({}, {}, {})",
+            r1,
+            g1,
+            b1,
+            r2,
+            g2,
+            b2
+        );
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        for color in [
+            (0.0, 0.0, 0.0),
+            (255.0, 255.0, 255.0),
+            (255.0, 0.0, 0.0),
+            (12.0, 200.0, 64.0),
+            (90.0, 30.0, 180.0),
+            (200.0, 10.0, 150.0),
+        ] {
+            assert_close(hsv_to_rgb(rgb_to_hsv(color)), color);
+        }
+    }
+
+    #[test]
+    fn identity_adjustment() {
+        let configuration = HsvConfiguration {
+            saturation: 1.0,
+            value_gamma: 1.0,
+        };
+        assert_close(
+            adjust(&configuration, (40.0, 120.0, 200.0)),
+            (40.0, 120.0, 200.0),
+        );
+    }
+
+    #[test]
+    fn boost_saturation() {
+        let configuration = HsvConfiguration {
+            saturation: 2.0,
+            value_gamma: 1.0,
+        };
+        assert_close(
+            adjust(&configuration, (100.0, 200.0, 200.0)),
+            (0.0, 200.0, 200.0),
+        );
+        assert_close(
+            adjust(&configuration, (150.0, 200.0, 200.0)),
+            (100.0, 200.0, 200.0),
+        );
+        assert_close(
+            adjust(&configuration, (128.0, 128.0, 128.0)),
+            (128.0, 128.0, 128.0),
+        );
+    }
+
+    #[test]
+    fn brighten_value() {
+        let configuration = HsvConfiguration {
+            saturation: 1.0,
+            value_gamma: 0.5,
+        };
+        assert_close(adjust(&configuration, (63.75, 0.0, 0.0)), (127.5, 0.0, 0.0));
+        assert_close(
+            adjust(&configuration, (255.0, 255.0, 0.0)),
+            (255.0, 255.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn blend_hue() {
+        assert_close(
+            blend((255.0, 0.0, 0.0), (0.0, 255.0, 0.0), 0.5),
+            (255.0, 255.0, 0.0),
+        );
+        assert_close(
+            blend((255.0, 0.0, 0.0), (255.0, 0.0, 255.0), 0.5),
+            (255.0, 0.0, 127.5),
+        );
+        assert_close(
+            blend((0.0, 0.0, 0.0), (0.0, 0.0, 255.0), 0.5),
+            (0.0, 0.0, 127.5),
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+/// Send a single HTTP/1.1 request with a JSON `body` to `host:port`, for the REST APIs which
+/// switch some of the outputs into a streaming mode. Returns the status code and the body of
+/// the response, or `None` if the request failed or timed out.
+pub fn request(
+    host: &str,
+    port: u16,
+    method: &str,
+    path: &str,
+    body: &str,
+    timeout: Duration,
+) -> Option<(u16, String)> {
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        host,
+        body.len(),
+        body
+    );
+
+    let address = (host, port).to_socket_addrs().ok()?.next()?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout).ok()?;
+    stream.set_read_timeout(Some(timeout)).ok()?;
+    stream.set_write_timeout(Some(timeout)).ok()?;
+    stream.write_all(request.as_bytes()).ok()?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    parse_response(&response)
+}
+
+/// Split an HTTP response into the status code and the body.
+fn parse_response(response: &str) -> Option<(u16, String)> {
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())?;
+    let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+    Some((status, body.to_string()))
+}
+
+/// Split an `http://host:port/path` URL into the host, port (default 80), and path.
+pub fn parse_url(url: &str) -> Option<(&str, u16, &str)> {
+    let url = url.strip_prefix("http://")?;
+    let (authority, path) = match url.find('/') {
+        Some(index) => url.split_at(index),
+        None => (url, "/"),
+    };
+    match authority.rsplit_once(':') {
+        Some((host, port)) => Some((host, port.parse().ok()?, path)),
+        None => Some((authority, 80, path)),
+    }
+}
+
+/// Test if an HTTP status code means the request succeeded.
+pub fn is_success(status: u16) -> bool {
+    (200..300).contains(&status)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn responses() {
+        assert_eq!(
+            parse_response("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n[]"),
+            Some((200, String::from("[]")))
+        );
+        assert_eq!(
+            parse_response("HTTP/1.1 204 No Content\r\n\r\n"),
+            Some((204, String::new()))
+        );
+        assert_eq!(parse_response(""), None);
+        assert!(is_success(204));
+        assert!(!is_success(403));
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            parse_url("http://localhost:54236/chromasdk"),
+            Some(("localhost", 54236, "/chromasdk"))
+        );
+        assert_eq!(parse_url("http://bridge"), Some(("bridge", 80, "/")));
+        assert_eq!(parse_url("https://bridge/"), None);
+        assert_eq!(parse_url("http://bridge:port/"), None);
+    }
+}
@@ -0,0 +1,190 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    net::UdpSocket,
+    runtime::{Builder, Runtime},
+    time,
+};
+use webrtc_dtls::{cipher_suite::CipherSuiteId, config::Config, conn::DTLSConn};
+
+use crate::{
+    http_client, output_sink::OutputSink, pixel_buffer::PixelBuffer, screen_samples::ScreenSamples,
+    settings::HueBridge,
+};
+
+/// UDP port which the bridge listens on for the DTLS stream.
+const HUE_STREAM_PORT: u16 = 2100;
+
+/// Give up on each request to the bridge, or on the DTLS handshake, after this long.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Decode the hex `client_key` from the bridge into the pre-shared key for the DTLS stream.
+fn decode_hex(client_key: &str) -> Option<Vec<u8>> {
+    client_key
+        .as_bytes()
+        .chunks(2)
+        .map(|digits| match digits {
+            [high, low] => Some(
+                ((char::from(*high).to_digit(16)? << 4) | char::from(*low).to_digit(16)?) as u8,
+            ),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Representation of a Hue Entertainment API stream to the lamps in an entertainment area on
+/// a [HueBridge]. The bridge only accepts the stream over DTLS with a pre-shared key, so the
+/// connection runs on its own async runtime.
+pub struct HueConnection<'a> {
+    bridge: &'a HueBridge,
+    runtime: Runtime,
+    connection: Option<DTLSConn>,
+}
+
+impl<'a> HueConnection<'a> {
+    /// Allocate a new [HueConnection] without starting the stream.
+    pub fn new(bridge: &'a HueBridge) -> Self {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("hue")
+            .enable_all()
+            .build()
+            .expect("create Hue runtime");
+        Self {
+            bridge,
+            runtime,
+            connection: None,
+        }
+    }
+
+    /// Start or stop streaming to the entertainment area with the (version 1) REST API on the
+    /// bridge. Returns `true` if the bridge reported success.
+    fn set_streaming(&self, active: bool) -> bool {
+        matches!(
+            http_client::request(
+                &self.bridge.host,
+                80,
+                "PUT",
+                &format!("/api/{}/groups/{}", self.bridge.username, self.bridge.group),
+                &format!(r#"{{"stream":{{"active":{}}}}}"#, active),
+                CONNECT_TIMEOUT,
+            ),
+            Some((status, body)) if http_client::is_success(status) && body.contains("\"success\"")
+        )
+    }
+
+    /// Perform the DTLS handshake with the bridge, using the application `username` as the
+    /// identity and the `client_key` as the pre-shared key.
+    async fn connect(host: String, username: String, key: Vec<u8>) -> Option<DTLSConn> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await.ok()?;
+        socket
+            .connect((host.as_str(), HUE_STREAM_PORT))
+            .await
+            .ok()?;
+        let config = Config {
+            psk: Some(Arc::new(move |_hint: &[u8]| Ok(key.clone()))),
+            psk_identity_hint: Some(username.into_bytes()),
+            cipher_suites: vec![CipherSuiteId::Tls_Psk_With_Aes_128_Gcm_Sha256],
+            ..Default::default()
+        };
+
+        time::timeout(
+            CONNECT_TIMEOUT,
+            DTLSConn::new(Arc::new(socket), config, true, None),
+        )
+        .await
+        .ok()?
+        .ok()
+    }
+}
+
+impl<'a> OutputSink for HueConnection<'a> {
+    /// Activate the entertainment area and start the DTLS stream if it isn't streaming yet.
+    fn open(&mut self) -> bool {
+        if self.connection.is_none() {
+            let key = match decode_hex(&self.bridge.client_key) {
+                Some(key) => key,
+                None => return false,
+            };
+
+            if !self.set_streaming(true) {
+                return false;
+            }
+
+            self.connection = self.runtime.block_on(Self::connect(
+                self.bridge.host.clone(),
+                self.bridge.username.clone(),
+                key,
+            ));
+
+            if self.connection.is_none() {
+                self.set_streaming(false);
+            }
+        }
+
+        self.healthy()
+    }
+
+    /// Render a message with the average color of the LEDs for each of the lights.
+    fn render(&self, samples: &ScreenSamples) -> Vec<PixelBuffer> {
+        let light_ids: Vec<u16> = self.bridge.lights.iter().map(|light| light.id).collect();
+        let mut pixels = PixelBuffer::new_hue_buffer(&light_ids);
+        for (index, light) in self.bridge.lights.iter().enumerate() {
+            pixels.set_hue_color(index, samples.average_leds(&light.leds));
+        }
+        vec![pixels]
+    }
+
+    /// Send the message over the DTLS stream.
+    fn send(&mut self, pixels: Vec<PixelBuffer>) -> bool {
+        let result = match self.connection.as_ref() {
+            Some(connection) => self.runtime.block_on(async {
+                for pixels in pixels.iter() {
+                    connection.write(pixels.data(), None).await?;
+                }
+                Ok::<(), webrtc_dtls::Error>(())
+            }),
+            None => return false,
+        };
+
+        if result.is_err() {
+            self.close();
+            return false;
+        }
+
+        true
+    }
+
+    /// Close the DTLS stream and deactivate the entertainment area, so the lamps go back to
+    /// their normal state.
+    fn close(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            let _ = self.runtime.block_on(connection.close());
+            self.set_streaming(false);
+        }
+    }
+
+    /// Test if the DTLS stream is open.
+    fn healthy(&self) -> bool {
+        self.connection.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn client_key() {
+        assert_eq!(
+            decode_hex("00112233445566778899AABBCCDDeeff"),
+            Some(vec![
+                0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+                0xEE, 0xFF
+            ])
+        );
+        assert_eq!(decode_hex(""), Some(Vec::new()));
+        assert_eq!(decode_hex("123"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
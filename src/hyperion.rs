@@ -0,0 +1,523 @@
+use std::{
+    io::{Error, ErrorKind, Read, Result, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    output_sink::OutputSink,
+    pixel_buffer::PixelBuffer,
+    screen_samples::ScreenSamples,
+    serial_port::SerialPort,
+    settings::{DisplayConfiguration, HyperionServer, Settings},
+};
+
+/// Types in the `Command` union of a Hyperion flatbuffers `Request`.
+const COMMAND_IMAGE: u8 = 2;
+const COMMAND_CLEAR: u8 = 3;
+const COMMAND_REGISTER: u8 = 4;
+
+/// Type in the `ImageType` union of a Hyperion flatbuffers `Image` for a `RawImage`.
+const IMAGE_TYPE_RAW: u8 = 1;
+
+/// Keep showing each image until the next one replaces it, or until we disconnect.
+const IMAGE_DURATION: i32 = -1;
+
+/// Vtable for the `Request` table, which only holds the `command` union.
+const REQUEST_VTABLE: [u16; 4] = [8, 12, 8, 4];
+
+/// Hyperion doesn't use the flatbuffers library for the framing, each message is a flatbuffer
+/// with a big-endian length prefix.
+fn frame_message(flatbuffer: &[u8]) -> Vec<u8> {
+    let mut message = (flatbuffer.len() as u32).to_be_bytes().to_vec();
+    message.extend_from_slice(flatbuffer);
+    message
+}
+
+/// Write each of the `values` to the flatbuffer in little-endian order, e.g. for a vtable.
+fn push_u16s(flatbuffer: &mut Vec<u8>, values: &[u16]) {
+    for value in values {
+        flatbuffer.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Encode a `Request` with a `Register` command, which sets the `origin` and `priority` for
+/// the rest of the commands on the connection. The layout is fixed, so it's encoded by hand
+/// instead of depending on the flatbuffers library:
+///
+/// | Offset | Contents                     |
+/// |--------|------------------------------|
+/// | 0      | Root offset to `Request`     |
+/// | 4      | `Request` vtable             |
+/// | 12     | `Register` vtable            |
+/// | 20     | `Request` table              |
+/// | 32     | `Register` table             |
+/// | 44     | `origin` string              |
+fn register_message(origin: &str, priority: i32) -> Vec<u8> {
+    let mut flatbuffer = 20_u32.to_le_bytes().to_vec();
+    push_u16s(&mut flatbuffer, &REQUEST_VTABLE);
+    push_u16s(&mut flatbuffer, &[8, 12, 4, 8]);
+
+    flatbuffer.extend_from_slice(&16_i32.to_le_bytes());
+    flatbuffer.extend_from_slice(&8_u32.to_le_bytes());
+    flatbuffer.extend_from_slice(&[COMMAND_REGISTER, 0, 0, 0]);
+
+    flatbuffer.extend_from_slice(&20_i32.to_le_bytes());
+    flatbuffer.extend_from_slice(&8_u32.to_le_bytes());
+    flatbuffer.extend_from_slice(&priority.to_le_bytes());
+
+    flatbuffer.extend_from_slice(&(origin.len() as u32).to_le_bytes());
+    flatbuffer.extend_from_slice(origin.as_bytes());
+    flatbuffer.push(0);
+    flatbuffer.resize((flatbuffer.len() + 3) & !3, 0);
+
+    frame_message(&flatbuffer)
+}
+
+/// Encode a `Request` with a `Clear` command for the `priority`, with the same layout as the
+/// [register_message] but a `Clear` table in place of the `Register` table and no string.
+fn clear_message(priority: i32) -> Vec<u8> {
+    let mut flatbuffer = 20_u32.to_le_bytes().to_vec();
+    push_u16s(&mut flatbuffer, &REQUEST_VTABLE);
+    push_u16s(&mut flatbuffer, &[6, 8, 4, 0]);
+
+    flatbuffer.extend_from_slice(&16_i32.to_le_bytes());
+    flatbuffer.extend_from_slice(&8_u32.to_le_bytes());
+    flatbuffer.extend_from_slice(&[COMMAND_CLEAR, 0, 0, 0]);
+
+    flatbuffer.extend_from_slice(&20_i32.to_le_bytes());
+    flatbuffer.extend_from_slice(&priority.to_le_bytes());
+
+    frame_message(&flatbuffer)
+}
+
+/// Encode everything in a `Request` with an `Image` command up to the start of the RGB data
+/// for a `RawImage` of `width` by `height` pixels. Returns the header along with the number of
+/// padding bytes which need to follow the RGB data.
+///
+/// | Offset | Contents                     |
+/// |--------|------------------------------|
+/// | 0      | Root offset to `Request`     |
+/// | 4      | `Request` vtable             |
+/// | 12     | `Image` vtable               |
+/// | 24     | `RawImage` vtable            |
+/// | 36     | `Request` table              |
+/// | 48     | `Image` table                |
+/// | 64     | `RawImage` table             |
+/// | 80     | `data` vector                |
+fn image_header(width: usize, height: usize) -> (Vec<u8>, usize) {
+    let data_size = 3 * width * height;
+    let padding = (4 - data_size % 4) % 4;
+
+    let mut flatbuffer = 36_u32.to_le_bytes().to_vec();
+    push_u16s(&mut flatbuffer, &REQUEST_VTABLE);
+    push_u16s(&mut flatbuffer, &[10, 16, 12, 4, 8, 0]);
+    push_u16s(&mut flatbuffer, &[10, 16, 4, 8, 12, 0]);
+
+    flatbuffer.extend_from_slice(&32_i32.to_le_bytes());
+    flatbuffer.extend_from_slice(&8_u32.to_le_bytes());
+    flatbuffer.extend_from_slice(&[COMMAND_IMAGE, 0, 0, 0]);
+
+    flatbuffer.extend_from_slice(&36_i32.to_le_bytes());
+    flatbuffer.extend_from_slice(&12_u32.to_le_bytes());
+    flatbuffer.extend_from_slice(&IMAGE_DURATION.to_le_bytes());
+    flatbuffer.extend_from_slice(&[IMAGE_TYPE_RAW, 0, 0, 0]);
+
+    flatbuffer.extend_from_slice(&40_i32.to_le_bytes());
+    flatbuffer.extend_from_slice(&12_u32.to_le_bytes());
+    flatbuffer.extend_from_slice(&(width as i32).to_le_bytes());
+    flatbuffer.extend_from_slice(&(height as i32).to_le_bytes());
+
+    flatbuffer.extend_from_slice(&(data_size as u32).to_le_bytes());
+
+    let mut header = ((flatbuffer.len() + data_size + padding) as u32)
+        .to_be_bytes()
+        .to_vec();
+    header.extend_from_slice(&flatbuffer);
+    (header, padding)
+}
+
+/// Map each pixel of a `horizontal_count` by `vertical_count` image of the `display` to the
+/// index of the nearest LED, offset by the `first_led` on the display. Hyperion samples its own
+/// LED layout from the image, so filling the whole image instead of just the LED positions
+/// keeps the edges from being averaged with black.
+fn image_leds(display: &DisplayConfiguration, first_led: usize) -> Vec<usize> {
+    if display.positions.is_empty() {
+        return Vec::new();
+    }
+
+    let mut leds = Vec::with_capacity(display.horizontal_count * display.vertical_count);
+    for y in 0..display.vertical_count {
+        for x in 0..display.horizontal_count {
+            let nearest = display
+                .positions
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, position)| {
+                    let (dx, dy) = (position.x.abs_diff(x), position.y.abs_diff(y));
+                    dx * dx + dy * dy
+                })
+                .map_or(0, |(index, _)| index);
+            leds.push(first_led + nearest);
+        }
+    }
+
+    leds
+}
+
+/// The latest frame for the [HyperionThread] which it hasn't written yet. If the thread is
+/// still busy with the last frame when the next one is ready, the next one replaces any frame
+/// which was already waiting, so a slow server gets fewer frames but never falls behind.
+#[derive(Default)]
+struct FrameSlot {
+    state: Mutex<SlotState>,
+    ready: Condvar,
+}
+
+/// The frame waiting in a [FrameSlot], and whether it was closed.
+#[derive(Default)]
+struct SlotState {
+    frame: Option<Vec<PixelBuffer>>,
+    closed: bool,
+}
+
+impl FrameSlot {
+    /// Replace the waiting frame with `pixels`, and wake up the thread if it's idle.
+    fn put(&self, pixels: Vec<PixelBuffer>) {
+        self.state.lock().expect("lock frame slot").frame = Some(pixels);
+        self.ready.notify_one();
+    }
+
+    /// Tell the thread to shut down the connection, even if it's waiting for a frame.
+    fn close(&self) {
+        self.state.lock().expect("lock frame slot").closed = true;
+        self.ready.notify_one();
+    }
+
+    /// Wait for the next frame, or return `None` once the slot is closed.
+    fn take(&self) -> Option<Vec<PixelBuffer>> {
+        let mut state = self.state.lock().expect("lock frame slot");
+        loop {
+            if state.closed {
+                return None;
+            }
+
+            if let Some(frame) = state.frame.take() {
+                return Some(frame);
+            }
+
+            state = self.ready.wait(state).expect("wait for frame");
+        }
+    }
+}
+
+/// Connect to the [HyperionServer] and register as an image source.
+fn connect(server: &HyperionServer) -> Result<TcpStream> {
+    let timeout = Duration::from_millis(u64::from(server.connect_timeout));
+    let address = (server.host.as_str(), server.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::from(ErrorKind::AddrNotAvailable))?;
+    let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // Hyperion sends a reply to every command, which we don't need. Keep reading them in
+    // the background until the connection is closed, so they don't back up.
+    let mut replies = stream.try_clone()?;
+    thread::spawn(move || {
+        let mut reply = [0_u8; 256];
+        while matches!(replies.read(&mut reply), Ok(count) if count > 0) {}
+    });
+
+    stream.write_all(&register_message(&server.origin, server.priority))?;
+    Ok(stream)
+}
+
+/// Handle to the thread which owns the connection to a [HyperionServer] and does all of the
+/// network I/O, so resolving the host, waiting to connect, or a stalled write never holds up
+/// the worker thread. The frames are handed over in a [FrameSlot]. If the connection is lost,
+/// the thread reconnects on its own with the [crate::settings::ReconnectConfiguration]
+/// backoff, see [SerialPort::backoff_delay].
+struct HyperionThread {
+    slot: Arc<FrameSlot>,
+    failed: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HyperionThread {
+    /// Start the [HyperionThread] for the [HyperionServer]. The `retries` and `delay` come
+    /// from the [crate::settings::ReconnectConfiguration], and the `max_delay` is the longest
+    /// delay (in milliseconds) between attempts to reconnect.
+    fn start(server: HyperionServer, retries: u32, delay: u32, max_delay: u32) -> Self {
+        let slot = Arc::new(FrameSlot::default());
+        let failed = Arc::new(AtomicBool::new(false));
+        let thread_slot = slot.clone();
+        let thread_failed = failed.clone();
+        let thread = thread::spawn(move || {
+            let mut stream: Option<TcpStream> = None;
+            let mut attempts = 0_u32;
+            let mut next_attempt = Instant::now();
+            while let Some(pixels) = thread_slot.take() {
+                if stream.is_none() {
+                    let now = Instant::now();
+                    if now < next_attempt {
+                        continue;
+                    }
+
+                    match connect(&server) {
+                        Ok(connected) => {
+                            stream = Some(connected);
+                            attempts = 0;
+                        }
+                        Err(_) => {
+                            attempts = attempts.saturating_add(1);
+                            let delay =
+                                SerialPort::backoff_delay(attempts, retries, delay, max_delay);
+                            next_attempt = now + Duration::from_millis(u64::from(delay));
+                            continue;
+                        }
+                    }
+                }
+
+                if let Some(connected) = stream.as_mut() {
+                    if pixels
+                        .iter()
+                        .try_for_each(|pixels| connected.write_all(pixels.data()))
+                        .is_err()
+                    {
+                        stream = None;
+                        thread_failed.store(true, Ordering::Relaxed);
+                    }
+                }
+            }
+
+            // Clear our image from the HyperionServer before closing the connection.
+            if let Some(mut stream) = stream {
+                let _ = stream.write_all(&clear_message(server.priority));
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        });
+
+        Self {
+            slot,
+            failed,
+            thread: Some(thread),
+        }
+    }
+
+    /// Hand the `pixels` to the [HyperionThread], replacing any frame it hasn't picked up yet.
+    /// Returns `false` if writing a previous frame failed and the connection was lost.
+    fn put(&self, pixels: Vec<PixelBuffer>) -> bool {
+        if self.failed.swap(false, Ordering::Relaxed) {
+            return false;
+        }
+
+        self.slot.put(pixels);
+        true
+    }
+
+    /// Close the [FrameSlot], and wait for the [HyperionThread] to clear our image and exit.
+    fn stop(&mut self) {
+        self.slot.close();
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("join hyperion thread");
+        }
+    }
+}
+
+impl Drop for HyperionThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Representation of a connection to the flatbuffers interface of a [HyperionServer], which
+/// shows the LED colors on one display as an external image source. The connection belongs to
+/// a [HyperionThread], so sending a frame never waits for the network.
+pub struct HyperionConnection<'a> {
+    server: &'a HyperionServer,
+    parameters: &'a Settings,
+    width: usize,
+    height: usize,
+    leds: Vec<usize>,
+    thread: Option<HyperionThread>,
+}
+
+impl<'a> HyperionConnection<'a> {
+    /// Allocate a new [HyperionConnection] without connecting to the [HyperionServer].
+    pub fn new(server: &'a HyperionServer, parameters: &'a Settings) -> Self {
+        let (width, height, leds) = match parameters.displays.get(server.display) {
+            Some(display) => {
+                let first_led = parameters.displays[..server.display]
+                    .iter()
+                    .map(|display| display.positions.len())
+                    .sum();
+                (
+                    display.horizontal_count,
+                    display.vertical_count,
+                    image_leds(display, first_led),
+                )
+            }
+            None => (0, 0, Vec::new()),
+        };
+
+        Self {
+            server,
+            parameters,
+            width,
+            height,
+            leds,
+            thread: None,
+        }
+    }
+}
+
+impl<'a> OutputSink for HyperionConnection<'a> {
+    /// Start the [HyperionThread] if it isn't running yet. It connects to the [HyperionServer]
+    /// with the first frame.
+    fn open(&mut self) -> bool {
+        if self.thread.is_none() {
+            self.thread = Some(HyperionThread::start(
+                self.server.clone(),
+                self.parameters.reconnect.retries,
+                self.parameters.reconnect.delay,
+                self.parameters.throttle_timer,
+            ));
+        }
+
+        self.healthy()
+    }
+
+    /// Render an image message with the LED colors for the display.
+    fn render(&self, samples: &ScreenSamples) -> Vec<PixelBuffer> {
+        if self.leds.is_empty() {
+            return Vec::new();
+        }
+
+        let (header, padding) = image_header(self.width, self.height);
+        let mut pixels = PixelBuffer::new_image_buffer(header, self.leds.len(), padding);
+        samples.render_leds(&self.leds, &mut pixels);
+        vec![pixels]
+    }
+
+    /// Hand the image message to the [HyperionThread]. Returns `false` if writing a previous
+    /// frame failed and it's reconnecting.
+    fn send(&mut self, pixels: Vec<PixelBuffer>) -> bool {
+        match self.thread.as_ref() {
+            Some(thread) => thread.put(pixels),
+            None => false,
+        }
+    }
+
+    /// Stop the [HyperionThread], which clears our image from the [HyperionServer] and closes
+    /// the connection.
+    fn close(&mut self) {
+        self.thread = None;
+    }
+
+    /// The [HyperionThread] reconnects to the [HyperionServer] on its own, so it's ready for the
+    /// next frame as long as it's running.
+    fn healthy(&self) -> bool {
+        self.thread.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::settings::LedPosition;
+
+    #[test]
+    fn write_from_thread() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let server = HyperionServer {
+            host: String::from("127.0.0.1"),
+            port: listener.local_addr().expect("listener address").port(),
+            priority: 150,
+            origin: String::from("AdaLight"),
+            display: 0,
+            connect_timeout: 1000,
+        };
+        let mut thread = HyperionThread::start(server, 3, 50, 1000);
+        assert!(thread.put(vec![PixelBuffer::new_image_buffer(vec![1, 2, 3], 0, 0)]));
+        let (mut stream, _) = listener.accept().expect("accept connection");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("set read timeout");
+        thread.stop();
+
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).expect("read messages");
+        let mut expected = register_message("AdaLight", 150);
+        expected.extend_from_slice(&[1, 2, 3]);
+        expected.extend_from_slice(&clear_message(150));
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn encode_messages() {
+        let register = register_message("AdaLight", 150);
+        assert_eq!(register[..4], [0, 0, 0, 60]);
+        assert_eq!(register.len(), 64);
+        assert_eq!(register[4..8], 20_u32.to_le_bytes());
+        assert_eq!(register[24..28], 16_i32.to_le_bytes());
+        assert_eq!(register[32], COMMAND_REGISTER);
+        assert_eq!(register[44..48], 150_i32.to_le_bytes());
+        assert_eq!(register[48..52], 8_u32.to_le_bytes());
+        assert_eq!(&register[52..61], b"AdaLight\0");
+
+        assert_eq!(
+            clear_message(150),
+            vec![
+                0, 0, 0, 40, 20, 0, 0, 0, 8, 0, 12, 0, 8, 0, 4, 0, 6, 0, 8, 0, 4, 0, 0, 0, 16, 0,
+                0, 0, 8, 0, 0, 0, 3, 0, 0, 0, 20, 0, 0, 0, 150, 0, 0, 0
+            ]
+        );
+
+        let (header, padding) = image_header(3, 2);
+        assert_eq!(header.len(), 88);
+        assert_eq!(padding, 2);
+        assert_eq!(header[..4], (84_u32 + 18 + 2).to_be_bytes());
+        assert_eq!(header[4..8], 36_u32.to_le_bytes());
+        assert_eq!(header[48], COMMAND_IMAGE);
+        assert_eq!(header[64], IMAGE_TYPE_RAW);
+        assert_eq!(header[60..64], (-1_i32).to_le_bytes());
+        assert_eq!(header[76..80], 3_i32.to_le_bytes());
+        assert_eq!(header[80..84], 2_i32.to_le_bytes());
+        assert_eq!(header[84..88], 18_u32.to_le_bytes());
+
+        let pixels = PixelBuffer::new_image_buffer(header, 6, padding);
+        assert_eq!(pixels.data().len(), 88 + 18 + 2);
+    }
+
+    #[test]
+    fn nearest_leds() {
+        let display = DisplayConfiguration {
+            horizontal_count: 3,
+            vertical_count: 2,
+            positions: vec![
+                LedPosition { x: 0, y: 0 },
+                LedPosition { x: 2, y: 0 },
+                LedPosition { x: 2, y: 1 },
+            ],
+            window: None,
+            exclusions: Vec::new(),
+            sample_depth: None,
+            angled_corners: false,
+            temporal_samples: 1,
+            follow_foreground: false,
+            color_profile: false,
+            overlap: None,
+            enabled: true,
+        };
+        assert_eq!(image_leds(&display, 10), vec![10, 10, 11, 10, 12, 12]);
+    }
+}
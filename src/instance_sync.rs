@@ -0,0 +1,197 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::settings::SyncConfiguration;
+
+/// First 4 bytes of every sync packet, so anything else sent to the port is ignored.
+const SYNC_MAGIC: &[u8; 4] = b"ALSY";
+
+/// Size of a sync packet: the [SYNC_MAGIC], the group, a flags byte, the brightness, and a
+/// 32-bit sequence number.
+const SYNC_PACKET_SIZE: usize = 11;
+
+/// Flag which is set if the brightness byte in the packet is valid.
+const SYNC_FLAG_BRIGHTNESS: u8 = 0x01;
+
+/// Contents of the packet which the leader broadcasts every time its timer fires.
+#[derive(Debug, PartialEq, Eq)]
+struct SyncPacket {
+    group: u8,
+    sequence: u32,
+    brightness: Option<u8>,
+}
+
+/// Encode a [SyncPacket] to send to the followers.
+fn encode_packet(packet: &SyncPacket) -> [u8; SYNC_PACKET_SIZE] {
+    let mut data = [0_u8; SYNC_PACKET_SIZE];
+    data[..4].copy_from_slice(SYNC_MAGIC);
+    data[4] = packet.group;
+    if let Some(brightness) = packet.brightness {
+        data[5] = SYNC_FLAG_BRIGHTNESS;
+        data[6] = brightness;
+    }
+    data[7..].copy_from_slice(&packet.sequence.to_be_bytes());
+    data
+}
+
+/// Decode a [SyncPacket] from a leader. Returns `None` if it's not a sync packet.
+fn decode_packet(data: &[u8]) -> Option<SyncPacket> {
+    if data.len() != SYNC_PACKET_SIZE || &data[..4] != SYNC_MAGIC {
+        return None;
+    }
+
+    Some(SyncPacket {
+        group: data[4],
+        sequence: u32::from_be_bytes([data[7], data[8], data[9], data[10]]),
+        brightness: if data[5] & SYNC_FLAG_BRIGHTNESS != 0 {
+            Some(data[6])
+        } else {
+            None
+        },
+    })
+}
+
+/// Broadcasts a [SyncPacket] with the frame timing and brightness every time the leader's
+/// timer fires, so the followers sample their displays at the same time.
+pub struct SyncLeader {
+    socket: UdpSocket,
+    destination: SocketAddr,
+    group: u8,
+    sequence: u32,
+}
+
+impl SyncLeader {
+    /// Open a non-blocking UDP socket which can broadcast to the `address` and `port` in the
+    /// [SyncConfiguration]. Returns `None` if the address can't be resolved.
+    pub fn new(configuration: &SyncConfiguration) -> Option<Self> {
+        let destination = (configuration.address.as_str(), configuration.port)
+            .to_socket_addrs()
+            .ok()?
+            .next()?;
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+        socket.set_broadcast(true).ok()?;
+        socket.set_nonblocking(true).ok()?;
+
+        Some(Self {
+            socket,
+            destination,
+            group: configuration.group,
+            sequence: 0,
+        })
+    }
+
+    /// Tell the followers to start the next frame, and change their `brightness` if it's set.
+    /// Nobody is waiting for an acknowledgement, so if the socket buffer is full the packet is
+    /// dropped and the followers fall back to their own timers.
+    pub fn send(&mut self, brightness: Option<u8>) {
+        self.sequence = self.sequence.wrapping_add(1);
+        let packet = encode_packet(&SyncPacket {
+            group: self.group,
+            sequence: self.sequence,
+            brightness,
+        });
+        let _ = self.socket.send_to(&packet, self.destination);
+    }
+}
+
+/// Waits for the [SyncPacket] from a leader in the same group, so the timer can fire as soon
+/// as it arrives. If the leader goes quiet for longer than the `timeout`, the timer falls back
+/// to its own frame rate until the leader comes back.
+pub struct SyncFollower {
+    socket: UdpSocket,
+    group: u8,
+    timeout: Duration,
+    last_packet: Option<Instant>,
+    last_sequence: Option<u32>,
+}
+
+impl SyncFollower {
+    /// Listen on the `port` in the [SyncConfiguration]. Returns `None` if the port is already
+    /// in use.
+    pub fn new(configuration: &SyncConfiguration) -> Option<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, configuration.port)).ok()?;
+
+        Some(Self {
+            socket,
+            group: configuration.group,
+            timeout: Duration::from_millis(u64::from(configuration.timeout)),
+            last_packet: None,
+            last_sequence: None,
+        })
+    }
+
+    /// Test if we heard from the leader within the `timeout` before `now`.
+    pub fn following(&self, now: Instant) -> bool {
+        self.last_packet
+            .is_some_and(|last_packet| now.duration_since(last_packet) < self.timeout)
+    }
+
+    /// Get the `timeout` after which the leader is considered gone.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Wait until the `deadline` for the next [SyncPacket] from the leader. Returns the
+    /// brightness it sent, or `None` if it timed out or the leader didn't send the brightness.
+    /// Either way, the caller should start the next frame when this returns.
+    pub fn wait(&mut self, deadline: Instant) -> Option<u8> {
+        let mut buffer = [0_u8; SYNC_PACKET_SIZE + 1];
+        loop {
+            let now = Instant::now();
+            if now >= deadline || self.socket.set_read_timeout(Some(deadline - now)).is_err() {
+                return None;
+            }
+
+            let length = match self.socket.recv(&mut buffer) {
+                Ok(length) => length,
+                Err(_) => return None,
+            };
+
+            // Skip duplicates, e.g. if the broadcast arrives on more than one interface.
+            match decode_packet(&buffer[..length]) {
+                Some(packet)
+                    if packet.group == self.group
+                        && self.last_sequence != Some(packet.sequence) =>
+                {
+                    self.last_packet = Some(Instant::now());
+                    self.last_sequence = Some(packet.sequence);
+                    return packet.brightness;
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_packets() {
+        let packet = SyncPacket {
+            group: 2,
+            sequence: 0x01020304,
+            brightness: Some(128),
+        };
+        let data = encode_packet(&packet);
+        assert_eq!(data, *b"ALSY\x02\x01\x80\x01\x02\x03\x04");
+        assert_eq!(decode_packet(&data), Some(packet));
+
+        let packet = SyncPacket {
+            group: 0,
+            sequence: 7,
+            brightness: None,
+        };
+        assert_eq!(decode_packet(&encode_packet(&packet)), Some(packet));
+    }
+
+    #[test]
+    fn ignore_other_packets() {
+        assert_eq!(decode_packet(b"ALSY\x00\x00\x00\x00\x00\x00"), None);
+        assert_eq!(decode_packet(b"OPCS\x00\x00\x00\x00\x00\x00\x01"), None);
+        assert_eq!(decode_packet(b"ALSY\x00\x00\x00\x00\x00\x00\x01\x00"), None);
+    }
+}
@@ -0,0 +1,207 @@
+use windows::Win32::Foundation::{RECT, SIZE};
+
+use crate::settings::LetterboxConfiguration;
+
+/// Number of pixels to test along each row or column when looking for black bars.
+const SCAN_SAMPLES: usize = 32;
+
+/// Track the active picture area of a display or window, excluding any uniform black bars
+/// above and below (letterbox) or to either side (pillarbox) of the content.
+pub struct LetterboxDetector {
+    /// The active picture area in desktop coordinates which the sample blocks are mapped to.
+    active: RECT,
+
+    /// A different picture area which was detected in the most recent frames, but which
+    /// hasn't been stable for long enough to replace the `active` area yet.
+    candidate: RECT,
+
+    /// How many consecutive detections have matched the `candidate` area.
+    candidate_count: u32,
+
+    /// How many frames have been checked since the last detection.
+    frames: u32,
+}
+
+impl LetterboxDetector {
+    /// Create a new [LetterboxDetector] which starts with the full `bounds` as the active area.
+    pub fn new(bounds: &SIZE) -> Self {
+        let full = RECT {
+            left: 0,
+            top: 0,
+            right: bounds.cx,
+            bottom: bounds.cy,
+        };
+
+        Self {
+            active: full,
+            candidate: full,
+            candidate_count: 0,
+            frames: 0,
+        }
+    }
+
+    /// Get the active picture area in desktop coordinates.
+    pub fn active(&self) -> &RECT {
+        &self.active
+    }
+
+    /// Test if the next call to `update` will look for black bars.
+    pub fn is_due(&self, parameters: &LetterboxConfiguration) -> bool {
+        self.frames + 1 >= parameters.interval.max(1)
+    }
+
+    /// Look for black bars every `interval` frames using `sample` to read the RGB color of
+    /// a pixel at desktop coordinates within `bounds`. Returns `true` if the active area
+    /// changed and the sample blocks need to be remapped.
+    pub fn update<F>(
+        &mut self,
+        parameters: &LetterboxConfiguration,
+        bounds: &SIZE,
+        sample: &F,
+    ) -> bool
+    where
+        F: Fn(usize, usize) -> (u8, u8, u8),
+    {
+        self.frames += 1;
+        if self.frames < parameters.interval.max(1) {
+            return false;
+        }
+        self.frames = 0;
+
+        // If the whole picture is black, there's nothing to go on, so keep the current area.
+        let detected = match Self::detect(parameters.threshold, bounds, sample) {
+            Some(detected) => detected,
+            None => return false,
+        };
+
+        if detected == self.active {
+            self.candidate_count = 0;
+            return false;
+        }
+
+        if detected == self.candidate {
+            self.candidate_count += 1;
+        } else {
+            self.candidate = detected;
+            self.candidate_count = 1;
+        }
+
+        // Wait until the new area has been stable for `debounce` detections, so a dark scene
+        // or a fade to black doesn't make the LEDs jump around.
+        if self.candidate_count < parameters.debounce.max(1) {
+            return false;
+        }
+
+        self.active = self.candidate;
+        self.candidate_count = 0;
+
+        true
+    }
+
+    /// Scan inwards from each edge for the first row or column which isn't uniformly black.
+    fn detect<F>(threshold: u8, bounds: &SIZE, sample: &F) -> Option<RECT>
+    where
+        F: Fn(usize, usize) -> (u8, u8, u8),
+    {
+        let width = bounds.cx.max(0) as usize;
+        let height = bounds.cy.max(0) as usize;
+
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let is_black = |x: usize, y: usize| {
+            let (r, g, b) = sample(x, y);
+            r.max(g).max(b) <= threshold
+        };
+        let scan_offset = |i: usize, length: usize| ((2 * i + 1) * length) / (2 * SCAN_SAMPLES);
+        let row_is_black =
+            |y: &usize| (0..SCAN_SAMPLES).all(|i| is_black(scan_offset(i, width), *y));
+        let top = (0..height).find(|y| !row_is_black(y))?;
+        let bottom = (top..height).rev().find(|y| !row_is_black(y))? + 1;
+        let column_is_black =
+            |x: &usize| (0..SCAN_SAMPLES).all(|i| is_black(*x, top + scan_offset(i, bottom - top)));
+        let left = (0..width).find(|x| !column_is_black(x))?;
+        let right = (left..width).rev().find(|x| !column_is_black(x))? + 1;
+
+        Some(RECT {
+            left: left as i32,
+            top: top as i32,
+            right: right as i32,
+            bottom: bottom as i32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BOUNDS: SIZE = SIZE { cx: 160, cy: 90 };
+
+    fn letterboxed(_x: usize, y: usize) -> (u8, u8, u8) {
+        if (10..80).contains(&y) {
+            (200, 100, 50)
+        } else {
+            (8, 8, 8)
+        }
+    }
+
+    #[test]
+    fn detect_letterbox() {
+        let parameters = LetterboxConfiguration {
+            threshold: 16,
+            debounce: 1,
+            interval: 1,
+        };
+        let mut detector = LetterboxDetector::new(&BOUNDS);
+        assert!(detector.update(&parameters, &BOUNDS, &letterboxed));
+        let active = detector.active();
+        assert_eq!((active.left, active.top), (0, 10));
+        assert_eq!((active.right, active.bottom), (160, 80));
+    }
+
+    #[test]
+    fn debounce_letterbox() {
+        let parameters = LetterboxConfiguration {
+            threshold: 16,
+            debounce: 3,
+            interval: 1,
+        };
+        let mut detector = LetterboxDetector::new(&BOUNDS);
+        assert!(!detector.update(&parameters, &BOUNDS, &letterboxed));
+        assert!(!detector.update(&parameters, &BOUNDS, &letterboxed));
+        assert_eq!(detector.active().top, 0);
+        assert!(detector.update(&parameters, &BOUNDS, &letterboxed));
+        assert_eq!(detector.active().top, 10);
+    }
+
+    #[test]
+    fn ignore_black_frame() {
+        let parameters = LetterboxConfiguration {
+            threshold: 16,
+            debounce: 1,
+            interval: 1,
+        };
+        let mut detector = LetterboxDetector::new(&BOUNDS);
+        assert!(!detector.update(&parameters, &BOUNDS, &|_, _| (0, 0, 0)));
+        assert_eq!(detector.active().bottom, 90);
+    }
+
+    #[test]
+    fn detection_interval() {
+        let parameters = LetterboxConfiguration {
+            threshold: 16,
+            debounce: 1,
+            interval: 3,
+        };
+        let mut detector = LetterboxDetector::new(&BOUNDS);
+        assert!(!detector.is_due(&parameters));
+        assert!(!detector.update(&parameters, &BOUNDS, &letterboxed));
+        assert!(!detector.is_due(&parameters));
+        assert!(!detector.update(&parameters, &BOUNDS, &letterboxed));
+        assert!(detector.is_due(&parameters));
+        assert!(detector.update(&parameters, &BOUNDS, &letterboxed));
+        assert!(!detector.is_due(&parameters));
+    }
+}
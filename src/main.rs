@@ -1,31 +1,159 @@
 #![cfg_attr(all(windows, not(test)), windows_subsystem = "windows")]
 
+mod auto_exposure;
+mod capture_source;
+mod chroma;
+mod color_profile;
+mod file_source;
 mod gamma_correction;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod health;
 mod hidden_window;
+mod hsv;
+mod http_client;
+#[cfg(feature = "hue")]
+mod hue;
+mod hyperion;
+mod instance_sync;
+mod letterbox;
+mod mdns;
+mod mqtt;
+mod nanoleaf;
+mod oklab;
 mod opc_pool;
+mod opc_receiver;
+mod output_sink;
 mod pixel_buffer;
+mod power_limit;
+mod preview;
+mod preview_stream;
+mod proxy;
+mod rest_api;
+mod sacn;
+mod sample_pattern;
 mod screen_samples;
 mod serial_port;
+mod serial_thread;
 mod settings;
+mod stats;
+mod test_source;
+mod udp_output;
 mod update_timer;
+mod window_capture;
+mod wled_udp;
 
-use std::fs;
+use std::{env, fs, io, process};
 
 use windows::Win32::{
     Foundation::HWND,
+    System::Console::{AllocConsole, AttachConsole, ATTACH_PARENT_PROCESS},
     UI::WindowsAndMessaging::{DispatchMessageA, GetMessageA, TranslateMessage, MSG},
 };
 
-use {hidden_window::HiddenWindow, settings::Settings, update_timer::UpdateTimer};
+use {
+    hidden_window::HiddenWindow,
+    preview_stream::PreviewStream,
+    serial_port::{SelfTestResult, SerialPort},
+    settings::{FileSourceConfiguration, Settings, TestPattern},
+    update_timer::UpdateTimer,
+};
+
+/// Look for the `--test-source [pattern]` argument, which overrides the `testSource` in the
+/// settings. If the pattern name is missing or unrecognized, use [TestPattern::MovingBars].
+fn test_source_argument() -> Option<TestPattern> {
+    let mut args = env::args().skip_while(|arg| arg != "--test-source");
+    args.next()?;
+
+    Some(
+        args.next()
+            .and_then(|name| TestPattern::from_name(&name))
+            .unwrap_or(TestPattern::MovingBars),
+    )
+}
+
+/// Look for the `--file-source <path>` argument, which overrides the `fileSource` in the
+/// settings and repeats the frames.
+fn file_source_argument() -> Option<FileSourceConfiguration> {
+    let mut args = env::args().skip_while(|arg| arg != "--file-source");
+    args.next()?;
+
+    Some(FileSourceConfiguration {
+        path: args.next()?,
+        repeat: true,
+    })
+}
+
+/// Look for the `--serial-test` argument, which runs a self-test on each serial output and
+/// prints a diagnosis instead of starting the [UpdateTimer].
+fn serial_test_argument() -> bool {
+    env::args().any(|arg| arg == "--serial-test")
+}
+
+/// The binary uses the windows subsystem, so it doesn't start with a console to print to. Attach
+/// to the console of the parent process, e.g. the command prompt which ran it, or open a new one
+/// if there isn't one. Returns `true` if it opened a new console, which closes when we exit.
+fn attach_console() -> bool {
+    unsafe { !AttachConsole(ATTACH_PARENT_PROCESS).as_bool() && AllocConsole().as_bool() }
+}
+
+/// Run [SerialPort::self_test_all] and print the results to the console. Returns `true` if they
+/// all passed.
+fn run_serial_test(settings: &Settings) -> bool {
+    let new_console = attach_console();
+    let mut passed = true;
+    for (index, (port_number, result)) in
+        SerialPort::self_test_all(settings).into_iter().enumerate()
+    {
+        match port_number {
+            0 => println!("Serial Output {}: {}", index, result),
+            port_number => println!("Serial Output {} (COM{}): {}", index, port_number, result),
+        }
+        passed &= result == SelfTestResult::Passed;
+    }
+
+    if new_console {
+        // Keep the new console open until the results have been read.
+        println!("Press Enter to close.");
+        let _ = io::stdin().read_line(&mut String::new());
+    }
+
+    passed
+}
 
 fn main() {
     let config_json = fs::read_to_string("AdaLight.config.json").expect("read config file");
     let settings = Settings::from_str(&config_json);
 
     match settings {
-        Ok(settings) => {
+        Ok(mut settings) => {
+            if let Some(pattern) = test_source_argument() {
+                settings.test_source = Some(pattern);
+            }
+
+            if let Some(file_source) = file_source_argument() {
+                settings.file_source = Some(file_source);
+            }
+
+            if serial_test_argument() {
+                let passed = run_serial_test(&settings);
+                process::exit(if passed { 0 } else { 1 });
+            }
+
+            if let Some(discovery) = settings.discovery.take() {
+                mdns::discover(&discovery, &mut settings);
+            }
+
+            let mqtt = settings.mqtt.clone();
+            let rest = settings.rest.clone();
+            let grpc = settings.grpc.clone();
+            let preview_stream = settings.preview_stream.clone();
             let timer = UpdateTimer::new(settings);
-            let _hidden_window = HiddenWindow::new(timer);
+
+            let _preview_stream = preview_stream
+                .and_then(|stream| PreviewStream::start(&stream, timer.preview_handle()));
+
+            let _hidden_window = HiddenWindow::new(timer, mqtt, rest, grpc);
             let mut msg = MSG::default();
 
             unsafe {
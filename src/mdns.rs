@@ -0,0 +1,486 @@
+use std::{
+    collections::HashMap,
+    fs,
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    rest_api::json_string,
+    settings::{DiscoveryConfiguration, DiscoveryMode, Settings},
+};
+
+/// Multicast group and port which mDNS responders listen on.
+const MDNS_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+/// Service type which OPC servers advertise.
+const OPC_SERVICE: &str = "_openpixelcontrol._tcp.local";
+
+/// Service type which WLED controllers advertise. This is the web UI, not the UDP realtime
+/// port, so only the address is useful for a [crate::settings::WledServer].
+const WLED_SERVICE: &str = "_wled._tcp.local";
+
+/// Service type which DDP receivers advertise.
+const DDP_SERVICE: &str = "_ddp._udp.local";
+
+/// DNS record types and class which we ask for or read from the responses.
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// Follow at most this many compression pointers in a name, so a malformed response with a
+/// loop in it can't hang the parser.
+const MAX_POINTERS: usize = 16;
+
+/// An instance of a service which responded to the mDNS query.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Service {
+    /// The service type, e.g. `_wled._tcp.local`.
+    pub service: String,
+
+    /// The instance name without the service type, e.g. `Living Room`.
+    pub name: String,
+
+    /// The host name from the SRV record, e.g. `wled-living.local`.
+    pub host: String,
+
+    /// The port from the SRV record.
+    pub port: u16,
+
+    /// The IPv4 address of the host, if the response included it.
+    pub address: Option<Ipv4Addr>,
+}
+
+/// Encode a DNS name as a sequence of length-prefixed labels.
+fn encode_name(name: &str, message: &mut Vec<u8>) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+}
+
+/// Encode an mDNS query with a PTR question for each of the `services`.
+fn query_message(services: &[&str]) -> Vec<u8> {
+    let mut message = vec![0, 0, 0, 0];
+    message.extend_from_slice(&(services.len() as u16).to_be_bytes());
+    message.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    for service in services {
+        encode_name(service, &mut message);
+        message.extend_from_slice(&TYPE_PTR.to_be_bytes());
+        message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    message
+}
+
+/// Read a big-endian `u16` from the `message` at `offset`.
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *message.get(offset)?,
+        *message.get(offset + 1)?,
+    ]))
+}
+
+/// Read a (possibly compressed) DNS name from the `message` at `offset`. Returns the name in
+/// lowercase, and the offset just past it in the original position.
+fn read_name(message: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut position = offset;
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let length = *message.get(position)? as usize;
+        match length {
+            0 => {
+                let end = end.unwrap_or(position + 1);
+                return Some((labels.join(".").to_lowercase(), end));
+            }
+            length if length & 0xC0 == 0xC0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                end.get_or_insert(position + 2);
+                position = (read_u16(message, position)? & 0x3FFF) as usize;
+            }
+            length => {
+                let label = message.get(position + 1..position + 1 + length)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                position += 1 + length;
+            }
+        }
+    }
+}
+
+/// The records we collected from all of the responses, by name.
+#[derive(Default)]
+struct Records {
+    /// Service type to instance names.
+    pointers: Vec<(String, String)>,
+
+    /// Instance name to the host and port.
+    services: HashMap<String, (String, u16)>,
+
+    /// Host name to address.
+    addresses: HashMap<String, Ipv4Addr>,
+}
+
+impl Records {
+    /// Add the PTR, SRV, and A records from an mDNS response `message`. Returns `None` if it's
+    /// malformed, but keeps any records before that.
+    fn add_response(&mut self, message: &[u8]) -> Option<()> {
+        let questions = read_u16(message, 4)?;
+        let records = [6, 8, 10]
+            .iter()
+            .map(|offset| read_u16(message, *offset).map(usize::from))
+            .sum::<Option<usize>>()?;
+        let mut offset = 12;
+        for _ in 0..questions {
+            offset = read_name(message, offset)?.1 + 4;
+        }
+
+        for _ in 0..records {
+            let (name, position) = read_name(message, offset)?;
+            let record_type = read_u16(message, position)?;
+            let length = read_u16(message, position + 8)? as usize;
+            let data = position + 10;
+            if data + length > message.len() {
+                return None;
+            }
+            offset = data + length;
+
+            match record_type {
+                TYPE_PTR => {
+                    let (instance, _) = read_name(message, data)?;
+                    if !self.pointers.contains(&(name.clone(), instance.clone())) {
+                        self.pointers.push((name, instance));
+                    }
+                }
+                TYPE_SRV if length >= 7 => {
+                    let port = read_u16(message, data + 4)?;
+                    let (host, _) = read_name(message, data + 6)?;
+                    self.services.insert(name, (host, port));
+                }
+                TYPE_A if length == 4 => {
+                    let address = Ipv4Addr::new(
+                        message[data],
+                        message[data + 1],
+                        message[data + 2],
+                        message[data + 3],
+                    );
+                    self.addresses.insert(name, address);
+                }
+                _ => (),
+            }
+        }
+
+        Some(())
+    }
+
+    /// Match up the records for each instance of the `services`.
+    fn services(&self, services: &[&str]) -> Vec<Service> {
+        self.pointers
+            .iter()
+            .filter(|(service, _)| services.contains(&service.as_str()))
+            .filter_map(|(service, instance)| {
+                let (host, port) = self.services.get(instance)?;
+                let name = instance
+                    .strip_suffix(service.as_str())
+                    .map_or(instance.as_str(), |name| name.trim_end_matches('.'));
+                Some(Service {
+                    service: service.clone(),
+                    name: name.to_string(),
+                    host: host.clone(),
+                    port: *port,
+                    address: self.addresses.get(host).copied(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Browse for the `services` with a multicast mDNS query, and collect the responses until the
+/// `timeout`. The query comes from an ephemeral port, so the responders answer us directly.
+pub fn browse(services: &[&str], timeout: Duration) -> Vec<Service> {
+    let mut records = Records::default();
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)) {
+        Ok(socket) => socket,
+        Err(_) => return Vec::new(),
+    };
+    if socket
+        .send_to(&query_message(services), (MDNS_ADDRESS, MDNS_PORT))
+        .is_err()
+    {
+        return Vec::new();
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0_u8; 9000];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() || socket.set_read_timeout(Some(remaining)).is_err() {
+            break;
+        }
+
+        match socket.recv_from(&mut buffer) {
+            Ok((length, _)) => {
+                records.add_response(&buffer[..length]);
+            }
+            Err(_) => break,
+        }
+    }
+
+    records.services(services)
+}
+
+/// Normalize a host name from the settings or an mDNS record for comparison.
+fn normalize_host(host: &str) -> String {
+    let host = host.trim_end_matches('.').to_lowercase();
+    match host.strip_suffix(".local") {
+        Some(host) => host.to_string(),
+        None => host,
+    }
+}
+
+/// Find the [Service] with the `service` type which matches a configured `host`, either by
+/// the instance name, the host name, or the address.
+fn find_service<'a>(found: &'a [Service], service: &str, host: &str) -> Option<&'a Service> {
+    let host = normalize_host(host);
+    found.iter().find(|found| {
+        found.service == service
+            && (normalize_host(&found.name) == host
+                || normalize_host(&found.host) == host
+                || found.address.map(|address| address.to_string()) == Some(host.clone()))
+    })
+}
+
+/// Get the address of a [Service] as a string, falling back to resolving the host name if
+/// the response didn't include an A record.
+fn service_address(service: &Service) -> Option<String> {
+    match service.address {
+        Some(address) => Some(address.to_string()),
+        None => (service.host.as_str(), service.port)
+            .to_socket_addrs()
+            .ok()?
+            .find(SocketAddr::is_ipv4)
+            .map(|address| address.ip().to_string()),
+    }
+}
+
+/// File next to the settings file which we write what was discovered to, see [discover].
+const DISCOVERY_FILE: &str = "AdaLight.discovery.json";
+
+/// What we found for one of the configured OPC servers or WLED controllers.
+#[derive(Debug, PartialEq, Eq)]
+struct HostCheck {
+    /// The configured host.
+    host: String,
+
+    /// Whether an instance which matches the host responded.
+    discovered: bool,
+
+    /// The address (and the port for an OPC server) which replaced the host with
+    /// [DiscoveryMode::Resolve].
+    resolved: Option<String>,
+}
+
+/// Encode the discovered services and the configured host checks as JSON.
+fn discovery_json(found: &[Service], servers: &[HostCheck], wled_servers: &[HostCheck]) -> String {
+    let services: Vec<String> = found
+        .iter()
+        .map(|service| {
+            format!(
+                r#"{{"service":{},"name":{},"host":{},"port":{},"address":{}}}"#,
+                json_string(&service.service),
+                json_string(&service.name),
+                json_string(&service.host),
+                service.port,
+                service.address.map_or_else(
+                    || String::from("null"),
+                    |address| json_string(&address.to_string())
+                )
+            )
+        })
+        .collect();
+    let checks = |checks: &[HostCheck]| {
+        let checks: Vec<String> = checks
+            .iter()
+            .map(|check| {
+                format!(
+                    r#"{{"host":{},"discovered":{},"resolved":{}}}"#,
+                    json_string(&check.host),
+                    check.discovered,
+                    check
+                        .resolved
+                        .as_deref()
+                        .map_or_else(|| String::from("null"), json_string)
+                )
+            })
+            .collect();
+        format!("[{}]", checks.join(","))
+    };
+    format!(
+        r#"{{"services":[{}],"servers":{},"wledServers":{}}}"#,
+        services.join(","),
+        checks(servers),
+        checks(wled_servers)
+    )
+}
+
+/// Browse for OPC servers, WLED controllers, and DDP receivers with the
+/// [DiscoveryConfiguration], and check that each configured OPC server and WLED controller is
+/// online. With [DiscoveryMode::Resolve], replace its host (and the port for an OPC server) with
+/// the one we discovered. We don't have a console to log to, so what we found is written to
+/// [DISCOVERY_FILE] in the working directory, next to the settings file.
+pub fn discover(discovery: &DiscoveryConfiguration, settings: &mut Settings) {
+    let found = browse(
+        &[OPC_SERVICE, WLED_SERVICE, DDP_SERVICE],
+        Duration::from_millis(u64::from(discovery.timeout)),
+    );
+
+    let resolve = discovery.mode == DiscoveryMode::Resolve;
+    let servers: Vec<HostCheck> = settings
+        .servers
+        .iter_mut()
+        .map(|server| {
+            let service = find_service(&found, OPC_SERVICE, &server.host);
+            let mut check = HostCheck {
+                host: server.host.clone(),
+                discovered: service.is_some(),
+                resolved: None,
+            };
+            if let (true, Some(service)) = (resolve, service) {
+                if let Some(address) = service_address(service) {
+                    check.resolved = Some(format!("{}:{}", address, service.port));
+                    server.host = address;
+                    server.port = service.port.to_string();
+                }
+            }
+            check
+        })
+        .collect();
+
+    let wled_servers: Vec<HostCheck> = settings
+        .wled_servers
+        .iter_mut()
+        .map(|server| {
+            let service = find_service(&found, WLED_SERVICE, &server.host);
+            let mut check = HostCheck {
+                host: server.host.clone(),
+                discovered: service.is_some(),
+                resolved: None,
+            };
+            if let (true, Some(service)) = (resolve, service) {
+                if let Some(address) = service_address(service) {
+                    check.resolved = Some(address.clone());
+                    server.host = address;
+                }
+            }
+            check
+        })
+        .collect();
+
+    // Discovery is only a diagnostic, so keep going with the settings if we can't write it.
+    let _ = fs::write(
+        DISCOVERY_FILE,
+        discovery_json(&found, &servers, &wled_servers),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_query() {
+        assert_eq!(
+            query_message(&[WLED_SERVICE]),
+            [
+                0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 5, b'_', b'w', b'l', b'e', b'd', 4, b'_', b't',
+                b'c', b'p', 5, b'l', b'o', b'c', b'a', b'l', 0, 0, 12, 0, 1
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_response() {
+        let mut message = vec![0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 2];
+
+        // PTR _wled._tcp.local -> Living Room._wled._tcp.local
+        encode_name(WLED_SERVICE, &mut message);
+        message.extend_from_slice(&[0, 12, 0, 1, 0, 0, 0, 120, 0, 14]);
+        message.push(11);
+        message.extend_from_slice(b"Living Room");
+        message.extend_from_slice(&[0xC0, 12]);
+
+        // SRV Living Room._wled._tcp.local -> wled-living.local:80
+        message.extend_from_slice(&[0xC0, 40]);
+        message.extend_from_slice(&[0, 33, 0x80, 1, 0, 0, 0, 120, 0, 25, 0, 0, 0, 0, 0, 80]);
+        let host = message.len();
+        encode_name("wled-living.local", &mut message);
+
+        // A wled-living.local -> 192.168.1.20
+        message.extend_from_slice(&[0xC0, host as u8]);
+        message.extend_from_slice(&[0, 1, 0x80, 1, 0, 0, 0, 120, 0, 4, 192, 168, 1, 20]);
+
+        let mut records = Records::default();
+        assert_eq!(records.add_response(&message), Some(()));
+        let found = records.services(&[OPC_SERVICE, WLED_SERVICE]);
+        assert_eq!(
+            found,
+            [Service {
+                service: String::from(WLED_SERVICE),
+                name: String::from("living room"),
+                host: String::from("wled-living.local"),
+                port: 80,
+                address: Some(Ipv4Addr::new(192, 168, 1, 20)),
+            }]
+        );
+        assert!(find_service(&found, WLED_SERVICE, "Living Room").is_some());
+        assert!(find_service(&found, WLED_SERVICE, "wled-living.local.").is_some());
+        assert!(find_service(&found, WLED_SERVICE, "192.168.1.20").is_some());
+        assert!(find_service(&found, OPC_SERVICE, "wled-living").is_none());
+        assert!(records.services(&[OPC_SERVICE]).is_empty());
+    }
+
+    #[test]
+    fn pointer_loop() {
+        let message = [0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xC0, 12];
+        assert_eq!(read_name(&message, 12), None);
+        assert_eq!(Records::default().add_response(&message), None);
+    }
+
+    #[test]
+    fn encode_discovery() {
+        let found = [Service {
+            service: String::from(WLED_SERVICE),
+            name: String::from("Living \"Room\""),
+            host: String::from("wled-living.local"),
+            port: 80,
+            address: Some(Ipv4Addr::new(192, 168, 1, 20)),
+        }];
+        let wled_servers = [
+            HostCheck {
+                host: String::from("Living Room"),
+                discovered: true,
+                resolved: Some(String::from("192.168.1.20")),
+            },
+            HostCheck {
+                host: String::from("kitchen.local"),
+                discovered: false,
+                resolved: None,
+            },
+        ];
+        assert_eq!(
+            discovery_json(&found, &[], &wled_servers),
+            concat!(
+                r#"{"services":[{"service":"_wled._tcp.local","name":"Living \"Room\"","#,
+                r#""host":"wled-living.local","port":80,"address":"192.168.1.20"}],"servers":[],"#,
+                r#""wledServers":[{"host":"Living Room","discovered":true,"resolved":"192.168.1.20"},"#,
+                r#"{"host":"kitchen.local","discovered":false,"resolved":null}]}"#
+            )
+        );
+    }
+}
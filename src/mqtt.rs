@@ -0,0 +1,484 @@
+use std::{
+    io::{Error, ErrorKind, Read, Result, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, RecvTimeoutError, TryRecvError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, WPARAM},
+    UI::WindowsAndMessaging::PostMessageA,
+};
+
+use crate::{
+    hidden_window::{WM_SET_BRIGHTNESS, WM_SET_ENABLED},
+    settings::MqttConfiguration,
+};
+
+/// Give up on connecting to the broker, or waiting for it to accept the connection, after
+/// this long.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to block reading from the broker before checking for a new [MqttState].
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long to wait before reconnecting to the broker after the connection is lost.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// MQTT 3.1.1 control packet types, in the high nibble of the first byte of each packet.
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xC0;
+const DISCONNECT: u8 = 0xE0;
+
+/// Snapshot of the state which we publish to the broker.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MqttState {
+    pub enabled: bool,
+    pub brightness: u8,
+    pub frame_rate: f64,
+    pub average_color: u32,
+}
+
+/// Command which we received from the broker on one of the command topics.
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Enabled(bool),
+    Brightness(u8),
+}
+
+/// The topics under the [MqttConfiguration] prefix.
+struct Topics {
+    availability: String,
+    state: String,
+    brightness: String,
+    fps: String,
+    color: String,
+    set: String,
+    set_brightness: String,
+}
+
+impl Topics {
+    fn new(prefix: &str) -> Self {
+        Self {
+            availability: format!("{}/availability", prefix),
+            state: format!("{}/state", prefix),
+            brightness: format!("{}/brightness", prefix),
+            fps: format!("{}/fps", prefix),
+            color: format!("{}/color", prefix),
+            set: format!("{}/set", prefix),
+            set_brightness: format!("{}/brightness/set", prefix),
+        }
+    }
+
+    /// Parse a `payload` which we received on one of the command topics.
+    fn command(&self, topic: &str, payload: &[u8]) -> Option<Command> {
+        let payload = std::str::from_utf8(payload).ok()?.trim();
+        if topic == self.set {
+            if payload.eq_ignore_ascii_case("ON") {
+                Some(Command::Enabled(true))
+            } else if payload.eq_ignore_ascii_case("OFF") {
+                Some(Command::Enabled(false))
+            } else {
+                None
+            }
+        } else if topic == self.set_brightness {
+            payload
+                .parse::<u32>()
+                .ok()
+                .map(|brightness| Command::Brightness(brightness.min(255) as u8))
+        } else {
+            None
+        }
+    }
+}
+
+/// Client which keeps a connection open to the MQTT broker on a background thread, publishes
+/// each [MqttState], and forwards commands from the broker to the
+/// [crate::hidden_window::HiddenWindow] as window messages.
+pub struct MqttClient {
+    tx: Option<mpsc::Sender<MqttState>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MqttClient {
+    /// Start the background thread which connects to the broker in the [MqttConfiguration] and
+    /// posts any commands to `h_wnd`.
+    pub fn start(configuration: MqttConfiguration, h_wnd: HWND) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::spawn(move || run(&configuration, h_wnd, &rx));
+        Self {
+            tx: Some(tx),
+            thread: Some(thread),
+        }
+    }
+
+    /// Hand the latest [MqttState] to the background thread to publish.
+    pub fn publish(&self, state: MqttState) {
+        if let Some(tx) = self.tx.as_ref() {
+            let _ = tx.send(state);
+        }
+    }
+}
+
+impl Drop for MqttClient {
+    fn drop(&mut self) {
+        // Closing the channel tells the thread to publish that we're offline and disconnect.
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Keep connecting to the broker and running a session until the [MqttClient] closes the
+/// channel.
+fn run(configuration: &MqttConfiguration, h_wnd: HWND, rx: &mpsc::Receiver<MqttState>) {
+    let topics = Topics::new(&configuration.topic);
+    loop {
+        if let Ok(stream) = connect(configuration, &topics) {
+            if session(configuration, &topics, h_wnd, rx, stream).is_ok() {
+                return;
+            }
+        }
+
+        // Drop any state updates until it's time to reconnect.
+        let deadline = Instant::now() + RECONNECT_DELAY;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(remaining) {
+                Ok(_) => (),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+}
+
+/// Connect to the broker, wait for it to accept the connection, and subscribe to the command
+/// topics. The broker publishes "offline" to the availability topic for us if the connection
+/// drops without a [DISCONNECT].
+fn connect(configuration: &MqttConfiguration, topics: &Topics) -> Result<TcpStream> {
+    let address = (configuration.host.as_str(), configuration.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| Error::from(ErrorKind::AddrNotAvailable))?;
+    let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+    stream.write_all(&connect_packet(configuration, &topics.availability))?;
+    let mut connack = [0_u8; 4];
+    stream.read_exact(&mut connack)?;
+    if connack[0] != CONNACK || connack[3] != 0 {
+        return Err(Error::from(ErrorKind::ConnectionRefused));
+    }
+
+    stream.write_all(&subscribe_packet(1, &[&topics.set, &topics.set_brightness]))?;
+    stream.write_all(&publish_packet(&topics.availability, b"online", true))?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Publish each [MqttState] and handle the commands from the broker until the connection is
+/// lost, which returns an error, or the [MqttClient] closes the channel, which returns `Ok`.
+fn session(
+    configuration: &MqttConfiguration,
+    topics: &Topics,
+    h_wnd: HWND,
+    rx: &mpsc::Receiver<MqttState>,
+    mut stream: TcpStream,
+) -> Result<()> {
+    let keep_alive = Duration::from_secs(u64::from(configuration.keep_alive)) / 2;
+    let mut last_write = Instant::now();
+    let mut buffer = Vec::new();
+    let mut chunk = [0_u8; 512];
+
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Err(Error::from(ErrorKind::ConnectionReset)),
+            Ok(count) => buffer.extend_from_slice(&chunk[..count]),
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(error) => return Err(error),
+        }
+
+        while let Some((header, body, len)) = read_packet(&buffer)? {
+            if header & 0xF0 == PUBLISH {
+                if let Some(command) = read_publish(header, body)
+                    .and_then(|(topic, payload)| topics.command(topic, payload))
+                {
+                    post_command(h_wnd, command);
+                }
+            }
+            buffer.drain(..len);
+        }
+
+        match rx.try_recv() {
+            Ok(state) => {
+                publish_state(&mut stream, topics, &state)?;
+                last_write = Instant::now();
+            }
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => {
+                stream.write_all(&publish_packet(&topics.availability, b"offline", true))?;
+                stream.write_all(&[DISCONNECT, 0])?;
+                return Ok(());
+            }
+        }
+
+        if keep_alive > Duration::ZERO && last_write.elapsed() >= keep_alive {
+            stream.write_all(&[PINGREQ, 0])?;
+            last_write = Instant::now();
+        }
+    }
+}
+
+/// Publish each of the values in the [MqttState] to its own topic.
+fn publish_state(stream: &mut TcpStream, topics: &Topics, state: &MqttState) -> Result<()> {
+    let color = state.average_color;
+    let messages = [
+        (
+            &topics.state,
+            String::from(if state.enabled { "ON" } else { "OFF" }),
+        ),
+        (&topics.brightness, state.brightness.to_string()),
+        (&topics.fps, format!("{:.1}", state.frame_rate)),
+        (
+            &topics.color,
+            format!(
+                "{},{},{}",
+                (color & 0xFF000000) >> 24,
+                (color & 0xFF0000) >> 16,
+                (color & 0xFF00) >> 8
+            ),
+        ),
+    ];
+
+    for (topic, payload) in messages {
+        stream.write_all(&publish_packet(topic, payload.as_bytes(), true))?;
+    }
+    Ok(())
+}
+
+/// Post a [Command] to the [crate::hidden_window::HiddenWindow], which owns the
+/// [crate::update_timer::UpdateTimer].
+fn post_command(h_wnd: HWND, command: Command) {
+    let (message, w_param) = match command {
+        Command::Enabled(enabled) => (WM_SET_ENABLED, usize::from(enabled)),
+        Command::Brightness(brightness) => (WM_SET_BRIGHTNESS, usize::from(brightness)),
+    };
+
+    unsafe {
+        PostMessageA(h_wnd, message, WPARAM(w_param), LPARAM(0));
+    }
+}
+
+/// Append an MQTT UTF-8 string, which has a 2 byte big-endian length prefix.
+fn push_string(packet: &mut Vec<u8>, value: &[u8]) {
+    packet.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    packet.extend_from_slice(value);
+}
+
+/// Frame the `body` of a packet with the `header` byte and the variable length encoding of
+/// the remaining length.
+fn frame_packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut remaining = body.len();
+    loop {
+        let byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            packet.push(byte | 0x80);
+        } else {
+            packet.push(byte);
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Encode a CONNECT packet with a clean session and a retained "offline" will on the
+/// `availability` topic.
+fn connect_packet(configuration: &MqttConfiguration, availability: &str) -> Vec<u8> {
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if configuration.username.is_some() {
+        flags |= 0x80;
+    }
+    if configuration.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut body = Vec::new();
+    push_string(&mut body, b"MQTT");
+    body.push(4);
+    body.push(flags);
+    body.extend_from_slice(&configuration.keep_alive.to_be_bytes());
+    push_string(&mut body, configuration.client_id.as_bytes());
+    push_string(&mut body, availability.as_bytes());
+    push_string(&mut body, b"offline");
+    if let Some(username) = configuration.username.as_ref() {
+        push_string(&mut body, username.as_bytes());
+    }
+    if let Some(password) = configuration.password.as_ref() {
+        push_string(&mut body, password.as_bytes());
+    }
+
+    frame_packet(CONNECT, &body)
+}
+
+/// Encode a SUBSCRIBE packet for each of the `topics` at QoS 0.
+fn subscribe_packet(packet_id: u16, topics: &[&str]) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    for topic in topics {
+        push_string(&mut body, topic.as_bytes());
+        body.push(0);
+    }
+
+    frame_packet(SUBSCRIBE, &body)
+}
+
+/// Encode a PUBLISH packet at QoS 0, optionally asking the broker to retain it.
+fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_string(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+
+    frame_packet(PUBLISH | u8::from(retain), &body)
+}
+
+/// Split the first complete packet off the front of the `buffer`. Returns the header byte, the
+/// body after the remaining length, and the total length of the packet, or `None` if the
+/// `buffer` doesn't hold a complete packet yet. The remaining length is at most 4 bytes, so a
+/// 4th byte which still has the continuation bit set is an error.
+fn read_packet(buffer: &[u8]) -> Result<Option<(u8, &[u8], usize)>> {
+    let header = match buffer.first() {
+        Some(header) => *header,
+        None => return Ok(None),
+    };
+    let mut remaining = 0_usize;
+    let mut offset = 1;
+    loop {
+        let byte = match buffer.get(offset) {
+            Some(byte) => *byte,
+            None => return Ok(None),
+        };
+        remaining |= usize::from(byte & 0x7F) << (7 * (offset - 1));
+        offset += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if offset > 4 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "MQTT remaining length is longer than 4 bytes",
+            ));
+        }
+    }
+
+    let len = offset + remaining;
+    Ok(buffer.get(offset..len).map(|body| (header, body, len)))
+}
+
+/// Split the body of a PUBLISH packet into the topic and payload, skipping the packet ID if the
+/// QoS in the `header` is above 0.
+fn read_publish(header: u8, body: &[u8]) -> Option<(&str, &[u8])> {
+    let topic_len = usize::from(u16::from_be_bytes([*body.first()?, *body.get(1)?]));
+    let topic = std::str::from_utf8(body.get(2..2 + topic_len)?).ok()?;
+    let packet_id_len = if header & 0x06 != 0 { 2 } else { 0 };
+    let payload = body.get(2 + topic_len + packet_id_len..)?;
+    Some((topic, payload))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_packets() {
+        assert_eq!(
+            publish_packet("a/b", b"ON", true),
+            vec![0x31, 7, 0, 3, b'a', b'/', b'b', b'O', b'N']
+        );
+        assert_eq!(
+            subscribe_packet(1, &["a/set"]),
+            vec![0x82, 10, 0, 1, 0, 5, b'a', b'/', b's', b'e', b't', 0]
+        );
+
+        let long = frame_packet(PUBLISH, &[0; 200]);
+        assert_eq!(long[..3], [0x30, 0xC8, 0x01]);
+        assert_eq!(long.len(), 203);
+
+        let configuration = MqttConfiguration {
+            host: String::from("broker"),
+            port: 1883,
+            client_id: String::from("id"),
+            username: Some(String::from("u")),
+            password: None,
+            topic: String::from("a"),
+            interval: 1000,
+            keep_alive: 30,
+        };
+        let connect = connect_packet(&configuration, "a/availability");
+        assert_eq!(connect[0], CONNECT);
+        assert_eq!(connect[2..9], [0, 4, b'M', b'Q', b'T', b'T', 4]);
+        assert_eq!(connect[9], 0x80 | 0x20 | 0x04 | 0x02);
+        assert_eq!(connect[10..12], 30_u16.to_be_bytes());
+        assert_eq!(usize::from(connect[1]), connect.len() - 2);
+    }
+
+    #[test]
+    fn decode_packets() {
+        let mut buffer = publish_packet("a/set", b"OFF", false);
+        buffer.extend_from_slice(&[0xD0, 0]);
+        let (header, body, len) = read_packet(&buffer)
+            .expect("decode the PUBLISH packet")
+            .expect("read the PUBLISH packet");
+        assert_eq!(header, PUBLISH);
+        assert_eq!(read_publish(header, body), Some(("a/set", &b"OFF"[..])));
+
+        let (header, body, _) = read_packet(&buffer[len..])
+            .expect("decode the PINGRESP packet")
+            .expect("read the PINGRESP packet");
+        assert_eq!(header, 0xD0);
+        assert!(body.is_empty());
+
+        assert!(read_packet(&buffer[..len - 1])
+            .expect("decode the partial packet")
+            .is_none());
+        assert!(read_packet(&[]).expect("decode the empty buffer").is_none());
+
+        // The largest remaining length fits in 4 bytes, so waiting for more would never end.
+        assert!(read_packet(&[0x30, 0xFF, 0xFF, 0xFF])
+            .expect("decode the partial remaining length")
+            .is_none());
+        assert!(read_packet(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+    }
+
+    #[test]
+    fn parse_commands() {
+        let topics = Topics::new("tv");
+        assert_eq!(
+            topics.command("tv/set", b"ON"),
+            Some(Command::Enabled(true))
+        );
+        assert_eq!(
+            topics.command("tv/set", b"off\n"),
+            Some(Command::Enabled(false))
+        );
+        assert_eq!(topics.command("tv/set", b"toggle"), None);
+        assert_eq!(
+            topics.command("tv/brightness/set", b"128"),
+            Some(Command::Brightness(128))
+        );
+        assert_eq!(
+            topics.command("tv/brightness/set", b"1000"),
+            Some(Command::Brightness(255))
+        );
+        assert_eq!(topics.command("tv/state", b"ON"), None);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, UdpSocket},
+    time::Duration,
+};
+
+use crate::{
+    http_client, output_sink::OutputSink, pixel_buffer::PixelBuffer, screen_samples::ScreenSamples,
+    settings::NanoleafController,
+};
+
+/// UDP port which the controller listens on in the external control (version 2) mode.
+const NANOLEAF_STREAM_PORT: u16 = 60222;
+
+/// Give up on the request to switch the controller to external control after this long.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Representation of a Nanoleaf controller in the external control streaming mode.
+pub struct NanoleafConnection<'a> {
+    controller: &'a NanoleafController,
+    socket: Option<UdpSocket>,
+    previous_effect: Option<String>,
+}
+
+impl<'a> NanoleafConnection<'a> {
+    /// Allocate a new [NanoleafConnection] without opening the socket.
+    pub fn new(controller: &'a NanoleafController) -> Self {
+        Self {
+            controller,
+            socket: None,
+            previous_effect: None,
+        }
+    }
+
+    /// Get the name of the effect which the controller is showing, as a JSON string.
+    fn selected_effect(&self) -> Option<String> {
+        match http_client::request(
+            &self.controller.host,
+            self.controller.port,
+            "GET",
+            &format!("/api/v1/{}/effects/select", self.controller.token),
+            "",
+            REQUEST_TIMEOUT,
+        ) {
+            Some((status, body)) if http_client::is_success(status) => {
+                Some(body.trim().to_string())
+            }
+            _ => None,
+        }
+    }
+
+    /// Switch the controller back to the `effect` from [NanoleafConnection::selected_effect].
+    fn select_effect(&self, effect: &str) {
+        http_client::request(
+            &self.controller.host,
+            self.controller.port,
+            "PUT",
+            &format!("/api/v1/{}/effects", self.controller.token),
+            &format!(r#"{{"select":{}}}"#, effect),
+            REQUEST_TIMEOUT,
+        );
+    }
+
+    /// Switch the controller to the external control (version 2) effect with the OpenAPI.
+    fn start_streaming(&self) -> bool {
+        matches!(
+            http_client::request(
+                &self.controller.host,
+                self.controller.port,
+                "PUT",
+                &format!("/api/v1/{}/effects", self.controller.token),
+                r#"{"write":{"command":"display","animType":"extControl","extControlVersion":"v2"}}"#,
+                REQUEST_TIMEOUT,
+            ),
+            Some((status, _)) if http_client::is_success(status)
+        )
+    }
+}
+
+impl<'a> OutputSink for NanoleafConnection<'a> {
+    /// Remember the effect which the controller is showing, switch it to external control, and
+    /// open a non-blocking UDP socket to it, if it isn't open yet.
+    fn open(&mut self) -> bool {
+        if self.socket.is_none() {
+            if self.previous_effect.is_none() {
+                self.previous_effect = self.selected_effect();
+            }
+
+            if !self.start_streaming() {
+                return false;
+            }
+
+            self.socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+                .and_then(|socket| {
+                    socket.connect((self.controller.host.as_str(), NANOLEAF_STREAM_PORT))?;
+                    socket.set_nonblocking(true)?;
+                    Ok(socket)
+                })
+                .ok();
+        }
+
+        self.healthy()
+    }
+
+    /// Render a message with the average color of the LEDs for each of the panels.
+    fn render(&self, samples: &ScreenSamples) -> Vec<PixelBuffer> {
+        let panel_ids: Vec<u16> = self
+            .controller
+            .panels
+            .iter()
+            .map(|panel| panel.id)
+            .collect();
+        let mut pixels = PixelBuffer::new_nanoleaf_buffer(&panel_ids, self.controller.transition);
+        for (index, panel) in self.controller.panels.iter().enumerate() {
+            pixels.set_nanoleaf_color(index, samples.average_leds(&panel.leds));
+        }
+        vec![pixels]
+    }
+
+    /// Send the message to the controller. If the socket buffer is full, the frame is dropped
+    /// instead of waiting.
+    fn send(&mut self, pixels: Vec<PixelBuffer>) -> bool {
+        let result = match self.socket.as_ref() {
+            Some(socket) => pixels
+                .iter()
+                .try_for_each(|pixels| socket.send(pixels.data()).map(|_| ())),
+            None => return false,
+        };
+
+        match result {
+            Ok(()) => true,
+            Err(error) if error.kind() == ErrorKind::WouldBlock => true,
+            Err(_) => {
+                self.close();
+                false
+            }
+        }
+    }
+
+    /// Close the socket and switch the controller back to the effect it was showing before.
+    fn close(&mut self) {
+        if self.socket.take().is_some() {
+            if let Some(effect) = self.previous_effect.take() {
+                self.select_effect(&effect);
+            }
+        }
+    }
+
+    /// Test if the socket to the controller is open.
+    fn healthy(&self) -> bool {
+        self.socket.is_some()
+    }
+}
@@ -0,0 +1,110 @@
+/// Convert an sRGB encoded channel in the range `0.0..=255.0` to linear light in the range
+/// `0.0..=1.0`.
+fn to_linear(value: f64) -> f64 {
+    let c = (value / 255.0).clamp(0.0, 1.0);
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a channel in linear light back to sRGB encoding in the range `0.0..=255.0`.
+fn to_srgb(linear: f64) -> f64 {
+    let linear = linear.clamp(0.0, 1.0);
+    let c = if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    c * 255.0
+}
+
+/// Convert an sRGB color with channels in the range `0.0..=255.0` to OKLab, with the
+/// lightness in the range `0.0..=1.0`, and the `a` and `b` opponent axes roughly in the range
+/// `-0.4..=0.4`.
+fn rgb_to_oklab((r, g, b): (f64, f64, f64)) -> (f64, f64, f64) {
+    let (r, g, b) = (to_linear(r), to_linear(g), to_linear(b));
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    (
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    )
+}
+
+/// Convert an OKLab color from [rgb_to_oklab] back to sRGB, with channels in the range
+/// `0.0..=255.0`. Colors outside of the sRGB gamut are clipped.
+fn oklab_to_rgb((lightness, a, b): (f64, f64, f64)) -> (f64, f64, f64) {
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    (
+        to_srgb(4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s),
+        to_srgb(-1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s),
+        to_srgb(-0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s),
+    )
+}
+
+/// Blend the `amount` (from `0.0` to `1.0`) of the `to` color into the `from` color in OKLab,
+/// which keeps the lightness and chroma of a fade perceptually even, e.g. red to green doesn't
+/// pass through a muddy brown.
+pub fn blend(from: (f64, f64, f64), to: (f64, f64, f64), amount: f64) -> (f64, f64, f64) {
+    let (from, to) = (rgb_to_oklab(from), rgb_to_oklab(to));
+    let mix = |from: f64, to: f64| from + (to - from) * amount;
+    oklab_to_rgb((mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close((r1, g1, b1): (f64, f64, f64), (r2, g2, b2): (f64, f64, f64)) {
+        assert!(
+            (r1 - r2).abs() < 0.01 && (g1 - g2).abs() < 0.01 && (b1 - b2).abs() < 0.01,
+            "({}, {}, {}) != ({}, {}, {})",
+            r1,
+            g1,
+            b1,
+            r2,
+            g2,
+            b2
+        );
+    }
+
+    #[test]
+    fn oklab_round_trip() {
+        for color in [
+            (0.0, 0.0, 0.0),
+            (255.0, 255.0, 255.0),
+            (255.0, 0.0, 0.0),
+            (12.0, 200.0, 64.0),
+            (90.0, 30.0, 180.0),
+        ] {
+            assert_close(oklab_to_rgb(rgb_to_oklab(color)), color);
+        }
+    }
+
+    #[test]
+    fn white_lightness() {
+        assert_close(rgb_to_oklab((255.0, 255.0, 255.0)), (1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn blend_end_points() {
+        let (red, green) = ((255.0, 0.0, 0.0), (0.0, 255.0, 0.0));
+        assert_close(blend(red, green, 0.0), red);
+        assert_close(blend(red, green, 1.0), green);
+    }
+
+    #[test]
+    fn blend_stays_bright() {
+        let (r, g, _) = blend((255.0, 0.0, 0.0), (0.0, 255.0, 0.0), 0.5);
+        let (rgb_r, rgb_g) = (127.5, 127.5);
+        assert!(r > rgb_r && g > rgb_g, "({}, {})", r, g);
+    }
+}
@@ -1,106 +1,853 @@
 use std::{
-    io::{Result, Write},
-    net::{Shutdown, TcpStream},
+    io::{Error, ErrorKind, Result},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{self, TcpStream},
+    runtime::{Builder, Handle, Runtime},
+    sync::{watch, Notify},
+    task::JoinHandle,
+    time,
 };
 
 use crate::{
+    health::{HealthEvent, HealthSender},
+    output_sink::OutputSink,
     pixel_buffer::PixelBuffer,
-    settings::{OpcServer, Settings},
+    proxy::Proxy,
+    screen_samples::ScreenSamples,
+    serial_port::SerialPort,
+    settings::{OpcServer, OpcSysex, Settings},
 };
 
-/// Representation of a connection to an [OpcServer].
-struct OpcConnection<'a> {
+/// OPC command for system exclusive messages.
+const OPC_SYSEX: u8 = 255;
+
+/// State of the connection to an [OpcServer], which the task shares with the [OpcConnection].
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+    Paused,
+}
+
+/// Switches to pause and resume each [OpcServer] at runtime, without restarting the [OpcPool].
+/// These are shared with the [crate::update_timer::UpdateTimer], which can flip them from any
+/// thread, and the task for each [OpcConnection] closes or reopens the connection to match.
+pub struct ServerSwitches(Vec<watch::Sender<bool>>);
+
+impl ServerSwitches {
+    /// Allocate a new set of [ServerSwitches], with each [OpcServer] enabled.
+    pub fn new(parameters: &Settings) -> Self {
+        Self(
+            parameters
+                .servers
+                .iter()
+                .map(|_| watch::channel(true).0)
+                .collect(),
+        )
+    }
+
+    /// Pause or resume the [OpcServer] at `server_index`. Returns `false` if there is no
+    /// configured server at that index.
+    pub fn set_enabled(&self, server_index: usize, enabled: bool) -> bool {
+        match self.0.get(server_index) {
+            Some(switch) => {
+                switch.send_replace(enabled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get a receiver for the switch at `server_index`, for the task which owns the connection.
+    fn subscribe(&self, server_index: usize) -> watch::Receiver<bool> {
+        self.0[server_index].subscribe()
+    }
+}
+
+/// The latest frame for an [OpcConnection] which its task hasn't written yet. If the task is
+/// still busy with the last frame when the next one is ready, the next one replaces any frame
+/// which was already waiting, so a slow server gets fewer frames but never falls behind.
+#[derive(Default)]
+struct FrameSlot {
+    frame: Mutex<Option<Vec<PixelBuffer>>>,
+    notify: Notify,
+    closed: AtomicBool,
+}
+
+impl FrameSlot {
+    /// Replace the waiting frame with `pixels`, and wake up the task if it's idle.
+    fn put(&self, pixels: Vec<PixelBuffer>) {
+        *self.frame.lock().expect("lock frame slot") = Some(pixels);
+        self.notify.notify_one();
+    }
+
+    /// Tell the task to shut down the connection, even if it's waiting for a frame.
+    fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.notify.notify_one();
+    }
+
+    /// Wait for the next frame, or return `None` once the slot is closed.
+    async fn take(&self) -> Option<Vec<PixelBuffer>> {
+        loop {
+            if self.closed.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let frame = self.frame.lock().expect("lock frame slot").take();
+            if frame.is_some() {
+                return frame;
+            }
+
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Tracks how long it takes to write each frame to an [OpcServer], so a server on a slow or
+/// flaky link can be limited to a lower frame rate instead of stuttering. The average is a
+/// moving average of the write times, like the smoothed round trip time in TCP. Once it goes
+/// over the `budget` for each frame, the frames are limited to a little longer than the
+/// average, and they go back to the full frame rate once that fits in the `budget` again.
+struct WriteRate {
+    budget: Duration,
+    average: Option<Duration>,
+    limited: bool,
+}
+
+impl WriteRate {
+    /// Allocate a new [WriteRate] with the `budget` for each frame.
+    fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            average: None,
+            limited: false,
+        }
+    }
+
+    /// Record how long it took to write the last frame, and return the interval between frames
+    /// which the server can keep up with, if it's over the `budget`.
+    fn record(&mut self, elapsed: Duration) -> Option<Duration> {
+        let average = match self.average {
+            Some(average) => (average * 7 + elapsed) / 8,
+            None => elapsed,
+        };
+        self.average = Some(average);
+
+        let interval = average * 5 / 4;
+        self.limited = if self.limited {
+            interval > self.budget
+        } else {
+            average > self.budget
+        };
+        Some(interval).filter(|_| self.limited)
+    }
+}
+
+/// Representation of a connection to an [OpcServer]. The connection itself is owned by an async
+/// task on the [OpcPool] runtime, so connecting and writing to each of the servers happens
+/// concurrently, and a slow server never holds up the worker thread or the other outputs. The
+/// frames are handed over in a [FrameSlot], which only keeps the latest one. If the connection is
+/// lost, the task reconnects on its own with the [crate::settings::ReconnectConfiguration]
+/// backoff. While the server is paused with the [ServerSwitches], it's not [OutputSink::healthy].
+/// If the `adaptive_rate` is enabled, the task also publishes the frame interval (in
+/// microseconds) which the server can keep up with, see [WriteRate]. The task reports when the
+/// server goes up or down as a [HealthEvent].
+pub struct OpcConnection<'a> {
     server: &'a OpcServer,
-    stream: Option<TcpStream>,
+    parameters: &'a Settings,
+    index: usize,
+    health: HealthSender,
+    runtime: Handle,
+    slot: Option<Arc<FrameSlot>>,
+    task: Option<JoinHandle<()>>,
+    enabled: watch::Receiver<bool>,
+    state: watch::Receiver<ConnectionState>,
+    failed: Arc<AtomicBool>,
+    adaptive_interval: Arc<AtomicU64>,
 }
 
 impl<'a> OpcConnection<'a> {
-    /// Allocate a new unconnected [OpcConnection].
-    pub fn new(server: &'a OpcServer) -> Self {
+    /// Allocate a new [OpcConnection] and start connecting to the [OpcServer] at `index` on the
+    /// `runtime`, unless the `enabled` switch for it is off. It reports when the server goes up
+    /// or down to `health`.
+    pub fn new(
+        server: &'a OpcServer,
+        parameters: &'a Settings,
+        index: usize,
+        runtime: &Handle,
+        enabled: watch::Receiver<bool>,
+        health: HealthSender,
+    ) -> Self {
+        let failed = Arc::new(AtomicBool::new(false));
+        let adaptive_interval = Arc::new(AtomicU64::new(0));
+        let (slot, task, state) = Self::start(
+            server,
+            parameters,
+            index,
+            runtime,
+            &enabled,
+            health.clone(),
+            failed.clone(),
+            adaptive_interval.clone(),
+        );
         Self {
             server,
-            stream: None,
+            parameters,
+            index,
+            health,
+            runtime: runtime.clone(),
+            slot: Some(slot),
+            task: Some(task),
+            enabled,
+            state,
+            failed,
+            adaptive_interval,
         }
     }
 
-    /// Try to open a connection to the [OpcServer].
-    pub fn open(&mut self) -> Result<()> {
-        let stream = TcpStream::connect(format!("{}:{}", self.server.host, self.server.port))?;
-        stream.shutdown(Shutdown::Read)?;
-        self.stream = Some(stream);
-        Ok(())
+    /// Spawn the task which owns the connection to the [OpcServer] on the `runtime`.
+    #[allow(clippy::too_many_arguments)]
+    fn start(
+        server: &OpcServer,
+        parameters: &Settings,
+        index: usize,
+        runtime: &Handle,
+        enabled: &watch::Receiver<bool>,
+        health: HealthSender,
+        failed: Arc<AtomicBool>,
+        adaptive_interval: Arc<AtomicU64>,
+    ) -> (
+        Arc<FrameSlot>,
+        JoinHandle<()>,
+        watch::Receiver<ConnectionState>,
+    ) {
+        let connection = ConnectionParameters {
+            addresses: std::iter::once(server_address(&server.host, &server.port))
+                .chain(
+                    server
+                        .failover
+                        .iter()
+                        .map(|failover| server_address(&failover.host, &failover.port)),
+                )
+                .collect(),
+            failback_interval: Some(server.failback_interval)
+                .filter(|failback_interval| *failback_interval > 0)
+                .map(|failback_interval| Duration::from_millis(u64::from(failback_interval))),
+            connect_timeout: Duration::from_millis(u64::from(server.connect_timeout)),
+            write_timeout: Duration::from_millis(u64::from(server.write_timeout)),
+            keep_alive: Some(server.keep_alive)
+                .filter(|keep_alive| *keep_alive > 0)
+                .map(|keep_alive| Duration::from_millis(u64::from(keep_alive))),
+            batch_channels: server.batch_channels,
+            proxy: server
+                .proxy
+                .as_ref()
+                .map(|proxy| Proxy::new(proxy, server_address(&proxy.host, &proxy.port))),
+            frame_budget: Some(frame_interval(server, parameters)).filter(|_| server.adaptive_rate),
+            retries: parameters.reconnect.retries,
+            delay: parameters.reconnect.delay,
+            max_delay: parameters.throttle_timer,
+            startup: sysex_messages(&server.startup_commands),
+            shutdown: sysex_messages(&server.shutdown_commands),
+            index,
+            health,
+        };
+        let slot = Arc::new(FrameSlot::default());
+        let (state_tx, state) = watch::channel(ConnectionState::Connecting);
+        let task = runtime.spawn(run_connection(
+            connection,
+            slot.clone(),
+            enabled.clone(),
+            state_tx,
+            failed,
+            adaptive_interval,
+        ));
+        (slot, task, state)
     }
+}
+
+/// Copy of the parameters for an [OpcConnection] which the task needs.
+struct ConnectionParameters {
+    /// The `host:port` of the [OpcServer] followed by each of its `failover` hosts in priority
+    /// order, with brackets around any IPv6 literals.
+    addresses: Vec<String>,
+
+    /// How often to try failing back to a higher priority address, if it's enabled.
+    failback_interval: Option<Duration>,
+
+    /// Give up on each attempt to connect after this long.
+    connect_timeout: Duration,
+
+    /// Treat the connection as lost if writing a frame takes longer than this.
+    write_timeout: Duration,
+
+    /// Start sending TCP keepalive probes if the connection is idle for this long.
+    keep_alive: Option<Duration>,
+
+    /// Write all of the channels in each frame together instead of one at a time.
+    batch_channels: bool,
 
-    /// Send a pre-packaged [PixelBuffer] to the [OpcConnection].
-    pub fn send(&mut self, pixels: &PixelBuffer) -> bool {
-        match self.stream.as_mut() {
-            Some(stream) => match stream.write_all(pixels.data()) {
-                Ok(()) => true,
-                Err(_) => {
-                    self.close();
-                    false
+    /// Tunnel each connection through this [Proxy], if it's set.
+    proxy: Option<Proxy>,
+
+    /// The time between frames for the [OpcServer], if the `adaptive_rate` is enabled.
+    frame_budget: Option<Duration>,
+
+    /// The `retries` and `delay` (in milliseconds) from the
+    /// [crate::settings::ReconnectConfiguration], see [SerialPort::backoff_delay].
+    retries: u32,
+    delay: u32,
+
+    /// The longest delay (in milliseconds) between attempts to reconnect.
+    max_delay: u32,
+
+    /// The encoded `startup_commands` and `shutdown_commands` from the [OpcServer].
+    startup: Vec<u8>,
+    shutdown: Vec<u8>,
+
+    /// Index of the [OpcServer] in the [Settings], which identifies it in each [HealthEvent].
+    index: usize,
+
+    /// Report when the [OpcServer] goes up or down.
+    health: HealthSender,
+}
+
+/// Get the time between frames for the [OpcServer], which is its own `fps_max` if it has one,
+/// or the delay between frames for the [Settings] `fps_max`.
+fn frame_interval(server: &OpcServer, parameters: &Settings) -> Duration {
+    server.fps_max.filter(|fps_max| *fps_max > 0).map_or_else(
+        || Duration::from_millis(u64::from(parameters.get_delay())),
+        |fps_max| Duration::from_secs(1) / fps_max,
+    )
+}
+
+/// Combine the `host` and `port` of an [OpcServer] into an address which [net::lookup_host]
+/// can resolve. An IPv6 literal like `fe80::1` needs brackets to separate it from the port.
+fn server_address(host: &str, port: &str) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Encode an [OpcSysex] as an OPC system exclusive message.
+fn sysex_message(command: &OpcSysex) -> Vec<u8> {
+    let length = (command.data.len() + 2) as u16;
+    let mut message = vec![command.channel, OPC_SYSEX];
+    message.extend_from_slice(&length.to_be_bytes());
+    message.extend_from_slice(&command.system_id.to_be_bytes());
+    message.extend_from_slice(&command.data);
+    message
+}
+
+/// Encode all of the `commands` back to back, so they can be written together.
+fn sysex_messages(commands: &[OpcSysex]) -> Vec<u8> {
+    commands.iter().flat_map(sysex_message).collect()
+}
+
+/// Something which the task for an [OpcConnection] was waiting for in [next_event].
+enum Event {
+    /// The next frame to write to the [OpcServer].
+    Frame(Vec<PixelBuffer>),
+
+    /// The [OpcConnection] closed the [FrameSlot], or the [ServerSwitches] went away.
+    Closed,
+
+    /// The [OpcServer] was paused or resumed with the [ServerSwitches].
+    Toggled,
+}
+
+/// Wait for the next frame from the [OpcConnection], or for the [OpcServer] to be paused or
+/// resumed.
+async fn next_event(slot: &FrameSlot, enabled: &mut watch::Receiver<bool>) -> Event {
+    tokio::select! {
+        pixels = slot.take() => pixels.map_or(Event::Closed, Event::Frame),
+        changed = enabled.changed() => match changed {
+            Ok(()) => Event::Toggled,
+            Err(_) => Event::Closed,
+        },
+    }
+}
+
+/// Keep a connection open to the [OpcServer] and write each frame to it, until the
+/// [OpcConnection] closes the [FrameSlot]. The `startup` commands are written first each time it
+/// connects, and the `shutdown` commands are written before it closes the connection. If the
+/// connection is lost, set the `failed` flag and keep trying to reconnect with an exponential
+/// backoff and some jitter, regardless of whether the worker thread is throttled. If the server
+/// is paused with the [ServerSwitches], close the connection and wait until it's resumed. Any
+/// frames which arrive in the meantime are dropped. While it's connected to one of the
+/// `failover` hosts, try to fail back to a higher priority one every `failback_interval`. The
+/// [WriteRate] starts over each time it connects. Each time it connects or gives up on an
+/// attempt after being connected, it reports a [HealthEvent].
+async fn run_connection(
+    connection: ConnectionParameters,
+    slot: Arc<FrameSlot>,
+    mut enabled: watch::Receiver<bool>,
+    state: watch::Sender<ConnectionState>,
+    failed: Arc<AtomicBool>,
+    adaptive_interval: Arc<AtomicU64>,
+) {
+    let mut attempts = 0_u32;
+    let mut up = None;
+    loop {
+        if !*enabled.borrow() {
+            state.send_replace(ConnectionState::Paused);
+            while !*enabled.borrow() {
+                if let Event::Closed = next_event(&slot, &mut enabled).await {
+                    return;
                 }
-            },
-            None => false,
+            }
+            attempts = 0;
+            state.send_replace(ConnectionState::Connecting);
         }
+
+        if let Some((mut stream, mut index)) =
+            connect_first(&connection, connection.addresses.len()).await
+        {
+            attempts = 0;
+            state.send_replace(ConnectionState::Connected);
+            report_health(&connection, &mut up, true);
+            let mut write_rate = connection.frame_budget.map(WriteRate::new);
+            adaptive_interval.store(0, Ordering::Relaxed);
+            let mut failback = connection
+                .failback_interval
+                .map(|interval| Instant::now() + interval);
+            loop {
+                let event = match failback.filter(|_| index > 0) {
+                    Some(deadline) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        match time::timeout(remaining, next_event(&slot, &mut enabled)).await {
+                            Ok(event) => event,
+                            Err(_) => {
+                                if let Some((primary, primary_index)) =
+                                    connect_first(&connection, index).await
+                                {
+                                    close_stream(&mut stream, &connection).await;
+                                    stream = primary;
+                                    index = primary_index;
+                                    write_rate = connection.frame_budget.map(WriteRate::new);
+                                    adaptive_interval.store(0, Ordering::Relaxed);
+                                }
+                                failback = connection
+                                    .failback_interval
+                                    .map(|interval| Instant::now() + interval);
+                                continue;
+                            }
+                        }
+                    }
+                    None => next_event(&slot, &mut enabled).await,
+                };
+
+                match event {
+                    Event::Frame(pixels) => {
+                        let start = Instant::now();
+                        if write_frame(&mut stream, &pixels, &connection)
+                            .await
+                            .is_err()
+                        {
+                            failed.store(true, Ordering::Relaxed);
+                            break;
+                        }
+
+                        if let Some(write_rate) = write_rate.as_mut() {
+                            let interval = write_rate.record(start.elapsed());
+                            adaptive_interval.store(
+                                interval.map_or(0, |interval| interval.as_micros() as u64),
+                                Ordering::Relaxed,
+                            );
+                        }
+                    }
+                    Event::Toggled => {
+                        if !*enabled.borrow() {
+                            close_stream(&mut stream, &connection).await;
+                            break;
+                        }
+                    }
+                    Event::Closed => {
+                        close_stream(&mut stream, &connection).await;
+                        return;
+                    }
+                }
+            }
+
+            if !*enabled.borrow() {
+                continue;
+            }
+        }
+
+        state.send_replace(ConnectionState::Disconnected);
+        report_health(&connection, &mut up, false);
+        let delay = jitter(
+            SerialPort::backoff_delay(
+                attempts,
+                connection.retries,
+                connection.delay,
+                connection.max_delay,
+            ),
+            random_seed(),
+        );
+        attempts = attempts.saturating_add(1);
+
+        let deadline = Instant::now() + Duration::from_millis(u64::from(delay));
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match time::timeout(remaining, next_event(&slot, &mut enabled)).await {
+                Ok(Event::Frame(_)) => (),
+                Ok(Event::Toggled) => {
+                    if !*enabled.borrow() {
+                        break;
+                    }
+                }
+                Ok(Event::Closed) => return,
+                Err(_) => break,
+            }
+        }
+        state.send_replace(ConnectionState::Connecting);
     }
+}
 
-    /// Close the connection to the [OpcServer].
-    pub fn close(&mut self) {
-        let _ = match self.stream.take() {
-            Some(stream) => stream.shutdown(Shutdown::Both),
-            None => Ok(()),
-        };
+/// Report a [HealthEvent] if the [OpcServer] went up or down since the last one, so it isn't
+/// reported as down again after every failed attempt to reconnect.
+fn report_health(connection: &ConnectionParameters, up: &mut Option<bool>, now_up: bool) {
+    if *up != Some(now_up) {
+        *up = Some(now_up);
+        connection.health.send(if now_up {
+            HealthEvent::OpcServerUp(connection.index)
+        } else {
+            HealthEvent::OpcServerDown(connection.index)
+        });
     }
 }
 
-/// A pool of [OpcConnection] structs maintaining connections to each [OpcServer].
-pub struct OpcPool<'a> {
-    parameters: &'a Settings,
-    connections: Vec<OpcConnection<'a>>,
+/// Add up to a quarter of the `delay` (in milliseconds) to it based on the `seed`, so that
+/// several [OpcConnection] tasks which lost their connection together don't all retry at once.
+fn jitter(delay: u32, seed: u32) -> u32 {
+    delay + seed % (delay / 4 + 1)
 }
 
-impl<'a> OpcPool<'a> {
-    /// Allocate a new instance of [OpcPool].
-    pub fn new(parameters: &'a Settings) -> Self {
-        Self {
-            parameters,
-            connections: Vec::new(),
+/// Get a seed for the [jitter] which varies between calls. It doesn't need to be a good
+/// source of randomness, so the sub-second part of the clock is enough.
+fn random_seed() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.subsec_nanos())
+}
+
+/// Resolve the `address` to every A and AAAA record, in the order the OS prefers them, and try
+/// to connect to each of them in turn. Each attempt gives up after the `connect_timeout` instead
+/// of waiting for the OS, which can take tens of seconds for an unreachable host. Returns the
+/// error from the last attempt if none of them succeed.
+async fn connect_any(connection: &ConnectionParameters, address: &str) -> Result<TcpStream> {
+    let addresses: Vec<SocketAddr> =
+        time::timeout(connection.connect_timeout, net::lookup_host(address))
+            .await
+            .map_err(|_| Error::from(ErrorKind::TimedOut))??
+            .collect();
+
+    let mut error = Error::from(ErrorKind::AddrNotAvailable);
+    for address in addresses {
+        match time::timeout(connection.connect_timeout, TcpStream::connect(address)).await {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(attempt)) => error = attempt,
+            Err(_) => error = Error::from(ErrorKind::TimedOut),
         }
     }
+    Err(error)
+}
 
-    /// Try to open a connection to each configured [OpcServer]. Returns `true` if
-    /// any connections are successfully opened, `false` if not.
-    pub fn open(&mut self) -> bool {
-        if self.connections.is_empty() {
-            self.connections
-                .reserve_exact(self.parameters.servers.len());
-            for server in self.parameters.servers.iter() {
-                self.connections.push(OpcConnection::new(server));
+/// Try to connect to each of the first `count` `addresses` in priority order, and return the
+/// first connection which opens along with the index of its address.
+async fn connect_first(
+    connection: &ConnectionParameters,
+    count: usize,
+) -> Option<(TcpStream, usize)> {
+    for (index, address) in connection.addresses.iter().take(count).enumerate() {
+        if let Ok(stream) = connect(connection, address).await {
+            return Some((stream, index));
+        }
+    }
+    None
+}
+
+/// Try to connect to the server at the `address`, see [connect_any], or to the [Proxy] and
+/// then through it to the `address`. Then turn on TCP keepalive, so a half-open connection to
+/// a server which rebooted or dropped off the network fails instead of hanging, and write the
+/// `startup` commands to it.
+async fn connect(connection: &ConnectionParameters, address: &str) -> Result<TcpStream> {
+    let mut stream = match connection.proxy.as_ref() {
+        Some(proxy) => {
+            let mut stream = connect_any(connection, proxy.address()).await?;
+            time::timeout(
+                connection.connect_timeout,
+                proxy.handshake(&mut stream, address),
+            )
+            .await
+            .map_err(|_| Error::from(ErrorKind::TimedOut))??;
+            stream
+        }
+        None => connect_any(connection, address).await?,
+    };
+    if let Some(keep_alive) = connection.keep_alive {
+        SockRef::from(&stream).set_tcp_keepalive(
+            &TcpKeepalive::new()
+                .with_time(keep_alive)
+                .with_interval(keep_alive),
+        )?;
+    }
+    time::timeout(
+        connection.write_timeout,
+        stream.write_all(&connection.startup),
+    )
+    .await
+    .map_err(|_| Error::from(ErrorKind::TimedOut))??;
+    Ok(stream)
+}
+
+/// Write the `shutdown` commands to the `stream` and shut it down, ignoring any errors since
+/// the connection is closing anyway.
+async fn close_stream(stream: &mut TcpStream, connection: &ConnectionParameters) {
+    let _ = time::timeout(connection.write_timeout, async {
+        let _ = stream.write_all(&connection.shutdown).await;
+        let _ = stream.shutdown().await;
+    })
+    .await;
+}
+
+/// Write the pre-packaged [PixelBuffer] for each channel to the `stream`, or copy them into
+/// one buffer and write them together if we're batching the channels. If the server stops
+/// reading and it takes longer than the `write_timeout`, give up on the connection.
+async fn write_frame(
+    stream: &mut TcpStream,
+    pixels: &[PixelBuffer],
+    connection: &ConnectionParameters,
+) -> Result<()> {
+    time::timeout(connection.write_timeout, async {
+        if connection.batch_channels && pixels.len() > 1 {
+            let frame: Vec<u8> = pixels
+                .iter()
+                .flat_map(|pixels| pixels.data())
+                .copied()
+                .collect();
+            stream.write_all(&frame).await
+        } else {
+            for pixels in pixels {
+                stream.write_all(pixels.data()).await?;
             }
+            Ok(())
         }
+    })
+    .await
+    .map_err(|_| Error::from(ErrorKind::TimedOut))?
+}
 
-        let mut opened = false;
+impl<'a> OutputSink for OpcConnection<'a> {
+    /// Wait for the current attempt to connect to the [OpcServer] to finish. All of the
+    /// [OpcConnection] tasks started connecting together, so this only waits as long as the
+    /// slowest one, which is never longer than its `connect_timeout` for each address its hosts
+    /// resolve to. If it's not connected, the task keeps trying to reconnect on its own, unless
+    /// it's paused.
+    fn open(&mut self) -> bool {
+        if self.slot.is_none() {
+            let (slot, task, state) = Self::start(
+                self.server,
+                self.parameters,
+                self.index,
+                &self.runtime,
+                &self.enabled,
+                self.health.clone(),
+                self.failed.clone(),
+                self.adaptive_interval.clone(),
+            );
+            self.slot = Some(slot);
+            self.task = Some(task);
+            self.state = state;
+        }
+
+        let mut state = self.state.clone();
+        self.runtime.block_on(async move {
+            matches!(
+                state
+                    .wait_for(|state| *state != ConnectionState::Connecting)
+                    .await
+                    .as_deref(),
+                Ok(ConnectionState::Connected)
+            )
+        })
+    }
+
+    /// The task reconnects to the [OpcServer] on its own with a backoff, so this doesn't wait
+    /// for it like [OutputSink::open].
+    fn reconnect(&mut self) -> bool {
+        self.healthy()
+    }
+
+    /// Render a pre-packaged [PixelBuffer] for each channel on the [OpcServer].
+    fn render(&self, samples: &ScreenSamples) -> Vec<PixelBuffer> {
+        self.server
+            .channels
+            .iter()
+            .map(|channel| {
+                let mut pixels = if self.server.alpha_channel {
+                    PixelBuffer::new_bob_buffer(channel)
+                } else {
+                    PixelBuffer::new_opc_buffer(channel)
+                };
+                if let Some(white_point) = self.server.white_point {
+                    pixels.set_white_point(white_point);
+                }
+
+                samples.render_channel(channel, &mut pixels);
+                pixels
+            })
+            .collect()
+    }
+
+    /// Hand the pre-packaged [PixelBuffer] for each channel to the task for the
+    /// [OpcConnection] in the [FrameSlot], replacing any frame it hasn't picked up yet. This
+    /// never waits for the network. Returns `false` if writing a previous frame failed and the
+    /// connection was lost.
+    fn send(&mut self, pixels: Vec<PixelBuffer>) -> bool {
+        if self.failed.swap(false, Ordering::Relaxed) {
+            return false;
+        }
 
-        for connection in self.connections.iter_mut() {
-            if connection.open().is_ok() {
-                opened = true;
+        match self.slot.as_ref() {
+            Some(slot) => {
+                slot.put(pixels);
+                true
             }
+            None => false,
         }
+    }
 
-        opened
+    /// Close the [FrameSlot], which tells the task to shut down the connection to the
+    /// [OpcServer].
+    fn close(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            slot.close();
+        }
     }
 
-    /// Send a [PixelBuffer] to the [OpcConnection] at index `server`.
-    pub fn send(&mut self, server: usize, pixels: &PixelBuffer) -> bool {
-        server < self.connections.len() && self.connections[server].send(pixels)
+    /// Test if the [OpcConnection] is connected to the [OpcServer], or if it still needs to
+    /// report that it lost the connection.
+    fn healthy(&self) -> bool {
+        self.slot.is_some()
+            && (self.failed.load(Ordering::Relaxed)
+                || *self.state.borrow() == ConnectionState::Connected)
     }
 
-    pub fn close(&mut self) {
-        for connection in self.connections.iter_mut() {
-            connection.close();
+    /// Limit the frames to the `fps_max` for the [OpcServer], if it has one, or to the interval
+    /// which the task found it can keep up with, whichever is longer.
+    fn frame_interval(&self) -> Option<Duration> {
+        let fps_max = self
+            .server
+            .fps_max
+            .filter(|fps_max| *fps_max > 0)
+            .map(|fps_max| Duration::from_secs(1) / fps_max);
+        let adaptive = match self.adaptive_interval.load(Ordering::Relaxed) {
+            0 => None,
+            interval => Some(Duration::from_micros(interval)),
+        };
+        fps_max.max(adaptive)
+    }
+
+    /// Get the `latency` of the [OpcServer].
+    fn latency(&self) -> Duration {
+        Duration::from_millis(u64::from(self.server.latency))
+    }
+}
+
+/// A pool of [OpcConnection] structs maintaining connections to each [OpcServer], along with
+/// the async runtime which drives all of them. Each connection is a task, not a thread of its
+/// own, and the work-stealing runtime runs them on as many worker threads as there are
+/// servers. None of the tasks block a worker: the sockets and timers are async, and
+/// [net::lookup_host] runs on the blocking pool, so a task waiting on a stalled socket yields
+/// until it can write again, and the others keep sending. The extra workers only let several servers encode and copy their
+/// frames in parallel.
+pub struct OpcPool<'a> {
+    connections: Vec<OpcConnection<'a>>,
+    shutdown_timeout: Duration,
+    runtime: Runtime,
+}
+
+impl<'a> OpcPool<'a> {
+    /// Allocate a new instance of [OpcPool] and start connecting an [OpcConnection] to each
+    /// configured [OpcServer] which is enabled in the [ServerSwitches]. Each of them reports when
+    /// its server goes up or down to `health`.
+    pub fn new(parameters: &'a Settings, switches: &ServerSwitches, health: HealthSender) -> Self {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(parameters.servers.len().max(1))
+            .thread_name("opc-pool")
+            .enable_all()
+            .build()
+            .expect("create OPC runtime");
+        Self {
+            connections: parameters
+                .servers
+                .iter()
+                .enumerate()
+                .map(|(index, server)| {
+                    OpcConnection::new(
+                        server,
+                        parameters,
+                        index,
+                        runtime.handle(),
+                        switches.subscribe(index),
+                        health.clone(),
+                    )
+                })
+                .collect(),
+            shutdown_timeout: parameters
+                .servers
+                .iter()
+                .map(|server| Duration::from_millis(u64::from(server.write_timeout)) * 2)
+                .max()
+                .unwrap_or_default(),
+            runtime,
         }
     }
+
+    /// Get each [OpcConnection] as an [OutputSink].
+    pub fn sinks(&mut self) -> Vec<&mut (dyn OutputSink + 'a)> {
+        self.connections
+            .iter_mut()
+            .map(|connection| connection as &mut (dyn OutputSink + 'a))
+            .collect()
+    }
+
+    /// Close each [OpcConnection], and wait for the tasks to write the `shutdown_commands` and
+    /// shut down the connections before the runtime goes away. A task might be in the middle of
+    /// writing a frame first, so this waits up to twice the longest `write_timeout`.
+    pub fn close(&mut self) {
+        let tasks: Vec<JoinHandle<()>> = self
+            .connections
+            .iter_mut()
+            .filter_map(|connection| {
+                connection.close();
+                connection.task.take()
+            })
+            .collect();
+        let _ = self
+            .runtime
+            .block_on(time::timeout(self.shutdown_timeout, async move {
+                for task in tasks {
+                    let _ = task.await;
+                }
+            }));
+    }
 }
 
 impl<'a> Drop for OpcPool<'a> {
@@ -108,3 +855,127 @@ impl<'a> Drop for OpcPool<'a> {
         self.close();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{io::Read, net::TcpListener};
+
+    use super::*;
+
+    #[test]
+    fn write_shutdown_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind listener");
+        let port = listener.local_addr().expect("listener address").port();
+        let settings = Settings::from_str(&format!(
+            r#"{{
+                "minBrightness": 0,
+                "fade": 0,
+                "timeout": 1000,
+                "fpsMax": 30,
+                "throttleTimer": 3000,
+                "displays": [],
+                "servers": [{{
+                    "host": "127.0.0.1",
+                    "port": "{}",
+                    "alphaChannel": false,
+                    "channels": [],
+                    "shutdownCommands": [ {{ "systemId": 1, "data": [ [ 0, 2, 0 ] ] }} ]
+                }}]
+            }}"#,
+            port
+        ))
+        .expect("parse settings");
+        let switches = ServerSwitches::new(&settings);
+        let mut pool = OpcPool::new(&settings, &switches, HealthSender::default());
+        let (mut stream, _) = listener.accept().expect("accept connection");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("set read timeout");
+        pool.close();
+
+        let mut received = Vec::new();
+        stream
+            .read_to_end(&mut received)
+            .expect("read shutdown commands");
+        assert_eq!(received, [0, 255, 0, 5, 0x00, 0x01, 0, 2, 0]);
+    }
+
+    #[test]
+    fn reconnect_jitter() {
+        assert_eq!(jitter(0, 12345), 0);
+        assert_eq!(jitter(400, 0), 400);
+        assert_eq!(jitter(400, 100), 500);
+        assert_eq!(jitter(400, 101), 400);
+        assert!((0..1000).all(|seed| (400..=500).contains(&jitter(400, seed))));
+    }
+
+    #[test]
+    fn latest_frame() {
+        let slot = FrameSlot::default();
+        slot.put(vec![PixelBuffer::new_image_buffer(vec![1], 0, 0)]);
+        slot.put(vec![PixelBuffer::new_image_buffer(vec![2], 0, 0)]);
+        let frame = slot
+            .frame
+            .lock()
+            .expect("lock frame slot")
+            .take()
+            .expect("latest frame");
+        assert_eq!(frame.len(), 1);
+        assert_eq!(frame[0].data(), [2]);
+        assert!(slot.frame.lock().expect("lock frame slot").is_none());
+    }
+
+    #[test]
+    fn adapt_write_rate() {
+        let mut write_rate = WriteRate::new(Duration::from_millis(33));
+        assert_eq!(write_rate.record(Duration::from_millis(5)), None);
+
+        // A few slow writes raise the average over the budget.
+        let slow = (0..8)
+            .map(|_| write_rate.record(Duration::from_millis(80)))
+            .last()
+            .flatten()
+            .expect("limit the frame rate");
+        assert!(slow > Duration::from_millis(33));
+
+        // Once the writes are fast again, it goes back to the full frame rate.
+        let recovered = (0..16)
+            .map(|_| write_rate.record(Duration::from_millis(5)))
+            .last()
+            .flatten();
+        assert_eq!(recovered, None);
+    }
+
+    #[test]
+    fn server_addresses() {
+        assert_eq!(server_address("darthfader.", "80"), "darthfader.:80");
+        assert_eq!(server_address("192.168.1.14", "7890"), "192.168.1.14:7890");
+        assert_eq!(server_address("fe80::1", "7890"), "[fe80::1]:7890");
+        assert_eq!(server_address("[::1]", "7890"), "[::1]:7890");
+    }
+
+    #[test]
+    fn encode_sysex() {
+        let commands = [
+            OpcSysex {
+                channel: 0,
+                system_id: 0x0001,
+                data: vec![0x00, 0x02, 0x03],
+            },
+            OpcSysex {
+                channel: 1,
+                system_id: 0xB0B,
+                data: Vec::new(),
+            },
+        ];
+        assert_eq!(
+            sysex_message(&commands[0]),
+            [0, 255, 0, 5, 0x00, 0x01, 0x00, 0x02, 0x03]
+        );
+        assert_eq!(
+            sysex_messages(&commands),
+            [0, 255, 0, 5, 0x00, 0x01, 0x00, 0x02, 0x03, 1, 255, 0, 2, 0x0B, 0x0B]
+        );
+        assert!(sysex_messages(&[]).is_empty());
+    }
+}
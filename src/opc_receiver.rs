@@ -0,0 +1,190 @@
+use std::{
+    io::{ErrorKind, Read, Result},
+    net::{Ipv4Addr, Shutdown, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::settings::OpcReceiverConfiguration;
+
+/// OPC command which sets the pixel colors on a channel.
+const OPC_SET_PIXEL_COLORS: u8 = 0;
+
+/// How often the listener checks if the [OpcReceiver] is stopping between connections.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The latest frame from any of the clients, and when it arrived.
+type LatestFrame = Arc<Mutex<Option<(Instant, Vec<u32>)>>>;
+
+/// A copy of the stream for each connected client, so the [OpcReceiver] can disconnect them,
+/// along with an ID which the client thread uses to remove it when it's done.
+type Clients = Arc<Mutex<Vec<(usize, TcpStream)>>>;
+
+/// Read the next OPC message from the `stream`. Returns the channel, the command, and the data.
+fn read_message(stream: &mut impl Read) -> Result<(u8, u8, Vec<u8>)> {
+    let mut header = [0_u8; 4];
+    stream.read_exact(&mut header)?;
+    let mut data = vec![0_u8; usize::from(u16::from_be_bytes([header[2], header[3]]))];
+    stream.read_exact(&mut data)?;
+    Ok((header[0], header[1], data))
+}
+
+/// Convert the RGB data from an OPC message into RGBA pixels. Any partial pixel at the end is
+/// ignored.
+fn decode_pixels(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(3)
+        .map(|rgb| {
+            (u32::from(rgb[0]) << 24) | (u32::from(rgb[1]) << 16) | (u32::from(rgb[2]) << 8) | 0xFF
+        })
+        .collect()
+}
+
+/// Test if a message for `channel` should be used with the configured `filter` channel.
+fn accept_channel(filter: u8, channel: u8) -> bool {
+    filter == 0 || channel == 0 || channel == filter
+}
+
+/// Listens for OPC clients on a TCP port, and keeps the latest frame which any of them sent
+/// for the worker thread to pick up. Each client gets its own thread, since there are rarely
+/// more than one or two of them.
+pub struct OpcReceiver {
+    latest: LatestFrame,
+    timeout: Duration,
+    stopped: Arc<AtomicBool>,
+    clients: Clients,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OpcReceiver {
+    /// Start listening on the port in the [OpcReceiverConfiguration]. Returns `None` if the
+    /// port is already in use.
+    pub fn start(configuration: &OpcReceiverConfiguration) -> Option<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, configuration.port)).ok()?;
+        listener.set_nonblocking(true).ok()?;
+
+        let latest = LatestFrame::default();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let clients = Clients::default();
+        let thread = {
+            let latest = latest.clone();
+            let stopped = stopped.clone();
+            let clients = clients.clone();
+            let channel = configuration.channel;
+            thread::spawn(move || listen(listener, channel, latest, stopped, clients))
+        };
+
+        Some(Self {
+            latest,
+            timeout: Duration::from_millis(u64::from(configuration.timeout)),
+            stopped,
+            clients,
+            thread: Some(thread),
+        })
+    }
+
+    /// Get the latest frame, unless it's older than the `timeout`.
+    pub fn latest(&self) -> Option<Vec<u32>> {
+        match &*self.latest.lock().expect("lock latest frame") {
+            Some((received, pixels)) if received.elapsed() < self.timeout => Some(pixels.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Accept connections on the `listener` until the [OpcReceiver] is `stopped`, and start a
+/// thread to read the frames from each one.
+fn listen(
+    listener: TcpListener,
+    channel: u8,
+    latest: LatestFrame,
+    stopped: Arc<AtomicBool>,
+    clients: Clients,
+) {
+    let mut next_id = 0_usize;
+    while !stopped.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let client = match stream
+                    .set_nonblocking(false)
+                    .and_then(|()| stream.try_clone())
+                {
+                    Ok(client) => client,
+                    Err(_) => continue,
+                };
+
+                let id = next_id;
+                next_id = next_id.wrapping_add(1);
+                clients.lock().expect("lock clients").push((id, client));
+
+                let latest = latest.clone();
+                let clients = clients.clone();
+                thread::spawn(move || {
+                    receive(stream, channel, latest);
+                    clients
+                        .lock()
+                        .expect("lock clients")
+                        .retain(|(client_id, _)| *client_id != id);
+                });
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_INTERVAL),
+            Err(_) => break,
+        }
+    }
+}
+
+/// Read messages from a client until it disconnects, and keep the pixels from each one which
+/// sets the colors on the `channel`.
+fn receive(mut stream: TcpStream, channel: u8, latest: LatestFrame) {
+    while let Ok((message_channel, command, data)) = read_message(&mut stream) {
+        if command == OPC_SET_PIXEL_COLORS && accept_channel(channel, message_channel) {
+            *latest.lock().expect("lock latest frame") =
+                Some((Instant::now(), decode_pixels(&data)));
+        }
+    }
+}
+
+impl Drop for OpcReceiver {
+    /// Stop the listener and disconnect all of the clients, which ends their threads.
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        for (_, client) in self.clients.lock().expect("lock clients").drain(..) {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_messages() {
+        let mut stream: &[u8] = &[
+            1, 0, 0, 7, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 2, 255, 0, 0,
+        ];
+        let (channel, command, data) = read_message(&mut stream).expect("read the first message");
+        assert_eq!((channel, command), (1, OPC_SET_PIXEL_COLORS));
+        assert_eq!(decode_pixels(&data), [0x102030FF, 0x405060FF]);
+
+        let (channel, command, data) = read_message(&mut stream).expect("read the second message");
+        assert_eq!((channel, command), (2, 255));
+        assert!(data.is_empty());
+        assert!(read_message(&mut stream).is_err());
+    }
+
+    #[test]
+    fn filter_channels() {
+        assert!(accept_channel(0, 3));
+        assert!(accept_channel(2, 0));
+        assert!(accept_channel(2, 2));
+        assert!(!accept_channel(2, 3));
+    }
+}
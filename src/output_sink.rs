@@ -0,0 +1,287 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    pixel_buffer::PixelBuffer, screen_samples::ScreenSamples, serial_port::SerialPort,
+    settings::ReconnectConfiguration,
+};
+
+/// Common interface for each kind of output which the [crate::update_timer::UpdateTimer]
+/// drives with the LED colors, so the worker thread can treat all of them the same way.
+pub trait OutputSink {
+    /// Try to open the output if it isn't open yet. Returns `true` if it's open.
+    fn open(&mut self) -> bool;
+
+    /// Try to open the output again after a failed send closed it, or after a device was
+    /// plugged in. Returns `true` if it's open. By default this calls [OutputSink::open].
+    fn reconnect(&mut self) -> bool {
+        self.open()
+    }
+
+    /// Render the [ScreenSamples] into a [PixelBuffer] for each LED strip or channel which the
+    /// output drives.
+    fn render(&self, samples: &ScreenSamples) -> Vec<PixelBuffer>;
+
+    /// Send the rendered `pixels` to the output. Returns `false` if it failed.
+    fn send(&mut self, pixels: Vec<PixelBuffer>) -> bool;
+
+    /// Close the output.
+    fn close(&mut self);
+
+    /// Test if the output is ready for the next frame.
+    fn healthy(&self) -> bool;
+
+    /// Get the shortest time between frames for the output, if it should get fewer frames than
+    /// the worker thread takes. This is checked before every frame, so it can change while the
+    /// output is running. By default it gets every frame.
+    fn frame_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Get the configured latency of the output, i.e. how long it takes for a frame to show up
+    /// on the LEDs after it's sent. By default it's 0.
+    fn latency(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Delay the frames for an [OutputSink] so it changes at the same time as the output with the
+/// highest [OutputSink::latency]. The frames are held in a queue until they're due, and each
+/// time a new frame is rendered, the newest one which is due is sent, so the delay is rounded
+/// up to the next frame.
+pub struct FrameDelay {
+    delay: Duration,
+    frames: VecDeque<(Instant, Vec<PixelBuffer>)>,
+}
+
+impl FrameDelay {
+    /// Allocate a new [FrameDelay] for the [OutputSink], which delays it by the difference
+    /// between its latency and the `max_latency` of all the outputs.
+    pub fn new(sink: &dyn OutputSink, max_latency: Duration) -> Self {
+        Self {
+            delay: max_latency.saturating_sub(sink.latency()),
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Queue the `pixels` rendered at `now`, and return the newest frame which is due, if any.
+    /// Older frames which are also due are dropped.
+    pub fn delay(&mut self, now: Instant, pixels: Vec<PixelBuffer>) -> Option<Vec<PixelBuffer>> {
+        if self.delay.is_zero() {
+            return Some(pixels);
+        }
+
+        self.frames.push_back((now + self.delay, pixels));
+        let mut due = None;
+        while self
+            .frames
+            .front()
+            .is_some_and(|(deadline, _)| *deadline <= now)
+        {
+            due = self.frames.pop_front().map(|(_, pixels)| pixels);
+        }
+        due
+    }
+}
+
+/// Decimate the frames for an [OutputSink] with a [OutputSink::frame_interval]. Each frame is
+/// due an `interval` after the last one was due, rather than after it was sent, so the output
+/// still averages the right frame rate when the frames don't line up exactly with it. A new
+/// [FrameLimiter] lets the first frame through.
+#[derive(Default)]
+pub struct FrameLimiter {
+    next: Option<Instant>,
+}
+
+impl FrameLimiter {
+    /// Test if the next frame is due at `now` with the current `interval` for the
+    /// [OutputSink], and if it is, schedule the one after it. If the output fell more than an
+    /// `interval` behind, start over from `now`.
+    pub fn ready(&mut self, now: Instant, interval: Option<Duration>) -> bool {
+        let interval = match interval {
+            Some(interval) => interval,
+            None => return true,
+        };
+
+        match self.next {
+            Some(next) if now < next => false,
+            Some(next) if now < next + interval => {
+                self.next = Some(next + interval);
+                true
+            }
+            _ => {
+                self.next = Some(now + interval);
+                true
+            }
+        }
+    }
+}
+
+/// Reopen an [OutputSink] which closed itself after a failed send, e.g. when a controller
+/// reboots, with the same [ReconnectConfiguration] backoff as a [SerialPort]. The first
+/// `retries` attempts happen with the next frames, then the delay starts at `delay`
+/// milliseconds and doubles each time, up to the `max_delay`.
+#[derive(Default)]
+pub struct ReconnectBackoff {
+    attempts: u32,
+    next: Option<Instant>,
+}
+
+impl ReconnectBackoff {
+    /// Test if the [OutputSink] is ready for the next frame at `now`. If it isn't, and it's
+    /// time for the next attempt, try to [OutputSink::reconnect] it.
+    pub fn ready(
+        &mut self,
+        sink: &mut dyn OutputSink,
+        now: Instant,
+        reconnect: &ReconnectConfiguration,
+        max_delay: u32,
+    ) -> bool {
+        if sink.healthy() {
+            self.attempts = 0;
+            self.next = None;
+            return true;
+        }
+
+        if self.next.is_some_and(|next| now < next) {
+            return false;
+        }
+
+        if sink.reconnect() {
+            self.attempts = 0;
+            self.next = None;
+            return true;
+        }
+
+        self.attempts = self.attempts.saturating_add(1);
+        let delay =
+            SerialPort::backoff_delay(self.attempts, reconnect.retries, reconnect.delay, max_delay);
+        self.next = Some(now + Duration::from_millis(u64::from(delay)));
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// An [OutputSink] which fails to send or reopen while the controller is `down`.
+    #[derive(Default)]
+    struct FlakySink {
+        open: bool,
+        down: bool,
+        opened: u32,
+    }
+
+    impl OutputSink for FlakySink {
+        fn open(&mut self) -> bool {
+            if !self.down {
+                self.open = true;
+                self.opened += 1;
+            }
+            self.open
+        }
+
+        fn render(&self, _samples: &ScreenSamples) -> Vec<PixelBuffer> {
+            Vec::new()
+        }
+
+        fn send(&mut self, _pixels: Vec<PixelBuffer>) -> bool {
+            if self.down {
+                self.close();
+            }
+            self.open
+        }
+
+        fn close(&mut self) {
+            self.open = false;
+        }
+
+        fn healthy(&self) -> bool {
+            self.open
+        }
+    }
+
+    #[test]
+    fn recover_after_failed_send() {
+        const RECONNECT: ReconnectConfiguration = ReconnectConfiguration {
+            retries: 1,
+            delay: 50,
+        };
+        let start = Instant::now();
+        let at = |millis: u64| start + Duration::from_millis(millis);
+        let mut sink = FlakySink::default();
+        let mut backoff = ReconnectBackoff::default();
+        assert!(sink.open());
+        assert!(backoff.ready(&mut sink, at(0), &RECONNECT, 1000));
+
+        sink.down = true;
+        assert!(!sink.send(Vec::new()));
+        assert!(!backoff.ready(&mut sink, at(10), &RECONNECT, 1000));
+        assert!(!backoff.ready(&mut sink, at(20), &RECONNECT, 1000));
+
+        // Wait for the backoff before trying again, even after it comes back up.
+        sink.down = false;
+        assert!(!backoff.ready(&mut sink, at(40), &RECONNECT, 1000));
+        assert_eq!(sink.opened, 1);
+        assert!(backoff.ready(&mut sink, at(130), &RECONNECT, 1000));
+        assert_eq!(sink.opened, 2);
+        assert!(sink.send(Vec::new()));
+    }
+
+    #[test]
+    fn decimate_frames() {
+        let start = Instant::now();
+        let frame = |index: u64| start + Duration::from_micros(index * 16_667);
+        let interval = Some(Duration::from_millis(50));
+        let mut limiter = FrameLimiter::default();
+        let sent = (0..60)
+            .filter(|index| limiter.ready(frame(*index), interval))
+            .count();
+        assert_eq!(sent, 20);
+
+        let mut limiter = FrameLimiter::default();
+        assert!((0..60).all(|index| limiter.ready(frame(index), None)));
+    }
+
+    #[test]
+    fn delay_frames() {
+        let start = Instant::now();
+        let frame = |index: u64| start + Duration::from_millis(index * 20);
+        let pixels = |index: u8| vec![PixelBuffer::new_image_buffer(vec![index], 0, 0)];
+        let sent = |frame: Option<Vec<PixelBuffer>>| frame.map(|frame| frame[0].data()[0]);
+        let mut delay = FrameDelay {
+            delay: Duration::from_millis(50),
+            frames: VecDeque::new(),
+        };
+        assert_eq!(sent(delay.delay(frame(0), pixels(0))), None);
+        assert_eq!(sent(delay.delay(frame(1), pixels(1))), None);
+        assert_eq!(sent(delay.delay(frame(2), pixels(2))), None);
+        assert_eq!(sent(delay.delay(frame(3), pixels(3))), Some(0));
+        assert_eq!(sent(delay.delay(frame(4), pixels(4))), Some(1));
+
+        // After a stall, only the newest frame which is due gets sent.
+        assert_eq!(sent(delay.delay(frame(10), pixels(10))), Some(4));
+        assert_eq!(delay.frames.len(), 1);
+
+        let mut delay = FrameDelay {
+            delay: Duration::ZERO,
+            frames: VecDeque::new(),
+        };
+        assert_eq!(sent(delay.delay(frame(0), pixels(0))), Some(0));
+    }
+
+    #[test]
+    fn fall_behind() {
+        let start = Instant::now();
+        let interval = Some(Duration::from_millis(50));
+        let mut limiter = FrameLimiter::default();
+        assert!(limiter.ready(start, interval));
+        assert!(!limiter.ready(start + Duration::from_millis(49), interval));
+        assert!(limiter.ready(start + Duration::from_millis(500), interval));
+        assert!(!limiter.ready(start + Duration::from_millis(520), interval));
+        assert!(limiter.ready(start + Duration::from_millis(550), interval));
+    }
+}
@@ -1,48 +1,395 @@
-use crate::settings::{OpcChannel, Settings};
+use crate::settings::{
+    ByteOrder, HeaderLedCount, OpcChannel, PixelFormat, RawConfiguration, SerialChecksum,
+    SerialOutput, WhitePoint, WledProtocol,
+};
 
 /// Each message uses the same header every time it is sent.
 struct Header(Vec<u8>);
 
-/// Representation of a fixed size message buffer for either a [crate::serial_port::SerialPort]
-/// or [crate::opc_pool::OpcPool].
+/// Size of the E1.31 (sACN) root, framing, and DMP layers, including the DMX start code.
+const SACN_HEADER_SIZE: usize = 126;
+
+/// Offset of the sequence number in the E1.31 (sACN) framing layer.
+const SACN_SEQUENCE_OFFSET: usize = 111;
+
+/// Component identifier (a UUID) which identifies us as the source of the E1.31 (sACN) packets.
+const SACN_CID: [u8; 16] = [
+    0x5c, 0x2a, 0x3f, 0x0e, 0x8b, 0x71, 0x4d, 0x2c, 0x9a, 0x06, 0x41, 0xd3, 0x7e, 0x58, 0xb1, 0x94,
+];
+
+/// Source name which receivers show for the E1.31 (sACN) packets.
+const SACN_SOURCE_NAME: &[u8] = b"AdaLight";
+
+/// Size of the HueStream header in front of the lights in a Hue Entertainment API message.
+#[cfg(feature = "hue")]
+const HUE_HEADER_SIZE: usize = 16;
+
+/// Size of each light in a Hue Entertainment API message: the device type, the light ID, and
+/// 16 bits for each color channel.
+#[cfg(feature = "hue")]
+const HUE_LIGHT_SIZE: usize = 9;
+
+/// Size of each panel in a Nanoleaf external control (version 2) message: the panel ID, the
+/// RGBW channels, and the transition time.
+const NANOLEAF_PANEL_SIZE: usize = 8;
+
+/// Optional byte in front of the color channels of each LED.
+#[derive(Clone, Copy)]
+enum LedPrefix {
+    None,
+
+    /// The same byte for every LED, e.g. the APA102 global brightness.
+    Fixed(u8),
+
+    /// The index of the LED in the [PixelBuffer], e.g. for the WLED WARLS protocol.
+    Index,
+}
+
+/// Representation of a fixed size message buffer for a [crate::serial_port::SerialPort],
+/// [crate::opc_pool::OpcConnection], [crate::wled_udp::WledConnection],
+/// [crate::sacn::SacnSender], [crate::hyperion::HyperionConnection],
+/// [crate::hue::HueConnection], [crate::nanoleaf::NanoleafConnection],
+/// [crate::chroma::ChromaConnection], or [crate::udp_output::UdpConnection].
 pub struct PixelBuffer {
     pub buffer: Vec<u8>,
     alpha_channel: bool,
+    white_channel: bool,
+    white_point: Option<WhitePoint>,
     offset: Header,
     position: usize,
+    checksum: SerialChecksum,
+    byte_order: ByteOrder,
+    led_prefix: LedPrefix,
+    footer_len: usize,
 }
 
 impl PixelBuffer {
-    /// Allocate a new [PixelBuffer] for the Arduino listening on a [crate::serial_port::SerialPort].
-    pub fn new_serial_buffer(settings: &Settings) -> Self {
-        let led_count = (settings.get_total_led_count() - 1) as u16;
-        let led_count_high = ((led_count & 0xFF00) >> 8) as u8;
-        let led_count_low = (led_count & 0xFF) as u8;
-        let led_count_checksum = led_count_high ^ led_count_low ^ 0x55;
-        let offset = Header(vec![
-            b'A',
-            b'd',
-            b'a',
-            led_count_high,
-            led_count_low,
-            led_count_checksum,
-        ]);
+    /// Allocate a new [PixelBuffer] for the Arduino listening on a [crate::serial_port::SerialPort]
+    /// which drives the LEDs in a [SerialOutput].
+    pub fn new_serial_buffer(output: &SerialOutput) -> Self {
+        if let Some(raw) = output.raw.as_ref() {
+            return Self::new_raw_buffer(raw, output.led_count);
+        }
+
+        let led_count = output.led_count.saturating_sub(1);
+        let led_count_bytes = match output.header.led_count {
+            HeaderLedCount::Byte => vec![(led_count & 0xFF) as u8],
+            HeaderLedCount::Word => {
+                vec![((led_count & 0xFF00) >> 8) as u8, (led_count & 0xFF) as u8]
+            }
+            HeaderLedCount::Extended => vec![
+                ((led_count & 0xFF0000) >> 16) as u8,
+                ((led_count & 0xFF00) >> 8) as u8,
+                (led_count & 0xFF) as u8,
+            ],
+        };
+        let led_count_checksum = led_count_bytes
+            .iter()
+            .fold(0x55_u8, |checksum, byte| checksum ^ byte);
+        let mut offset = Header(output.header.magic.as_bytes().to_vec());
+        offset.0.extend_from_slice(&led_count_bytes);
+        offset.0.push(led_count_checksum);
         let position = offset.0.len();
-        let buffer_size = position + (3 * settings.get_total_led_count());
+        let buffer_size = position + (3 * output.led_count) + Self::checksum_len(output.checksum);
         let mut buffer = Vec::new();
         buffer.reserve_exact(buffer_size);
         buffer.extend_from_slice(&offset.0);
         buffer.resize(buffer_size, 0_u8);
 
+        let mut pixel_buffer = Self {
+            buffer,
+            alpha_channel: false,
+            white_channel: false,
+            white_point: None,
+            offset,
+            position,
+            checksum: output.checksum,
+            byte_order: ByteOrder::Rgb,
+            led_prefix: LedPrefix::None,
+            footer_len: 0,
+        };
+        pixel_buffer.finish();
+        pixel_buffer
+    }
+
+    /// Allocate a new [PixelBuffer] with the raw frame format in a [RawConfiguration] for
+    /// `led_count` LEDs, e.g. for a serial-to-SPI bridge.
+    fn new_raw_buffer(raw: &RawConfiguration, led_count: usize) -> Self {
+        let offset = Header(raw.header.clone());
+        let position = offset.0.len();
+        let led_size = 3 + usize::from(raw.led_prefix.is_some());
+        let buffer_size = position + (led_size * led_count) + raw.footer.len();
+        let mut buffer = Vec::new();
+        buffer.reserve_exact(buffer_size);
+        buffer.extend_from_slice(&offset.0);
+        buffer.resize(buffer_size - raw.footer.len(), 0_u8);
+        buffer.extend_from_slice(&raw.footer);
+
+        let mut pixel_buffer = Self {
+            buffer,
+            alpha_channel: false,
+            white_channel: false,
+            white_point: None,
+            offset,
+            position,
+            checksum: SerialChecksum::None,
+            byte_order: raw.byte_order,
+            led_prefix: raw.led_prefix.map_or(LedPrefix::None, LedPrefix::Fixed),
+            footer_len: raw.footer.len(),
+        };
+        pixel_buffer.clear();
+        pixel_buffer
+    }
+
+    /// Allocate a new [PixelBuffer] for one packet of a WLED UDP realtime `protocol`, with
+    /// `led_count` LEDs starting at the `start` index. WLED waits `timeout` seconds after the
+    /// last packet before it goes back to its own effects.
+    pub fn new_wled_buffer(
+        protocol: WledProtocol,
+        timeout: u8,
+        start: usize,
+        led_count: usize,
+    ) -> Self {
+        let (offset, led_prefix) = match protocol {
+            WledProtocol::Warls => (Header(vec![1, timeout]), LedPrefix::Index),
+            WledProtocol::Drgb => (Header(vec![2, timeout]), LedPrefix::None),
+            WledProtocol::Dnrgb => (
+                Header(vec![
+                    4,
+                    timeout,
+                    ((start & 0xFF00) >> 8) as u8,
+                    (start & 0xFF) as u8,
+                ]),
+                LedPrefix::None,
+            ),
+        };
+        let position = offset.0.len();
+        let led_size = 3 + usize::from(matches!(led_prefix, LedPrefix::Index));
+        let buffer_size = position + (led_size * led_count);
+        let mut buffer = Vec::new();
+        buffer.reserve_exact(buffer_size);
+        buffer.extend_from_slice(&offset.0);
+        buffer.resize(buffer_size, 0_u8);
+
+        let mut pixel_buffer = Self {
+            buffer,
+            alpha_channel: false,
+            white_channel: false,
+            white_point: None,
+            offset,
+            position,
+            checksum: SerialChecksum::None,
+            byte_order: ByteOrder::Rgb,
+            led_prefix,
+            footer_len: 0,
+        };
+        pixel_buffer.clear();
+        pixel_buffer
+    }
+
+    /// Allocate a new [PixelBuffer] for one packet of a [crate::udp_output::UdpConnection],
+    /// with the fixed `header` followed by `led_count` LEDs in the [PixelFormat].
+    pub fn new_udp_buffer(header: &[u8], pixel_format: PixelFormat, led_count: usize) -> Self {
+        let offset = Header(header.to_vec());
+        let position = offset.0.len();
+        let white_channel = pixel_format == PixelFormat::Rgbw;
+        let led_size = 3 + usize::from(white_channel);
+        let buffer_size = position + (led_size * led_count);
+        let mut buffer = Vec::new();
+        buffer.reserve_exact(buffer_size);
+        buffer.extend_from_slice(&offset.0);
+        buffer.resize(buffer_size, 0_u8);
+
+        Self {
+            buffer,
+            alpha_channel: false,
+            white_channel,
+            white_point: None,
+            offset,
+            position,
+            checksum: SerialChecksum::None,
+            byte_order: ByteOrder::Rgb,
+            led_prefix: LedPrefix::None,
+            footer_len: 0,
+        }
+    }
+
+    /// Allocate a new [PixelBuffer] for an E1.31 (sACN) data packet with `led_count` LEDs on
+    /// the DMX `universe`. The sequence number starts at 0, see [PixelBuffer::set_sacn_sequence].
+    pub fn new_sacn_buffer(universe: u16, priority: u8, led_count: usize) -> Self {
+        let data_size = 3 * led_count;
+        let packet_size = SACN_HEADER_SIZE + data_size;
+        let flags_length = |offset: usize| (0x7000 | (packet_size - offset) as u16).to_be_bytes();
+
+        // Root layer
+        let mut header = vec![0x00, 0x10, 0x00, 0x00];
+        header.extend_from_slice(b"ASC-E1.17\0\0\0");
+        header.extend_from_slice(&flags_length(16));
+        header.extend_from_slice(&0x0000_0004_u32.to_be_bytes());
+        header.extend_from_slice(&SACN_CID);
+
+        // Framing layer
+        header.extend_from_slice(&flags_length(38));
+        header.extend_from_slice(&0x0000_0002_u32.to_be_bytes());
+        let mut source_name = [0_u8; 64];
+        source_name[..SACN_SOURCE_NAME.len()].copy_from_slice(SACN_SOURCE_NAME);
+        header.extend_from_slice(&source_name);
+        header.push(priority);
+        header.extend_from_slice(&[0x00, 0x00]); // synchronization address
+        header.push(0); // sequence number
+        header.push(0); // options
+        header.extend_from_slice(&universe.to_be_bytes());
+
+        // DMP layer
+        header.extend_from_slice(&flags_length(115));
+        header.extend_from_slice(&[0x02, 0xA1, 0x00, 0x00, 0x00, 0x01]);
+        header.extend_from_slice(&(1 + data_size as u16).to_be_bytes());
+        header.push(0); // DMX start code
+
+        let offset = Header(header);
+        let position = offset.0.len();
+        let mut buffer = Vec::new();
+        buffer.reserve_exact(packet_size);
+        buffer.extend_from_slice(&offset.0);
+        buffer.resize(packet_size, 0_u8);
+
+        Self {
+            buffer,
+            alpha_channel: false,
+            white_channel: false,
+            white_point: None,
+            offset,
+            position,
+            checksum: SerialChecksum::None,
+            byte_order: ByteOrder::Rgb,
+            led_prefix: LedPrefix::None,
+            footer_len: 0,
+        }
+    }
+
+    /// Set the sequence number in an E1.31 (sACN) data packet from
+    /// [PixelBuffer::new_sacn_buffer], which receivers use to drop packets arriving out of order.
+    pub fn set_sacn_sequence(&mut self, sequence: u8) {
+        self.buffer[SACN_SEQUENCE_OFFSET] = sequence;
+    }
+
+    /// Allocate a new [PixelBuffer] with `pixel_count` RGB pixels between a pre-encoded
+    /// `header` and `padding` bytes at the end, e.g. for an image message to a
+    /// [crate::hyperion::HyperionConnection].
+    pub fn new_image_buffer(header: Vec<u8>, pixel_count: usize, padding: usize) -> Self {
+        let offset = Header(header);
+        let position = offset.0.len();
+        let buffer_size = position + (3 * pixel_count) + padding;
+        let mut buffer = Vec::new();
+        buffer.reserve_exact(buffer_size);
+        buffer.extend_from_slice(&offset.0);
+        buffer.resize(buffer_size, 0_u8);
+
+        Self {
+            buffer,
+            alpha_channel: false,
+            white_channel: false,
+            white_point: None,
+            offset,
+            position,
+            checksum: SerialChecksum::None,
+            byte_order: ByteOrder::Rgb,
+            led_prefix: LedPrefix::None,
+            footer_len: padding,
+        }
+    }
+
+    /// Allocate a new [PixelBuffer] for a Hue Entertainment API (HueStream version 1) message
+    /// with RGB colors for each of the `light_ids`, see [PixelBuffer::set_hue_color].
+    #[cfg(feature = "hue")]
+    pub fn new_hue_buffer(light_ids: &[u16]) -> Self {
+        let mut header = b"HueStream".to_vec();
+        header.extend_from_slice(&[0x01, 0x00]); // version 1.0
+        header.push(0); // sequence number, which the bridge ignores
+        header.extend_from_slice(&[0x00, 0x00]);
+        header.push(0x00); // RGB color space
+        header.push(0x00);
+
+        let offset = Header(header);
+        let position = offset.0.len();
+        let mut buffer = Vec::new();
+        buffer.reserve_exact(HUE_HEADER_SIZE + (HUE_LIGHT_SIZE * light_ids.len()));
+        buffer.extend_from_slice(&offset.0);
+        for light_id in light_ids {
+            buffer.push(0x00); // light
+            buffer.extend_from_slice(&light_id.to_be_bytes());
+            buffer.extend_from_slice(&[0; 6]);
+        }
+
         Self {
             buffer,
             alpha_channel: false,
+            white_channel: false,
+            white_point: None,
             offset,
             position,
+            checksum: SerialChecksum::None,
+            byte_order: ByteOrder::Rgb,
+            led_prefix: LedPrefix::None,
+            footer_len: 0,
         }
     }
 
-    /// Allocate a new [PixelBuffer] to send to an [crate::opc_pool::OpcPool] which
+    /// Set the color of the light at `index` in a Hue Entertainment API message from
+    /// [PixelBuffer::new_hue_buffer], scaling each channel up to 16 bits.
+    #[cfg(feature = "hue")]
+    pub fn set_hue_color(&mut self, index: usize, rgba_pixel: u32) {
+        let start = HUE_HEADER_SIZE + (HUE_LIGHT_SIZE * index) + 3;
+        let channels = [
+            ((rgba_pixel & 0xFF000000) >> 24) as u16,
+            ((rgba_pixel & 0xFF0000) >> 16) as u16,
+            ((rgba_pixel & 0xFF00) >> 8) as u16,
+        ];
+        for (channel, value) in channels.into_iter().enumerate() {
+            let offset = start + (2 * channel);
+            self.buffer[offset..offset + 2].copy_from_slice(&(value * 257).to_be_bytes());
+        }
+    }
+
+    /// Allocate a new [PixelBuffer] for a Nanoleaf external control (version 2) message with
+    /// the `transition` time (in tenths of a second) for each of the `panel_ids`, see
+    /// [PixelBuffer::set_nanoleaf_color].
+    pub fn new_nanoleaf_buffer(panel_ids: &[u16], transition: u16) -> Self {
+        let offset = Header((panel_ids.len() as u16).to_be_bytes().to_vec());
+        let position = offset.0.len();
+        let mut buffer = Vec::new();
+        buffer.reserve_exact(position + (NANOLEAF_PANEL_SIZE * panel_ids.len()));
+        buffer.extend_from_slice(&offset.0);
+        for panel_id in panel_ids {
+            buffer.extend_from_slice(&panel_id.to_be_bytes());
+            buffer.extend_from_slice(&[0; 4]);
+            buffer.extend_from_slice(&transition.to_be_bytes());
+        }
+
+        Self {
+            buffer,
+            alpha_channel: false,
+            white_channel: false,
+            white_point: None,
+            offset,
+            position,
+            checksum: SerialChecksum::None,
+            byte_order: ByteOrder::Rgb,
+            led_prefix: LedPrefix::None,
+            footer_len: 0,
+        }
+    }
+
+    /// Set the color of the panel at `index` in a Nanoleaf external control message from
+    /// [PixelBuffer::new_nanoleaf_buffer]. The white channel is unused.
+    pub fn set_nanoleaf_color(&mut self, index: usize, rgba_pixel: u32) {
+        let start = self.offset.0.len() + (NANOLEAF_PANEL_SIZE * index) + 2;
+        self.buffer[start] = ((rgba_pixel & 0xFF000000) >> 24) as u8;
+        self.buffer[start + 1] = ((rgba_pixel & 0xFF0000) >> 16) as u8;
+        self.buffer[start + 2] = ((rgba_pixel & 0xFF00) >> 8) as u8;
+    }
+
+    /// Allocate a new [PixelBuffer] to send to an [crate::opc_pool::OpcConnection] which
     /// implements the standard OPC protocol and does not support the `alphaChannel`.
     pub fn new_opc_buffer(opc_channel: &OpcChannel) -> Self {
         let channel = opc_channel.channel;
@@ -61,12 +408,18 @@ impl PixelBuffer {
         Self {
             buffer,
             alpha_channel: false,
+            white_channel: false,
+            white_point: None,
             offset,
             position,
+            checksum: SerialChecksum::None,
+            byte_order: ByteOrder::Rgb,
+            led_prefix: LedPrefix::None,
+            footer_len: 0,
         }
     }
 
-    /// Allocate a new [PixelBuffer] to send to an [crate::opc_pool::OpcPool] which
+    /// Allocate a new [PixelBuffer] to send to an [crate::opc_pool::OpcConnection] which
     /// implements the `BobLight` OPC protocol extension and supports the `alphaChannel`.
     /// # WARNING
     /// This has not been tested against any real server implementations.
@@ -97,19 +450,76 @@ impl PixelBuffer {
         Self {
             buffer,
             alpha_channel: true,
+            white_channel: false,
+            white_point: None,
             offset,
             position,
+            checksum: SerialChecksum::None,
+            byte_order: ByteOrder::Rgb,
+            led_prefix: LedPrefix::None,
+            footer_len: 0,
         }
     }
 
+    /// Scale the channels of every pixel which is added after this by the [WhitePoint] gains
+    /// for the output.
+    pub fn set_white_point(&mut self, white_point: WhitePoint) {
+        self.white_point = Some(white_point);
+    }
+
     /// Add an RGBA pixel to the [PixelBuffer].
     pub fn add(&mut self, rgba_pixel: u32) {
-        self.buffer[self.position] = ((rgba_pixel & 0xFF000000) >> 24) as u8;
-        self.position += 1;
-        self.buffer[self.position] = ((rgba_pixel & 0xFF0000) >> 16) as u8;
-        self.position += 1;
-        self.buffer[self.position] = ((rgba_pixel & 0xFF00) >> 8) as u8;
-        self.position += 1;
+        match self.led_prefix {
+            LedPrefix::None => (),
+            LedPrefix::Fixed(led_prefix) => {
+                self.buffer[self.position] = led_prefix;
+                self.position += 1;
+            }
+            LedPrefix::Index => {
+                let led_size = 4 + self.extra_channels();
+                self.buffer[self.position] =
+                    ((self.position - self.offset.0.len()) / led_size) as u8;
+                self.position += 1;
+            }
+        }
+
+        let (mut r, mut g, mut b) = (
+            ((rgba_pixel & 0xFF000000) >> 24) as u8,
+            ((rgba_pixel & 0xFF0000) >> 16) as u8,
+            ((rgba_pixel & 0xFF00) >> 8) as u8,
+        );
+        if let Some(white_point) = self.white_point.as_ref() {
+            let scale = |channel: u8, gain: f64| (f64::from(channel) * gain).round() as u8;
+            r = scale(r, white_point.red);
+            g = scale(g, white_point.green);
+            b = scale(b, white_point.blue);
+        }
+        let w = if self.white_channel {
+            let w = r.min(g).min(b);
+            r -= w;
+            g -= w;
+            b -= w;
+            Some(w)
+        } else {
+            None
+        };
+        let channels = match self.byte_order {
+            ByteOrder::Rgb => [r, g, b],
+            ByteOrder::Rbg => [r, b, g],
+            ByteOrder::Grb => [g, r, b],
+            ByteOrder::Gbr => [g, b, r],
+            ByteOrder::Brg => [b, r, g],
+            ByteOrder::Bgr => [b, g, r],
+        };
+        for channel in channels {
+            self.buffer[self.position] = channel;
+            self.position += 1;
+        }
+
+        if let Some(w) = w {
+            self.buffer[self.position] = w;
+            self.position += 1;
+        }
 
         if self.alpha_channel {
             self.buffer[self.position] = (rgba_pixel & 0xFF) as u8;
@@ -117,19 +527,392 @@ impl PixelBuffer {
         }
     }
 
-    /// Reset the buffer position to the start of the pixel data in the [PixelBuffer].
+    /// Reset the buffer position to the start of the pixel data in the [PixelBuffer], and set
+    /// all of the pixels to black.
     pub fn clear(&mut self) {
-        let buffer_size = self.buffer.len();
-        if buffer_size > self.offset.0.len() {
-            self.position = self.offset.0.len();
-            self.buffer.resize(self.offset.0.len(), 0_u8);
-            self.buffer.resize(buffer_size, 0_u8);
+        let start = self.offset.0.len();
+        let end = self.buffer.len() - self.trailer_len();
+        if end > start {
+            self.position = start;
+            while self.position < end {
+                self.add(0);
+            }
+            self.position = start;
+            self.finish();
+        }
+    }
+
+    /// Scale the pixel data from another copy of this [PixelBuffer] by `level` (out of 255)
+    /// and write the [SerialChecksum], e.g. to fade the last frame out to black.
+    pub fn fade(&mut self, from: &[u8], level: u8) {
+        let start = self.offset.0.len();
+        let end = self.buffer.len() - self.trailer_len();
+        let led_prefix_len = self.led_prefix_len();
+        let led_size = 3 + self.extra_channels() + led_prefix_len;
+        for (pixel, from) in self.buffer[start..end]
+            .chunks_mut(led_size)
+            .zip(from[start..end].chunks(led_size))
+        {
+            // Leave the LED prefix alone, e.g. the APA102 global brightness.
+            for (channel, from) in pixel.iter_mut().zip(from.iter()).skip(led_prefix_len) {
+                *channel = ((u16::from(*from) * u16::from(level)) / 255) as u8;
+            }
+        }
+
+        self.position = start;
+        self.finish();
+    }
+
+    /// Write the [SerialChecksum] of the pixel data at the end of the [PixelBuffer], once all
+    /// of the pixels have been added.
+    pub fn finish(&mut self) {
+        let checksum_len = Self::checksum_len(self.checksum);
+        if checksum_len == 0 {
+            return;
+        }
+
+        let end = self.buffer.len() - checksum_len;
+        let pixels = &self.buffer[self.offset.0.len()..end];
+        match self.checksum {
+            SerialChecksum::None => (),
+            SerialChecksum::Crc8 => self.buffer[end] = Self::crc8(pixels),
+            SerialChecksum::Fletcher16 => {
+                let checksum = Self::fletcher16(pixels);
+                self.buffer[end] = ((checksum & 0xFF00) >> 8) as u8;
+                self.buffer[end + 1] = (checksum & 0xFF) as u8;
+            }
         }
     }
 
+    /// Get the number of bytes the [SerialChecksum] and any raw footer take up at the end of
+    /// the buffer.
+    fn trailer_len(&self) -> usize {
+        Self::checksum_len(self.checksum) + self.footer_len
+    }
+
+    /// Get the number of white and alpha channels after the RGB channels of each LED.
+    fn extra_channels(&self) -> usize {
+        usize::from(self.white_channel) + usize::from(self.alpha_channel)
+    }
+
+    /// Get the number of bytes before the color channels of each LED.
+    fn led_prefix_len(&self) -> usize {
+        match self.led_prefix {
+            LedPrefix::None => 0,
+            LedPrefix::Fixed(_) | LedPrefix::Index => 1,
+        }
+    }
+
+    /// Get the number of bytes the [SerialChecksum] takes up at the end of the buffer.
+    fn checksum_len(checksum: SerialChecksum) -> usize {
+        match checksum {
+            SerialChecksum::None => 0,
+            SerialChecksum::Crc8 => 1,
+            SerialChecksum::Fletcher16 => 2,
+        }
+    }
+
+    /// Calculate the CRC-8 of `data` with polynomial `0x07` and an initial value of 0.
+    fn crc8(data: &[u8]) -> u8 {
+        data.iter().fold(0_u8, |crc, byte| {
+            (0..8).fold(crc ^ byte, |crc, _| {
+                if crc & 0x80 != 0 {
+                    (crc << 1) ^ 0x07
+                } else {
+                    crc << 1
+                }
+            })
+        })
+    }
+
+    /// Calculate the Fletcher-16 checksum of `data`.
+    fn fletcher16(data: &[u8]) -> u16 {
+        let (sum1, sum2) = data.iter().fold((0_u16, 0_u16), |(sum1, sum2), byte| {
+            let sum1 = (sum1 + u16::from(*byte)) % 255;
+            (sum1, (sum2 + sum1) % 255)
+        });
+        (sum2 << 8) | sum1
+    }
+
     /// Get a [u8] slice for the full [PixelBuffer] buffer, including the [Header] at
     /// the beginning.
     pub fn data(&self) -> &[u8] {
         &self.buffer
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::settings::{BusyPolicy, SerialHeader};
+
+    /// Create a [SerialOutput] for `led_count` LEDs with the default settings, which each test
+    /// overrides with the fields it checks.
+    fn serial_output(led_count: usize) -> SerialOutput {
+        SerialOutput {
+            first_led: 0,
+            led_count,
+            port: None,
+            device_path: None,
+            wled: None,
+            flow_control: false,
+            checksum: SerialChecksum::None,
+            dtr: None,
+            rts: None,
+            busy_policy: BusyPolicy::Drop,
+            header: SerialHeader::default(),
+            read_timeout: None,
+            write_timeout: None,
+            latency_timer: None,
+            raw: None,
+            baud_rates: Vec::new(),
+            white_point: None,
+        }
+    }
+
+    #[test]
+    fn crc8_checksum() {
+        assert_eq!(PixelBuffer::crc8(b"123456789"), 0xF4);
+    }
+
+    #[test]
+    fn fletcher16_checksum() {
+        assert_eq!(PixelBuffer::fletcher16(b"abcde"), 0xC8F0);
+        assert_eq!(PixelBuffer::fletcher16(b"abcdef"), 0x2057);
+    }
+
+    #[test]
+    fn serial_checksum() {
+        let output = SerialOutput {
+            checksum: SerialChecksum::Crc8,
+            ..serial_output(2)
+        };
+        let mut pixels = PixelBuffer::new_serial_buffer(&output);
+        assert_eq!(pixels.data().len(), 6 + 6 + 1);
+        assert_eq!(pixels.data()[12], 0);
+
+        pixels.add(0x31323300);
+        pixels.add(0x34353600);
+        pixels.finish();
+        assert_eq!(pixels.data()[12], PixelBuffer::crc8(b"123456"));
+
+        pixels.clear();
+        assert_eq!(pixels.data()[12], 0);
+    }
+
+    #[test]
+    fn serial_header_variants() {
+        let mut output = serial_output(300);
+        let pixels = PixelBuffer::new_serial_buffer(&output);
+        assert_eq!(
+            pixels.data()[..6],
+            [b'A', b'd', b'a', 0x01, 0x2B, 0x01 ^ 0x2B ^ 0x55]
+        );
+        assert_eq!(pixels.data().len(), 6 + 900);
+
+        output.header = SerialHeader {
+            magic: String::from("Awa"),
+            led_count: HeaderLedCount::Extended,
+        };
+        output.led_count = 70000;
+        let pixels = PixelBuffer::new_serial_buffer(&output);
+        assert_eq!(
+            pixels.data()[..7],
+            [
+                b'A',
+                b'w',
+                b'a',
+                0x01,
+                0x11,
+                0x6F,
+                0x01 ^ 0x11 ^ 0x6F ^ 0x55
+            ]
+        );
+        assert_eq!(pixels.data().len(), 7 + 210000);
+
+        output.header.led_count = HeaderLedCount::Byte;
+        output.led_count = 100;
+        let pixels = PixelBuffer::new_serial_buffer(&output);
+        assert_eq!(pixels.data()[..5], [b'A', b'w', b'a', 99, 99 ^ 0x55]);
+    }
+
+    #[test]
+    fn fade_serial_buffer() {
+        let output = SerialOutput {
+            checksum: SerialChecksum::Crc8,
+            ..serial_output(2)
+        };
+        let mut pixels = PixelBuffer::new_serial_buffer(&output);
+        pixels.add(0xFF804000);
+        pixels.add(0x01020300);
+        pixels.finish();
+        let from = pixels.data().to_vec();
+
+        pixels.fade(&from, 128);
+        assert_eq!(pixels.data()[..6], from[..6]);
+        assert_eq!(pixels.data()[6..12], [128, 64, 32, 0, 1, 1]);
+        assert_eq!(
+            pixels.data()[12],
+            PixelBuffer::crc8(&[128, 64, 32, 0, 1, 1])
+        );
+
+        pixels.fade(&from, 0);
+        assert_eq!(pixels.data()[6..12], [0; 6]);
+        assert_eq!(pixels.data()[12], 0);
+    }
+
+    #[test]
+    fn raw_serial_buffer() {
+        let output = SerialOutput {
+            checksum: SerialChecksum::Crc8,
+            raw: Some(RawConfiguration {
+                header: vec![0, 0, 0, 0],
+                byte_order: ByteOrder::Bgr,
+                led_prefix: Some(0xFF),
+                footer: vec![0xFF, 0xFF],
+            }),
+            ..serial_output(2)
+        };
+        let mut pixels = PixelBuffer::new_serial_buffer(&output);
+        assert_eq!(
+            pixels.data(),
+            [0, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0xFF]
+        );
+
+        pixels.add(0x10203000);
+        pixels.add(0x40506000);
+        pixels.finish();
+        assert_eq!(
+            pixels.data()[4..12],
+            [0xFF, 0x30, 0x20, 0x10, 0xFF, 0x60, 0x50, 0x40]
+        );
+        assert_eq!(pixels.data()[12..], [0xFF, 0xFF]);
+
+        let from = pixels.data().to_vec();
+        pixels.fade(&from, 0);
+        assert_eq!(pixels.data()[4..12], [0xFF, 0, 0, 0, 0xFF, 0, 0, 0]);
+
+        pixels.clear();
+        assert_eq!(
+            pixels.data(),
+            [0, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn wled_buffer() {
+        let mut pixels = PixelBuffer::new_wled_buffer(WledProtocol::Warls, 2, 0, 2);
+        assert_eq!(pixels.data(), [1, 2, 0, 0, 0, 0, 1, 0, 0, 0]);
+        pixels.add(0x10203000);
+        pixels.add(0x40506000);
+        pixels.finish();
+        assert_eq!(
+            pixels.data(),
+            [1, 2, 0, 0x10, 0x20, 0x30, 1, 0x40, 0x50, 0x60]
+        );
+
+        let pixels = PixelBuffer::new_wled_buffer(WledProtocol::Drgb, 255, 0, 2);
+        assert_eq!(pixels.data(), [2, 255, 0, 0, 0, 0, 0, 0]);
+
+        let pixels = PixelBuffer::new_wled_buffer(WledProtocol::Dnrgb, 1, 489, 1);
+        assert_eq!(pixels.data(), [4, 1, 0x01, 0xE9, 0, 0, 0]);
+    }
+
+    #[test]
+    fn sacn_buffer() {
+        let mut pixels = PixelBuffer::new_sacn_buffer(0x0102, 100, 170);
+        assert_eq!(pixels.data().len(), 126 + 510);
+        assert_eq!(pixels.data()[..4], [0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(&pixels.data()[4..16], b"ASC-E1.17\0\0\0");
+        assert_eq!(
+            pixels.data()[16..18],
+            (0x7000_u16 | (636 - 16)).to_be_bytes()
+        );
+        assert_eq!(
+            pixels.data()[38..40],
+            (0x7000_u16 | (636 - 38)).to_be_bytes()
+        );
+        assert_eq!(&pixels.data()[44..52], b"AdaLight");
+        assert_eq!(pixels.data()[108], 100);
+        assert_eq!(pixels.data()[113..115], [0x01, 0x02]);
+        assert_eq!(
+            pixels.data()[115..117],
+            (0x7000_u16 | (636 - 115)).to_be_bytes()
+        );
+        assert_eq!(pixels.data()[123..125], 511_u16.to_be_bytes());
+        assert_eq!(pixels.data()[125], 0);
+
+        pixels.set_sacn_sequence(7);
+        assert_eq!(pixels.data()[111], 7);
+
+        pixels.add(0x10203000);
+        assert_eq!(pixels.data()[126..129], [0x10, 0x20, 0x30]);
+    }
+
+    #[cfg(feature = "hue")]
+    #[test]
+    fn hue_buffer() {
+        let mut pixels = PixelBuffer::new_hue_buffer(&[3, 0x0107]);
+        assert_eq!(pixels.data().len(), 16 + 18);
+        assert_eq!(&pixels.data()[..9], b"HueStream");
+        assert_eq!(pixels.data()[9..11], [0x01, 0x00]);
+        assert_eq!(pixels.data()[16..19], [0x00, 0x00, 0x03]);
+        assert_eq!(pixels.data()[25..28], [0x00, 0x01, 0x07]);
+
+        pixels.set_hue_color(1, 0xFF8000FF);
+        assert_eq!(pixels.data()[28..34], [0xFF, 0xFF, 0x80, 0x80, 0x00, 0x00]);
+        assert_eq!(pixels.data()[19..25], [0; 6]);
+    }
+
+    #[test]
+    fn nanoleaf_buffer() {
+        let mut pixels = PixelBuffer::new_nanoleaf_buffer(&[0x1234, 7], 1);
+        assert_eq!(pixels.data().len(), 2 + 16);
+        assert_eq!(pixels.data()[..2], [0x00, 0x02]);
+        assert_eq!(
+            pixels.data()[2..10],
+            [0x12, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01]
+        );
+
+        pixels.set_nanoleaf_color(1, 0x102030FF);
+        assert_eq!(
+            pixels.data()[10..18],
+            [0x00, 0x07, 0x10, 0x20, 0x30, 0x00, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn udp_buffer() {
+        let mut pixels = PixelBuffer::new_udp_buffer(b"AL", PixelFormat::Rgb, 2);
+        assert_eq!(pixels.data().len(), 2 + 6);
+        pixels.add(0x102030FF);
+        assert_eq!(pixels.data(), [b'A', b'L', 0x10, 0x20, 0x30, 0, 0, 0]);
+
+        let mut pixels = PixelBuffer::new_udp_buffer(&[], PixelFormat::Rgbw, 2);
+        assert_eq!(pixels.data().len(), 8);
+        pixels.add(0x402010FF);
+        pixels.add(0x808080FF);
+        assert_eq!(
+            pixels.data(),
+            [0x30, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00, 0x80]
+        );
+
+        pixels.fade(&[0x30, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00, 0x80], 128);
+        assert_eq!(
+            pixels.data(),
+            [0x18, 0x08, 0x00, 0x08, 0x00, 0x00, 0x00, 0x40]
+        );
+    }
+
+    #[test]
+    fn white_point_gains() {
+        let mut pixels = PixelBuffer::new_udp_buffer(&[], PixelFormat::Rgb, 1);
+        pixels.set_white_point(WhitePoint {
+            red: 1.0,
+            green: 0.5,
+            blue: 0.25,
+        });
+        pixels.add(0xFFFFFFFF);
+        assert_eq!(pixels.data(), [0xFF, 0x80, 0x40]);
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::Instant;
+
+use crate::settings::PowerLimitConfiguration;
+
+/// Track how far the whole frame needs to be dimmed to keep the estimated current draw of
+/// the LEDs under the `max_milliamps` of the supply.
+pub struct PowerLimiter {
+    /// Fraction of the full brightness which the LEDs are allowed to show.
+    scale: f64,
+
+    /// When the `scale` was last updated.
+    last_update: Option<Instant>,
+}
+
+impl PowerLimiter {
+    /// Create a new [PowerLimiter] which starts at full brightness.
+    pub fn new() -> Self {
+        Self {
+            scale: 1.0,
+            last_update: None,
+        }
+    }
+
+    /// Get the scale to multiply each LED color by for the current limit.
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Update the `scale` with the `milliamps` the latest frame would draw at full brightness.
+    /// If it's over the limit, the frame is dimmed right away to protect the supply. It only
+    /// brightens again once the frame fits under the limit with room to spare for the
+    /// `hysteresis`, and then it eases back up with the `release` time constant, so the
+    /// brightness doesn't pump when a bright scene hovers around the limit.
+    pub fn update(&mut self, parameters: &PowerLimitConfiguration, milliamps: f64, now: Instant) {
+        let elapsed = self.last_update.map_or(0.0, |last_update| {
+            now.saturating_duration_since(last_update).as_secs_f64()
+        });
+        self.last_update = Some(now);
+
+        if milliamps <= 0.0 {
+            self.scale = Self::release(parameters, self.scale, 1.0, elapsed);
+            return;
+        }
+
+        let limit = parameters.max_milliamps / milliamps;
+        if limit < self.scale {
+            self.scale = limit;
+            return;
+        }
+
+        let target = (limit * (1.0 - parameters.hysteresis.clamp(0.0, 1.0))).min(1.0);
+        if target > self.scale {
+            self.scale = Self::release(parameters, self.scale, target, elapsed);
+        }
+    }
+
+    /// Move the `scale` towards the `target` by the fraction of the `release` time constant
+    /// which has `elapsed` since the last update.
+    fn release(parameters: &PowerLimitConfiguration, scale: f64, target: f64, elapsed: f64) -> f64 {
+        let weight = if parameters.release > 0.0 {
+            1.0 - (-elapsed / parameters.release).exp()
+        } else {
+            1.0
+        };
+        scale + (target - scale) * weight
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    const PARAMETERS: PowerLimitConfiguration = PowerLimitConfiguration {
+        milliamps_per_channel: 20.0,
+        max_milliamps: 1000.0,
+        hysteresis: 0.1,
+        release: 1.0,
+    };
+
+    #[test]
+    fn start_at_full_brightness() {
+        let mut limiter = PowerLimiter::new();
+        assert_eq!(limiter.scale(), 1.0);
+        limiter.update(&PARAMETERS, 500.0, Instant::now());
+        assert_eq!(limiter.scale(), 1.0);
+    }
+
+    #[test]
+    fn dim_immediately() {
+        let mut limiter = PowerLimiter::new();
+        limiter.update(&PARAMETERS, 4000.0, Instant::now());
+        assert_eq!(limiter.scale(), 0.25);
+    }
+
+    #[test]
+    fn hold_within_hysteresis() {
+        let start = Instant::now();
+        let mut limiter = PowerLimiter::new();
+        limiter.update(&PARAMETERS, 2000.0, start);
+        limiter.update(&PARAMETERS, 1900.0, start + Duration::from_secs(1));
+        assert_eq!(limiter.scale(), 0.5);
+    }
+
+    #[test]
+    fn release_slowly() {
+        let start = Instant::now();
+        let mut limiter = PowerLimiter::new();
+        limiter.update(&PARAMETERS, 2000.0, start);
+        limiter.update(&PARAMETERS, 0.0, start + Duration::from_secs(1));
+        let expected = 1.0 - 0.5 / std::f64::consts::E;
+        assert!((limiter.scale() - expected).abs() < 0.001);
+    }
+}
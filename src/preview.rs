@@ -0,0 +1,121 @@
+use std::fmt;
+
+/// A low resolution RGB thumbnail of the last frame sampled from one of the configured displays.
+#[derive(Clone, Debug)]
+pub struct Thumbnail {
+    /// Index of the matching [crate::settings::DisplayConfiguration] in
+    /// [crate::settings::Settings].
+    pub display_index: usize,
+
+    /// Width of the thumbnail in pixels.
+    pub width: usize,
+
+    /// Height of the thumbnail in pixels.
+    pub height: usize,
+
+    /// The RGB pixels of the thumbnail, 3 bytes per pixel with no padding between rows.
+    pub pixels: Vec<u8>,
+}
+
+impl Thumbnail {
+    /// Convert a BGRA buffer with `width * height` pixels to a [Thumbnail].
+    pub fn from_bgra(display_index: usize, width: usize, height: usize, bgra: &[u8]) -> Self {
+        Self {
+            display_index,
+            width,
+            height,
+            pixels: bgra
+                .chunks_exact(4)
+                .flat_map(|pixel| [pixel[2], pixel[1], pixel[0]])
+                .collect(),
+        }
+    }
+
+    /// Get the average RGB color of all the `pixels`.
+    pub fn average(&self) -> (u8, u8, u8) {
+        let count = (self.pixels.len() / 3).max(1) as u64;
+        let (r, g, b) =
+            self.pixels
+                .chunks_exact(3)
+                .fold((0_u64, 0_u64, 0_u64), |(r, g, b), pixel| {
+                    (
+                        r + u64::from(pixel[0]),
+                        g + u64::from(pixel[1]),
+                        b + u64::from(pixel[2]),
+                    )
+                });
+
+        ((r / count) as u8, (g / count) as u8, (b / count) as u8)
+    }
+}
+
+/// Snapshot of the LED colors from the last frame, which a GUI, web preview, or debug logger
+/// can observe through [crate::update_timer::UpdateTimer] without touching the serial or OPC
+/// buffers.
+#[derive(Clone, Debug, Default)]
+pub struct Preview {
+    /// The RGB color of every LED in the order they appear in the configured displays, after
+    /// fading and the minimum brightness but before gamma correction.
+    pub colors: Vec<(u8, u8, u8)>,
+
+    /// Optional [Thumbnail] for each display which was sampled, if they were requested.
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+impl Preview {
+    /// Create a [Preview] from the RGBA colors which are rendered to the LEDs.
+    pub fn from_colors(colors: &[u32], thumbnails: Vec<Thumbnail>) -> Self {
+        Self {
+            colors: colors
+                .iter()
+                .map(|color| {
+                    (
+                        ((color & 0xFF000000) >> 24) as u8,
+                        ((color & 0xFF0000) >> 16) as u8,
+                        ((color & 0xFF00) >> 8) as u8,
+                    )
+                })
+                .collect(),
+            thumbnails,
+        }
+    }
+}
+
+impl fmt::Display for Preview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LED Colors:")?;
+        for (r, g, b) in self.colors.iter() {
+            write!(f, " #{:02X}{:02X}{:02X}", r, g, b)?;
+        }
+
+        for thumbnail in self.thumbnails.iter() {
+            let (r, g, b) = thumbnail.average();
+            write!(
+                f,
+                ", Display {}: {}x{} #{:02X}{:02X}{:02X}",
+                thumbnail.display_index, thumbnail.width, thumbnail.height, r, g, b
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn preview_colors() {
+        let preview = Preview::from_colors(&[0x102030FF, 0xFF000000], Vec::new());
+        assert_eq!(preview.colors, vec![(0x10, 0x20, 0x30), (0xFF, 0, 0)]);
+    }
+
+    #[test]
+    fn thumbnail_pixels() {
+        let thumbnail = Thumbnail::from_bgra(1, 2, 1, &[1, 2, 3, 0xFF, 4, 5, 6, 0xFF]);
+        assert_eq!(thumbnail.display_index, 1);
+        assert_eq!(thumbnail.pixels, vec![3, 2, 1, 6, 5, 4]);
+        assert_eq!(thumbnail.average(), (4, 3, 2));
+    }
+}
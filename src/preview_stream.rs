@@ -0,0 +1,308 @@
+use std::{
+    io::{ErrorKind, Read, Result, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{settings::PreviewStreamConfiguration, update_timer::PreviewHandle};
+
+/// How often the listener checks if the [PreviewStream] is stopping between connections.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Give up on a client which doesn't finish the handshake, or stops reading the frames, after
+/// this long.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The handshake is only a few short lines, so anything bigger than this is rejected.
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// GUID which RFC 6455 appends to the `Sec-WebSocket-Key` to compute the
+/// `Sec-WebSocket-Accept` header.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// First byte of a WebSocket frame with the FIN bit and the binary opcode.
+const WEBSOCKET_BINARY_FRAME: u8 = 0x82;
+
+/// Alphabet for the standard base64 encoding.
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Compute the SHA-1 digest of `data`. The WebSocket handshake is the only thing which needs
+/// it, so it's not worth another dependency.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    for block in message.chunks_exact(64) {
+        let mut words = [0_u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, result) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(result);
+        }
+    }
+
+    let mut digest = [0_u8; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+/// Encode `data` with the standard base64 alphabet and padding.
+pub fn base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = (u32::from(chunk[0]) << 16)
+            | (u32::from(chunk.get(1).copied().unwrap_or_default()) << 8)
+            | u32::from(chunk.get(2).copied().unwrap_or_default());
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(
+                    BASE64_ALPHABET[(bits >> (18 - 6 * i)) as usize & 0x3F],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Compute the `Sec-WebSocket-Accept` header for the `Sec-WebSocket-Key` from a client.
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+/// Get the `Sec-WebSocket-Key` header from a `GET` request, if it's a WebSocket handshake.
+fn websocket_key(request: &str) -> Option<&str> {
+    let mut lines = request.lines();
+    if !lines.next()?.starts_with("GET ") {
+        return None;
+    }
+
+    lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-key"))
+        .map(|(_, value)| value.trim())
+}
+
+/// Wrap the `payload` in an unmasked binary WebSocket frame.
+fn binary_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![WEBSOCKET_BINARY_FRAME];
+    match payload.len() {
+        length @ 0..=125 => frame.push(length as u8),
+        length @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Streams the LED colors from the latest [crate::preview::Preview] to WebSocket clients. Each
+/// frame is a binary message with 3 bytes (RGB) for every LED in order, and it's only sent if
+/// the colors changed. Each client gets its own thread, which stops within one frame interval
+/// after the [PreviewStream] is dropped.
+pub struct PreviewStream {
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PreviewStream {
+    /// Start listening on the `bind` address in the [PreviewStreamConfiguration]. Returns
+    /// `None` if the address is invalid or already in use.
+    pub fn start(
+        configuration: &PreviewStreamConfiguration,
+        preview: PreviewHandle,
+    ) -> Option<Self> {
+        let listener = TcpListener::bind(configuration.bind.as_str()).ok()?;
+        listener.set_nonblocking(true).ok()?;
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stopped = stopped.clone();
+            let interval = Duration::from_secs(1) / configuration.fps.max(1);
+            thread::spawn(move || listen(listener, interval, preview, stopped))
+        };
+
+        Some(Self {
+            stopped,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Accept connections on the `listener` until the [PreviewStream] is `stopped`, and start a
+/// thread to stream the colors to each one.
+fn listen(
+    listener: TcpListener,
+    interval: Duration,
+    preview: PreviewHandle,
+    stopped: Arc<AtomicBool>,
+) {
+    while !stopped.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let preview = preview.clone();
+                let stopped = stopped.clone();
+                thread::spawn(move || {
+                    if let Ok(true) = handshake(&stream) {
+                        let _ = stream_colors(stream, interval, &preview, &stopped);
+                    }
+                });
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_INTERVAL),
+            Err(_) => break,
+        }
+    }
+}
+
+/// Read the WebSocket handshake from a client and accept it. Returns `false` if it's not a
+/// WebSocket request, after telling the client to upgrade.
+fn handshake(mut stream: &TcpStream) -> Result<bool> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut request = Vec::new();
+    let mut buffer = [0_u8; 1024];
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+        let length = stream.read(&mut buffer)?;
+        if length == 0 || request.len() + length > MAX_REQUEST_SIZE {
+            return Ok(false);
+        }
+        request.extend_from_slice(&buffer[..length]);
+    }
+
+    let response = match websocket_key(&String::from_utf8_lossy(&request)) {
+        Some(key) => format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        ),
+        None => String::from(
+            "HTTP/1.1 426 Upgrade Required\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ),
+    };
+    stream.write_all(response.as_bytes())?;
+    Ok(response.starts_with("HTTP/1.1 101"))
+}
+
+/// Send the colors from the latest [crate::preview::Preview] to the client every `interval`
+/// if they changed, until the [PreviewStream] is `stopped` or the client goes away.
+fn stream_colors(
+    mut stream: TcpStream,
+    interval: Duration,
+    preview: &PreviewHandle,
+    stopped: &AtomicBool,
+) -> Result<()> {
+    let mut sent = Vec::new();
+    while !stopped.load(Ordering::Relaxed) {
+        if let Some(latest) = preview.latest() {
+            let colors: Vec<u8> = latest
+                .colors
+                .iter()
+                .flat_map(|(r, g, b)| [*r, *g, *b])
+                .collect();
+            if colors != sent {
+                stream.write_all(&binary_frame(&colors))?;
+                sent = colors;
+            }
+        }
+
+        thread::sleep(interval);
+    }
+    Ok(())
+}
+
+impl Drop for PreviewStream {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn accept_handshake() {
+        let request = "GET /chat HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nsec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+        let key = websocket_key(request).expect("find the Sec-WebSocket-Key");
+        assert_eq!(key, "dGhlIHNhbXBsZSBub25jZQ==");
+        assert_eq!(accept_key(key), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(
+            websocket_key("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            None
+        );
+        assert_eq!(
+            websocket_key("POST / HTTP/1.1\r\nSec-WebSocket-Key: abc\r\n\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn encode_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn encode_frames() {
+        assert_eq!(binary_frame(&[1, 2, 3]), [0x82, 3, 1, 2, 3]);
+
+        let frame = binary_frame(&[0; 300]);
+        assert_eq!(frame[..4], [0x82, 126, 0x01, 0x2C]);
+        assert_eq!(frame.len(), 304);
+
+        let frame = binary_frame(&[0; 0x10000]);
+        assert_eq!(frame[..10], [0x82, 127, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(frame.len(), 0x1000A);
+    }
+}
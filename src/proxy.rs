@@ -0,0 +1,289 @@
+use std::{
+    io::{Error, ErrorKind, Result},
+    net::IpAddr,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    preview_stream::base64,
+    settings::{ProxyConfiguration, ProxyProtocol},
+};
+
+/// SOCKS protocol version 5.
+const SOCKS5_VERSION: u8 = 5;
+
+/// SOCKS5 authentication method which doesn't need any credentials.
+const SOCKS5_NO_AUTH: u8 = 0;
+
+/// SOCKS5 username and password authentication method (RFC 1929).
+const SOCKS5_USERNAME_PASSWORD: u8 = 2;
+
+/// SOCKS5 command to open a TCP connection.
+const SOCKS5_CONNECT: u8 = 1;
+
+/// SOCKS5 address types.
+const SOCKS5_IPV4: u8 = 1;
+const SOCKS5_DOMAIN_NAME: u8 = 3;
+const SOCKS5_IPV6: u8 = 4;
+
+/// The response to an HTTP `CONNECT` request is only a few short lines, so anything bigger
+/// than this is rejected.
+const MAX_RESPONSE_SIZE: usize = 4096;
+
+/// Copy of a [ProxyConfiguration] for an [crate::opc_pool::OpcConnection], which it uses to
+/// tunnel each connection to the OPC server through the proxy.
+pub struct Proxy {
+    protocol: ProxyProtocol,
+    address: String,
+    credentials: Option<(String, String)>,
+}
+
+impl Proxy {
+    /// Copy the [ProxyConfiguration], along with the `address` of the proxy which
+    /// [tokio::net::lookup_host] can resolve.
+    pub fn new(configuration: &ProxyConfiguration, address: String) -> Self {
+        Self {
+            protocol: configuration.protocol,
+            address,
+            credentials: configuration
+                .username
+                .clone()
+                .zip(configuration.password.clone()),
+        }
+    }
+
+    /// Get the address of the proxy, which the caller should connect to first.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Ask the proxy on the other end of the `stream` to open a tunnel to the `target`
+    /// `host:port`. Once this returns, the `stream` is connected to the `target`.
+    pub async fn handshake(&self, stream: &mut TcpStream, target: &str) -> Result<()> {
+        match self.protocol {
+            ProxyProtocol::Socks5 => self.socks5_handshake(stream, target).await,
+            ProxyProtocol::Http => self.http_handshake(stream, target).await,
+        }
+    }
+
+    /// Negotiate the authentication method, log in if the proxy asks for it, and send the
+    /// `CONNECT` command.
+    async fn socks5_handshake(&self, stream: &mut TcpStream, target: &str) -> Result<()> {
+        stream
+            .write_all(&socks5_greeting(self.credentials.is_some()))
+            .await?;
+        let mut reply = [0_u8; 2];
+        stream.read_exact(&mut reply).await?;
+        match (reply, self.credentials.as_ref()) {
+            ([SOCKS5_VERSION, SOCKS5_NO_AUTH], _) => (),
+            ([SOCKS5_VERSION, SOCKS5_USERNAME_PASSWORD], Some((username, password))) => {
+                stream.write_all(&socks5_login(username, password)?).await?;
+                stream.read_exact(&mut reply).await?;
+                if reply[1] != 0 {
+                    return Err(Error::from(ErrorKind::PermissionDenied));
+                }
+            }
+            _ => return Err(Error::from(ErrorKind::PermissionDenied)),
+        }
+
+        let (host, port) = split_target(target)?;
+        stream.write_all(&socks5_request(host, port)?).await?;
+        let mut reply = [0_u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != SOCKS5_VERSION || reply[1] != 0 {
+            return Err(Error::from(ErrorKind::ConnectionRefused));
+        }
+
+        // Skip the address which the proxy bound for the tunnel.
+        let address_len = match reply[3] {
+            SOCKS5_IPV4 => 4,
+            SOCKS5_IPV6 => 16,
+            SOCKS5_DOMAIN_NAME => {
+                let mut len = [0_u8; 1];
+                stream.read_exact(&mut len).await?;
+                usize::from(len[0])
+            }
+            _ => return Err(Error::from(ErrorKind::InvalidData)),
+        };
+        let mut bound = vec![0_u8; address_len + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(())
+    }
+
+    /// Send an HTTP `CONNECT` request, and check the status in the response.
+    async fn http_handshake(&self, stream: &mut TcpStream, target: &str) -> Result<()> {
+        stream
+            .write_all(http_connect_request(target, self.credentials.as_ref()).as_bytes())
+            .await?;
+
+        // Read one byte at a time, so we don't read past the end of the headers into the data
+        // from the OPC server.
+        let mut response = Vec::new();
+        let mut byte = [0_u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() >= MAX_RESPONSE_SIZE {
+                return Err(Error::from(ErrorKind::InvalidData));
+            }
+            stream.read_exact(&mut byte).await?;
+            response.push(byte[0]);
+        }
+
+        match http_connect_status(&String::from_utf8_lossy(&response)) {
+            Some(200..=299) => Ok(()),
+            Some(407) => Err(Error::from(ErrorKind::PermissionDenied)),
+            Some(_) => Err(Error::from(ErrorKind::ConnectionRefused)),
+            None => Err(Error::from(ErrorKind::InvalidData)),
+        }
+    }
+}
+
+/// Split a `host:port` target into the host, without any brackets around an IPv6 literal, and
+/// the port number.
+fn split_target(target: &str) -> Result<(&str, u16)> {
+    target
+        .rsplit_once(':')
+        .and_then(|(host, port)| {
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            Some((host, port.parse().ok()?))
+        })
+        .ok_or_else(|| Error::from(ErrorKind::InvalidInput))
+}
+
+/// Encode the SOCKS5 greeting, which offers username and password authentication if we have
+/// the credentials for it.
+fn socks5_greeting(authenticate: bool) -> Vec<u8> {
+    if authenticate {
+        vec![SOCKS5_VERSION, 2, SOCKS5_NO_AUTH, SOCKS5_USERNAME_PASSWORD]
+    } else {
+        vec![SOCKS5_VERSION, 1, SOCKS5_NO_AUTH]
+    }
+}
+
+/// Encode the SOCKS5 username and password request. Each of them must fit in 255 bytes.
+fn socks5_login(username: &str, password: &str) -> Result<Vec<u8>> {
+    let mut request = vec![1];
+    for value in [username, password] {
+        let len = u8::try_from(value.len()).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+        request.push(len);
+        request.extend_from_slice(value.as_bytes());
+    }
+    Ok(request)
+}
+
+/// Encode the SOCKS5 `CONNECT` command for the `host` and `port`. Host names are passed to the
+/// proxy to resolve, so they can be names on the remote network.
+fn socks5_request(host: &str, port: u16) -> Result<Vec<u8>> {
+    let mut request = vec![SOCKS5_VERSION, SOCKS5_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(address)) => {
+            request.push(SOCKS5_IPV4);
+            request.extend_from_slice(&address.octets());
+        }
+        Ok(IpAddr::V6(address)) => {
+            request.push(SOCKS5_IPV6);
+            request.extend_from_slice(&address.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len()).map_err(|_| Error::from(ErrorKind::InvalidInput))?;
+            request.push(SOCKS5_DOMAIN_NAME);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    Ok(request)
+}
+
+/// Encode an HTTP `CONNECT` request for the `target`, with basic authentication if we have
+/// the `credentials`.
+fn http_connect_request(target: &str, credentials: Option<&(String, String)>) -> String {
+    let authorization = credentials.map_or_else(String::new, |(username, password)| {
+        format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64(format!("{}:{}", username, password).as_bytes())
+        )
+    });
+    format!(
+        "CONNECT {} HTTP/1.1\r\nHost: {}\r\n{}\r\n",
+        target, target, authorization
+    )
+}
+
+/// Get the status code from the response to an HTTP `CONNECT` request.
+fn http_connect_status(response: &str) -> Option<u16> {
+    let mut status_line = response.lines().next()?.split_whitespace();
+    if !status_line.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    status_line.next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_targets() {
+        assert_eq!(
+            split_target("fadecandy.lan:7890").expect("split the host"),
+            ("fadecandy.lan", 7890)
+        );
+        assert_eq!(
+            split_target("[fe80::1]:80").expect("split the IPv6 literal"),
+            ("fe80::1", 80)
+        );
+        assert!(split_target("fadecandy.lan:http").is_err());
+        assert!(split_target("fadecandy.lan").is_err());
+    }
+
+    #[test]
+    fn encode_socks5() {
+        assert_eq!(socks5_greeting(false), [5, 1, 0]);
+        assert_eq!(socks5_greeting(true), [5, 2, 0, 2]);
+        assert_eq!(
+            socks5_login("led", "pw").expect("encode the login"),
+            [1, 3, b'l', b'e', b'd', 2, b'p', b'w']
+        );
+        assert!(socks5_login(&"x".repeat(256), "pw").is_err());
+        assert_eq!(
+            socks5_request("192.168.1.14", 7890).expect("encode the IPv4 request"),
+            [5, 1, 0, 1, 192, 168, 1, 14, 0x1E, 0xD2]
+        );
+        assert_eq!(
+            socks5_request("::1", 80).expect("encode the IPv6 request"),
+            [5, 1, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 80]
+        );
+        assert_eq!(
+            socks5_request("fc.lan", 80).expect("encode the domain name request"),
+            [5, 1, 0, 3, 6, b'f', b'c', b'.', b'l', b'a', b'n', 0, 80]
+        );
+    }
+
+    #[test]
+    fn encode_http_connect() {
+        assert_eq!(
+            http_connect_request("fadecandy.lan:7890", None),
+            "CONNECT fadecandy.lan:7890 HTTP/1.1\r\nHost: fadecandy.lan:7890\r\n\r\n"
+        );
+        assert_eq!(
+            http_connect_request(
+                "[fe80::1]:80",
+                Some(&(String::from("led"), String::from("secret")))
+            ),
+            "CONNECT [fe80::1]:80 HTTP/1.1\r\nHost: [fe80::1]:80\r\nProxy-Authorization: Basic bGVkOnNlY3JldA==\r\n\r\n"
+        );
+        assert_eq!(
+            http_connect_status("HTTP/1.1 200 Connection established\r\n\r\n"),
+            Some(200)
+        );
+        assert_eq!(
+            http_connect_status("HTTP/1.0 407 Proxy Authentication Required\r\n\r\n"),
+            Some(407)
+        );
+        assert_eq!(http_connect_status("SSH-2.0-OpenSSH\r\n"), None);
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    mem,
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use windows::Win32::{
+    Foundation::{HWND, LPARAM, WPARAM},
+    UI::WindowsAndMessaging::PostMessageA,
+};
+
+use crate::{
+    health::HealthStatus,
+    hidden_window::{WM_GET_STATUS, WM_SET_BRIGHTNESS, WM_SET_ENABLED, WM_SET_PROFILE},
+    mqtt::MqttState,
+    settings::RestConfiguration,
+    stats::Stats,
+};
+
+/// How often the listener checks if the [RestServer] is stopping between requests.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Give up on a client which doesn't finish sending its request after this long.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Give up on getting the [ControlStatus] from the [crate::hidden_window::HiddenWindow] after
+/// this long, e.g. if it's shutting down.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Requests are only a few short lines, so anything bigger than this is rejected.
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// Sender for the [ControlStatus] reply to a [WM_GET_STATUS] message. The `LPARAM` owns a
+/// boxed [StatusReply], so the [crate::hidden_window::HiddenWindow] can still reply safely if
+/// the request already gave up waiting for it.
+pub type StatusReply = mpsc::SyncSender<ControlStatus>;
+
+/// Snapshot of the state which the [crate::hidden_window::HiddenWindow] sends back for a
+/// [WM_GET_STATUS] message.
+#[derive(Clone, Debug, Default)]
+pub struct ControlStatus {
+    pub state: MqttState,
+    pub profile: Option<usize>,
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub stats: Stats,
+    pub health: HealthStatus,
+}
+
+/// The requests which the API understands.
+#[derive(Debug, PartialEq, Eq)]
+enum Request {
+    /// `GET /api/status`
+    Status,
+
+    /// `GET /api/profiles`
+    Profiles,
+
+    /// `POST /api/on` or `POST /api/off`
+    Enabled(bool),
+
+    /// `PUT /api/brightness` with a number from 0 to 255 in the body, which changes the global
+    /// brightness of every output.
+    Brightness(u8),
+
+    /// `PUT /api/profile` with the name of one of the profiles in the body.
+    Profile(usize),
+}
+
+/// Encode a JSON string.
+pub(crate) fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Split an HTTP request into the method, path, and body. Returns `None` if it's incomplete.
+fn parse_request(request: &[u8]) -> Option<(&str, &str, &str)> {
+    let request = std::str::from_utf8(request).ok()?;
+    let (head, body) = request.split_once("\r\n\r\n")?;
+    let content_length = head
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .map_or(Some(0), |(_, value)| value.trim().parse().ok())?;
+    let body = body.get(..content_length)?;
+    let mut request_line = head.lines().next()?.split_whitespace();
+    let method = request_line.next()?;
+    let path = request_line.next()?;
+    Some((method, path, body))
+}
+
+/// Match the `method` and `path` to a [Request], and parse the `body` if it has one. Returns
+/// the HTTP status code for the error if it doesn't match.
+fn route(method: &str, path: &str, body: &str, profiles: &[String]) -> Result<Request, u16> {
+    let path = path
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    let expected = match path {
+        "/api/status" | "/api/profiles" => "GET",
+        "/api/on" | "/api/off" => "POST",
+        "/api/brightness" | "/api/profile" => "PUT",
+        _ => return Err(404),
+    };
+    if method != expected {
+        return Err(405);
+    }
+
+    let body = body.trim().trim_matches('"');
+    match path {
+        "/api/status" => Ok(Request::Status),
+        "/api/profiles" => Ok(Request::Profiles),
+        "/api/on" => Ok(Request::Enabled(true)),
+        "/api/off" => Ok(Request::Enabled(false)),
+        "/api/brightness" => body.parse().map(Request::Brightness).map_err(|_| 400),
+        _ => profiles
+            .iter()
+            .position(|profile| profile == body)
+            .map(Request::Profile)
+            .ok_or(404),
+    }
+}
+
+/// Encode the connected state of each output in the [HealthStatus] as a JSON array, with
+/// `null` for any which haven't reported anything yet.
+fn health_array(values: &[Option<bool>]) -> String {
+    let values: Vec<&str> = values
+        .iter()
+        .map(|value| match value {
+            Some(true) => "true",
+            Some(false) => "false",
+            None => "null",
+        })
+        .collect();
+    format!("[{}]", values.join(","))
+}
+
+/// Encode the [HealthStatus] as JSON.
+fn health_json(health: &HealthStatus) -> String {
+    format!(
+        r#"{{"serial":{},"servers":{},"captureThrottled":{},"contentProtected":{},"grpcFailed":{}}}"#,
+        health_array(&health.serial),
+        health_array(&health.servers),
+        health.capture_throttled,
+        health.content_protected,
+        health.grpc_failed
+    )
+}
+
+/// Encode the [ControlStatus] as JSON.
+fn status_json(status: &ControlStatus, profiles: &[String]) -> String {
+    let color = status.state.average_color;
+    format!(
+        r#"{{"enabled":{},"brightness":{},"fps":{:.1},"color":[{},{},{}],"profile":{},"health":{}}}"#,
+        status.state.enabled,
+        status.state.brightness,
+        status.state.frame_rate,
+        (color & 0xFF000000) >> 24,
+        (color & 0xFF0000) >> 16,
+        (color & 0xFF00) >> 8,
+        status
+            .profile
+            .and_then(|profile| profiles.get(profile))
+            .map_or_else(|| String::from("null"), |profile| json_string(profile)),
+        health_json(&status.health)
+    )
+}
+
+/// Get the reason phrase for one of the status codes which we send.
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Service Unavailable",
+    }
+}
+
+/// Serves the REST API on a background thread. Commands are posted to the
+/// [crate::hidden_window::HiddenWindow] as window messages, like the [crate::mqtt::MqttClient]
+/// does, and the status comes back from it in a [WM_GET_STATUS] message.
+pub struct RestServer {
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RestServer {
+    /// Start listening on the `bind` address in the [RestConfiguration], and post any commands
+    /// to `h_wnd`. Returns `None` if the address is invalid or already in use.
+    pub fn start(
+        configuration: &RestConfiguration,
+        profiles: Vec<String>,
+        h_wnd: HWND,
+    ) -> Option<Self> {
+        let listener = TcpListener::bind(configuration.bind.as_str()).ok()?;
+        listener.set_nonblocking(true).ok()?;
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stopped = stopped.clone();
+            thread::spawn(move || listen(listener, &profiles, h_wnd, &stopped))
+        };
+
+        Some(Self {
+            stopped,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for RestServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Accept connections on the `listener` until the [RestServer] is `stopped`, and handle one
+/// request on each of them.
+fn listen(listener: TcpListener, profiles: &[String], h_wnd: HWND, stopped: &AtomicBool) {
+    while !stopped.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let _ = handle_connection(stream, profiles, h_wnd);
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_INTERVAL),
+            Err(_) => break,
+        }
+    }
+}
+
+/// Read a request from the `stream`, handle it, and write the response.
+fn handle_connection(
+    mut stream: TcpStream,
+    profiles: &[String],
+    h_wnd: HWND,
+) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut request = Vec::new();
+    let mut buffer = [0_u8; 1024];
+    let (status, body) = loop {
+        let length = stream.read(&mut buffer)?;
+        if length == 0 {
+            return Ok(());
+        }
+
+        request.extend_from_slice(&buffer[..length]);
+        if let Some((method, path, body)) = parse_request(&request) {
+            break match route(method, path, body, profiles) {
+                Ok(request) => handle_request(request, profiles, h_wnd),
+                Err(status) => (status, String::new()),
+            };
+        }
+
+        if request.len() > MAX_REQUEST_SIZE {
+            break (413, String::new());
+        }
+    };
+
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())
+}
+
+/// Handle a [Request] by posting a command to `h_wnd`, or by getting the status from it.
+/// Returns the HTTP status code and the body of the response.
+fn handle_request(request: Request, profiles: &[String], h_wnd: HWND) -> (u16, String) {
+    let (message, w_param) = match request {
+        Request::Status => {
+            return match get_status(h_wnd) {
+                Some(status) => (200, status_json(&status, profiles)),
+                None => (503, String::new()),
+            };
+        }
+        Request::Profiles => {
+            let names: Vec<String> = profiles.iter().map(|name| json_string(name)).collect();
+            return (200, format!("[{}]", names.join(",")));
+        }
+        Request::Enabled(enabled) => (WM_SET_ENABLED, enabled as usize),
+        Request::Brightness(brightness) => (WM_SET_BRIGHTNESS, usize::from(brightness)),
+        Request::Profile(profile) => (WM_SET_PROFILE, profile),
+    };
+
+    if unsafe { PostMessageA(h_wnd, message, WPARAM(w_param), LPARAM(0)) }.as_bool() {
+        (204, String::new())
+    } else {
+        (503, String::new())
+    }
+}
+
+/// Ask the [crate::hidden_window::HiddenWindow] for the [ControlStatus]. This waits for the
+/// window thread to reply, so it gives up after [STATUS_TIMEOUT] in case it's busy shutting
+/// down the [RestServer]. The window thread owns the [StatusReply] once the message is posted,
+/// so nothing it writes outlives this call.
+pub fn get_status(h_wnd: HWND) -> Option<ControlStatus> {
+    let (reply, status) = mpsc::sync_channel(1);
+    let reply = Box::into_raw(Box::new(reply));
+    let posted = unsafe {
+        PostMessageA(
+            h_wnd,
+            WM_GET_STATUS,
+            WPARAM(mem::size_of::<StatusReply>()),
+            LPARAM(reply as isize),
+        )
+    }
+    .as_bool();
+
+    if !posted {
+        // The window never got the message, so take back the reply.
+        drop(unsafe { Box::from_raw(reply) });
+        return None;
+    }
+
+    status.recv_timeout(STATUS_TIMEOUT).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(
+            parse_request(b"GET /api/status HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            Some(("GET", "/api/status", ""))
+        );
+        assert_eq!(
+            parse_request(b"PUT /api/brightness HTTP/1.1\r\ncontent-length: 3\r\n\r\n128"),
+            Some(("PUT", "/api/brightness", "128"))
+        );
+        assert_eq!(
+            parse_request(b"PUT /api/brightness HTTP/1.1\r\nContent-Length: 3\r\n\r\n12"),
+            None
+        );
+        assert_eq!(parse_request(b"GET /api/status HTTP/1.1\r\n"), None);
+    }
+
+    #[test]
+    fn route_requests() {
+        let profiles = [String::from("movie"), String::from("game")];
+        assert_eq!(
+            route("GET", "/api/status", "", &profiles),
+            Ok(Request::Status)
+        );
+        assert_eq!(
+            route("GET", "/api/profiles/", "", &profiles),
+            Ok(Request::Profiles)
+        );
+        assert_eq!(
+            route("POST", "/api/on", "", &profiles),
+            Ok(Request::Enabled(true))
+        );
+        assert_eq!(
+            route("POST", "/api/off", "", &profiles),
+            Ok(Request::Enabled(false))
+        );
+        assert_eq!(
+            route("PUT", "/api/brightness", "64\n", &profiles),
+            Ok(Request::Brightness(64))
+        );
+        assert_eq!(route("PUT", "/api/brightness", "256", &profiles), Err(400));
+        assert_eq!(
+            route("PUT", "/api/profile", r#""game""#, &profiles),
+            Ok(Request::Profile(1))
+        );
+        assert_eq!(route("PUT", "/api/profile", "work", &profiles), Err(404));
+        assert_eq!(route("GET", "/api/on", "", &profiles), Err(405));
+        assert_eq!(route("GET", "/", "", &profiles), Err(404));
+    }
+
+    #[test]
+    fn encode_status() {
+        let status = ControlStatus {
+            state: MqttState {
+                enabled: true,
+                brightness: 200,
+                frame_rate: 29.97,
+                average_color: 0x102030FF,
+            },
+            profile: Some(0),
+            health: HealthStatus {
+                serial: vec![None, Some(true)],
+                servers: vec![Some(false)],
+                capture_throttled: false,
+                content_protected: true,
+                grpc_failed: false,
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            status_json(&status, &[String::from("movie \"night\"")]),
+            r#"{"enabled":true,"brightness":200,"fps":30.0,"color":[16,32,48],"profile":"movie \"night\"","health":{"serial":[null,true],"servers":[false],"captureThrottled":false,"contentProtected":true,"grpcFailed":false}}"#
+        );
+        assert_eq!(
+            status_json(&ControlStatus::default(), &[]),
+            r#"{"enabled":false,"brightness":0,"fps":0.0,"color":[0,0,0],"profile":null,"health":{"serial":[],"servers":[],"captureThrottled":false,"contentProtected":false,"grpcFailed":false}}"#
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use crate::{
+    output_sink::OutputSink, pixel_buffer::PixelBuffer, screen_samples::ScreenSamples,
+    settings::SacnOutput,
+};
+
+/// UDP port which E1.31 (sACN) receivers listen on.
+const SACN_PORT: u16 = 5568;
+
+/// Number of RGB pixels which fit in the 512 channels of a DMX universe.
+const PIXELS_PER_UNIVERSE: usize = 170;
+
+/// Sends the LEDs in a [SacnOutput] as E1.31 (sACN) data packets, one for each DMX universe.
+pub struct SacnSender<'a> {
+    output: &'a SacnOutput,
+    socket: Option<UdpSocket>,
+    destination: Option<SocketAddr>,
+    sequence: u8,
+}
+
+impl<'a> SacnSender<'a> {
+    /// Allocate a new [SacnSender] without opening the socket.
+    pub fn new(output: &'a SacnOutput) -> Self {
+        Self {
+            output,
+            socket: None,
+            destination: None,
+            sequence: 0,
+        }
+    }
+
+    /// Split `led_count` LEDs into the `(universe, start, led_count)` of each DMX universe,
+    /// starting with the `first_universe`.
+    fn universes(first_universe: u16, led_count: usize) -> Vec<(u16, usize, usize)> {
+        (0..led_count)
+            .step_by(PIXELS_PER_UNIVERSE)
+            .enumerate()
+            .map(|(index, start)| {
+                (
+                    first_universe.wrapping_add(index as u16),
+                    start,
+                    (led_count - start).min(PIXELS_PER_UNIVERSE),
+                )
+            })
+            .collect()
+    }
+
+    /// Get the standard multicast address for the `universe`, which is `239.255.x.y` with the
+    /// universe number in the last 2 bytes.
+    fn multicast_address(universe: u16) -> SocketAddr {
+        let [high, low] = universe.to_be_bytes();
+        SocketAddr::from((Ipv4Addr::new(239, 255, high, low), SACN_PORT))
+    }
+
+    /// Read the universe number back out of an E1.31 (sACN) data packet.
+    fn packet_universe(pixels: &PixelBuffer) -> u16 {
+        u16::from_be_bytes([pixels.data()[113], pixels.data()[114]])
+    }
+}
+
+impl<'a> OutputSink for SacnSender<'a> {
+    /// Open a non-blocking UDP socket, and look up the unicast `destination` if it's set.
+    fn open(&mut self) -> bool {
+        if self.socket.is_none() {
+            let destination = match self.output.destination.as_ref() {
+                Some(destination) => match (destination.as_str(), SACN_PORT)
+                    .to_socket_addrs()
+                    .ok()
+                    .and_then(|mut addresses| addresses.next())
+                {
+                    Some(destination) => Some(destination),
+                    None => return false,
+                },
+                None => None,
+            };
+
+            self.socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+                .and_then(|socket| {
+                    socket.set_nonblocking(true)?;
+                    Ok(socket)
+                })
+                .ok();
+            self.destination = destination;
+        }
+
+        self.healthy()
+    }
+
+    /// Render a data packet for each DMX universe in the [SacnOutput].
+    fn render(&self, samples: &ScreenSamples) -> Vec<PixelBuffer> {
+        Self::universes(self.output.universe, self.output.led_count)
+            .into_iter()
+            .map(|(universe, start, led_count)| {
+                let mut pixels =
+                    PixelBuffer::new_sacn_buffer(universe, self.output.priority, led_count);
+                samples.render_range(self.output.first_led + start, led_count, &mut pixels);
+                pixels
+            })
+            .collect()
+    }
+
+    /// Send each of the data packets with the next sequence number, either to the unicast
+    /// `destination` or to the multicast address for its universe. If the socket buffer is
+    /// full, the rest of the frame is dropped instead of waiting.
+    fn send(&mut self, mut pixels: Vec<PixelBuffer>) -> bool {
+        self.sequence = self.sequence.wrapping_add(1);
+        let result = match self.socket.as_ref() {
+            Some(socket) => pixels.iter_mut().try_for_each(|pixels| {
+                pixels.set_sacn_sequence(self.sequence);
+                let destination = self
+                    .destination
+                    .unwrap_or_else(|| Self::multicast_address(Self::packet_universe(pixels)));
+                socket.send_to(pixels.data(), destination).map(|_| ())
+            }),
+            None => return false,
+        };
+
+        match result {
+            Ok(()) => true,
+            Err(error) if error.kind() == ErrorKind::WouldBlock => true,
+            Err(_) => {
+                self.close();
+                false
+            }
+        }
+    }
+
+    /// Close the socket.
+    fn close(&mut self) {
+        self.socket = None;
+    }
+
+    /// Test if the socket is open.
+    fn healthy(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Get the `latency` of the [SacnOutput].
+    fn latency(&self) -> Duration {
+        Duration::from_millis(u64::from(self.output.latency))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn map_universes() {
+        assert_eq!(SacnSender::universes(1, 100), vec![(1, 0, 100)]);
+        assert_eq!(
+            SacnSender::universes(1, 400),
+            vec![(1, 0, 170), (2, 170, 170), (3, 340, 60)]
+        );
+        assert_eq!(SacnSender::universes(7, 170), vec![(7, 0, 170)]);
+    }
+
+    #[test]
+    fn multicast_addresses() {
+        assert_eq!(
+            SacnSender::multicast_address(1),
+            "239.255.0.1:5568".parse().expect("parse the address")
+        );
+        assert_eq!(
+            SacnSender::multicast_address(0x0102),
+            "239.255.1.2:5568".parse().expect("parse the address")
+        );
+        assert_eq!(
+            SacnSender::packet_universe(&PixelBuffer::new_sacn_buffer(0x0102, 100, 1)),
+            0x0102
+        );
+    }
+}
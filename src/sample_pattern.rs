@@ -0,0 +1,170 @@
+use crate::settings::SampleDistribution;
+
+/// Minimum distance in cells between neighboring points in a [SampleDistribution::Poisson]
+/// pattern. A single point per cell can't be spaced much further apart than this.
+const POISSON_DISTANCE: f64 = 0.75;
+
+/// Number of random candidates to try for each cell in a [SampleDistribution::Poisson]
+/// pattern before settling for the one furthest from its neighbors.
+const POISSON_ATTEMPTS: usize = 30;
+
+/// Small deterministic xorshift generator, so the same LED always gets the same pattern.
+struct Random(u64);
+
+impl Random {
+    /// Scramble the `seed` with a splitmix64 step, since xorshift needs a non-zero state and
+    /// nearby seeds should still produce unrelated sequences.
+    fn new(seed: u64) -> Self {
+        let mut state = seed.wrapping_add(0x9E3779B97F4A7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D049BB133111EB);
+        Self((state ^ (state >> 31)).max(1))
+    }
+
+    /// Get the next value in the range `[0, 1)`.
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1_u64 << 53) as f64
+    }
+}
+
+/// Positions of the sample pixels in a square block of `cells` x `cells` cells with one
+/// sample in each cell. Each point is the fraction of the way across its cell in the x and y
+/// directions, in row-major order.
+pub struct SamplePattern {
+    points: Vec<(f64, f64)>,
+}
+
+impl SamplePattern {
+    /// Generate a [SamplePattern] with the [SampleDistribution]. The `seed` picks a different
+    /// random pattern for each LED, so neighboring blocks don't alias the same way.
+    pub fn new(distribution: SampleDistribution, cells: usize, seed: u64) -> Self {
+        let mut random = Random::new(seed);
+        let points = match distribution {
+            SampleDistribution::Grid => vec![(0.5, 0.5); cells * cells],
+            SampleDistribution::Jittered => (0..cells * cells)
+                .map(|_| (random.next(), random.next()))
+                .collect(),
+            SampleDistribution::Poisson => Self::poisson(&mut random, cells),
+        };
+
+        Self { points }
+    }
+
+    /// Get the points in row-major order.
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points
+    }
+
+    /// Place one point in each cell, trying random candidates until one of them is at least
+    /// [POISSON_DISTANCE] away from the points already placed in the neighboring cells.
+    fn poisson(random: &mut Random, cells: usize) -> Vec<(f64, f64)> {
+        let mut points: Vec<(f64, f64)> = Vec::with_capacity(cells * cells);
+
+        for row in 0..cells {
+            for col in 0..cells {
+                // Only the cells above and to the left have been placed so far.
+                let mut neighbors = Vec::with_capacity(4);
+                if col > 0 {
+                    neighbors.push((row, col - 1));
+                }
+                if row > 0 {
+                    for neighbor_col in col.saturating_sub(1)..(col + 2).min(cells) {
+                        neighbors.push((row - 1, neighbor_col));
+                    }
+                }
+                let neighbors: Vec<(f64, f64)> = neighbors
+                    .into_iter()
+                    .map(|(neighbor_row, neighbor_col)| {
+                        let (x, y) = points[neighbor_row * cells + neighbor_col];
+                        (
+                            x + neighbor_col as f64 - col as f64,
+                            y + neighbor_row as f64 - row as f64,
+                        )
+                    })
+                    .collect();
+                let distance = |(x, y): (f64, f64)| {
+                    neighbors
+                        .iter()
+                        .map(|(neighbor_x, neighbor_y)| (x - neighbor_x).hypot(y - neighbor_y))
+                        .fold(f64::MAX, f64::min)
+                };
+
+                let mut best = (random.next(), random.next());
+                let mut best_distance = distance(best);
+                for _ in 1..POISSON_ATTEMPTS {
+                    if best_distance >= POISSON_DISTANCE {
+                        break;
+                    }
+
+                    let candidate = (random.next(), random.next());
+                    let candidate_distance = distance(candidate);
+                    if candidate_distance > best_distance {
+                        best = candidate;
+                        best_distance = candidate_distance;
+                    }
+                }
+
+                points.push(best);
+            }
+        }
+
+        points
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CELLS: usize = 16;
+
+    fn min_distance(pattern: &SamplePattern) -> f64 {
+        let points: Vec<(f64, f64)> = pattern
+            .points()
+            .iter()
+            .enumerate()
+            .map(|(index, (x, y))| ((index % CELLS) as f64 + x, (index / CELLS) as f64 + y))
+            .collect();
+        let mut min = f64::MAX;
+        for (i, (x1, y1)) in points.iter().enumerate() {
+            for (x2, y2) in points[i + 1..].iter() {
+                min = min.min((x1 - x2).hypot(y1 - y2));
+            }
+        }
+        min
+    }
+
+    #[test]
+    fn grid_pattern() {
+        let pattern = SamplePattern::new(SampleDistribution::Grid, CELLS, 0);
+        assert_eq!(pattern.points().len(), CELLS * CELLS);
+        assert!(pattern.points().iter().all(|point| *point == (0.5, 0.5)));
+    }
+
+    #[test]
+    fn jittered_pattern() {
+        let pattern = SamplePattern::new(SampleDistribution::Jittered, CELLS, 1);
+        assert_eq!(pattern.points().len(), CELLS * CELLS);
+        assert!(pattern
+            .points()
+            .iter()
+            .all(|(x, y)| (0.0..1.0).contains(x) && (0.0..1.0).contains(y)));
+
+        let same = SamplePattern::new(SampleDistribution::Jittered, CELLS, 1);
+        let other = SamplePattern::new(SampleDistribution::Jittered, CELLS, 2);
+        assert_eq!(pattern.points(), same.points());
+        assert_ne!(pattern.points(), other.points());
+    }
+
+    #[test]
+    fn poisson_pattern() {
+        let jittered = SamplePattern::new(SampleDistribution::Jittered, CELLS, 3);
+        let poisson = SamplePattern::new(SampleDistribution::Poisson, CELLS, 3);
+        assert_eq!(poisson.points().len(), CELLS * CELLS);
+        assert!(min_distance(&poisson) > min_distance(&jittered));
+        assert!(min_distance(&poisson) >= POISSON_DISTANCE / 2.0);
+    }
+}
@@ -1,106 +1,1842 @@
-use std::{mem, ptr, time::Instant};
+use std::{
+    cell::Cell,
+    mem,
+    ops::Range,
+    ptr, slice,
+    time::{Duration, Instant},
+};
+
+use rayon::prelude::*;
 
 use windows::{
-    core::{Interface, Result},
+    core::{Error, Interface, Result},
     Win32::{
-        Foundation::{E_FAIL, HINSTANCE, SIZE},
+        Foundation::{E_FAIL, HINSTANCE, HWND, LUID, RECT, SIZE},
         Graphics::{
             Direct3D::D3D_DRIVER_TYPE_UNKNOWN,
             Direct3D11::{
                 D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D,
-                D3D11_BIND_FLAG, D3D11_CPU_ACCESS_READ, D3D11_CREATE_DEVICE_BGRA_SUPPORT,
-                D3D11_CREATE_DEVICE_SINGLETHREADED, D3D11_MAP_READ, D3D11_RESOURCE_MISC_FLAG,
-                D3D11_SDK_VERSION, D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
+                D3D11_BIND_FLAG, D3D11_BOX, D3D11_CPU_ACCESS_READ,
+                D3D11_CREATE_DEVICE_BGRA_SUPPORT, D3D11_CREATE_DEVICE_SINGLETHREADED,
+                D3D11_MAP_READ, D3D11_RESOURCE_MISC_FLAG, D3D11_SDK_VERSION, D3D11_TEXTURE2D_DESC,
+                D3D11_USAGE_STAGING,
             },
             Dxgi::{
-                Common::{DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_SAMPLE_DESC},
-                CreateDXGIFactory1, IDXGIAdapter1, IDXGIFactory1, IDXGIOutput1,
-                IDXGIOutputDuplication, DXGI_ERROR_ACCESS_LOST, DXGI_ERROR_INVALID_CALL,
-                DXGI_ERROR_UNSUPPORTED,
+                Common::{
+                    DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R10G10B10A2_UNORM,
+                    DXGI_MODE_ROTATION, DXGI_MODE_ROTATION_IDENTITY, DXGI_MODE_ROTATION_ROTATE270,
+                    DXGI_MODE_ROTATION_ROTATE90, DXGI_SAMPLE_DESC,
+                },
+                CreateDXGIFactory1, IDXGIAdapter1, IDXGIFactory1, IDXGIOutput1, IDXGIOutput5,
+                IDXGIOutputDuplication, DXGI_ERROR_ACCESS_LOST, DXGI_ERROR_DEVICE_REMOVED,
+                DXGI_ERROR_DEVICE_RESET, DXGI_ERROR_INVALID_CALL, DXGI_ERROR_UNSUPPORTED,
+                DXGI_MAPPED_RECT, DXGI_OUTPUT_DESC,
             },
+            Gdi::{MonitorFromWindow, HMONITOR, MONITOR_DEFAULTTONULL},
+        },
+        UI::WindowsAndMessaging::{
+            GetForegroundWindow, GetWindowLongA, GetWindowRect, GWL_STYLE, WS_CAPTION,
         },
     },
 };
 
 use crate::{
-    gamma_correction::GammaLookup,
+    auto_exposure::AutoExposure,
+    capture_source::{CaptureSource, MappedSurface, PixelOffset, SurfaceFormat},
+    color_profile::ColorProfile,
+    file_source::FileSource,
+    gamma_correction::{GammaLookup, LinearLookup},
+    hsv,
+    letterbox::LetterboxDetector,
+    oklab,
     pixel_buffer::PixelBuffer,
-    settings::{OpcChannel, Settings},
+    power_limit::PowerLimiter,
+    preview::{Preview, Thumbnail},
+    sample_pattern::SamplePattern,
+    settings::{
+        DisplayConfiguration, FadeSpace, FileSourceConfiguration, OpcChannel,
+        ProtectedContentFallback, SerialOutput, Settings, TestPattern, WindowConfiguration,
+    },
+    test_source::TestSource,
+    window_capture::WindowCapture,
 };
 
 /// Resources we need to use or just keep alive to get screen samples with the DXGI
 /// and D3D11 screen duplication APIs.
 struct DisplayResources {
-    /// The [IDXGIAdapter1] interface, which we just need to keep alive once set.
-    pub _adapter: IDXGIAdapter1,
+    /// The [IDXGIAdapter1] interface, which we need to keep alive along with the `device`.
+    pub adapter: IDXGIAdapter1,
+
+    /// The [ID3D11Device] interface, which can be reused in a [PooledDisplay] after the
+    /// `duplication` is lost.
+    pub device: ID3D11Device,
+
+    /// The [ID3D11DeviceContext] interface.
+    pub context: ID3D11DeviceContext,
+
+    /// The [IDXGIOutputDuplication] interface.
+    pub duplication: IDXGIOutputDuplication,
+
+    /// Optional [ID3D11Texture2D] interface containing a staging texture. If the contents
+    /// of the screen texture are already in main memory, we don't need to copy it from
+    /// the GPU, and we don't need a `staging` texture. If the contents are not in main
+    /// memory, we need to copy it to a `staging` texture first before we can map it.
+    pub staging: Option<ID3D11Texture2D>,
+
+    /// Rows of the duplicated texture which the sample blocks read from, if `parameters`
+    /// enables `partial_copy` and the sample blocks are already mapped. If this is empty, the
+    /// whole frame is copied to the `staging` texture.
+    pub copy_rows: Vec<u32>,
+
+    /// The [PartialStaging] texture for the last set of `copy_rows`.
+    pub partial: Option<PartialStaging>,
+
+    /// True if the last frame was copied to the `partial` texture instead of `staging`.
+    pub partial_frame: bool,
+
+    /// True if we've acquired a frame with `AcquireNextFrame` and it needs to be released.
+    pub acquired_frame: bool,
+
+    /// The [DXGI_MAPPED_RECT] from `MapDesktopSurface`, if there's no `staging` texture and
+    /// we've mapped the desktop surface for the acquired frame. The mapping stays open until
+    /// the frame is released, so every display and every call to `take_samples` which reads
+    /// the same frame shares it.
+    pub desktop_map: Cell<Option<DXGI_MAPPED_RECT>>,
+
+    /// True if the desktop image has changed since the last call to `take_samples`. When
+    /// `AcquireNextFrame` reports that no frames were accumulated (e.g. only the mouse moved)
+    /// or times out, the contents of the `staging` texture are still current.
+    pub new_frame: bool,
+
+    /// True if DXGI masked out protected content (e.g. a DRM video stream) in the last new
+    /// frame, so the desktop image is blacked out where the content should be.
+    pub protected_content: bool,
+
+    /// The `bounds` of the display in desktop coordinates.
+    pub bounds: SIZE,
+
+    /// The `DeviceName` of the output in [DXGI_OUTPUT_DESC], which identifies the output
+    /// when matching a [PooledDisplay].
+    pub device_name: [u16; 32],
+
+    /// The `DesktopCoordinates` of the output in [DXGI_OUTPUT_DESC].
+    pub desktop_coordinates: RECT,
+
+    /// The `Monitor` of the output in [DXGI_OUTPUT_DESC].
+    pub monitor: HMONITOR,
+
+    /// The [ColorProfile] of the display, if the configuration enables `colorProfile`.
+    pub color_profile: Option<ColorProfile>,
+
+    /// The [SurfaceFormat] of the duplicated surface.
+    pub format: SurfaceFormat,
+
+    /// The [DXGI_MODE_ROTATION] of the output. The duplicated texture is always in the
+    /// native orientation of the display, so for portrait or flipped displays we need to
+    /// rotate the sample positions from desktop coordinates to texture coordinates.
+    pub rotation: DXGI_MODE_ROTATION,
+
+    /// How long to wait in milliseconds for `AcquireNextFrame` to return a new frame.
+    pub capture_timeout: u32,
+
+    /// Index of the matching [crate::settings::DisplayConfiguration] in [Settings].
+    pub display_index: usize,
+
+    /// Index in `previous_colors` of the first LED belonging to this display.
+    pub led_offset: usize,
+}
+
+impl DisplayResources {
+    /// Get the width in pixels of the duplicated texture, which is not rotated along with
+    /// the desktop.
+    pub fn texture_width(&self) -> usize {
+        match self.rotation {
+            DXGI_MODE_ROTATION_ROTATE90 | DXGI_MODE_ROTATION_ROTATE270 => self.bounds.cy as usize,
+            _ => self.bounds.cx as usize,
+        }
+    }
+
+    /// Get the height in pixels of the duplicated texture, which is not rotated along with
+    /// the desktop.
+    pub fn texture_height(&self) -> usize {
+        match self.rotation {
+            DXGI_MODE_ROTATION_ROTATE90 | DXGI_MODE_ROTATION_ROTATE270 => self.bounds.cx as usize,
+            _ => self.bounds.cy as usize,
+        }
+    }
+
+    /// Update the `copy_rows` for the next frame, or clear them to copy the whole frame.
+    pub fn set_copy_rows(&mut self, rows: Option<&[u32]>) {
+        let rows = rows.unwrap_or_default();
+        if self.copy_rows != rows {
+            self.copy_rows = rows.to_vec();
+        }
+    }
+
+    /// Check the [SurfaceFormat] of the acquired `screen_texture`, since the duplication
+    /// description only reports the display mode. If it changed, recreate the `staging`
+    /// texture to match and drop the `partial` texture, otherwise `CopyResource` would fail.
+    fn match_format(&mut self, screen_texture: &ID3D11Texture2D) -> Result<()> {
+        let mut description = D3D11_TEXTURE2D_DESC::default();
+        unsafe {
+            screen_texture.GetDesc(&mut description);
+        }
+        let format =
+            SurfaceFormat::from_dxgi(description.Format).ok_or_else(|| Error::from(E_FAIL))?;
+        if format == self.format {
+            return Ok(());
+        }
+
+        self.format = format;
+        self.partial = None;
+        if self.staging.is_some() {
+            self.staging = Some(create_staging(
+                &self.device,
+                format,
+                self.texture_width(),
+                self.texture_height(),
+            )?);
+        }
+
+        Ok(())
+    }
+
+    /// Copy the `copy_rows` of the `screen_texture` to the `partial` texture, creating it if
+    /// the rows changed, or copy the whole frame to the `staging` texture if there are none.
+    /// Each run of consecutive rows is copied with a single `CopySubresourceRegion` call.
+    fn copy_frame(&mut self, screen_texture: &ID3D11Texture2D) -> Result<()> {
+        self.partial_frame = false;
+        let staging = match &self.staging {
+            Some(staging) => staging,
+            None => return Ok(()),
+        };
+
+        if self.copy_rows.is_empty() {
+            unsafe {
+                self.context.CopyResource(staging, screen_texture);
+            }
+            return Ok(());
+        }
+
+        if !matches!(&self.partial, Some(partial) if partial.rows == self.copy_rows) {
+            self.partial = Some(PartialStaging::new(
+                &self.device,
+                self.format,
+                self.texture_width(),
+                self.texture_height(),
+                &self.copy_rows,
+            )?);
+        }
+
+        let partial = match &self.partial {
+            Some(partial) => partial,
+            None => return Ok(()),
+        };
+        let rows = &partial.rows;
+        let mut start = 0;
+
+        for end in 1..=rows.len() {
+            if end < rows.len() && rows[end] == rows[end - 1] + 1 {
+                continue;
+            }
+
+            let source_box = D3D11_BOX {
+                left: 0,
+                top: rows[start],
+                front: 0,
+                right: self.texture_width() as u32,
+                bottom: rows[end - 1] + 1,
+                back: 1,
+            };
+            unsafe {
+                self.context.CopySubresourceRegion(
+                    &partial.texture,
+                    0,
+                    0,
+                    start as u32,
+                    0,
+                    screen_texture,
+                    0,
+                    &source_box,
+                );
+            }
+            start = end;
+        }
+
+        self.partial_frame = true;
+
+        Ok(())
+    }
+
+    /// Release the last frame from `AcquireNextFrame`, unmapping the desktop surface first if
+    /// it's still mapped.
+    pub fn release_frame(&mut self) {
+        unsafe {
+            if self.desktop_map.take().is_some() {
+                let _ = self.duplication.UnMapDesktopSurface();
+            }
+
+            if self.acquired_frame {
+                let _ = self.duplication.ReleaseFrame();
+                self.acquired_frame = false;
+            }
+        }
+    }
+
+    /// Find the area covered by the foreground window within the `bounds`, if it's a borderless
+    /// or fullscreen window centered on this display, e.g. a game running at a lower
+    /// resolution than the display. Windows with a caption or which aren't centered, like
+    /// menus and popups, are ignored.
+    pub fn foreground_rect(&self) -> Option<RECT> {
+        unsafe {
+            let h_wnd = GetForegroundWindow();
+            if h_wnd == HWND::default()
+                || MonitorFromWindow(h_wnd, MONITOR_DEFAULTTONULL) != self.monitor
+            {
+                return None;
+            }
+
+            let style = GetWindowLongA(h_wnd, GWL_STYLE) as u32;
+            if style & WS_CAPTION.0 == WS_CAPTION.0 {
+                return None;
+            }
+
+            let mut window_rect = RECT::default();
+            if !GetWindowRect(h_wnd, &mut window_rect).as_bool() {
+                return None;
+            }
+
+            let (left, top) = (self.desktop_coordinates.left, self.desktop_coordinates.top);
+            let rect = RECT {
+                left: (window_rect.left - left).max(0),
+                top: (window_rect.top - top).max(0),
+                right: (window_rect.right - left).min(self.bounds.cx),
+                bottom: (window_rect.bottom - top).min(self.bounds.cy),
+            };
+            if rect.right <= rect.left || rect.bottom <= rect.top {
+                return None;
+            }
+
+            let centered = (rect.left + rect.right - self.bounds.cx).abs() <= CENTER_TOLERANCE
+                && (rect.top + rect.bottom - self.bounds.cy).abs() <= CENTER_TOLERANCE;
+
+            if centered {
+                Some(rect)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Formats which `DuplicateOutput1` can hand us, in order of preference. These need to match
+/// the [SurfaceFormat] variants.
+const DUPLICATION_FORMATS: [DXGI_FORMAT; 2] =
+    [DXGI_FORMAT_R10G10B10A2_UNORM, DXGI_FORMAT_B8G8R8A8_UNORM];
+
+/// How far off center in pixels (counting both sides) a foreground window can be and still be
+/// followed by a display with `followForeground`.
+const CENTER_TOLERANCE: i32 = 4;
+
+impl CaptureSource for DisplayResources {
+    /// Copy the next frame to the `staging` texture with `AcquireNextFrame`. Returns an [Err]
+    /// value if the duplication interface or the device were lost, and `false` if the frame
+    /// didn't change or we timed out waiting for it. If the desktop image is already in system
+    /// memory, there's no `staging` texture and we map the desktop surface directly instead.
+    fn acquire_frame(&mut self) -> Result<bool> {
+        self.release_frame();
+
+        unsafe {
+            let mut info = Default::default();
+            let mut resource = None;
+            match self
+                .duplication
+                .AcquireNextFrame(self.capture_timeout, &mut info, &mut resource)
+            {
+                Ok(()) => {
+                    self.acquired_frame = true;
+                    self.new_frame = info.AccumulatedFrames > 0;
+
+                    if self.new_frame {
+                        self.protected_content = info.ProtectedContentMaskedOut.as_bool();
+
+                        if let Some(screen_texture) = resource {
+                            let screen_texture: ID3D11Texture2D = screen_texture.cast()?;
+                            self.match_format(&screen_texture)?;
+                            self.copy_frame(&screen_texture)?;
+                        }
+                    }
+                }
+                Err(error) => match error.code() {
+                    DXGI_ERROR_ACCESS_LOST
+                    | DXGI_ERROR_INVALID_CALL
+                    | DXGI_ERROR_DEVICE_REMOVED
+                    | DXGI_ERROR_DEVICE_RESET => return Err(error),
+                    _ => self.new_frame = false,
+                },
+            }
+        }
 
-    /// The [ID3D11Device] interface, which we just need to keep alive once set.
-    pub _device: ID3D11Device,
+        Ok(self.new_frame)
+    }
+
+    /// Get the `bounds` of the display in desktop coordinates.
+    fn bounds(&self) -> SIZE {
+        self.bounds
+    }
+
+    /// Get the `rotation` of the output.
+    fn rotation(&self) -> DXGI_MODE_ROTATION {
+        self.rotation
+    }
+
+    /// Map the whole `staging` texture, the `partial` texture, or the desktop surface, so it can
+    /// be shared between all of the LEDs. The desktop surface is only mapped once for each
+    /// acquired frame.
+    fn read_pixels(&self) -> Result<MappedSurface<'_>> {
+        unsafe {
+            if let (true, Some(partial)) = (self.partial_frame, &self.partial) {
+                let partial_map = self.context.Map(&partial.texture, 0, D3D11_MAP_READ, 0)?;
+                let pixels: *const u8 = mem::transmute(partial_map.pData);
+                let pitch = partial_map.RowPitch as usize;
+
+                return Ok(MappedSurface {
+                    pixels: slice::from_raw_parts(pixels, pitch * partial.rows.len()),
+                    pitch,
+                    format: self.format,
+                    rows: Some(&partial.row_map),
+                });
+            }
+
+            let (pixels, pitch) = match &self.staging {
+                Some(staging) => {
+                    let staging_map = self.context.Map(staging, 0, D3D11_MAP_READ, 0)?;
+                    let pixels: *const u8 = mem::transmute(staging_map.pData);
+                    (pixels, staging_map.RowPitch as usize)
+                }
+                None => {
+                    let desktop_map = match self.desktop_map.get() {
+                        Some(desktop_map) => desktop_map,
+                        None => {
+                            let desktop_map = self.duplication.MapDesktopSurface()?;
+                            self.desktop_map.set(Some(desktop_map));
+                            desktop_map
+                        }
+                    };
+                    let pixels: *const u8 = desktop_map.pBits;
+                    (pixels, desktop_map.Pitch as usize)
+                }
+            };
+
+            Ok(MappedSurface {
+                pixels: slice::from_raw_parts(pixels, pitch * self.texture_height()),
+                pitch,
+                format: self.format,
+                rows: None,
+            })
+        }
+    }
+
+    /// Unmap the `staging` or `partial` texture. The desktop surface stays mapped until
+    /// `release_frame`.
+    fn release_pixels(&self) {
+        unsafe {
+            if let (true, Some(partial)) = (self.partial_frame, &self.partial) {
+                self.context.Unmap(&partial.texture, 0);
+            } else if let Some(staging) = &self.staging {
+                self.context.Unmap(staging, 0);
+            }
+        }
+    }
+}
+
+/// A staging texture which only holds some of the `rows` of the duplicated texture, and the
+/// `row_map` from each row of the duplicated texture to its row in the `texture`.
+struct PartialStaging {
+    /// The [ID3D11Texture2D] staging texture with one row for each of the `rows`.
+    pub texture: ID3D11Texture2D,
+
+    /// Sorted rows of the duplicated texture which are copied to the `texture`.
+    pub rows: Vec<u32>,
+
+    /// Row in the `texture` for each row of the duplicated texture. Rows which aren't copied
+    /// map to 0, but the sample blocks never read from them.
+    pub row_map: Vec<u32>,
+}
+
+impl PartialStaging {
+    /// Create a [PartialStaging] texture for the `rows` of a duplicated texture.
+    pub fn new(
+        device: &ID3D11Device,
+        format: SurfaceFormat,
+        width: usize,
+        height: usize,
+        rows: &[u32],
+    ) -> Result<Self> {
+        let texture = create_staging(device, format, width, rows.len())?;
+        let mut row_map = vec![0; height];
+        for (index, row) in rows.iter().enumerate() {
+            row_map[*row as usize] = index as u32;
+        }
+
+        Ok(Self {
+            texture,
+            rows: rows.to_vec(),
+            row_map,
+        })
+    }
+}
+
+/// Create a staging texture with the [SurfaceFormat] of the duplicated texture, which the CPU
+/// can map and read.
+fn create_staging(
+    device: &ID3D11Device,
+    format: SurfaceFormat,
+    width: usize,
+    height: usize,
+) -> Result<ID3D11Texture2D> {
+    let texture_description = D3D11_TEXTURE2D_DESC {
+        Width: width as u32,
+        Height: height as u32,
+        MipLevels: 1,
+        ArraySize: 1,
+        Format: format.to_dxgi(),
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        Usage: D3D11_USAGE_STAGING,
+        BindFlags: D3D11_BIND_FLAG(0),
+        CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+        MiscFlags: D3D11_RESOURCE_MISC_FLAG(0),
+    };
+    unsafe { device.CreateTexture2D(&texture_description, ptr::null()) }
+}
+
+/// The expensive resources from a [DisplayResources] struct which are kept by
+/// `free_resources`, so the next call to `create_resources` only needs to recreate the
+/// [IDXGIOutputDuplication] interface if the output hasn't changed.
+struct PooledDisplay {
+    /// The [IDXGIAdapter1] interface for the `device`.
+    pub adapter: IDXGIAdapter1,
+
+    /// The [ID3D11Device] interface.
+    pub device: ID3D11Device,
 
     /// The [ID3D11DeviceContext] interface.
     pub context: ID3D11DeviceContext,
 
-    /// The [IDXGIOutputDuplication] interface.
-    pub duplication: IDXGIOutputDuplication,
+    /// Optional [ID3D11Texture2D] staging texture, which is reused if the display mode and
+    /// [SurfaceFormat] still match.
+    pub staging: Option<ID3D11Texture2D>,
+
+    /// The `bounds` of the display in desktop coordinates.
+    pub bounds: SIZE,
+
+    /// The `DeviceName` of the output in [DXGI_OUTPUT_DESC].
+    pub device_name: [u16; 32],
+
+    /// The `DesktopCoordinates` of the output in [DXGI_OUTPUT_DESC].
+    pub desktop_coordinates: RECT,
+
+    /// The [DXGI_MODE_ROTATION] of the output.
+    pub rotation: DXGI_MODE_ROTATION,
+
+    /// Index of the matching [crate::settings::DisplayConfiguration] in [Settings].
+    pub display_index: usize,
+}
+
+impl PooledDisplay {
+    /// Test if the [PooledDisplay] was created for the same configured display and output.
+    pub fn matches(&self, display_index: usize, description: &DXGI_OUTPUT_DESC) -> bool {
+        self.display_index == display_index
+            && self.device_name == description.DeviceName
+            && self.desktop_coordinates == description.DesktopCoordinates
+            && self.rotation == description.Rotation
+    }
+}
+
+/// An output attached to the desktop, along with every adapter which enumerated it.
+struct DesktopOutput {
+    /// The [DXGI_OUTPUT_DESC] of the output.
+    pub description: DXGI_OUTPUT_DESC,
+
+    /// The [IDXGIAdapter1] and [IDXGIOutput1] interfaces from each adapter which enumerated
+    /// the output, in enumeration order.
+    pub candidates: Vec<(IDXGIAdapter1, IDXGIOutput1)>,
+}
+
+/// How long to wait between attempts to find a configured window which isn't open yet.
+const WINDOW_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Resources for a [DisplayConfiguration] which follows a single application window
+/// instead of a whole output.
+struct WindowResources<'a> {
+    /// The [WindowConfiguration] to look for.
+    pub configuration: &'a WindowConfiguration,
+
+    /// The [WindowCapture] for the window, if we found a matching window.
+    pub capture: Option<WindowCapture>,
+
+    /// The [Instant] when we last tried to find the window.
+    pub last_attempt: Option<Instant>,
+
+    /// Index of the matching [DisplayConfiguration] in [Settings].
+    pub display_index: usize,
+
+    /// Index in `previous_colors` of the first LED belonging to this window.
+    pub led_offset: usize,
+}
+
+impl<'a> CaptureSource for WindowResources<'a> {
+    /// Keep looking for the window if it wasn't open yet or it was closed.
+    fn create(&mut self) -> Result<()> {
+        let retry = match self.last_attempt {
+            Some(last_attempt) => last_attempt.elapsed() >= WINDOW_RETRY_INTERVAL,
+            None => true,
+        };
+        if self.capture.is_none() && retry {
+            self.last_attempt = Some(Instant::now());
+            self.capture = WindowCapture::new(self.configuration).ok();
+        }
+
+        Ok(())
+    }
+
+    /// Acquire the next frame from the [WindowCapture]. If the window was closed, drop the
+    /// [WindowCapture] so `create` will look for it again.
+    fn acquire_frame(&mut self) -> Result<bool> {
+        match &mut self.capture {
+            Some(capture) => match capture.acquire_frame() {
+                Ok(new_frame) => Ok(new_frame),
+                Err(_) => {
+                    self.capture = None;
+                    Ok(false)
+                }
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Get the size of the window contents, which the sample positions follow when the window
+    /// is resized.
+    fn bounds(&self) -> SIZE {
+        match &self.capture {
+            Some(capture) => capture.bounds(),
+            None => SIZE::default(),
+        }
+    }
+
+    /// Map the pixels from the [WindowCapture].
+    fn read_pixels(&self) -> Result<MappedSurface<'_>> {
+        match &self.capture {
+            Some(capture) => capture.read_pixels(),
+            None => Err(E_FAIL.into()),
+        }
+    }
+
+    /// Unmap the pixels from the [WindowCapture].
+    fn release_pixels(&self) {
+        if let Some(capture) = &self.capture {
+            capture.release_pixels();
+        }
+    }
+}
+
+/// Number of sample pixels in the x and y directions for each sample block.
+const PIXEL_SAMPLES: usize = 16;
+
+/// Number of sample pixels in each 16x16 sample block.
+const OFFSET_ARRAY_SIZE: usize = PIXEL_SAMPLES * PIXEL_SAMPLES;
+
+/// Width in pixels of each [Thumbnail] in the [Preview]. The height follows the aspect ratio
+/// of the display.
+const THUMBNAIL_WIDTH: i32 = 64;
+
+/// Ratio of the smaller to the larger distance from the outside edges of the display for
+/// sample pixels in a corner block with `angledCorners`, which is `tan(22.5°)`. That keeps
+/// a 45° wedge centered on the diagonal from the corner of the display.
+const CORNER_WEDGE: f64 = 0.414_213_562_373_095;
+
+/// How [OffsetArray::average] converts the sample pixels before averaging them.
+#[derive(Clone, Copy)]
+enum Averaging<'b> {
+    /// Average the encoded channels as is.
+    Encoded,
+
+    /// Average in linear light with a [LinearLookup].
+    Linear(&'b LinearLookup),
+
+    /// Transform each sample from a [ColorProfile] to sRGB and average in linear light.
+    Profile(&'b ColorProfile),
+}
+
+impl<'b> Averaging<'b> {
+    /// Choose the [Averaging] for a display. A [ColorProfile] always averages in linear light.
+    pub fn new(linear: Option<&'b LinearLookup>, profile: Option<&'b ColorProfile>) -> Self {
+        match (profile, linear) {
+            (Some(profile), _) => Self::Profile(profile),
+            (None, Some(linear)) => Self::Linear(linear),
+            (None, None) => Self::Encoded,
+        }
+    }
+}
+
+/// New-type wrapped around an array of [PixelOffset] values for a sample block.
+struct OffsetArray([Option<PixelOffset>; OFFSET_ARRAY_SIZE]);
+
+impl OffsetArray {
+    /// Average the pixels at each of the sample positions in a [MappedSurface]. The channels are
+    /// summed as integers, which is much cheaper than converting every sample to floating
+    /// point. They're read with 10 bits of precision, so the extra bits of a 10-bit surface
+    /// aren't lost before averaging, and the average is reduced to the 8-bit range at the end.
+    /// Positions which were masked by an exclusion region are skipped, and the average only
+    /// includes the rest. The [Averaging] determines whether the samples are averaged in
+    /// linear light instead.
+    pub fn average(&self, surface: &MappedSurface, averaging: Averaging) -> (f64, f64, f64) {
+        let (r, g, b, count) = self.0.iter().flatten().fold(
+            (0_u32, 0_u32, 0_u32, 0_u32),
+            |(r, g, b, count), offset| {
+                let (red, green, blue) = surface.rgb10(offset);
+                let (red, green, blue) = match averaging {
+                    Averaging::Encoded => (red.into(), green.into(), blue.into()),
+                    Averaging::Linear(linear) => (
+                        linear.to_linear10(red),
+                        linear.to_linear10(green),
+                        linear.to_linear10(blue),
+                    ),
+                    Averaging::Profile(profile) => profile.to_linear(red, green, blue),
+                };
+                (r + red, g + green, b + blue, count + 1)
+            },
+        );
+
+        if count == 0 {
+            return (0.0, 0.0, 0.0);
+        }
+
+        // Each sample is 4 times the range of an 8-bit channel, or of the linear lookups.
+        let divisor = count as f64 * 4.0;
+        let (r, g, b) = (r as f64 / divisor, g as f64 / divisor, b as f64 / divisor);
+
+        match averaging {
+            Averaging::Encoded => (r.min(255.0), g.min(255.0), b.min(255.0)),
+            Averaging::Linear(linear) => (linear.to_srgb(r), linear.to_srgb(g), linear.to_srgb(b)),
+            Averaging::Profile(profile) => {
+                (profile.to_srgb(r), profile.to_srgb(g), profile.to_srgb(b))
+            }
+        }
+    }
+}
+
+/// Ring buffer of the last few averages for a sample block, which smooths out shimmer from
+/// dithered or noisy content by averaging each LED over several frames.
+struct SampleHistory {
+    /// The most recent averages, up to `frames` of them.
+    samples: Vec<(f64, f64, f64)>,
+
+    /// Number of frames to average.
+    frames: usize,
+
+    /// Index in `samples` to replace with the next average once there are `frames` of them.
+    next: usize,
+}
+
+impl SampleHistory {
+    /// Create a [SampleHistory] which averages the last `frames` samples. If `frames` is 0 or 1,
+    /// each sample is used as is.
+    pub fn new(frames: usize) -> Self {
+        Self {
+            samples: Vec::with_capacity(frames),
+            frames,
+            next: 0,
+        }
+    }
+
+    /// Add the latest `sample` and return the average of the samples in the buffer.
+    pub fn add(&mut self, sample: (f64, f64, f64)) -> (f64, f64, f64) {
+        if self.frames < 2 {
+            return sample;
+        }
+
+        if self.samples.len() < self.frames {
+            self.samples.push(sample);
+        } else {
+            self.samples[self.next] = sample;
+        }
+        self.next = (self.next + 1) % self.frames;
+
+        let (r, g, b) = self
+            .samples
+            .iter()
+            .fold((0.0, 0.0, 0.0), |(r, g, b), sample| {
+                (r + sample.0, g + sample.1, b + sample.2)
+            });
+        let divisor = self.samples.len() as f64;
+
+        (r / divisor, g / divisor, b / divisor)
+    }
+}
+
+/// Sample block state for one configured display which doesn't depend on the
+/// [CaptureSource] it's captured from.
+struct DisplaySamples {
+    /// The `bounds` of the frames which the `pixel_offsets` were mapped to.
+    pub bounds: SIZE,
+
+    /// The [DXGI_MODE_ROTATION] of the frames which the `pixel_offsets` were mapped to.
+    pub rotation: DXGI_MODE_ROTATION,
+
+    /// The [LetterboxDetector] which tracks the active picture area within the `bounds`.
+    pub letterbox: LetterboxDetector,
+
+    /// The area covered by the foreground window within the `bounds`, if the display follows
+    /// a borderless or fullscreen foreground window. This overrides the `letterbox`.
+    pub foreground: Option<RECT>,
+
+    /// Cached [OffsetArray] sample blocks, which are empty if they need to be mapped again.
+    pub pixel_offsets: Vec<OffsetArray>,
+
+    /// Sorted rows of the frame in texture coordinates which the `pixel_offsets` (or the
+    /// downscaling) read from, if `parameters` enables `partial_copy`.
+    pub texture_rows: Vec<u32>,
+
+    /// Buffer for the downscaled BGRA frame, if `parameters` enables downscaling. The
+    /// `pixel_offsets` are mapped to this instead of the surface.
+    pub downscaled: Vec<u8>,
+
+    /// Buffer for a BGRA [Thumbnail] of the last frame with the `thumbnail_size`, if the
+    /// [SamplePipeline] is collecting `thumbnails`.
+    pub thumbnail: Vec<u8>,
+
+    /// The size of the `thumbnail`.
+    pub thumbnail_size: SIZE,
+}
+
+impl DisplaySamples {
+    /// Create an empty [DisplaySamples] which will be mapped to the first frame.
+    pub fn new() -> Self {
+        Self {
+            bounds: SIZE::default(),
+            rotation: DXGI_MODE_ROTATION_IDENTITY,
+            letterbox: LetterboxDetector::new(&SIZE::default()),
+            foreground: None,
+            pixel_offsets: Vec::new(),
+            texture_rows: Vec::new(),
+            downscaled: Vec::new(),
+            thumbnail: Vec::new(),
+            thumbnail_size: SIZE::default(),
+        }
+    }
+
+    /// Get the active picture area which the sample blocks are mapped to. This is the area
+    /// covered by the `foreground` window if there is one, otherwise it's the area tracked
+    /// by the [LetterboxDetector].
+    pub fn active(&self) -> RECT {
+        self.foreground.unwrap_or(*self.letterbox.active())
+    }
+}
+
+/// The part of [ScreenSamples] which maps the sample blocks to the frames from any
+/// [CaptureSource], averages them, fades the LEDs towards the results, and quantizes them.
+struct SamplePipeline<'a> {
+    /// Parameters for the sample blocks, fading and minimum brightness in a [Settings] struct.
+    parameters: &'a Settings,
+
+    /// Gamma correction lookup table in a [GammaLookup] struct.
+    gamma: &'a GammaLookup,
+
+    /// Optional [LinearLookup] tables, if `parameters` enables linear light averaging.
+    linear: Option<LinearLookup>,
+
+    /// The [DisplaySamples] for each configured display. These are kept between calls to
+    /// `create_resources` and only mapped again if the frames change size or orientation.
+    displays: Vec<DisplaySamples>,
+
+    /// Last RGB color computed for each sample block in `take_samples`, before it's quantized
+    /// to 8 bits. Fading blends with these, so it doesn't get stuck between the levels.
+    exact_colors: Vec<(f64, f64, f64)>,
+
+    /// Last set of RGBA colors computed for each sample block in `take_samples`. This determines
+    /// the content of the [PixelBuffer] filled in by `render_channel`.
+    previous_colors: Vec<u32>,
+
+    /// The `exact_colors` with gamma correction, which `render_serial` copies to the
+    /// [PixelBuffer].
+    corrected_colors: Vec<u32>,
+
+    /// The rounding error of each LED in the `previous_colors` and `corrected_colors`, which
+    /// is carried over to the next frame if `parameters` enables `temporal_dithering`. With
+    /// `spatial_dithering`, the rest of the error goes to the next LED in the same frame.
+    dither_errors: Vec<[(f64, f64, f64); 2]>,
+
+    /// Last average RGB color of each sample block before fading and boosting it to the minimum
+    /// brightness. If the desktop hasn't changed, we can reuse these instead of sampling again.
+    sample_averages: Vec<(f64, f64, f64)>,
+
+    /// The [SampleHistory] of each sample block, for displays which average the samples over
+    /// several frames.
+    sample_history: Vec<SampleHistory>,
+
+    /// Number of consecutive frames where every sample block was black, if `parameters`
+    /// enables black frame detection.
+    black_frames: u32,
+
+    /// The [AutoExposure] which tracks the average luminance of the `sample_averages`, if
+    /// `parameters` enables automatic exposure compensation.
+    exposure: AutoExposure,
+
+    /// The [PowerLimiter] which dims the whole frame if `parameters` sets a power limit and
+    /// the `exact_colors` would draw more current than that.
+    power: PowerLimiter,
+
+    /// Global brightness scale (from 0 to 1) for every LED, which the [UpdateTimer] can change
+    /// at runtime.
+    ///
+    /// [UpdateTimer]: crate::update_timer::UpdateTimer
+    brightness: f64,
+
+    /// True if `sample` should also downscale each frame to a [Thumbnail] for the [Preview].
+    thumbnails: bool,
+
+    /// The [SamplePattern] for each LED, which is generated once in `reset` with the
+    /// [crate::settings::SampleDistribution] from `parameters`.
+    patterns: Vec<SamplePattern>,
+}
+
+impl<'a> SamplePipeline<'a> {
+    /// Allocate a new instance of [SamplePipeline].
+    pub fn new(parameters: &'a Settings, gamma: &'a GammaLookup) -> Self {
+        Self {
+            parameters,
+            gamma,
+            linear: if parameters.linear_averaging {
+                Some(LinearLookup::new())
+            } else {
+                None
+            },
+            displays: Vec::new(),
+            exact_colors: Vec::new(),
+            previous_colors: Vec::new(),
+            corrected_colors: Vec::new(),
+            dither_errors: Vec::new(),
+            sample_averages: Vec::new(),
+            sample_history: Vec::new(),
+            black_frames: 0,
+            exposure: AutoExposure::new(),
+            power: PowerLimiter::new(),
+            brightness: 1.0,
+            thumbnails: false,
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Start every LED over at the minimum brightness when the resources are created.
+    pub fn reset(&mut self) {
+        let parameters = self.parameters;
+        let led_count = parameters.get_total_led_count();
+
+        self.displays
+            .resize_with(parameters.displays.len(), DisplaySamples::new);
+        self.exact_colors = vec![unpack(parameters.get_min_brightness_color()); led_count];
+        self.previous_colors = vec![0xFF; led_count];
+        self.corrected_colors = vec![0xFF; led_count];
+        self.dither_errors = vec![[(0.0, 0.0, 0.0); 2]; led_count];
+        self.sample_averages = vec![(0.0, 0.0, 0.0); led_count];
+        self.sample_history = parameters
+            .displays
+            .iter()
+            .flat_map(|display| {
+                display
+                    .positions
+                    .iter()
+                    .map(|_| SampleHistory::new(display.temporal_samples))
+            })
+            .collect();
+
+        if self.patterns.len() != led_count {
+            self.patterns = (0..led_count)
+                .map(|led| {
+                    SamplePattern::new(parameters.sample_distribution, PIXEL_SAMPLES, led as u64)
+                })
+                .collect();
+        }
+
+        self.quantize();
+    }
+
+    /// Forget the [DisplaySamples] when the displays change.
+    pub fn clear_displays(&mut self) {
+        self.displays.clear();
+    }
+
+    /// Update the area covered by the foreground window on a display which follows it, and
+    /// map the sample blocks again if it moved.
+    pub fn set_foreground(&mut self, display_index: usize, foreground: Option<RECT>) {
+        let display = &mut self.displays[display_index];
+        if display.foreground != foreground {
+            display.foreground = foreground;
+            display.pixel_offsets.clear();
+        }
+    }
+
+    /// Read the last frame acquired by a [CaptureSource] and update the `exact_colors` and
+    /// `sample_averages` for the range of `leds` belonging to a configured display with
+    /// `sample_surface`.
+    pub fn sample(
+        &mut self,
+        display_index: usize,
+        leds: Range<usize>,
+        source: &dyn CaptureSource,
+        profile: Option<&ColorProfile>,
+    ) -> Result<()> {
+        let (bounds, rotation) = (source.bounds(), source.rotation());
+        if bounds.cx <= 0 || bounds.cy <= 0 {
+            return Ok(());
+        }
+
+        let surface = source.read_pixels()?;
+        self.sample_surface(display_index, leds, &surface, &bounds, rotation, profile);
+        source.release_pixels();
+
+        Ok(())
+    }
+
+    /// Update the `exact_colors` and `sample_averages` for the range of `leds` belonging to a
+    /// configured display from the pixels of a frame in a [MappedSurface] with the given
+    /// `bounds` and `rotation`. The sample blocks are mapped again if the frames changed size
+    /// or the active picture area moved.
+    fn sample_surface(
+        &mut self,
+        display_index: usize,
+        leds: Range<usize>,
+        surface: &MappedSurface,
+        bounds: &SIZE,
+        rotation: DXGI_MODE_ROTATION,
+        profile: Option<&ColorProfile>,
+    ) {
+        let parameters = self.parameters;
+        let display = &parameters.displays[display_index];
+        let gain = self.exposure_gain();
+        let state = &mut self.displays[display_index];
+        let bounds = *bounds;
+
+        // Start over if the frames changed size or orientation, e.g. the window was resized.
+        if bounds != state.bounds || rotation != state.rotation {
+            state.bounds = bounds;
+            state.rotation = rotation;
+            state.letterbox = LetterboxDetector::new(&bounds);
+            state.pixel_offsets.clear();
+        }
+
+        if let Some(letterbox) = &parameters.letterbox {
+            let sample = |x, y| surface.rgb(&PixelOffset::from_desktop(x, y, &bounds, rotation));
+            if state.letterbox.update(letterbox, &bounds, &sample) {
+                state.pixel_offsets.clear();
+            }
+        }
+
+        if state.pixel_offsets.is_empty() {
+            state.pixel_offsets = Self::map_offsets(
+                parameters,
+                display,
+                &self.patterns[leds.clone()],
+                &bounds,
+                &state.active(),
+                rotation,
+            );
+
+            if parameters.partial_copy {
+                state.texture_rows = Self::texture_rows(
+                    parameters,
+                    display,
+                    &state.pixel_offsets,
+                    &bounds,
+                    rotation,
+                );
+            }
+        }
+
+        if self.thumbnails {
+            state.thumbnail_size = SIZE {
+                cx: THUMBNAIL_WIDTH,
+                cy: ((THUMBNAIL_WIDTH as i64 * bounds.cy as i64) / bounds.cx as i64).max(1) as i32,
+            };
+            Self::downscale(
+                &state.thumbnail_size,
+                surface,
+                &bounds,
+                rotation,
+                &mut state.thumbnail,
+            );
+        }
+
+        let downscaled;
+        let sampled = if parameters.downscale {
+            downscaled = Self::downscale(
+                &Self::downscaled_size(display),
+                surface,
+                &bounds,
+                rotation,
+                &mut state.downscaled,
+            );
+            &downscaled
+        } else {
+            surface
+        };
+        let averaging = Averaging::new(self.linear.as_ref(), profile);
+
+        // Each LED only reads from the shared surface and writes its own color, so we can
+        // average all of the sample blocks in parallel.
+        self.exact_colors[leds.clone()]
+            .par_iter_mut()
+            .zip(self.sample_averages[leds.clone()].par_iter_mut())
+            .zip(self.sample_history[leds].par_iter_mut())
+            .zip(state.pixel_offsets.par_iter())
+            .for_each(|(((exact_color, average), history), offsets)| {
+                *average = history.add(offsets.average(sampled, averaging));
+                let color = Self::expose(*average, gain);
+                *exact_color = Self::adjust_color(
+                    parameters,
+                    color,
+                    Self::is_black(parameters, color),
+                    *exact_color,
+                );
+            });
+    }
+
+    /// Run the whole color pipeline for a frame which every configured display samples from,
+    /// e.g. the plain BGRA pixels from a [TestSource] or a [FileSource]. The sample blocks are
+    /// averaged, cut off at the black level, faded, and boosted to the minimum brightness with
+    /// `sample_surface`, and then `finish_frame` limits them to their brightness zones and the
+    /// power limit and gamma corrects them. If there's no new `frame`, the LEDs keep fading
+    /// towards the last averages, and displays which aren't `enabled` are turned off.
+    pub fn process_frame(&mut self, frame: Option<(&MappedSurface, &SIZE)>, enabled: &[bool]) {
+        let mut led_offset = 0;
+
+        for (i, display) in self.parameters.displays.iter().enumerate() {
+            let leds = led_offset..led_offset + display.positions.len();
+            led_offset = leds.end;
+
+            match frame {
+                _ if !enabled.get(i).copied().unwrap_or(true) => self.turn_off(leds),
+                Some((surface, bounds)) if bounds.cx > 0 && bounds.cy > 0 => {
+                    self.sample_surface(i, leds, surface, bounds, DXGI_MODE_ROTATION_IDENTITY, None)
+                }
+                _ => self.fade(leds),
+            }
+        }
+
+        self.finish_frame();
+    }
+
+    /// Get the rows of the next frame which need to be copied for a display with the given
+    /// `bounds` and `rotation`, if `parameters` enables `partial_copy`. Returns [None] if the
+    /// whole frame is needed, because the sample blocks haven't been mapped to frames of that
+    /// size yet, the [LetterboxDetector] is going to scan the next frame, or we're collecting
+    /// `thumbnails` of the whole frame.
+    pub fn copy_rows(
+        &self,
+        display_index: usize,
+        bounds: &SIZE,
+        rotation: DXGI_MODE_ROTATION,
+    ) -> Option<&[u32]> {
+        let state = self.displays.get(display_index)?;
+        let letterbox_due = match &self.parameters.letterbox {
+            Some(letterbox) => state.letterbox.is_due(letterbox),
+            None => false,
+        };
+
+        if !self.parameters.partial_copy
+            || state.bounds != *bounds
+            || state.rotation != rotation
+            || state.pixel_offsets.is_empty()
+            || state.texture_rows.is_empty()
+            || letterbox_due
+            || self.thumbnails
+        {
+            return None;
+        }
+
+        Some(&state.texture_rows)
+    }
+
+    /// Start or stop collecting a [Thumbnail] of each frame for the [Preview].
+    pub fn set_thumbnails(&mut self, thumbnails: bool) {
+        self.thumbnails = thumbnails;
+    }
+
+    /// Get a [Preview] of the `previous_colors`, and a [Thumbnail] of the last frame from each
+    /// display if we're collecting `thumbnails`.
+    pub fn preview(&self) -> Preview {
+        let thumbnails = if self.thumbnails {
+            self.displays
+                .iter()
+                .enumerate()
+                .filter(|(_, display)| !display.thumbnail.is_empty())
+                .map(|(display_index, display)| {
+                    Thumbnail::from_bgra(
+                        display_index,
+                        display.thumbnail_size.cx as usize,
+                        display.thumbnail_size.cy as usize,
+                        &display.thumbnail,
+                    )
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Preview::from_colors(&self.previous_colors, thumbnails)
+    }
+
+    /// Keep fading a range of LEDs towards their last `sample_averages` without sampling.
+    pub fn fade(&mut self, leds: Range<usize>) {
+        let gain = self.exposure_gain();
+        for (exact_color, average) in self.exact_colors[leds.clone()]
+            .iter_mut()
+            .zip(self.sample_averages[leds].iter())
+        {
+            let color = Self::expose(*average, gain);
+            *exact_color = Self::adjust_color(
+                self.parameters,
+                color,
+                Self::is_black(self.parameters, color),
+                *exact_color,
+            );
+        }
+    }
+
+    /// Fade a range of LEDs towards the minimum brightness without sampling, while their
+    /// display is masking out protected content with [ProtectedContentFallback::MinBrightness].
+    /// Nothing was measured to compare with the `black_level`, so they aren't cut off to black.
+    pub fn fade_to_min_brightness(&mut self, leds: Range<usize>) {
+        self.sample_averages[leds.clone()].fill((0.0, 0.0, 0.0));
+        for exact_color in self.exact_colors[leds].iter_mut() {
+            *exact_color =
+                Self::adjust_color(self.parameters, (0.0, 0.0, 0.0), false, *exact_color);
+        }
+    }
+
+    /// Turn a range of LEDs off for a disabled display, ignoring the minimum brightness.
+    pub fn turn_off(&mut self, leds: Range<usize>) {
+        self.exact_colors[leds.clone()].fill((0.0, 0.0, 0.0));
+        self.sample_averages[leds].fill((0.0, 0.0, 0.0));
+    }
+
+    /// Get the gain from the [AutoExposure], or 1 if `parameters` doesn't enable it.
+    fn exposure_gain(&self) -> f64 {
+        match &self.parameters.auto_exposure {
+            Some(auto_exposure) => self.exposure.gain(auto_exposure),
+            None => 1.0,
+        }
+    }
+
+    /// Multiply a sample average by the exposure `gain`, without going past full brightness.
+    fn expose((r, g, b): (f64, f64, f64), gain: f64) -> (f64, f64, f64) {
+        (
+            (r * gain).min(255.0),
+            (g * gain).min(255.0),
+            (b * gain).min(255.0),
+        )
+    }
+
+    /// Multiply a color by the `color_matrix`, keeping each channel in the range `0..=255`.
+    fn correct((r, g, b): (f64, f64, f64), matrix: &[[f64; 3]; 3]) -> (f64, f64, f64) {
+        let row = |[from_r, from_g, from_b]: [f64; 3]| {
+            (r * from_r + g * from_g + b * from_b).clamp(0.0, 255.0)
+        };
+        (row(matrix[0]), row(matrix[1]), row(matrix[2]))
+    }
+
+    /// Update the [AutoExposure], blank black frames, and quantize the `exact_colors` once every
+    /// display has been sampled or faded.
+    pub fn finish_frame(&mut self) {
+        self.update_exposure();
+        self.blank_black_frames();
+        self.quantize();
+    }
+
+    /// Update the [AutoExposure] with the average luminance of all the `sample_averages` at
+    /// the end of each frame. The new gain takes effect on the next frame.
+    pub fn update_exposure(&mut self) {
+        let auto_exposure = match &self.parameters.auto_exposure {
+            Some(auto_exposure) => auto_exposure,
+            None => return,
+        };
+        if self.sample_averages.is_empty() {
+            return;
+        }
+
+        let luminance = self
+            .sample_averages
+            .iter()
+            .map(|average| AutoExposure::luminance(*average))
+            .sum::<f64>()
+            / self.sample_averages.len() as f64;
+        self.exposure
+            .update(auto_exposure, luminance, Instant::now());
+    }
+
+    /// Count the consecutive frames where every one of the `sample_averages` is black, and
+    /// once there have been enough of them, turn all of the LEDs off by setting the
+    /// `exact_colors` to black, ignoring the minimum brightness.
+    pub fn blank_black_frames(&mut self) {
+        let black_frame = match &self.parameters.black_frame {
+            Some(black_frame) => black_frame,
+            None => return,
+        };
+        let threshold = f64::from(black_frame.threshold);
+        let is_black = self
+            .sample_averages
+            .iter()
+            .all(|(r, g, b)| r.max(*g).max(*b) <= threshold);
+
+        if !is_black {
+            self.black_frames = 0;
+            return;
+        }
+
+        self.black_frames = self.black_frames.saturating_add(1);
+        if self.black_frames >= black_frame.frames {
+            self.exact_colors.fill((0.0, 0.0, 0.0));
+        }
+    }
+
+    /// Quantize the `exact_colors` to the 8-bit `previous_colors` at the end of each frame,
+    /// and look up the `corrected_colors` in the [GammaLookup]. If `parameters` enables
+    /// `temporal_dithering` or `spatial_dithering`, each channel is rounded with the error left
+    /// over from the last frame or the previous LED, and the gamma correction is interpolated
+    /// from the `exact_colors`, so a color between two levels alternates between them in the
+    /// right proportion over time or along the strip. Every LED is limited to its
+    /// [crate::settings::BrightnessZone] with `limit_brightness` first, and then dimmed by the
+    /// global `brightness` and the scale from `limit_power`.
+    pub fn quantize(&mut self) {
+        let gamma = self.gamma;
+        let colors: Vec<(f64, f64, f64)> = self
+            .exact_colors
+            .iter()
+            .enumerate()
+            .map(|(led, color)| self.limit_brightness(led, *color))
+            .collect();
+        let scale = self.brightness * self.limit_power(&colors);
+        let keep = match (
+            self.parameters.temporal_dithering,
+            self.parameters.spatial_dithering,
+        ) {
+            (false, false) => None,
+            (true, false) => Some(1.0),
+            (false, true) => Some(0.0),
+            (true, true) => Some(0.5),
+        };
+        let (mut carry, mut gamma_carry) = ((0.0, 0.0, 0.0), (0.0, 0.0, 0.0));
+        for (((exact_color, previous_color), corrected_color), [error, gamma_error]) in colors
+            .iter()
+            .zip(self.previous_colors.iter_mut())
+            .zip(self.corrected_colors.iter_mut())
+            .zip(self.dither_errors.iter_mut())
+        {
+            if let Some(keep) = keep {
+                *previous_color =
+                    Self::dither(Self::expose(*exact_color, scale), error, &mut carry, keep);
+                *corrected_color = Self::dither(
+                    Self::expose(gamma.interpolate(*exact_color), scale),
+                    gamma_error,
+                    &mut gamma_carry,
+                    keep,
+                );
+            } else {
+                *previous_color = pack(Self::expose(*exact_color, scale));
+                *corrected_color = pack(Self::expose(
+                    unpack(Self::gamma_correct(gamma, pack(*exact_color))),
+                    scale,
+                ));
+            }
+        }
+    }
+
+    /// Scale the `color` for the LED at index `led` so its brightest channel is between the
+    /// `min_brightness` and `max_brightness` of the first [crate::settings::BrightnessZone]
+    /// which covers it. Black is left alone, so it doesn't defeat the `black_level` or black
+    /// frame detection.
+    fn limit_brightness(&self, led: usize, (r, g, b): (f64, f64, f64)) -> (f64, f64, f64) {
+        let zone = match self
+            .parameters
+            .brightness_zones
+            .iter()
+            .find(|zone| (zone.first_led..zone.first_led + zone.led_count).contains(&led))
+        {
+            Some(zone) => zone,
+            None => return (r, g, b),
+        };
+
+        let brightest = r.max(g).max(b);
+        let (min, max) = (
+            f64::from(zone.min_brightness),
+            f64::from(zone.max_brightness),
+        );
+        let scale = if brightest > max {
+            max / brightest
+        } else if brightest < min && brightest > 0.0 {
+            min / brightest
+        } else {
+            return (r, g, b);
+        };
+
+        (r * scale, g * scale, b * scale)
+    }
+
+    /// Estimate how much current the gamma corrected `colors` would draw at the global
+    /// `brightness` if `parameters` sets a power limit, update the [PowerLimiter], and return
+    /// the scale for every LED. The serial outputs are dimmed by that much after gamma
+    /// correction, and the other outputs are dimmed by the same scale before it, which is at
+    /// least as much.
+    fn limit_power(&mut self, colors: &[(f64, f64, f64)]) -> f64 {
+        let power_limit = match &self.parameters.power_limit {
+            Some(power_limit) => power_limit,
+            None => return 1.0,
+        };
+
+        let gamma = self.gamma;
+        let channels = colors
+            .iter()
+            .map(|color| {
+                let (r, g, b) = gamma.interpolate(*color);
+                r + g + b
+            })
+            .sum::<f64>();
+        let milliamps = channels / 255.0 * power_limit.milliamps_per_channel * self.brightness;
+        self.power.update(power_limit, milliamps, Instant::now());
+        self.power.scale()
+    }
+
+    /// Round each channel of a `color` with the `error` from the last frame and the `carry`
+    /// from the previous LED. The new rounding error is split between the next frame, which
+    /// gets the `keep` fraction of it, and the next LED, which gets the rest.
+    fn dither(
+        (r, g, b): (f64, f64, f64),
+        error: &mut (f64, f64, f64),
+        carry: &mut (f64, f64, f64),
+        keep: f64,
+    ) -> u32 {
+        let round = |value: f64, error: &mut f64, carry: &mut f64| {
+            let target = value + *error + *carry;
+            let rounded = target.round().clamp(0.0, 255.0);
+            *error = (target - rounded) * keep;
+            *carry = target - rounded - *error;
+            rounded
+        };
+        pack((
+            round(r, &mut error.0, &mut carry.0),
+            round(g, &mut error.1, &mut carry.1),
+            round(b, &mut error.2, &mut carry.2),
+        ))
+    }
+
+    /// Apply the [GammaLookup] to each channel of an RGBA `color`.
+    fn gamma_correct(gamma: &GammaLookup, color: u32) -> u32 {
+        let (r, g, b) = (
+            gamma.red(((color & 0xFF000000) >> 24) as u8),
+            gamma.green(((color & 0xFF0000) >> 16) as u8),
+            gamma.blue(((color & 0xFF00) >> 8) as u8),
+        );
+        ((r as u32) << 24) | ((g as u32) << 16) | ((b as u32) << 8) | 0xFF
+    }
+
+    /// Build the sample blocks for a display with `build_offsets`. If `parameters` enables
+    /// downscaling, they are mapped to the downscaled frame instead of the surface.
+    fn map_offsets(
+        parameters: &Settings,
+        display: &DisplayConfiguration,
+        patterns: &[SamplePattern],
+        bounds: &SIZE,
+        active: &RECT,
+        rotation: DXGI_MODE_ROTATION,
+    ) -> Vec<OffsetArray> {
+        if !parameters.downscale {
+            return Self::build_offsets(display, patterns, bounds, active, rotation);
+        }
+
+        let size = Self::downscaled_size(display);
+        let scale = |value: i32, from: i32, to: i32| {
+            ((value as i64 * to as i64) / (from.max(1) as i64)) as i32
+        };
+        let active = RECT {
+            left: scale(active.left, bounds.cx, size.cx),
+            top: scale(active.top, bounds.cy, size.cy),
+            right: scale(active.right, bounds.cx, size.cx),
+            bottom: scale(active.bottom, bounds.cy, size.cy),
+        };
+
+        Self::build_offsets(
+            display,
+            patterns,
+            &size,
+            &active,
+            DXGI_MODE_ROTATION_IDENTITY,
+        )
+    }
+
+    /// Get the size of the downscaled frame for a display, which has [PIXEL_SAMPLES] pixels
+    /// in each direction for every cell in the grid.
+    fn downscaled_size(display: &DisplayConfiguration) -> SIZE {
+        SIZE {
+            cx: (display.horizontal_count * PIXEL_SAMPLES) as i32,
+            cy: (display.vertical_count * PIXEL_SAMPLES) as i32,
+        }
+    }
+
+    /// Downscale the whole `surface` of a display into the `buffer` as BGRA pixels with the
+    /// given `size` in desktop orientation, by reading the pixel at the center of the area
+    /// covered by each one. The rows of the surface are read in order, and then the sample
+    /// blocks only need to read from the small buffer.
+    fn downscale<'b>(
+        size: &SIZE,
+        surface: &MappedSurface,
+        bounds: &SIZE,
+        rotation: DXGI_MODE_ROTATION,
+        buffer: &'b mut Vec<u8>,
+    ) -> MappedSurface<'b> {
+        let (width, height) = (size.cx as usize, size.cy as usize);
+        let pitch = width * mem::size_of::<u32>();
+        let scale_x = bounds.cx as f64 / width.max(1) as f64;
+        let scale_y = bounds.cy as f64 / height.max(1) as f64;
+        buffer.resize(pitch * height, 0);
+
+        buffer
+            .par_chunks_exact_mut(pitch.max(1))
+            .enumerate()
+            .for_each(|(y, row)| {
+                let source_y = ((y as f64 + 0.5) * scale_y) as usize;
+                for (x, pixel) in row.chunks_exact_mut(mem::size_of::<u32>()).enumerate() {
+                    let source_x = ((x as f64 + 0.5) * scale_x) as usize;
+                    let (r, g, b) = surface.rgb(&PixelOffset::from_desktop(
+                        source_x, source_y, bounds, rotation,
+                    ));
+                    pixel.copy_from_slice(&[b, g, r, 0xFF]);
+                }
+            });
+
+        MappedSurface {
+            pixels: buffer,
+            pitch,
+            format: SurfaceFormat::Bgra8,
+            rows: None,
+        }
+    }
+
+    /// Collect the sorted rows of a frame in texture coordinates which the sample blocks read
+    /// from, or which `downscale` reads from if `parameters` enables downscaling.
+    fn texture_rows(
+        parameters: &Settings,
+        display: &DisplayConfiguration,
+        pixel_offsets: &[OffsetArray],
+        bounds: &SIZE,
+        rotation: DXGI_MODE_ROTATION,
+    ) -> Vec<u32> {
+        let mut rows: Vec<u32> = if parameters.downscale {
+            let size = Self::downscaled_size(display);
+            let (width, height) = (size.cx as usize, size.cy as usize);
+            let scale_x = bounds.cx as f64 / width.max(1) as f64;
+            let scale_y = bounds.cy as f64 / height.max(1) as f64;
+
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let source_x = ((x as f64 + 0.5) * scale_x) as usize;
+                    let source_y = ((y as f64 + 0.5) * scale_y) as usize;
+                    PixelOffset::from_desktop(source_x, source_y, bounds, rotation).y as u32
+                })
+                .collect()
+        } else {
+            pixel_offsets
+                .iter()
+                .flat_map(|offsets| offsets.0.iter().flatten())
+                .map(|offset| offset.y as u32)
+                .collect()
+        };
+
+        rows.sort_unstable();
+        rows.dedup();
+        rows
+    }
+
+    /// Calculate the [PixelOffset] sample positions for each LED in a [DisplayConfiguration]
+    /// spread across the `active` picture area of a surface with the given `bounds` and
+    /// `rotation`. Positions which fall inside one of the exclusion regions are left empty,
+    /// unless that would exclude the whole sample block.
+    fn build_offsets(
+        display: &DisplayConfiguration,
+        patterns: &[SamplePattern],
+        bounds: &SIZE,
+        active: &RECT,
+        rotation: DXGI_MODE_ROTATION,
+    ) -> Vec<OffsetArray> {
+        let (left, top) = (active.left as f64, active.top as f64);
+        let range_x = (active.right - active.left) as f64 / display.horizontal_count as f64;
+        let range_y = (active.bottom - active.top) as f64 / display.vertical_count as f64;
+        let depth = display
+            .sample_depth
+            .map(|sample_depth| sample_depth.clamp(1.0, 100.0) / 100.0);
+        let overlap = display
+            .overlap
+            .map_or(0.0, |overlap| overlap.clamp(0.0, 100.0) / 100.0);
+        let (width, height) = (
+            range_x * display.horizontal_count as f64,
+            range_y * display.vertical_count as f64,
+        );
+        let mut pixel_offsets = Vec::with_capacity(display.positions.len());
+        let is_excluded = |x: usize, y: usize| {
+            let (grid_x, grid_y) = ((x as f64 - left) / range_x, (y as f64 - top) / range_y);
+            display
+                .exclusions
+                .iter()
+                .any(|exclusion| exclusion.contains(grid_x, grid_y))
+        };
+
+        for (led, pattern) in display.positions.iter().zip(patterns.iter()) {
+            let mut offsets = OffsetArray([None; OFFSET_ARRAY_SIZE]);
+            let mut points = [(0_usize, 0_usize); OFFSET_ARRAY_SIZE];
+            let (start_x, extent_x) =
+                Self::block_extent(led.x, display.horizontal_count, left, range_x, depth);
+            let (start_y, extent_y) =
+                Self::block_extent(led.y, display.vertical_count, top, range_y, depth);
+
+            // Blocks along the top and bottom edges overlap their neighbors to either side, and
+            // blocks along the left and right edges overlap the ones above and below them.
+            let on_horizontal_edge = led.y == 0 || led.y + 1 == display.vertical_count;
+            let on_vertical_edge = led.x == 0 || led.x + 1 == display.horizontal_count;
+            let (start_x, extent_x) = if on_horizontal_edge || !on_vertical_edge {
+                Self::overlap_extent(start_x, extent_x, overlap * range_x, left, width)
+            } else {
+                (start_x, extent_x)
+            };
+            let (start_y, extent_y) = if on_vertical_edge || !on_horizontal_edge {
+                Self::overlap_extent(start_y, extent_y, overlap * range_y, top, height)
+            } else {
+                (start_y, extent_y)
+            };
+            let step_x = extent_x / PIXEL_SAMPLES as f64;
+            let step_y = extent_y / PIXEL_SAMPLES as f64;
+
+            // Corner LEDs light a diagonal region of the wall, so with angledCorners we only
+            // keep the samples in a wedge pointing from the corner of the display towards the
+            // center. Measure the distance from the outside edges as a fraction of the block.
+            let is_corner = (led.x == 0 || led.x + 1 == display.horizontal_count)
+                && (led.y == 0 || led.y + 1 == display.vertical_count);
+            let corner_x = if led.x == 0 {
+                start_x
+            } else {
+                start_x + extent_x
+            };
+            let corner_y = if led.y == 0 {
+                start_y
+            } else {
+                start_y + extent_y
+            };
+            let is_outside_wedge = |x: usize, y: usize| {
+                if !display.angled_corners || !is_corner {
+                    return false;
+                }
+                let u = (x as f64 - corner_x).abs() / extent_x;
+                let v = (y as f64 - corner_y).abs() / extent_y;
+                u.min(v) < CORNER_WEDGE * u.max(v)
+            };
+            let is_masked = |x: usize, y: usize| is_excluded(x, y) || is_outside_wedge(x, y);
+
+            // Each point in the pattern is a fraction of the way across its cell in the grid.
+            for (pixel_index, (point, (fraction_x, fraction_y))) in
+                points.iter_mut().zip(pattern.points().iter()).enumerate()
+            {
+                let (row, col) = (pixel_index / PIXEL_SAMPLES, pixel_index % PIXEL_SAMPLES);
+                *point = (
+                    (start_x + (step_x * fraction_x) + (step_x * (col as f64))) as usize,
+                    (start_y + (step_y * fraction_y) + (step_y * (row as f64))) as usize,
+                );
+            }
+
+            // If the whole block is masked, sample it anyway rather than leaving the LED dark.
+            let mask = !points.iter().all(|(x, y)| is_masked(*x, *y));
+            for (pixel_index, (x, y)) in points.iter().enumerate() {
+                if mask && is_masked(*x, *y) {
+                    continue;
+                }
+                offsets.0[pixel_index] = Some(PixelOffset::from_desktop(*x, *y, bounds, rotation));
+            }
+
+            pixel_offsets.push(offsets);
+        }
+
+        pixel_offsets
+    }
 
-    /// Optional [ID3D11Texture2D] interface containing a staging texture. If the contents
-    /// of the screen texture are already in main memory, we don't need to copy it from
-    /// the GPU, and we don't need a `staging` texture. If the contents are not in main
-    /// memory, we need to copy it to a `staging` texture first before we can map it.
-    pub staging: Option<ID3D11Texture2D>,
+    /// Get the start and the extent along one axis of the sample block for an LED at grid
+    /// `position` in a row or column of `count` cells which are `range` pixels wide, starting
+    /// at `origin`. With a sample `depth`, blocks on the first or last cell reach that fraction
+    /// of the way from the edge to the center instead of covering a single cell.
+    fn block_extent(
+        position: usize,
+        count: usize,
+        origin: f64,
+        range: f64,
+        depth: Option<f64>,
+    ) -> (f64, f64) {
+        let length = range * count as f64;
+
+        match depth {
+            Some(depth) if position == 0 => (origin, depth * length / 2.0),
+            Some(depth) if position + 1 == count => {
+                let extent = depth * length / 2.0;
+                (origin + length - extent, extent)
+            }
+            _ => (origin + range * position as f64, range),
+        }
+    }
 
-    /// True if we've mapped the texture memory and it needs to be unmapped.
-    pub acquired_frame: bool,
+    /// Grow a sample block which starts at `start` and covers `extent` pixels by `overlap` pixels
+    /// on both sides, without going past the active area which starts at `origin` and covers
+    /// `length` pixels.
+    fn overlap_extent(
+        start: f64,
+        extent: f64,
+        overlap: f64,
+        origin: f64,
+        length: f64,
+    ) -> (f64, f64) {
+        let begin = (start - overlap).max(origin);
+        let end = (start + extent + overlap).min(origin + length);
+        (begin, end - begin)
+    }
 
-    /// The `bounds` of the texture in pixels.
-    pub bounds: SIZE,
-}
+    /// Check whether the measured `color` of a sample block is below the `black_level`.
+    fn is_black(parameters: &Settings, (r, g, b): (f64, f64, f64)) -> bool {
+        r.max(g).max(b) < f64::from(parameters.black_level)
+    }
 
-/// Position of a sample pixel in an evenly spaced 16x16 grid for each sample block.
-#[derive(Copy)]
-struct PixelOffset {
-    pub x: usize,
-    pub y: usize,
-}
+    /// Adjust the saturation and value of the averaged `color` of a sample block if `hsv` is
+    /// enabled, correct it with the `color_matrix` if there is one, blend it with the
+    /// `previous_color` in the `fade_space` if fading is enabled, and boost it to the minimum
+    /// brightness. If the measured color was `black`, i.e. below the `black_level`, it fades
+    /// to black without the boost instead.
+    fn adjust_color(
+        parameters: &Settings,
+        color: (f64, f64, f64),
+        black: bool,
+        previous_color: (f64, f64, f64),
+    ) -> (f64, f64, f64) {
+        let color = if black { (0.0, 0.0, 0.0) } else { color };
+        let color = match &parameters.hsv {
+            Some(configuration) => hsv::adjust(configuration, color),
+            None => color,
+        };
+        let (mut r, mut g, mut b) = match &parameters.color_matrix {
+            Some(matrix) => Self::correct(color, matrix),
+            None => color,
+        };
+
+        // Average in the previous color if fading is enabled.
+        if parameters.fade.abs() > f64::EPSILON {
+            let weight = parameters.get_weight();
+            (r, g, b) = match parameters.fade_space {
+                FadeSpace::Rgb => (
+                    r * weight + previous_color.0 * parameters.fade,
+                    g * weight + previous_color.1 * parameters.fade,
+                    b * weight + previous_color.2 * parameters.fade,
+                ),
+                FadeSpace::Oklab => oklab::blend(previous_color, (r, g, b), weight),
+                FadeSpace::Hsv => hsv::blend(previous_color, (r, g, b), weight),
+            };
+        }
 
-impl Clone for PixelOffset {
-    fn clone(&self) -> Self {
-        Self {
-            x: self.x,
-            y: self.y,
+        let min_brightness = parameters.min_brightness as f64;
+        let sum = r + b + g;
+
+        // Boost pixels that fall below the minimum brightness, unless they're below the black
+        // level.
+        if !black && sum < min_brightness {
+            if sum.abs() < f64::EPSILON {
+                // Spread equally to R, G, and B.
+                let value = min_brightness / 3.0;
+
+                r = value;
+                g = value;
+                b = value;
+            } else {
+                // Spread the "brightness deficit" back into R, G, and B in proportion
+                // to their individual contribition to that deficit.  Rather than simply
+                // boosting all pixels at the low end, this allows deep (but saturated)
+                // colors to stay saturated...they don't "pink out."
+                let deficit = min_brightness - sum;
+                let sum_2 = sum * 2.0;
+
+                r += (deficit * (sum - r)) / sum_2;
+                g += (deficit * (sum - g)) / sum_2;
+                b += (deficit * (sum - b)) / sum_2;
+            }
         }
+
+        (
+            r.clamp(0.0, 255.0),
+            g.clamp(0.0, 255.0),
+            b.clamp(0.0, 255.0),
+        )
     }
 }
 
-/// Number of sample pixels in the x and y directions for each sample block.
-const PIXEL_SAMPLES: usize = 16;
-
-/// Number of sample pixels in each 16x16 sample block.
-const OFFSET_ARRAY_SIZE: usize = PIXEL_SAMPLES * PIXEL_SAMPLES;
+/// Split an RGBA `color` into its RGB channels in the range `0.0..=255.0`.
+fn unpack(color: u32) -> (f64, f64, f64) {
+    (
+        ((color & 0xFF000000) >> 24) as f64,
+        ((color & 0xFF0000) >> 16) as f64,
+        ((color & 0xFF00) >> 8) as f64,
+    )
+}
 
-/// New-type wrapped around an array of [PixelOffset] values for a sample block.
-struct OffsetArray([Option<PixelOffset>; OFFSET_ARRAY_SIZE]);
+/// Truncate RGB channels in the range `0.0..=255.0` to an opaque RGBA color.
+fn pack((r, g, b): (f64, f64, f64)) -> u32 {
+    let (r, g, b, a) = (
+        (r as u32 & 0xFF) << 24,
+        (g as u32 & 0xFF) << 16,
+        (b as u32 & 0xFF) << 8,
+        0xFF_u32,
+    );
+    r | g | b | a
+}
 
 /// Public interface for capturing [PixelBuffer] samples of the console session displays.
 pub struct ScreenSamples<'a> {
     /// Parameters including timeouts and the delay between frames in a [Settings] struct.
     parameters: &'a Settings,
 
-    /// Gamma correction lookup table in a [GammaLookup] struct.
-    gamma: &'a GammaLookup,
+    /// The [SamplePipeline] which turns the frames from every [CaptureSource] into LED colors.
+    pipeline: SamplePipeline<'a>,
+
+    /// Whether each configured display is sampled, starting with the `enabled` flag in
+    /// `parameters`. The LEDs for a disabled display are turned off.
+    enabled_displays: Vec<bool>,
 
     /// Optional instance of [IDXGIFactory1] which is used to request DXGI resources.
     factory: Option<IDXGIFactory1>,
 
-    /// Resources for all configured displays in `parameters`, stored in [DisplayResources] structs.
+    /// Resources for the configured displays in `parameters` which were matched to an output,
+    /// stored in [DisplayResources] structs.
     displays: Vec<DisplayResources>,
 
-    /// Cached [PixelOffset] structs for the sample pixel positions in each sample block.
-    pixel_offsets: Vec<Vec<OffsetArray>>,
+    /// Expensive resources kept from the `displays` by `free_resources` in [PooledDisplay]
+    /// structs, which can be reused by `create_resources` until the displays change.
+    pool: Vec<PooledDisplay>,
 
-    /// Last set of RGBA colors computed for each sample block in `take_samples`. This determines
-    /// the content of the [PixelBuffer] filled in by `render_serial` and `render_channel`.
-    previous_colors: Vec<u32>,
+    /// Resources for the configured displays in `parameters` which follow an application
+    /// window, stored in [WindowResources] structs.
+    windows: Vec<WindowResources<'a>>,
+
+    /// Optional [TestSource] which generates frames for every configured display instead of
+    /// capturing them, if `parameters` specifies a [TestPattern].
+    test_source: Option<TestSource>,
+
+    /// Optional [FileSource] which reads frames for every configured display instead of
+    /// capturing them, if `parameters` specifies a [FileSourceConfiguration].
+    file_source: Option<FileSource>,
 
     /// True if the last call to `create_resources` succeeded and [ScreenSamples] can successfully
     /// handle a call to `take_samples`.
@@ -122,11 +1858,18 @@ impl<'a> ScreenSamples<'a> {
     pub fn new(parameters: &'a Settings, gamma: &'a GammaLookup) -> Self {
         Self {
             parameters,
-            gamma,
+            pipeline: SamplePipeline::new(parameters, gamma),
+            enabled_displays: parameters
+                .displays
+                .iter()
+                .map(|display| display.enabled)
+                .collect(),
             factory: None,
             displays: Vec::new(),
-            pixel_offsets: Vec::new(),
-            previous_colors: Vec::new(),
+            pool: Vec::new(),
+            windows: Vec::new(),
+            test_source: None,
+            file_source: None,
             acquired_resources: false,
             frame_count: 0,
             start_tick: None,
@@ -141,153 +1884,345 @@ impl<'a> ScreenSamples<'a> {
             return Ok(());
         }
 
-        let display_len = self.parameters.displays.len();
+        let parameters = self.parameters;
+
+        match (parameters.test_source, &parameters.file_source) {
+            (Some(pattern), _) => self.create_test_source(pattern),
+            (None, Some(file_source)) => self.create_file_source(file_source)?,
+            (None, None) => self.create_displays()?,
+        }
+
+        self.pipeline.reset();
+        self.acquired_resources = true;
+        self.start_tick = Some(Instant::now());
+
+        Ok(())
+    }
+
+    /// Generate frames with a [TestSource] for every configured display instead of acquiring
+    /// any DXGI resources.
+    fn create_test_source(&mut self, pattern: TestPattern) {
+        self.test_source = Some(TestSource::new(pattern));
+    }
+
+    /// Read frames with a [FileSource] for every configured display instead of acquiring any
+    /// DXGI resources.
+    fn create_file_source(&mut self, file_source: &FileSourceConfiguration) -> Result<()> {
+        let source = match FileSource::new(file_source) {
+            Ok(source) => source,
+            Err(_) => return Err(E_FAIL.into()),
+        };
+        self.file_source = Some(source);
+
+        Ok(())
+    }
+
+    /// Match the configured displays to outputs or application windows and acquire the
+    /// resources to capture them.
+    fn create_displays(&mut self) -> Result<()> {
+        let parameters = self.parameters;
+        let display_len = parameters.displays.len();
         self.displays.reserve(display_len);
-        let factory = self.get_factory()?;
+        let mut outputs = self.enumerate_outputs()?.into_iter();
+        let mut led_offset = 0;
+
+        // Match the configured displays to the outputs attached to the desktop in the order that
+        // DXGI enumerates them across all of the adapters. Window entries don't use an output,
+        // and we wait until `take_samples` to look for the window.
+        for (display_index, display) in parameters.displays.iter().enumerate() {
+            let first_led = led_offset;
+            led_offset += display.positions.len();
+
+            if let Some(configuration) = &display.window {
+                if !display.positions.is_empty() {
+                    self.windows.push(WindowResources {
+                        configuration,
+                        capture: None,
+                        last_attempt: None,
+                        display_index,
+                        led_offset: first_led,
+                    });
+                }
+                continue;
+            }
 
-        for i in 0..(display_len as u32) {
-            unsafe {
-                match factory.EnumAdapters1(i) {
-                    Ok(ref adapter) => {
-                        for j in 0..(display_len as u32) {
-                            match adapter.EnumOutputs(j) {
-                                Ok(output) => {
-                                    let output: IDXGIOutput1 = output.cast()?;
-                                    let output_description = match output.GetDesc() {
-                                        Ok(description) => description,
-                                        Err(_) => continue,
-                                    };
-                                    if !output_description.AttachedToDesktop.as_bool() {
-                                        continue;
-                                    }
-                                    let mut device = None;
-                                    let mut context = None;
-                                    if D3D11CreateDevice(
-                                        adapter,
-                                        D3D_DRIVER_TYPE_UNKNOWN,
-                                        HINSTANCE::default(),
-                                        D3D11_CREATE_DEVICE_SINGLETHREADED
-                                            | D3D11_CREATE_DEVICE_BGRA_SUPPORT,
-                                        ptr::null(),
-                                        0,
-                                        D3D11_SDK_VERSION,
-                                        &mut device,
-                                        ptr::null_mut(),
-                                        &mut context,
-                                    )
-                                    .is_err()
-                                    {
-                                        continue;
-                                    }
-                                    let (device, context) = match (device, context) {
-                                        (Some(device), Some(context)) => (device, context),
-                                        _ => continue,
-                                    };
-                                    let duplication = match output.DuplicateOutput(&device) {
-                                        Ok(duplication) => duplication,
-                                        Err(_) => continue,
-                                    };
-                                    let mut duplication_description = Default::default();
-                                    duplication.GetDesc(&mut duplication_description);
-                                    let use_map_desktop_surface = duplication_description
-                                        .DesktopImageInSystemMemory
-                                        .as_bool();
-                                    let bounds = &output_description.DesktopCoordinates;
-                                    let width = bounds.right - bounds.left;
-                                    let height = bounds.bottom - bounds.top;
-                                    let mut staging = None;
-
-                                    if !use_map_desktop_surface {
-                                        let texture_description = D3D11_TEXTURE2D_DESC {
-                                            Width: width as u32,
-                                            Height: height as u32,
-                                            MipLevels: 1,
-                                            ArraySize: 1,
-                                            Format: DXGI_FORMAT_B8G8R8A8_UNORM,
-                                            SampleDesc: DXGI_SAMPLE_DESC {
-                                                Count: 1,
-                                                Quality: 0,
-                                            },
-                                            Usage: D3D11_USAGE_STAGING,
-                                            BindFlags: D3D11_BIND_FLAG(0),
-                                            CPUAccessFlags: D3D11_CPU_ACCESS_READ,
-                                            MiscFlags: D3D11_RESOURCE_MISC_FLAG(0),
-                                        };
-                                        staging =
-                                            Some(device.CreateTexture2D(
-                                                &texture_description,
-                                                ptr::null(),
-                                            )?);
-                                    }
-
-                                    self.displays.push(DisplayResources {
-                                        _adapter: adapter.clone(),
-                                        _device: device,
-                                        context,
-                                        duplication,
-                                        staging,
-                                        acquired_frame: false,
-                                        bounds: SIZE {
-                                            cx: width,
-                                            cy: height,
-                                        },
-                                    })
-                                }
-                                Err(_) => break,
-                            }
-                        }
+            let DesktopOutput {
+                description,
+                mut candidates,
+            } = match outputs.next() {
+                Some(output) => output,
+                None => continue,
+            };
+
+            // Placeholder entries for skipped displays don't need any resources.
+            if display.positions.is_empty() {
+                continue;
+            }
+
+            // Reuse the device from the last time we matched this output if we still can, and
+            // fall back to creating a new one if duplicating the output with it fails.
+            let mut pooled = self
+                .pool
+                .iter()
+                .position(|pooled| pooled.matches(display_index, &description))
+                .map(|index| self.pool.swap_remove(index));
+
+            // Try the adapter which worked last time first.
+            if let Some(pooled_luid) = pooled
+                .as_ref()
+                .and_then(|pooled| Self::adapter_luid(&pooled.adapter))
+            {
+                candidates
+                    .sort_by_key(|(adapter, _)| Self::adapter_luid(adapter) != Some(pooled_luid));
+            }
+
+            // On hybrid GPU systems only the adapter which owns the output can duplicate it, so
+            // keep trying the adapters which enumerated it until one of them succeeds.
+            let mut resources = Err(E_FAIL.into());
+            for (adapter, output) in candidates {
+                if let Some(pooled) = pooled.take() {
+                    resources = Self::create_display(
+                        adapter.clone(),
+                        output.clone(),
+                        &description,
+                        display_index,
+                        first_led,
+                        parameters.get_capture_timeout(),
+                        Some(pooled),
+                    );
+                    if resources.is_ok() {
+                        break;
                     }
-                    Err(_) => break,
                 }
+
+                resources = Self::create_display(
+                    adapter,
+                    output,
+                    &description,
+                    display_index,
+                    first_led,
+                    parameters.get_capture_timeout(),
+                    None,
+                );
+                if resources.is_ok() {
+                    break;
+                }
+            }
+
+            if let Ok(mut resources) = resources {
+                if display.color_profile {
+                    resources.color_profile = ColorProfile::for_device(&description.DeviceName);
+                }
+                self.displays.push(resources);
             }
         }
 
-        if self.displays.is_empty() {
+        // Anything left in the pool didn't match an output this time.
+        self.pool.clear();
+
+        if self.displays.is_empty() && self.windows.is_empty() {
             E_FAIL.ok()?;
         }
 
-        self.pixel_offsets
-            .resize_with(self.displays.len(), Vec::new);
+        Ok(())
+    }
+
+    /// Enumerate every output attached to the desktop on every adapter, in the order that DXGI
+    /// reports them. Both enumerations end with `DXGI_ERROR_NOT_FOUND` once we run out of
+    /// adapters or outputs. On hybrid GPU systems the same output can be enumerated by more
+    /// than one adapter, so each [DesktopOutput] lists all of the adapters that reported it.
+    fn enumerate_outputs(&mut self) -> Result<Vec<DesktopOutput>> {
+        let factory = self.get_factory()?;
+        let mut outputs: Vec<DesktopOutput> = Vec::new();
 
-        for (i, display) in self.parameters.displays.iter().enumerate() {
-            let bounds = &self.displays[i].bounds;
-            let range_x = bounds.cx as f64 / display.horizontal_count as f64;
-            let step_x = range_x / PIXEL_SAMPLES as f64;
-            let range_y = bounds.cy as f64 / display.vertical_count as f64;
-            let step_y = range_y / PIXEL_SAMPLES as f64;
-            self.pixel_offsets[i].resize_with(display.positions.len(), || {
-                let offsets = [None; OFFSET_ARRAY_SIZE];
-                OffsetArray(offsets)
-            });
-            for (j, led) in display.positions.iter().enumerate() {
-                let mut x = [0_usize; PIXEL_SAMPLES];
-                let mut y = [0_usize; PIXEL_SAMPLES];
-                let start_x = (range_x * led.x as f64) + (step_x / 2.0);
-                let start_y = (range_y * led.y as f64) + (step_y / 2.0);
-                for i in 0..PIXEL_SAMPLES {
-                    x[i] = (start_x + (step_x * (i as f64))) as usize;
-                    y[i] = (start_y + (step_y * (i as f64))) as usize;
+        for i in 0.. {
+            let adapter = match unsafe { factory.EnumAdapters1(i) } {
+                Ok(adapter) => adapter,
+                Err(_) => break,
+            };
+
+            for j in 0.. {
+                let output: IDXGIOutput1 = match unsafe { adapter.EnumOutputs(j) } {
+                    Ok(output) => output.cast()?,
+                    Err(_) => break,
+                };
+                let description = match unsafe { output.GetDesc() } {
+                    Ok(description) => description,
+                    Err(_) => continue,
+                };
+
+                if !description.AttachedToDesktop.as_bool() {
+                    continue;
                 }
-                for (row, y) in y.iter().enumerate() {
-                    for (col, x) in x.iter().enumerate() {
-                        let pixel_index = (row * PIXEL_SAMPLES) + col;
-                        self.pixel_offsets[i][j].0[pixel_index] =
-                            Some(PixelOffset { x: *x, y: *y });
-                    }
+
+                match outputs
+                    .iter_mut()
+                    .find(|existing| existing.description.DeviceName == description.DeviceName)
+                {
+                    Some(existing) => existing.candidates.push((adapter.clone(), output)),
+                    None => outputs.push(DesktopOutput {
+                        description,
+                        candidates: vec![(adapter.clone(), output)],
+                    }),
                 }
             }
         }
 
-        self.previous_colors = Vec::new();
-        self.previous_colors.resize(
-            self.parameters.get_total_led_count(),
-            self.parameters.get_min_brightness_color(),
-        );
+        Ok(outputs)
+    }
 
-        self.acquired_resources = true;
-        self.start_tick = Some(Instant::now());
+    /// Get the `AdapterLuid` which uniquely identifies an adapter, even if it's enumerated
+    /// again by a new factory.
+    fn adapter_luid(adapter: &IDXGIAdapter1) -> Option<LUID> {
+        unsafe { adapter.GetDesc1() }
+            .ok()
+            .map(|description| description.AdapterLuid)
+    }
 
-        Ok(())
+    /// Create the D3D11 device, the [IDXGIOutputDuplication] interface, and if necessary the
+    /// `staging` texture for a single output. If there is a [PooledDisplay] for the output,
+    /// reuse its device and the `staging` texture if it still matches the display mode.
+    fn create_display(
+        adapter: IDXGIAdapter1,
+        output: IDXGIOutput1,
+        output_description: &DXGI_OUTPUT_DESC,
+        display_index: usize,
+        led_offset: usize,
+        capture_timeout: u32,
+        pooled: Option<PooledDisplay>,
+    ) -> Result<DisplayResources> {
+        unsafe {
+            let (adapter, device, context, pooled) = match pooled {
+                Some(pooled) => (
+                    pooled.adapter,
+                    pooled.device,
+                    pooled.context,
+                    Some((pooled.staging, pooled.bounds)),
+                ),
+                None => {
+                    let mut device = None;
+                    let mut context = None;
+                    D3D11CreateDevice(
+                        &adapter,
+                        D3D_DRIVER_TYPE_UNKNOWN,
+                        HINSTANCE::default(),
+                        D3D11_CREATE_DEVICE_SINGLETHREADED | D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                        ptr::null(),
+                        0,
+                        D3D11_SDK_VERSION,
+                        &mut device,
+                        ptr::null_mut(),
+                        &mut context,
+                    )?;
+                    match (device, context) {
+                        (Some(device), Some(context)) => (adapter, device, context, None),
+                        _ => return Err(E_FAIL.into()),
+                    }
+                }
+            };
+
+            // DuplicateOutput always converts the desktop to 8-bit BGRA, so ask for 10 bits per
+            // channel with DuplicateOutput1 where it's available. Either way, the display mode
+            // is only a guess at the format until `match_format` sees the first frame.
+            let (duplication, format) = match output.cast::<IDXGIOutput5>() {
+                Ok(output) => {
+                    let duplication = output.DuplicateOutput1(
+                        &device,
+                        0,
+                        DUPLICATION_FORMATS.len() as u32,
+                        DUPLICATION_FORMATS.as_ptr(),
+                    )?;
+                    (duplication, None)
+                }
+                Err(_) => (output.DuplicateOutput(&device)?, Some(SurfaceFormat::Bgra8)),
+            };
+            let mut duplication_description = Default::default();
+            duplication.GetDesc(&mut duplication_description);
+            let use_map_desktop_surface =
+                duplication_description.DesktopImageInSystemMemory.as_bool();
+            let rotation = output_description.Rotation;
+            let format = format
+                .or_else(|| SurfaceFormat::from_dxgi(duplication_description.ModeDesc.Format))
+                .unwrap_or(SurfaceFormat::Bgra8);
+
+            // The duplicated surface has the true dimensions of the display mode in its native
+            // orientation. The desktop coordinates should match once it's rotated, but they
+            // can be scaled if the process isn't DPI aware, so only fall back to them if the
+            // mode is unknown.
+            let mode = &duplication_description.ModeDesc;
+            let (texture_width, texture_height) = if mode.Width > 0 && mode.Height > 0 {
+                (mode.Width as i32, mode.Height as i32)
+            } else {
+                let bounds = &output_description.DesktopCoordinates;
+                let (width, height) = (bounds.right - bounds.left, bounds.bottom - bounds.top);
+                match rotation {
+                    DXGI_MODE_ROTATION_ROTATE90 | DXGI_MODE_ROTATION_ROTATE270 => (height, width),
+                    _ => (width, height),
+                }
+            };
+            let (width, height) = match rotation {
+                DXGI_MODE_ROTATION_ROTATE90 | DXGI_MODE_ROTATION_ROTATE270 => {
+                    (texture_height, texture_width)
+                }
+                _ => (texture_width, texture_height),
+            };
+            let bounds = SIZE {
+                cx: width,
+                cy: height,
+            };
+            let pooled_staging = match pooled {
+                Some((staging, pooled_bounds)) if pooled_bounds == bounds => staging,
+                _ => None,
+            };
+            let mut staging = None;
+
+            if !use_map_desktop_surface {
+                if let Some(pooled_staging) = pooled_staging {
+                    let mut pooled_description = D3D11_TEXTURE2D_DESC::default();
+                    pooled_staging.GetDesc(&mut pooled_description);
+                    if pooled_description.Format == format.to_dxgi()
+                        && pooled_description.Width == texture_width as u32
+                        && pooled_description.Height == texture_height as u32
+                    {
+                        staging = Some(pooled_staging);
+                    }
+                }
+            }
+
+            if !use_map_desktop_surface && staging.is_none() {
+                staging = Some(create_staging(
+                    &device,
+                    format,
+                    texture_width as usize,
+                    texture_height as usize,
+                )?);
+            }
+
+            Ok(DisplayResources {
+                adapter,
+                device,
+                context,
+                duplication,
+                staging,
+                copy_rows: Vec::new(),
+                partial: None,
+                partial_frame: false,
+                format,
+                acquired_frame: false,
+                desktop_map: Cell::new(None),
+                new_frame: true,
+                protected_content: false,
+                capture_timeout,
+                bounds,
+                device_name: output_description.DeviceName,
+                desktop_coordinates: output_description.DesktopCoordinates,
+                monitor: output_description.Monitor,
+                color_profile: None,
+                rotation,
+                display_index,
+                led_offset,
+            })
+        }
     }
 
     /// Free all of the resources acquired in `create_resources`.
@@ -296,21 +2231,30 @@ impl<'a> ScreenSamples<'a> {
             return;
         }
 
-        for device in self
-            .displays
-            .iter_mut()
-            .filter(|device| device.staging.is_some())
-        {
-            unsafe {
-                if device.acquired_frame {
-                    let _ = device.duplication.ReleaseFrame();
-                    device.acquired_frame = false;
-                }
-            }
+        for device in self.displays.iter_mut() {
+            device.release_frame();
         }
 
-        self.displays.clear();
-        self.pixel_offsets.clear();
+        // Keep everything but the duplication interface in the pool, so we can reuse it the
+        // next time we call `create_resources` after a transient error.
+        self.pool = self
+            .displays
+            .drain(..)
+            .map(|device| PooledDisplay {
+                adapter: device.adapter,
+                device: device.device,
+                context: device.context,
+                staging: device.staging,
+                bounds: device.bounds,
+                device_name: device.device_name,
+                desktop_coordinates: device.desktop_coordinates,
+                rotation: device.rotation,
+                display_index: device.display_index,
+            })
+            .collect();
+        self.windows.clear();
+        self.test_source = None;
+        self.file_source = None;
 
         if let Some(start_tick) = self.start_tick {
             let elapsed = (Instant::now() - start_tick).as_secs_f64();
@@ -319,14 +2263,20 @@ impl<'a> ScreenSamples<'a> {
             }
             self.frame_count = 0;
             self.start_tick = None;
-
-            let message = format!("Frame Rate: {}", self.frame_rate);
-            dbg!(message);
         }
 
         self.acquired_resources = false;
     }
 
+    /// Free all of the resources acquired in `create_resources`, including the [PooledDisplay]
+    /// resources which `free_resources` would keep. Call this when the displays change, since
+    /// the pool won't match the new outputs anyway.
+    pub fn free_all_resources(&mut self) {
+        self.free_resources();
+        self.pool.clear();
+        self.pipeline.clear_displays();
+    }
+
     /// If resources were successfully acquired in `create_resources`, iterate over the
     /// displays and calculate the new values in `previous_colors` for each sample block.
     pub fn take_samples(&mut self) -> Result<()> {
@@ -334,193 +2284,239 @@ impl<'a> ScreenSamples<'a> {
             E_FAIL.ok()?;
         }
 
-        // Take a screenshot for all of the devices that require a staging texture.
-        for device in self
-            .displays
-            .iter_mut()
-            .filter(|device| device.staging.is_some())
-        {
-            unsafe {
-                if device.acquired_frame {
-                    let _ = device.duplication.ReleaseFrame();
-                    device.acquired_frame = false;
-                }
+        if let Some(mut source) = self.test_source.take() {
+            let result = self.sample_frame(&mut source);
+            self.test_source = Some(source);
 
-                let mut info = Default::default();
-                let mut resource = None;
-                match device.duplication.AcquireNextFrame(
-                    self.parameters.get_delay(),
-                    &mut info,
-                    &mut resource,
-                ) {
-                    Ok(()) => {
-                        if let (Some(staging), Some(screen_texture)) =
-                            (device.staging.clone(), resource)
-                        {
-                            let screen_texture: ID3D11Texture2D = screen_texture.cast()?;
-                            device.acquired_frame = true;
-                            device.context.CopyResource(staging, screen_texture);
-                        }
-                    }
-                    Err(error) => match error.code() {
-                        DXGI_ERROR_ACCESS_LOST | DXGI_ERROR_INVALID_CALL => {
-                            // Recreate the duplication interface if this fails with with an expected
-                            // error that invalidates the duplication interface or that might allow us
-                            // to switch to MapDesktopSurface.
-                            self.free_resources();
-                            return Err(error);
-                        }
-                        _ => (),
-                    },
-                };
+            return result;
+        }
+
+        if let Some(mut source) = self.file_source.take() {
+            let result = self.sample_frame(&mut source);
+            self.file_source = Some(source);
+
+            return result;
+        }
+
+        let parameters = self.parameters;
+
+        // Check the foreground windows first, so the rows we copy with `partial_copy` match
+        // the sample blocks.
+        for device in self.displays.iter_mut() {
+            let i = device.display_index;
+            if parameters.displays[i].follow_foreground {
+                self.pipeline.set_foreground(i, device.foreground_rect());
             }
+
+            device.set_copy_rows(self.pipeline.copy_rows(i, &device.bounds, device.rotation));
         }
 
-        let mut previous_color = self.previous_colors.iter_mut();
+        // Take a screenshot for all of the displays before sampling any of them.
+        for index in 0..self.displays.len() {
+            if !self.is_display_enabled(self.displays[index].display_index) {
+                self.displays[index].release_frame();
+                continue;
+            }
 
-        for (i, device) in self.displays.iter_mut().enumerate() {
-            let display = &self.parameters.displays[i];
-            for j in 0..display.positions.len() {
-                let offsets = &self.pixel_offsets[i][j];
-                let (pixels, pitch) = if let Some(staging) = &device.staging {
-                    unsafe {
-                        let staging_map = match device.context.Map(staging, 0, D3D11_MAP_READ, 0) {
-                            Ok(map) => map,
-                            Err(_) => continue,
-                        };
-                        let pixels: *const u8 = mem::transmute(staging_map.pData);
-                        let pitch = staging_map.RowPitch as usize;
-                        (pixels, pitch)
-                    }
-                } else {
-                    unsafe {
-                        let desktop_map = match device.duplication.MapDesktopSurface() {
-                            Ok(map) => map,
-                            Err(error) => match error.code() {
-                                DXGI_ERROR_ACCESS_LOST
-                                | DXGI_ERROR_UNSUPPORTED
-                                | DXGI_ERROR_INVALID_CALL => {
-                                    // Recreate the duplication interface if this fails with with an expected
-                                    // error that invalidates the duplication interface or requires that we
-                                    // switch to AcquireNextFrame.
-                                    self.free_resources();
-                                    return Err(error);
-                                }
-                                _ => continue,
-                            },
-                        };
-                        let pixels: *const u8 = mem::transmute(desktop_map.pBits);
-                        let pitch = desktop_map.Pitch as usize;
-                        (pixels, pitch)
-                    }
-                };
+            if let Err(error) = self.displays[index].acquire_frame() {
+                self.recover(error)?;
+            }
+        }
 
-                let previous_color = previous_color.next().unwrap();
+        for index in 0..self.displays.len() {
+            let device = &self.displays[index];
+            let i = device.display_index;
+            let led_count = parameters.displays[i].positions.len();
+            let leds = device.led_offset..device.led_offset + led_count;
 
-                let divisor = OFFSET_ARRAY_SIZE as f64;
-                let (r, g, b) = offsets
-                    .0
-                    .iter()
-                    .map(|offset| {
-                        if let Some(ref offset) = offset {
-                            let byte_offset =
-                                (offset.y * pitch) + (offset.x * mem::size_of::<u32>());
-                            let pixels = ptr::slice_from_raw_parts(
-                                pixels,
-                                byte_offset + mem::size_of::<u32>(),
-                            );
-                            unsafe {
-                                (
-                                    (*pixels)[byte_offset + 2] as f64,
-                                    (*pixels)[byte_offset + 1] as f64,
-                                    (*pixels)[byte_offset] as f64,
-                                )
-                            }
-                        } else {
-                            unreachable!()
-                        }
-                    })
-                    .reduce(|total, rgb| (total.0 + rgb.0, total.1 + rgb.1, total.2 + rgb.2))
-                    .unwrap();
-                let (mut r, mut g, mut b) = (r / divisor, g / divisor, b / divisor);
-
-                // Average in the previous color if fading is enabled.
-                if self.parameters.fade.abs() > f64::EPSILON {
-                    r = r * self.parameters.get_weight()
-                        + ((*previous_color & 0xFF000000) >> 24) as f64 * self.parameters.fade;
-                    g = g * self.parameters.get_weight()
-                        + ((*previous_color & 0xFF0000) >> 16) as f64 * self.parameters.fade;
-                    b = b * self.parameters.get_weight()
-                        + ((*previous_color & 0xFF00) >> 8) as f64 * self.parameters.fade;
-                }
+            if !self.is_display_enabled(i) {
+                self.pipeline.turn_off(leds);
+                continue;
+            }
 
-                let min_brightness = self.parameters.min_brightness as f64;
-                let sum = r + b + g;
-
-                // Boost pixels that fall below the minimum brightness.
-                if sum < min_brightness {
-                    if sum.abs() < f64::EPSILON {
-                        // Spread equally to R, G, and B.
-                        let value = min_brightness / 3.0;
-
-                        r = value;
-                        g = value;
-                        b = value;
-                    } else {
-                        // Spread the "brightness deficit" back into R, G, and B in proportion
-                        // to their individual contribition to that deficit.  Rather than simply
-                        // boosting all pixels at the low end, this allows deep (but saturated)
-                        // colors to stay saturated...they don't "pink out."
-                        let deficit = min_brightness - sum;
-                        let sum_2 = sum * 2.0;
-
-                        r += (deficit * (sum - r)) / sum_2;
-                        g += (deficit * (sum - g)) / sum_2;
-                        b += (deficit * (sum - b)) / sum_2;
-                    }
+            if device.protected_content {
+                // Don't sample the masked out content, which would just make the LEDs go dark.
+                if parameters.protected_content == ProtectedContentFallback::MinBrightness {
+                    self.pipeline.fade_to_min_brightness(leds);
+                } else {
+                    self.pipeline.fade(leds);
                 }
+                continue;
+            }
 
-                let (r, g, b, a) = (
-                    (r as u32 & 0xFF) << 24,
-                    (g as u32 & 0xFF) << 16,
-                    (b as u32 & 0xFF) << 8,
-                    0xFF_u32,
-                );
-                *previous_color = r | g | b | a;
+            if !device.new_frame {
+                // Nothing changed on this display, so skip sampling and just keep fading towards
+                // the last averages.
+                self.pipeline.fade(leds);
+                continue;
+            }
+
+            if let Err(error) = self
+                .pipeline
+                .sample(i, leds, device, device.color_profile.as_ref())
+            {
+                self.recover(error)?;
+            }
+        }
+
+        for window in self.windows.iter_mut() {
+            let i = window.display_index;
+            let display = &parameters.displays[i];
+            let leds = window.led_offset..window.led_offset + display.positions.len();
+
+            if !self.enabled_displays.get(i).copied().unwrap_or(true) {
+                self.pipeline.turn_off(leds);
+                continue;
+            }
+
+            let new_frame = window.create().is_ok() && window.acquire_frame().unwrap_or(false);
+            if !new_frame {
+                self.pipeline.fade(leds);
+                continue;
             }
+
+            // If we can't read the window contents, try again on the next frame.
+            let _ = self.pipeline.sample(i, leds, window, None);
         }
 
+        self.pipeline.finish_frame();
         self.frame_count += 1;
 
         Ok(())
     }
 
-    /// Copy the values in `previous_colors` with gamma correction to the `serial`
-    /// [PixelBuffer].
-    pub fn render_serial(&self, serial: &mut PixelBuffer) -> bool {
+    /// Sample every configured display from the same frame generated by a [TestSource] or
+    /// read by a [FileSource].
+    fn sample_frame(&mut self, source: &mut dyn CaptureSource) -> Result<()> {
+        let new_frame = source.acquire_frame()?;
+        let enabled: Vec<bool> = (0..self.parameters.displays.len())
+            .map(|i| self.is_display_enabled(i))
+            .collect();
+
+        if new_frame {
+            let bounds = source.bounds();
+            let surface = source.read_pixels()?;
+            self.pipeline
+                .process_frame(Some((&surface, &bounds)), &enabled);
+            source.release_pixels();
+        } else {
+            self.pipeline.process_frame(None, &enabled);
+        }
+        self.frame_count += 1;
+
+        Ok(())
+    }
+
+    /// Handle an [Err] value from one of the `displays`. If the duplication interface was lost
+    /// or the call might succeed with `MapDesktopSurface` instead of `AcquireNextFrame` (or
+    /// vice versa), free the resources so they will be recreated. If the GPU was reset or the
+    /// driver was updated, the devices in the pool are lost too and everything needs to be
+    /// recreated. Any other errors just skip that display for this frame.
+    fn recover(&mut self, error: Error) -> Result<()> {
+        match error.code() {
+            DXGI_ERROR_ACCESS_LOST | DXGI_ERROR_UNSUPPORTED | DXGI_ERROR_INVALID_CALL => {
+                self.free_resources();
+                Err(error)
+            }
+            DXGI_ERROR_DEVICE_REMOVED | DXGI_ERROR_DEVICE_RESET => {
+                self.free_all_resources();
+                Err(error)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Copy the values in `corrected_colors` for the range of LEDs in the [SerialOutput] to
+    /// the `serial` [PixelBuffer], limited to their [crate::settings::BrightnessZone].
+    pub fn render_serial(&self, output: &SerialOutput, serial: &mut PixelBuffer) -> bool {
+        self.render_range(output.first_led, output.led_count, serial)
+    }
+
+    /// Copy the values in `corrected_colors` for `led_count` LEDs starting at `first_led` to
+    /// the `serial` [PixelBuffer], limited to their [crate::settings::BrightnessZone].
+    pub fn render_range(
+        &self,
+        first_led: usize,
+        led_count: usize,
+        serial: &mut PixelBuffer,
+    ) -> bool {
         serial.clear();
 
         if !self.acquired_resources {
             return false;
         }
 
-        for pixel in self.previous_colors.iter() {
-            let (r, g, b) = (
-                self.gamma.red(((*pixel & 0xFF000000) >> 24) as u8),
-                self.gamma.green(((*pixel & 0xFF0000) >> 16) as u8),
-                self.gamma.blue(((*pixel & 0xFF00) >> 8) as u8),
-            );
-            let (r, g, b, a) = (
-                (r as u32 & 0xFF) << 24,
-                (g as u32 & 0xFF) << 16,
-                (b as u32 & 0xFF) << 8,
-                0xFF_u32,
-            );
-
+        for pixel in self
+            .pipeline
+            .corrected_colors
+            .iter()
+            .skip(first_led)
+            .take(led_count)
+        {
             // Write the gamma corrected values to the serial data.
-            serial.add(r | g | b | a);
+            serial.add(*pixel);
+        }
+
+        serial.finish();
+
+        true
+    }
+
+    /// Get the average of the values in `previous_colors`, i.e. the overall color of the LEDs
+    /// before gamma correction.
+    pub fn average_color(&self) -> u32 {
+        Self::average(self.pipeline.previous_colors.iter())
+    }
+
+    /// Get the average of the values in `previous_colors` for each of the `leds` indices,
+    /// without gamma correction, e.g. for a lamp which covers several LEDs.
+    pub fn average_leds(&self, leds: &[usize]) -> u32 {
+        Self::average(
+            leds.iter()
+                .filter_map(|led| self.pipeline.previous_colors.get(*led)),
+        )
+    }
+
+    /// Average the RGB channels of the `pixels`.
+    fn average<'b>(pixels: impl Iterator<Item = &'b u32>) -> u32 {
+        let (r, g, b, count) =
+            pixels.fold((0_u64, 0_u64, 0_u64, 0_u64), |(r, g, b, count), pixel| {
+                (
+                    r + u64::from((*pixel & 0xFF000000) >> 24),
+                    g + u64::from((*pixel & 0xFF0000) >> 16),
+                    b + u64::from((*pixel & 0xFF00) >> 8),
+                    count + 1,
+                )
+            });
+        if count == 0 {
+            return 0;
+        }
+
+        (((r / count) << 24) | ((g / count) << 16) | ((b / count) << 8) | 0xFF) as u32
+    }
+
+    /// Copy the values in `previous_colors` for each of the `leds` indices in order, without
+    /// gamma correction, to the `pixels` [PixelBuffer], e.g. for an image which repeats some of
+    /// the LEDs.
+    pub fn render_leds(&self, leds: &[usize], pixels: &mut PixelBuffer) -> bool {
+        pixels.clear();
+
+        if !self.acquired_resources {
+            return false;
+        }
+
+        for led in leds {
+            pixels.add(
+                self.pipeline
+                    .previous_colors
+                    .get(*led)
+                    .copied()
+                    .unwrap_or_default(),
+            );
         }
 
+        pixels.finish();
+
         true
     }
 
@@ -555,13 +2551,13 @@ impl<'a> ScreenSamples<'a> {
                     }
 
                     pixel_offset -= range.display_index.len();
-                    previous_color_index += self.pixel_offsets[display].len();
+                    previous_color_index += self.parameters.displays[display].positions.len();
                     display += 1;
                 }
 
                 if display < range.display_index.len() {
                     previous_color_index += range.display_index[display][pixel_offset];
-                    pixel_color = self.previous_colors[previous_color_index];
+                    pixel_color = self.pipeline.previous_colors[previous_color_index];
                 }
 
                 *sample = pixel_color;
@@ -600,13 +2596,90 @@ impl<'a> ScreenSamples<'a> {
         true
     }
 
+    /// Replace the values in `previous_colors` with `colors` from somewhere other than the
+    /// displays, e.g. the [crate::opc_receiver::OpcReceiver], so every output renders them
+    /// instead of the last samples. Any LEDs past the end of `colors` keep their samples. They
+    /// still go through `quantize`, so the brightness zones and limits apply to them too.
+    pub fn set_colors(&mut self, colors: &[u32]) {
+        for (exact_color, color) in self.pipeline.exact_colors.iter_mut().zip(colors) {
+            *exact_color = unpack(*color);
+        }
+        self.pipeline.quantize();
+    }
+
+    /// Start or stop collecting a low resolution [Thumbnail] of each frame for the [Preview].
+    /// This needs the whole frame, so it disables `partial_copy` while it's enabled.
+    pub fn set_preview_thumbnails(&mut self, thumbnails: bool) {
+        self.pipeline.set_thumbnails(thumbnails);
+    }
+
+    /// Get a [Preview] of the colors computed in the last call to `take_samples`.
+    pub fn preview(&self) -> Preview {
+        self.pipeline.preview()
+    }
+
+    /// Test if any of the enabled displays are masking out protected content, so the LEDs are
+    /// showing the [ProtectedContentFallback] instead of the desktop.
+    pub fn is_content_protected(&self) -> bool {
+        self.displays
+            .iter()
+            .any(|device| device.protected_content && self.is_display_enabled(device.display_index))
+    }
+
+    /// Enable or disable sampling each configured display, by index in [Settings]. The LEDs for
+    /// a disabled display are turned off, but it keeps its resources so it can resume quickly.
+    pub fn set_enabled_displays(&mut self, enabled_displays: &[bool]) {
+        if self.enabled_displays != enabled_displays {
+            self.enabled_displays = enabled_displays.to_vec();
+        }
+    }
+
+    /// Set the global brightness (0-255) for every LED, if it was changed at runtime. The new
+    /// brightness takes effect at the end of the next frame.
+    pub fn set_brightness(&mut self, brightness: Option<u8>) {
+        self.pipeline.brightness =
+            brightness.map_or(1.0, |brightness| f64::from(brightness) / 255.0);
+    }
+
+    /// Test if the configured display at `display_index` in [Settings] is enabled.
+    fn is_display_enabled(&self, display_index: usize) -> bool {
+        self.enabled_displays
+            .get(display_index)
+            .copied()
+            .unwrap_or(true)
+    }
+
+    /// Get the effective frame rate since the last call to `create_resources`, or between the
+    /// last calls to `create_resources` and `free_resources` if the resources were freed.
+    pub fn frame_rate(&self) -> f64 {
+        match self.start_tick {
+            Some(start_tick) => {
+                let elapsed = (Instant::now() - start_tick).as_secs_f64();
+                if elapsed > 0.0 {
+                    self.frame_count as f64 / elapsed
+                } else {
+                    self.frame_rate
+                }
+            }
+            None => self.frame_rate,
+        }
+    }
+
     /// Test if we acquired the resources we need with `create_resources` to call `take_samples`.
     pub fn is_empty(&self) -> bool {
         !self.acquired_resources
     }
 
-    /// Convenience function to create an instance of [IDXGIFactory1].
+    /// Convenience function to create an instance of [IDXGIFactory1]. The factory caches the
+    /// adapters and outputs, so if a monitor was added or removed since we created it, we need
+    /// to create a new one to enumerate them again.
     fn get_factory(&mut self) -> Result<IDXGIFactory1> {
+        if let Some(factory) = &self.factory {
+            if !unsafe { factory.IsCurrent() }.as_bool() {
+                self.factory = None;
+            }
+        }
+
         if self.factory.is_none() {
             self.factory = Some(unsafe { CreateDXGIFactory1() }?);
         }
@@ -614,3 +2687,169 @@ impl<'a> ScreenSamples<'a> {
         Ok(self.factory.as_ref().unwrap().clone())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Size of the frames in the tests, which cover a display with 2 LEDs side by side.
+    const WIDTH: usize = 4;
+    const HEIGHT: usize = 2;
+
+    /// Parse [Settings] for a display with 2 LEDs side by side, with the `min_brightness` and
+    /// any other `options`.
+    fn settings(min_brightness: u8, options: &str) -> Settings {
+        Settings::from_str(&format!(
+            r#"{{
+                "minBrightness": {},
+                "fade": 0,
+                "timeout": 5000,
+                "fpsMax": 30,
+                "throttleTimer": 3000,
+                "displays": [
+                    {{
+                        "horizontalCount": 2,
+                        "verticalCount": 1,
+                        "positions": [ {{ "x": 0, "y": 0 }}, {{ "x": 1, "y": 0 }} ]
+                    }}
+                ],
+                "servers": []{}
+            }}"#,
+            min_brightness, options
+        ))
+        .expect("parse the settings")
+    }
+
+    /// Fill a BGRA frame with the `left` color in the left half and the `right` color in the
+    /// right half.
+    fn frame(left: (u8, u8, u8), right: (u8, u8, u8)) -> Vec<u8> {
+        (0..WIDTH * HEIGHT)
+            .flat_map(|pixel| {
+                let (r, g, b) = if pixel % WIDTH < WIDTH / 2 {
+                    left
+                } else {
+                    right
+                };
+                [b, g, r, 0xFF]
+            })
+            .collect()
+    }
+
+    /// Run one frame of `pixels` through a new [SamplePipeline] and return the
+    /// `previous_colors` and the `corrected_colors`.
+    fn process(settings: &Settings, pixels: &[u8], bounds: SIZE) -> (Vec<u32>, Vec<u32>) {
+        let gamma = GammaLookup::new(&settings.gamma);
+        let mut pipeline = SamplePipeline::new(settings, &gamma);
+        pipeline.reset();
+        let surface = MappedSurface {
+            pixels,
+            pitch: bounds.cx as usize * mem::size_of::<u32>(),
+            format: SurfaceFormat::Bgra8,
+            rows: None,
+        };
+        pipeline.process_frame(Some((&surface, &bounds)), &[]);
+        (pipeline.previous_colors, pipeline.corrected_colors)
+    }
+
+    fn bounds() -> SIZE {
+        SIZE {
+            cx: WIDTH as i32,
+            cy: HEIGHT as i32,
+        }
+    }
+
+    #[test]
+    fn average_blocks() {
+        let settings = settings(0, "");
+        let gamma = GammaLookup::new(&settings.gamma);
+        let (colors, corrected) = process(&settings, &frame((200, 100, 50), (0, 0, 255)), bounds());
+        assert_eq!(colors, [0xC86432FF, 0x0000FFFF]);
+        assert_eq!(
+            corrected,
+            [
+                u32::from_be_bytes([gamma.red(200), gamma.green(100), gamma.blue(50), 0xFF]),
+                u32::from_be_bytes([0, 0, gamma.blue(255), 0xFF]),
+            ]
+        );
+    }
+
+    #[test]
+    fn black_level_and_min_brightness() {
+        let settings = settings(60, r#", "blackLevel": 12"#);
+        let (colors, _) = process(&settings, &frame((8, 8, 8), (10, 20, 0)), bounds());
+        assert_eq!(colors, [0x000000FF, 0x14190FFF]);
+    }
+
+    #[test]
+    fn measured_black_skips_min_brightness() {
+        let settings = settings(60, r#", "blackLevel": 12"#);
+        let (colors, _) = process(&settings, &frame((8, 8, 8), (0, 0, 11)), bounds());
+        assert_eq!(colors, [0x000000FF, 0x000000FF]);
+    }
+
+    #[test]
+    fn min_brightness_fallback_below_black_level() {
+        let settings = settings(60, r#", "blackLevel": 12"#);
+        let gamma = GammaLookup::new(&settings.gamma);
+        let mut pipeline = SamplePipeline::new(&settings, &gamma);
+        pipeline.reset();
+        pipeline.fade_to_min_brightness(0..2);
+        pipeline.finish_frame();
+        assert_eq!(pipeline.previous_colors, [0x141414FF, 0x141414FF]);
+    }
+
+    #[test]
+    fn limit_brightness_zones() {
+        let settings = settings(
+            0,
+            r#", "brightnessZones": [ { "firstLed": 1, "ledCount": 1, "maxBrightness": 100 } ]"#,
+        );
+        let (colors, _) = process(&settings, &frame((200, 100, 0), (200, 100, 0)), bounds());
+        assert_eq!(colors, [0xC86400FF, 0x643200FF]);
+    }
+
+    #[test]
+    fn limit_power() {
+        let settings = settings(
+            0,
+            r#",
+                "gamma": { "exponent": 1, "green": { "max": 255 }, "blue": { "max": 255 } },
+                "powerLimit": { "milliampsPerChannel": 20, "maxMilliamps": 30 }"#,
+        );
+        let (colors, corrected) = process(&settings, &frame((255, 255, 255), (0, 0, 0)), bounds());
+        assert_eq!(colors, [0x7F7F7FFF, 0x000000FF]);
+        assert_eq!(corrected, colors);
+    }
+
+    #[test]
+    fn average_10_bit_samples() {
+        // Two pixels which are both 128 in 8 bits, but 513 and 515 in 10 bits.
+        let pixels: Vec<u8> = [513_u32 | (4 << 10), 515 | (4 << 10)]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let surface = MappedSurface {
+            pixels: &pixels,
+            pitch: pixels.len(),
+            format: SurfaceFormat::Rgb10A2,
+            rows: None,
+        };
+        let mut offsets = OffsetArray([None; OFFSET_ARRAY_SIZE]);
+        offsets.0[0] = Some(PixelOffset { x: 0, y: 0 });
+        offsets.0[1] = Some(PixelOffset { x: 1, y: 0 });
+        assert_eq!(
+            offsets.average(&surface, Averaging::Encoded),
+            (128.5, 1.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn sample_test_source() {
+        let settings = settings(0, "");
+        let mut source = TestSource::new(TestPattern::SolidColors);
+        let bounds = source.bounds();
+        let (pixels, _) = source.next_frame();
+        let (colors, _) = process(&settings, pixels, bounds);
+        assert_eq!(colors, [0xFF0000FF, 0xFF0000FF]);
+    }
+}
@@ -1,18 +1,34 @@
-use std::{mem, ptr};
+use std::{
+    fmt, fs, iter, mem, ptr,
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
 
+use serde::{Deserialize, Serialize};
 use windows::Win32::{
-    Devices::Communication::{
-        GetCommState, SetCommState, SetCommTimeouts, COMMTIMEOUTS, DCB, NOPARITY, ONESTOPBIT,
+    Devices::{
+        Communication::{
+            GetCommState, SetCommState, SetCommTimeouts, COMMTIMEOUTS, DCB, NOPARITY, ONESTOPBIT,
+        },
+        DeviceAndDriverInstallation::{
+            SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInfo, SetupDiGetClassDevsW,
+            SetupDiGetDeviceInstanceIdW, SetupDiOpenDevRegKey, DICS_FLAG_GLOBAL, DIGCF_PRESENT,
+            DIREG_DEV, GUID_DEVCLASS_PORTS, SP_DEVINFO_DATA,
+        },
     },
     Foundation::{
-        CloseHandle, GetLastError, ERROR_IO_INCOMPLETE, ERROR_IO_PENDING, HANDLE,
-        INVALID_HANDLE_VALUE, PWSTR,
+        CloseHandle, GetLastError, ERROR_IO_INCOMPLETE, ERROR_IO_PENDING, ERROR_SUCCESS, HANDLE,
+        HWND, INVALID_HANDLE_VALUE, PWSTR,
     },
     Storage::FileSystem::{
-        CreateFileW, ReadFile, WriteFile, FILE_ACCESS_FLAGS, FILE_ATTRIBUTE_NORMAL,
-        FILE_FLAG_OVERLAPPED, OPEN_EXISTING,
+        CreateFileW, ReadFile, WriteFile, FILE_ACCESS_FLAGS, FILE_FLAG_OVERLAPPED, OPEN_EXISTING,
     },
     System::{
+        Registry::{
+            RegCloseKey, RegEnumValueW, RegOpenKeyExW, RegQueryValueExW, RegSetValueExW, HKEY,
+            HKEY_LOCAL_MACHINE, KEY_READ, KEY_SET_VALUE, REG_DWORD, REG_SZ,
+        },
         SystemServices::{GENERIC_READ, GENERIC_WRITE},
         Threading::CreateEventW,
         WindowsProgramming::CBR_115200,
@@ -20,11 +36,96 @@ use windows::Win32::{
     },
 };
 
-use crate::{pixel_buffer::PixelBuffer, settings::Settings};
+use crate::{
+    health::{HealthEvent, HealthSender},
+    pixel_buffer::PixelBuffer,
+    settings::{BusyPolicy, SerialOutput, Settings},
+    stats::SerialStats,
+};
 
 /// Messages to and from the Adalight Arduino sketch (program) all start with this header/cookie.
 const COOKIE: [u8; 4] = [b'A', b'd', b'a', b'\n'];
 
+/// ASCII ACK byte which a sketch with flow control sends back after it latches each frame.
+const ACK: u8 = 0x06;
+
+/// Number of frame delays to wait for an [ACK] before sending the next frame anyway, in case
+/// the sketch doesn't support flow control or the [ACK] was lost.
+const ACK_TIMEOUT_FRAMES: u32 = 4;
+
+/// Position of the `fDtrControl` bits in the [DCB] bitfield.
+const DTR_CONTROL_SHIFT: u32 = 4;
+
+/// Position of the `fRtsControl` bits in the [DCB] bitfield.
+const RTS_CONTROL_SHIFT: u32 = 12;
+
+/// Both `fDtrControl` and `fRtsControl` are 2 bits wide, where 0 disables the line and 1
+/// enables (asserts) it.
+const LINE_CONTROL_MASK: u32 = 0b11;
+
+/// Baud rates which we'll try when probing for the Arduino, from fastest to slowest.
+const BAUD_RATES: [u32; 5] = [2_000_000, 1_000_000, 500_000, 230_400, CBR_115200];
+
+/// Registry key where the serial port drivers publish the names of the COM ports which exist.
+const SERIALCOMM_KEY: &str = "HARDWARE\\DEVICEMAP\\SERIALCOMM";
+
+/// File next to `AdaLight.config.json` where the last working port for each
+/// [SerialOutput] is remembered.
+const STATE_FILE: &str = "AdaLight.port.json";
+
+/// The last COM port where we found the Arduino, and the device instance ID of the USB serial
+/// adapter which was attached to it at the time.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct PortState {
+    port_number: u8,
+    device_id: Option<String>,
+    baud_rate: Option<u32>,
+}
+
+impl PortState {
+    /// Look up the device instance ID of the `port_number` to go with it, and remember the
+    /// `baud_rate` where the Arduino answered.
+    fn new(port_number: u8, baud_rate: u32) -> Self {
+        Self {
+            port_number,
+            baud_rate: Some(baud_rate),
+            device_id: SerialPort::port_devices()
+                .into_iter()
+                .find(|(device_port, _)| *device_port == port_number)
+                .map(|(_, device_id)| device_id),
+        }
+    }
+
+    /// Read the [PortState] for each [SerialOutput] from the [STATE_FILE], if it exists and
+    /// can be parsed.
+    fn load_all() -> Vec<Option<Self>> {
+        fs::read_to_string(STATE_FILE)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Read the [PortState] for the [SerialOutput] at `index`.
+    fn load(index: usize) -> Option<Self> {
+        Self::load_all().get(index).cloned().flatten()
+    }
+
+    /// Replace the [PortState] for the [SerialOutput] at `index` in the [STATE_FILE]. This is
+    /// just a hint for the next time we open the port, so it's not an error if we can't save it.
+    fn save(self, index: usize) {
+        let mut states = Self::load_all();
+        if states.len() <= index {
+            states.resize(index + 1, None);
+        }
+        states[index] = Some(self);
+
+        if let Ok(json) = serde_json::to_string(&states) {
+            let _ = fs::write(STATE_FILE, json);
+        }
+    }
+}
+
 /// Resources associated with an open serial port in Windows using [OVERLAPPED] I/O.
 struct PortResources {
     pub port_handle: HANDLE,
@@ -67,173 +168,819 @@ impl Drop for PortResources {
     }
 }
 
+/// Overlapped read which watches for the [ACK] after each frame when flow control is enabled.
+struct FrameAck {
+    /// Event [HANDLE] which is signaled when the [OVERLAPPED] read completes.
+    event: HANDLE,
+
+    /// The [OVERLAPPED] struct for the pending read.
+    overlapped: Box<OVERLAPPED>,
+
+    /// The byte which is being read.
+    buffer: Box<[u8; 1]>,
+
+    /// True if a read has been started and hasn't completed yet.
+    read_pending: bool,
+
+    /// When the last frame was sent, if we're still waiting for its [ACK].
+    awaiting: Option<Instant>,
+}
+
+impl FrameAck {
+    /// Allocate a new [FrameAck] struct.
+    fn new() -> Self {
+        Self {
+            event: unsafe { CreateEventW(ptr::null(), true, false, PWSTR::default()) },
+            overlapped: Box::default(),
+            buffer: Box::new([0_u8; 1]),
+            read_pending: false,
+            awaiting: None,
+        }
+    }
+
+    /// Check for an [ACK] without blocking, skipping over anything else the sketch sends,
+    /// e.g. the [COOKIE] heartbeat. Returns `true` if an [ACK] arrived.
+    fn poll(&mut self, port_handle: HANDLE) -> bool {
+        loop {
+            unsafe {
+                if !self.read_pending {
+                    *self.overlapped = OVERLAPPED {
+                        hEvent: self.event,
+                        ..Default::default()
+                    };
+
+                    if !ReadFile(
+                        port_handle,
+                        self.buffer.as_mut_ptr() as *mut _,
+                        self.buffer.len() as u32,
+                        ptr::null_mut(),
+                        &mut *self.overlapped,
+                    )
+                    .as_bool()
+                        && ERROR_IO_PENDING != GetLastError()
+                    {
+                        return false;
+                    }
+
+                    self.read_pending = true;
+                }
+
+                let mut cb = 0_u32;
+                if !GetOverlappedResult(port_handle, &*self.overlapped, &mut cb, false).as_bool() {
+                    if GetLastError() != ERROR_IO_INCOMPLETE {
+                        self.read_pending = false;
+                    }
+                    return false;
+                }
+
+                self.read_pending = false;
+                match (cb, self.buffer[0]) {
+                    (1, ACK) => return true,
+                    // The read timed out, so try again next frame.
+                    (0, _) => return false,
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    /// Cancel the pending read and stop waiting for an [ACK], e.g. before closing the port.
+    fn cancel(&mut self, port_handle: HANDLE) {
+        if self.read_pending {
+            let mut cb = 0_u32;
+            unsafe {
+                CancelIo(port_handle);
+                GetOverlappedResult(port_handle, &*self.overlapped, &mut cb, true);
+            }
+            self.read_pending = false;
+        }
+
+        self.awaiting = None;
+    }
+}
+
+impl Drop for FrameAck {
+    fn drop(&mut self) {
+        if INVALID_HANDLE_VALUE != self.event {
+            unsafe {
+                CloseHandle(self.event);
+            }
+            self.event = INVALID_HANDLE_VALUE;
+        }
+    }
+}
+
+/// Background thread which scans the COM ports for each [SerialPort] that needs it, so the
+/// [crate::update_timer::UpdateTimer] can start driving any other outputs in the meantime.
+pub struct PortDiscovery {
+    /// Configuration parameters which the background thread shares with the worker thread.
+    parameters: Arc<Settings>,
+
+    /// Receive the index, port number, and baud rate of each [SerialPort] which was found,
+    /// while a scan is in progress.
+    rx: Option<mpsc::Receiver<Vec<(usize, u8, u32)>>>,
+}
+
+impl PortDiscovery {
+    /// Allocate a new [PortDiscovery] struct, without starting a scan.
+    pub fn new(parameters: Arc<Settings>) -> Self {
+        Self {
+            parameters,
+            rx: None,
+        }
+    }
+
+    /// True if the background thread is still scanning.
+    pub fn is_running(&self) -> bool {
+        self.rx.is_some()
+    }
+
+    /// Start scanning for the [SerialPort] at each of the `indices` on a background thread,
+    /// skipping any `claimed` ports, unless we're already scanning.
+    fn start(&mut self, indices: Vec<usize>, mut claimed: Vec<u8>) {
+        if self.is_running() {
+            return;
+        }
+
+        let parameters = self.parameters.clone();
+        let (tx, rx) = mpsc::channel();
+        self.rx = Some(rx);
+        thread::spawn(move || {
+            let mut found = Vec::new();
+            for index in indices {
+                let mut port = SerialPort::new(&parameters, index, HealthSender::default());
+                if port.locate(&claimed) {
+                    claimed.push(port.port_number);
+                    found.push((index, port.port_number, port.baud_rate));
+                }
+            }
+
+            // The worker thread may have stopped while we were scanning.
+            let _ = tx.send(found);
+        });
+    }
+
+    /// Get the ports which the background thread found, if it finished scanning.
+    fn poll(&mut self) -> Option<Vec<(usize, u8, u32)>> {
+        let found = match self.rx.as_ref()?.try_recv() {
+            Ok(found) => found,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => Vec::new(),
+        };
+
+        self.rx = None;
+        Some(found)
+    }
+}
+
+/// Colors which [SerialPort::self_test] sends to the whole strip in turn: red, green, blue,
+/// and white.
+const TEST_PATTERN: [u32; 4] = [0xFF000000, 0x00FF0000, 0x0000FF00, 0xFFFFFF00];
+
+/// How long [SerialPort::self_test] holds each color in the [TEST_PATTERN].
+const TEST_PATTERN_DELAY: Duration = Duration::from_millis(500);
+
+/// Diagnosis from [SerialPort::self_test], which tries to narrow down whether flaky LEDs are a
+/// wiring, sketch, or host problem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelfTestResult {
+    /// Nothing answered with the [COOKIE] heartbeat on any of the available ports.
+    NotFound,
+
+    /// The port exists, but we couldn't open or configure it.
+    OpenFailed,
+
+    /// The port opened, but nothing sent the [COOKIE] heartbeat on it.
+    NoHeartbeat,
+
+    /// Writing the [TEST_PATTERN] to the port failed.
+    WriteFailed,
+
+    /// The sketch never acknowledged a frame of the [TEST_PATTERN] with flow control.
+    NoAck,
+
+    /// Everything worked, so any remaining problems are probably in the LED wiring or power.
+    Passed,
+}
+
+impl fmt::Display for SelfTestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let diagnosis = match self {
+            Self::NotFound => "FAIL: no Arduino sent the heartbeat on any COM port, check the USB cable, that the LEDstream sketch is running, and the baudRate",
+            Self::OpenFailed => "FAIL: couldn't open the COM port, check that nothing else is using it and the USB serial driver is installed",
+            Self::NoHeartbeat => "FAIL: the COM port opened but the sketch didn't send the heartbeat, check that the LEDstream sketch is running and the baudRate",
+            Self::WriteFailed => "FAIL: writing to the COM port failed, check the USB cable and try another USB port",
+            Self::NoAck => "FAIL: the sketch didn't acknowledge the frames, check that it supports flowControl",
+            Self::Passed => "PASS: the strip should have shown red, green, blue, and white, if it didn't, check the LED data and power wiring",
+        };
+        f.write_str(diagnosis)
+    }
+}
+
+/// Outcome of [SerialPort::send].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendResult {
+    /// Started writing the [PixelBuffer] to the port.
+    Sent,
+
+    /// The previous [PixelBuffer] is still being written, or the sketch hasn't acknowledged
+    /// it yet, so this one was dropped instead of blocking the worker thread.
+    Dropped,
+
+    /// The previous [PixelBuffer] is still being written, so this one is waiting to be sent
+    /// by [SerialPort::poll] with [BusyPolicy::Latest].
+    Queued,
+
+    /// The port isn't open, or writing to it failed and it was closed.
+    Failed,
+}
+
 /// Public interface to send [PixelBuffer] messages to the Arduino.
 pub struct SerialPort<'a> {
     /// Parameters including timeouts and the delay between frames in a [Settings] struct.
     parameters: &'a Settings,
 
+    /// Index of the [SerialOutput] in the [Settings] which this port drives.
+    index: usize,
+
+    /// The [SerialOutput] at `index`.
+    output: &'a SerialOutput,
+
     /// The COM (serial) port [HANDLE].
     port_handle: HANDLE,
 
     /// The COM (serial) port number.
     port_number: u8,
+
+    /// The baud rate where the Arduino answered on the `port_number`.
+    baud_rate: u32,
+
+    /// Event [HANDLE] which is signaled when an [OVERLAPPED] write completes.
+    write_event: HANDLE,
+
+    /// The [OVERLAPPED] struct for the pending write. It's boxed so the address doesn't
+    /// change while the I/O is in progress.
+    write_overlapped: Box<OVERLAPPED>,
+
+    /// Copy of the last [PixelBuffer] we started writing, which needs to stay valid until
+    /// the write completes.
+    write_buffer: Vec<u8>,
+
+    /// True if the last write hasn't been checked for completion yet.
+    write_pending: bool,
+
+    /// Copy of the latest [PixelBuffer] which is waiting for the port with
+    /// [BusyPolicy::Latest].
+    queued_buffer: Vec<u8>,
+
+    /// True if `queued_buffer` still needs to be sent.
+    queued: bool,
+
+    /// When the last write was started.
+    write_started: Instant,
+
+    /// True once the port has been opened, so we can count reconnects.
+    opened: bool,
+
+    /// The [SerialStats] for this port.
+    stats: SerialStats,
+
+    /// Brightness to send to a WLED device, which starts with the configured `brightness`
+    /// in the [crate::settings::WledConfiguration].
+    brightness: Option<u8>,
+
+    /// Watch for the [ACK] after each frame if `flow_control` is set in the [SerialOutput].
+    ack: Option<FrameAck>,
+
+    /// Number of times we've tried to reopen the port since a write failed.
+    reconnect_attempts: u32,
+
+    /// When [SerialPort::reconnect] should try to reopen the port next, after a write failed.
+    next_reconnect: Option<Instant>,
+
+    /// Report when the port is opened or lost.
+    health: HealthSender,
 }
 
 impl<'a> SerialPort<'a> {
-    /// Allocate a new [SerialPort] struct.
-    pub fn new(settings: &'a Settings) -> Self {
+    /// Allocate a new [SerialPort] struct for the [SerialOutput] at `index`, which reports
+    /// when it's opened or lost to `health`.
+    pub fn new(settings: &'a Settings, index: usize, health: HealthSender) -> Self {
+        let output = &settings.serial_outputs[index];
         Self {
             parameters: settings,
+            index,
+            output,
             port_handle: INVALID_HANDLE_VALUE,
-            port_number: 0,
+            port_number: output.port.unwrap_or(0),
+            baud_rate: output
+                .baud_rates
+                .first()
+                .copied()
+                .unwrap_or(settings.baud_rate),
+            write_event: unsafe { CreateEventW(ptr::null(), true, false, PWSTR::default()) },
+            write_overlapped: Box::default(),
+            write_buffer: Vec::new(),
+            write_pending: false,
+            queued_buffer: Vec::new(),
+            queued: false,
+            write_started: Instant::now(),
+            opened: false,
+            stats: SerialStats::default(),
+            brightness: output.wled.as_ref().and_then(|wled| wled.brightness),
+            ack: output.flow_control.then(FrameAck::new),
+            reconnect_attempts: 0,
+            next_reconnect: None,
+            health,
+        }
+    }
+
+    /// Try to open each of the COM ports returned by [SerialPort::available_ports] and look
+    /// for an Arduino sending the [COOKIE] identifier as a heartbeat message. The COM ports
+    /// are all opened and read using async [OVERLAPPED] I/O. If nothing answers at the
+    /// configured baud rate and `probe_baud_rate` is set, try again at each slower rate. If the
+    /// [SerialOutput] has its own list of `baud_rates`, try each of those instead, starting with
+    /// the one which worked last time. Any `claimed` ports already belong to another
+    /// [SerialPort], so they're skipped along with any [SerialPort::excluded_ports]. Returns
+    /// `true` if we found the Arduino, and remembers the port and baud rate for next time.
+    fn locate(&mut self, claimed: &[u8]) -> bool {
+        let mut claimed = claimed.to_vec();
+        claimed.extend(self.excluded_ports());
+        let claimed = claimed.as_slice();
+
+        let baud_rates = if self.output.baud_rates.is_empty() {
+            Self::baud_rates(self.parameters.baud_rate, self.parameters.probe_baud_rate)
+        } else {
+            let locked = PortState::load(self.index).and_then(|state| state.baud_rate);
+            Self::negotiated_baud_rates(&self.output.baud_rates, locked)
+        };
+
+        for baud_rate in baud_rates {
+            self.baud_rate = baud_rate;
+
+            // Try the port which worked last time before scanning all of them.
+            self.port_number = match self.remembered_port() {
+                Some(port_number)
+                    if !claimed.contains(&port_number) && self.probe_port(port_number) =>
+                {
+                    port_number
+                }
+                _ => self.scan_ports(claimed),
+            };
+
+            if self.port_number != 0 {
+                PortState::new(self.port_number, self.baud_rate).save(self.index);
+                return true;
+            }
         }
+
+        false
     }
 
-    /// Try to open all potential COM ports, from COM1 - COM255 and look for an
-    /// Arduino sending the [COOKIE] identifier as a heartbeat message. The COM
-    /// ports are all opened and read using async [OVERLAPPED] I/O.
-    pub fn open(&mut self) -> bool {
+    /// True if we still need to scan the COM ports for the Arduino before we can open it. WLED
+    /// and raw serial bridges don't send the [COOKIE], so they can only use the configured port,
+    /// and a configured `device_path` is always opened directly.
+    fn needs_scan(&self) -> bool {
+        self.port_number == 0 && self.output.device_path.is_none() && self.sends_heartbeat()
+    }
+
+    /// True if the device on the other end sends the [COOKIE] heartbeat, which WLED and raw
+    /// serial bridges don't.
+    fn sends_heartbeat(&self) -> bool {
+        self.output.wled.is_none() && self.output.raw.is_none()
+    }
+
+    /// Open the configured `device_path`, or the COM port where we last found the Arduino.
+    fn open(&mut self) -> bool {
         if INVALID_HANDLE_VALUE == self.port_handle {
-            if self.port_number == 0 {
-                let mut pending_ports: Vec<Option<PortResources>> = Vec::new();
-
-                // Try to open every possible port from COM1 - COM255
-                for port_number in 0_u8..255 {
-                    // See if any pending asynch reads have finished.
-                    for port in pending_ports.iter_mut() {
-                        if let Some(resources) = port {
-                            let mut cb = 0_u32;
-                            unsafe {
-                                if GetOverlappedResult(
-                                    resources.port_handle,
-                                    resources.overlapped,
-                                    &mut cb,
-                                    false,
-                                )
-                                .as_bool()
-                                {
-                                    if cb as usize == COOKIE.len() && *resources.buffer == COOKIE {
-                                        // We found a match!
-                                        self.port_number = resources.port_number;
-                                        break;
-                                    }
-                                } else if GetLastError() == ERROR_IO_INCOMPLETE {
-                                    // Still pending, go on to the next port.
-                                    continue;
-                                }
-
-                                // Any mismatched data or other error means we can't read from the port at all.
-                                *port = None;
-                            }
-                        }
-                    }
+            let port_path = match self.output.device_path.as_ref() {
+                Some(device_path) => Some(device_path.clone()),
+                None if self.port_number != 0 => Some(Self::port_path(self.port_number)),
+                None => None,
+            };
 
-                    if self.port_number != 0 {
-                        // If we found a match, we can skip waiting for the rest of the I/O to complete below.
-                        pending_ports.clear();
-                        break;
+            if let Some(port_path) = port_path {
+                match self.output.latency_timer {
+                    Some(latency_timer) if self.port_number != 0 => {
+                        Self::set_latency_timer(self.port_number, latency_timer);
                     }
+                    _ => (),
+                }
 
-                    // Try opening the next port.
-                    let port_number = port_number + 1;
-                    let (port_handle, configuration) = self.get_port(port_number, true);
-                    if INVALID_HANDLE_VALUE == port_handle {
-                        continue;
+                self.port_handle = self.get_port(&port_path, false).0;
+
+                if INVALID_HANDLE_VALUE != self.port_handle {
+                    if self.opened {
+                        self.stats.reconnects += 1;
                     }
+                    self.opened = true;
+                    self.next_reconnect = None;
+                    self.health.send(HealthEvent::SerialConnected(self.index));
 
-                    unsafe {
-                        // Start an overlapped I/O call to look for the cookie sent from the Arduino.
-                        let wait_handle = CreateEventW(ptr::null(), true, false, PWSTR::default());
-                        let port = PortResources {
-                            port_handle,
-                            configuration,
-                            port_number,
-                            wait_handle,
-                            buffer: Box::into_raw(Box::new([0_u8; COOKIE.len()])),
-                            overlapped: Box::into_raw(Box::new(OVERLAPPED {
-                                hEvent: wait_handle,
-                                ..Default::default()
-                            })),
-                        };
-
-                        if !ReadFile(
-                            port.port_handle,
-                            mem::transmute((*port.buffer).as_mut_ptr()),
-                            (*port.buffer).len() as u32,
-                            ptr::null_mut(),
-                            port.overlapped,
-                        )
-                        .as_bool()
-                            && ERROR_IO_PENDING != GetLastError()
-                        {
-                            // Any other error means we can't read from the port at all.
-                            continue;
-                        }
+                    self.wled_power(true);
+                }
+            }
+        }
 
-                        // Add the new port to the list for the next iteration.
-                        pending_ports.push(Some(port));
-                    }
+        INVALID_HANDLE_VALUE != self.port_handle
+    }
+
+    /// Open each of the `ports` which isn't open yet. Scanning for an Arduino can take several
+    /// seconds, so any which need it are handed to the [PortDiscovery] instead, making sure that
+    /// two of them don't claim the same COM port, and they're opened by a later call once it's
+    /// done. With `reconnect`, the closed ports forget where we last found their Arduino and
+    /// scan for it again, e.g. after a USB serial adapter is plugged in and may have been
+    /// assigned a different COM port. Returns `true` if any of the `ports` are open.
+    pub fn open_all(
+        ports: &mut [SerialPort],
+        reconnect: bool,
+        discovery: &mut PortDiscovery,
+    ) -> bool {
+        let mut opened = Self::open_discovered(ports, discovery);
+        let mut scan = Vec::new();
+
+        for (index, port) in ports.iter_mut().enumerate() {
+            if port.is_open() {
+                opened = true;
+                continue;
+            }
+
+            if reconnect {
+                port.port_number = port.output.port.unwrap_or(0);
+            }
+
+            if port.needs_scan() {
+                scan.push(index);
+            } else if port.open() {
+                opened = true;
+            }
+        }
+
+        if !scan.is_empty() {
+            let claimed: Vec<u8> = ports
+                .iter()
+                .filter(|port| port.port_number != 0)
+                .map(|port| port.port_number)
+                .collect();
+            discovery.start(scan, claimed);
+        }
+
+        opened
+    }
+
+    /// Open any of the `ports` which the [PortDiscovery] found since the last time we checked.
+    /// Returns `true` if that opened any of them.
+    pub fn open_discovered(ports: &mut [SerialPort], discovery: &mut PortDiscovery) -> bool {
+        let mut opened = false;
+
+        for (index, port_number, baud_rate) in discovery.poll().unwrap_or_default() {
+            let port = &mut ports[index];
+            if !port.is_open() && port.needs_scan() {
+                port.port_number = port_number;
+                port.baud_rate = baud_rate;
+                if port.open() {
+                    opened = true;
                 }
+            }
+        }
 
-                // Finish waiting for any outstanding I/O.
-                for port in pending_ports.iter_mut() {
-                    if let Some(resources) = port {
-                        let mut cb = 0_u32;
-                        unsafe {
-                            if GetOverlappedResult(
-                                resources.port_handle,
-                                resources.overlapped,
-                                &mut cb,
-                                true,
-                            )
-                            .as_bool()
-                                && cb as usize == COOKIE.len()
-                                && *resources.buffer == COOKIE
-                            {
-                                // We found a match!
-                                self.port_number = resources.port_number;
-                                break;
-                            }
+        opened
+    }
 
-                            *port = None;
-                        }
-                    }
+    /// If a write failed and it's time for the next attempt in the
+    /// [crate::settings::ReconnectConfiguration] backoff, try to reopen the port. Returns `true`
+    /// if the port is open.
+    pub fn reconnect(&mut self) -> bool {
+        let due =
+            matches!(self.next_reconnect, Some(next_reconnect) if Instant::now() >= next_reconnect);
+
+        if due && !self.is_open() && !self.open() {
+            self.reconnect_attempts += 1;
+            let reconnect = &self.parameters.reconnect;
+            let delay = Self::backoff_delay(
+                self.reconnect_attempts,
+                reconnect.retries,
+                reconnect.delay,
+                self.parameters.throttle_timer,
+            );
+            self.next_reconnect = Some(Instant::now() + Duration::from_millis(u64::from(delay)));
+        }
+
+        self.is_open()
+    }
+
+    /// Get the delay in milliseconds before the next attempt to reopen the port, after it
+    /// already failed `attempts` times. The first `retries` attempts don't wait, then it starts
+    /// at `delay` and doubles each time, up to `max_delay`.
+    pub(crate) fn backoff_delay(attempts: u32, retries: u32, delay: u32, max_delay: u32) -> u32 {
+        if attempts < retries {
+            0
+        } else {
+            delay
+                .saturating_mul(2_u32.saturating_pow(attempts - retries))
+                .min(max_delay)
+        }
+    }
+
+    /// Close the port after a write failed, and let [SerialPort::reconnect] start trying to
+    /// reopen it with the next frame.
+    fn disconnect(&mut self) {
+        self.close();
+        self.health.send(HealthEvent::SerialLost(self.index));
+        self.reconnect_attempts = 0;
+        self.next_reconnect = Some(Instant::now());
+    }
+
+    /// Run [SerialPort::self_test] for each [SerialOutput] in the [Settings], and return the
+    /// COM port number (or 0 if it's unknown) with each [SelfTestResult].
+    pub fn self_test_all(settings: &Settings) -> Vec<(u8, SelfTestResult)> {
+        let mut claimed = Vec::new();
+        (0..settings.serial_outputs.len())
+            .map(|index| {
+                let mut port = SerialPort::new(settings, index, HealthSender::default());
+                let result = port.self_test(&claimed);
+                if port.port_number != 0 {
+                    claimed.push(port.port_number);
                 }
+                (port.port_number, result)
+            })
+            .collect()
+    }
+
+    /// Find and open the port, checking for the [COOKIE] heartbeat, then send each color in
+    /// the [TEST_PATTERN] and wait for the [ACK] if `flow_control` is set. Finally reset the
+    /// LED strip and close the port.
+    fn self_test(&mut self, claimed: &[u8]) -> SelfTestResult {
+        if self.needs_scan() {
+            if !self.locate(claimed) {
+                return SelfTestResult::NotFound;
             }
+        } else if self.sends_heartbeat() && self.output.device_path.is_none() {
+            // We can't probe a device path before opening it.
+            if self.start_read(self.port_number).is_none() {
+                return SelfTestResult::OpenFailed;
+            } else if !self.probe_port(self.port_number) {
+                return SelfTestResult::NoHeartbeat;
+            }
+        }
 
-            if self.port_number != 0 {
-                // Once we find the right port we can just open it directly.
-                self.port_handle = self.get_port(self.port_number, false).0;
+        if !self.open() {
+            return SelfTestResult::OpenFailed;
+        }
+
+        let mut pixels = PixelBuffer::new_serial_buffer(self.output);
+        let mut result = SelfTestResult::Passed;
+        for color in TEST_PATTERN {
+            pixels.clear();
+            for _ in 0..self.output.led_count {
+                pixels.add(color);
+            }
+            pixels.finish();
+
+            if !self.flush() || self.send(&pixels) != SendResult::Sent || !self.flush() {
+                result = SelfTestResult::WriteFailed;
+                break;
+            } else if !self.wait_for_ack() {
+                result = SelfTestResult::NoAck;
+                break;
             }
+
+            thread::sleep(TEST_PATTERN_DELAY);
         }
 
+        pixels.clear();
+        self.flush();
+        self.send(&pixels);
+        self.flush();
+        self.close();
+
+        result
+    }
+
+    /// With flow control, wait up to the `timeout` for the sketch to acknowledge the last
+    /// frame. Returns `true` if the [ACK] arrived, or if flow control is off.
+    fn wait_for_ack(&mut self) -> bool {
+        let port_handle = self.port_handle;
+        let timeout = Duration::from_millis(u64::from(self.parameters.timeout));
+        let start = Instant::now();
+
+        match self.ack.as_mut() {
+            Some(ack) => loop {
+                if ack.poll(port_handle) {
+                    ack.awaiting = None;
+                    return true;
+                } else if start.elapsed() >= timeout {
+                    return false;
+                }
+
+                thread::sleep(Duration::from_millis(1));
+            },
+            None => true,
+        }
+    }
+
+    /// Test if the [SerialPort] is open.
+    pub fn is_open(&self) -> bool {
         INVALID_HANDLE_VALUE != self.port_handle
     }
 
-    /// Send the [PixelBuffer] to the opened [SerialPort].
-    pub fn send(&mut self, buffer: &PixelBuffer) -> bool {
+    /// Start writing the [PixelBuffer] to the opened [SerialPort] with [OVERLAPPED] I/O. If
+    /// the previous write still hasn't finished, never wait for a slow or wedged USB serial
+    /// adapter to catch up. Depending on the [BusyPolicy], either drop this frame or queue it
+    /// for [SerialPort::poll], replacing (and dropping) any frame which was already queued.
+    pub fn send(&mut self, buffer: &PixelBuffer) -> SendResult {
+        let result = if self.frame_acknowledged() {
+            self.write(&buffer.buffer)
+        } else {
+            SendResult::Dropped
+        };
+
+        let result = match (result, self.output.busy_policy) {
+            (SendResult::Dropped, BusyPolicy::Latest) => {
+                if self.queued {
+                    self.stats.dropped += 1;
+                }
+
+                self.queued_buffer.clear();
+                self.queued_buffer.extend_from_slice(&buffer.buffer);
+                self.queued = true;
+                SendResult::Queued
+            }
+            (result, _) => result,
+        };
+
+        self.finish_send(result);
+        result
+    }
+
+    /// True if there's a frame waiting for [SerialPort::poll] to send it.
+    pub fn has_queued_frame(&self) -> bool {
+        self.queued
+    }
+
+    /// With [BusyPolicy::Latest], send the queued frame if the port has finished the previous
+    /// write in the meantime.
+    pub fn poll(&mut self) -> SendResult {
+        if !self.queued {
+            return SendResult::Sent;
+        }
+
+        if !self.frame_acknowledged() {
+            return SendResult::Queued;
+        }
+
+        let queued_buffer = mem::take(&mut self.queued_buffer);
+        let result = match self.write(&queued_buffer) {
+            SendResult::Dropped => SendResult::Queued,
+            result => result,
+        };
+        self.queued_buffer = queued_buffer;
+
+        self.finish_send(result);
+        result
+    }
+
+    /// Update the flow control and [SerialStats] after trying to send a frame.
+    fn finish_send(&mut self, result: SendResult) {
+        match result {
+            SendResult::Sent => {
+                self.queued = false;
+                if let Some(ack) = self.ack.as_mut() {
+                    ack.awaiting = Some(Instant::now());
+                }
+            }
+            SendResult::Dropped => self.stats.dropped += 1,
+            SendResult::Queued => (),
+            SendResult::Failed => {
+                self.queued = false;
+                self.stats.failures += 1;
+            }
+        }
+    }
+
+    /// Get the [SerialStats] for this port.
+    pub fn stats(&self) -> SerialStats {
+        self.stats
+    }
+
+    /// With flow control, check if the sketch has acknowledged the last frame, or if we've
+    /// given up waiting for it after [ACK_TIMEOUT_FRAMES] frames.
+    fn frame_acknowledged(&mut self) -> bool {
+        let port_handle = self.port_handle;
+        let timeout =
+            Duration::from_millis(u64::from(self.parameters.get_delay() * ACK_TIMEOUT_FRAMES));
+
+        match self.ack.as_mut() {
+            Some(ack) if INVALID_HANDLE_VALUE != port_handle => {
+                let acknowledged = match ack.awaiting {
+                    Some(sent) => ack.poll(port_handle) || sent.elapsed() >= timeout,
+                    None => true,
+                };
+
+                if acknowledged {
+                    ack.awaiting = None;
+                }
+
+                acknowledged
+            }
+            _ => true,
+        }
+    }
+
+    /// Start writing the `data` with [OVERLAPPED] I/O, unless the previous write is still
+    /// pending.
+    fn write(&mut self, data: &[u8]) -> SendResult {
         if INVALID_HANDLE_VALUE == self.port_handle {
-            return false;
+            return SendResult::Failed;
         }
 
-        let mut cb_written = 0_u32;
+        if self.write_pending {
+            match self.finish_write(false) {
+                Some(true) => (),
+                Some(false) => {
+                    self.disconnect();
+                    return SendResult::Failed;
+                }
+                None => return SendResult::Dropped,
+            }
+        }
+
+        self.write_buffer.clear();
+        self.write_buffer.extend_from_slice(data);
+        *self.write_overlapped = OVERLAPPED {
+            hEvent: self.write_event,
+            ..Default::default()
+        };
 
         unsafe {
             if !WriteFile(
                 self.port_handle,
-                mem::transmute(buffer.buffer.as_ptr()),
-                buffer.buffer.len() as u32,
-                &mut cb_written,
+                mem::transmute(self.write_buffer.as_ptr()),
+                self.write_buffer.len() as u32,
                 ptr::null_mut(),
+                &mut *self.write_overlapped,
             )
             .as_bool()
-                || cb_written as usize != buffer.buffer.len()
+                && ERROR_IO_PENDING != GetLastError()
             {
-                self.close();
-                return false;
+                self.disconnect();
+                return SendResult::Failed;
             }
         }
 
+        self.write_pending = true;
+        self.write_started = Instant::now();
+        SendResult::Sent
+    }
+
+    /// Change the brightness of a WLED device, and send it right away if the port is open.
+    /// This has no effect on an Arduino running the Adalight sketch.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        if self.output.wled.is_some() && self.brightness != Some(brightness) {
+            self.brightness = Some(brightness);
+            self.send_command(&format!(r#"{{"bri":{brightness}}}"#));
+        }
+    }
+
+    /// Turn a WLED device on or off if `power` is set in its [crate::settings::WledConfiguration].
+    /// Turning it on also sends the current `brightness`.
+    pub fn wled_power(&mut self, on: bool) {
+        let command = match (self.output.wled.as_ref(), self.brightness) {
+            (Some(wled), Some(brightness)) if wled.power && on => {
+                format!(r#"{{"on":true,"bri":{brightness}}}"#)
+            }
+            (Some(_), Some(brightness)) if on => format!(r#"{{"bri":{brightness}}}"#),
+            (Some(wled), _) if wled.power => format!(r#"{{"on":{on}}}"#),
+            _ => return,
+        };
+
+        self.send_command(&command);
+    }
+
+    /// Send a JSON command to a WLED device between frames. Unlike the frames, commands are
+    /// never dropped, so wait for the pending write first.
+    fn send_command(&mut self, command: &str) -> bool {
+        self.flush() && self.write(command.as_bytes()) == SendResult::Sent
+    }
+
+    /// Wait for the pending write to finish, e.g. before and after sending the last frame when
+    /// the worker stops, so it isn't dropped or cancelled. The wait is bounded by the write
+    /// timeout on the port. Returns `false` if the write failed and the port was closed.
+    pub fn flush(&mut self) -> bool {
+        if INVALID_HANDLE_VALUE == self.port_handle {
+            return false;
+        }
+
+        if self.write_pending && self.finish_write(true) == Some(false) {
+            self.disconnect();
+            return false;
+        }
+
+        // Don't hold the next frame for an ACK either.
+        if let Some(ack) = self.ack.as_mut() {
+            ack.awaiting = None;
+        }
+
         true
     }
 
@@ -241,30 +988,472 @@ impl<'a> SerialPort<'a> {
     pub fn close(&mut self) {
         if INVALID_HANDLE_VALUE != self.port_handle {
             unsafe {
+                if self.write_pending {
+                    // Make sure the write is finished with the `write_buffer` before we let go.
+                    CancelIo(self.port_handle);
+                    self.finish_write(true);
+                }
+
+                if let Some(ack) = self.ack.as_mut() {
+                    ack.cancel(self.port_handle);
+                }
+
                 CloseHandle(self.port_handle);
             }
             self.port_handle = INVALID_HANDLE_VALUE;
         }
     }
 
-    /// Try to open the port and save the [HANDLE] and [DCB] configuration struct for later.
-    /// The configuration is saved so we can restore the original settings when closing the
-    /// COM port if it's not a match.
-    fn get_port(&self, port_number: u8, read_test: bool) -> (HANDLE, DCB) {
-        let port_name = format!("COM{port_number}");
-        let (desired_access, flags_and_attributes) = if read_test {
-            (FILE_ACCESS_FLAGS(GENERIC_READ), FILE_FLAG_OVERLAPPED)
+    /// Check on the pending write, optionally blocking until it completes. Returns [None] if
+    /// it's still in progress, otherwise whether the whole `write_buffer` was written.
+    fn finish_write(&mut self, wait: bool) -> Option<bool> {
+        let mut cb_written = 0_u32;
+
+        unsafe {
+            if GetOverlappedResult(
+                self.port_handle,
+                &*self.write_overlapped,
+                &mut cb_written,
+                wait,
+            )
+            .as_bool()
+            {
+                self.write_pending = false;
+                self.stats.bytes_written += u64::from(cb_written);
+                self.stats.record_write(self.write_started.elapsed());
+                Some(cb_written as usize == self.write_buffer.len())
+            } else if !wait && GetLastError() == ERROR_IO_INCOMPLETE {
+                None
+            } else {
+                self.write_pending = false;
+                Some(false)
+            }
+        }
+    }
+
+    /// Try to open all of the [SerialPort::available_ports] which aren't `claimed` at the
+    /// current `baud_rate` and return the number of the first port where the Arduino answers,
+    /// or 0 if none of them do.
+    fn scan_ports(&self, claimed: &[u8]) -> u8 {
+        let mut pending_ports: Vec<Option<PortResources>> = Vec::new();
+
+        // Try to open every port which exists on this machine.
+        for port_number in Self::available_ports()
+            .into_iter()
+            .filter(|port_number| !claimed.contains(port_number))
+        {
+            // See if any pending asynch reads have finished.
+            for port in pending_ports.iter_mut() {
+                if let Some(resources) = port {
+                    let mut cb = 0_u32;
+                    unsafe {
+                        if GetOverlappedResult(
+                            resources.port_handle,
+                            resources.overlapped,
+                            &mut cb,
+                            false,
+                        )
+                        .as_bool()
+                        {
+                            if cb as usize == COOKIE.len() && *resources.buffer == COOKIE {
+                                // We found a match! Dropping the rest of the pending ports
+                                // cancels their I/O, so we don't need to wait for it below.
+                                return resources.port_number;
+                            }
+                        } else if GetLastError() == ERROR_IO_INCOMPLETE {
+                            // Still pending, go on to the next port.
+                            continue;
+                        }
+
+                        // Any mismatched data or other error means we can't read from the port at all.
+                        *port = None;
+                    }
+                }
+            }
+
+            // Try opening the next port, and add it to the list for the next iteration.
+            if let Some(port) = self.start_read(port_number) {
+                pending_ports.push(Some(port));
+            }
+        }
+
+        // Finish waiting for any outstanding I/O.
+        for port in pending_ports.iter_mut() {
+            if let Some(resources) = port {
+                let mut cb = 0_u32;
+                unsafe {
+                    if GetOverlappedResult(
+                        resources.port_handle,
+                        resources.overlapped,
+                        &mut cb,
+                        true,
+                    )
+                    .as_bool()
+                        && cb as usize == COOKIE.len()
+                        && *resources.buffer == COOKIE
+                    {
+                        // We found a match!
+                        return resources.port_number;
+                    }
+
+                    *port = None;
+                }
+            }
+        }
+
+        0
+    }
+
+    /// Get the baud rates to try in order, starting with the configured `baud_rate`. When
+    /// `probe` is set, this continues with each of the slower [BAUD_RATES].
+    fn baud_rates(baud_rate: u32, probe: bool) -> Vec<u32> {
+        let mut baud_rates = vec![baud_rate];
+        if probe {
+            baud_rates.extend(BAUD_RATES.iter().filter(|rate| **rate < baud_rate));
+        }
+        baud_rates
+    }
+
+    /// Get the COM port numbers which the scan must never open, either listed in the
+    /// `exclude_ports` or belonging to one of the `exclude_devices` in the [Settings].
+    fn excluded_ports(&self) -> Vec<u8> {
+        let mut excluded = self.parameters.exclude_ports.clone();
+        if !self.parameters.exclude_devices.is_empty() {
+            excluded.extend(
+                Self::port_devices()
+                    .into_iter()
+                    .filter(|(_, device_id)| {
+                        Self::is_excluded_device(device_id, &self.parameters.exclude_devices)
+                    })
+                    .map(|(port_number, _)| port_number),
+            );
+        }
+        excluded
+    }
+
+    /// True if the `device_id` starts with any of the `exclude_devices`, ignoring case, so a
+    /// prefix like `USB\VID_2341&PID_0043` matches every adapter with that VID and PID.
+    fn is_excluded_device(device_id: &str, exclude_devices: &[String]) -> bool {
+        let device_id = device_id.to_ascii_uppercase();
+        exclude_devices
+            .iter()
+            .any(|excluded| device_id.starts_with(&excluded.to_ascii_uppercase()))
+    }
+
+    /// Get the `baud_rates` configured for a [SerialOutput] in order, moving the `locked` rate
+    /// where the Arduino answered last time to the front if it's still in the list.
+    fn negotiated_baud_rates(baud_rates: &[u32], locked: Option<u32>) -> Vec<u32> {
+        match locked {
+            Some(locked) if baud_rates.contains(&locked) => iter::once(locked)
+                .chain(baud_rates.iter().copied().filter(|rate| *rate != locked))
+                .collect(),
+            _ => baud_rates.to_vec(),
+        }
+    }
+
+    /// Open the port and start an overlapped I/O call to look for the [COOKIE] sent from the
+    /// Arduino.
+    fn start_read(&self, port_number: u8) -> Option<PortResources> {
+        let (port_handle, configuration) = self.get_port(&Self::port_path(port_number), true);
+        if INVALID_HANDLE_VALUE == port_handle {
+            return None;
+        }
+
+        unsafe {
+            let wait_handle = CreateEventW(ptr::null(), true, false, PWSTR::default());
+            let port = PortResources {
+                port_handle,
+                configuration,
+                port_number,
+                wait_handle,
+                buffer: Box::into_raw(Box::new([0_u8; COOKIE.len()])),
+                overlapped: Box::into_raw(Box::new(OVERLAPPED {
+                    hEvent: wait_handle,
+                    ..Default::default()
+                })),
+            };
+
+            if !ReadFile(
+                port.port_handle,
+                mem::transmute((*port.buffer).as_mut_ptr()),
+                (*port.buffer).len() as u32,
+                ptr::null_mut(),
+                port.overlapped,
+            )
+            .as_bool()
+                && ERROR_IO_PENDING != GetLastError()
+            {
+                // Any other error means we can't read from the port at all.
+                return None;
+            }
+
+            Some(port)
+        }
+    }
+
+    /// Wait for the [COOKIE] on a single port. The read is bounded by the `timeout` in the
+    /// [Settings], so a missing device doesn't hold up the full scan for long.
+    fn probe_port(&self, port_number: u8) -> bool {
+        match self.start_read(port_number) {
+            Some(port) => {
+                let mut cb = 0_u32;
+                unsafe {
+                    GetOverlappedResult(port.port_handle, port.overlapped, &mut cb, true).as_bool()
+                        && cb as usize == COOKIE.len()
+                        && *port.buffer == COOKIE
+                }
+            }
+            None => false,
+        }
+    }
+
+    /// Load the [PortState] from the last successful scan. If the same USB device is still
+    /// attached, prefer whichever port it's on now in case it was re-plugged and assigned a
+    /// different COM port number.
+    fn remembered_port(&self) -> Option<u8> {
+        let state = PortState::load(self.index)?;
+
+        if let Some(device_id) = state.device_id.as_ref() {
+            if let Some((port_number, _)) = Self::port_devices()
+                .into_iter()
+                .find(|(_, id)| id.eq_ignore_ascii_case(device_id))
+            {
+                return Some(port_number);
+            }
+        }
+
+        match state.port_number {
+            0 => None,
+            port_number => Some(port_number),
+        }
+    }
+
+    /// Get the device instance ID (e.g. `USB\VID_2341&PID_0043\...`) of every present COM
+    /// port from SetupAPI, paired with its port number.
+    fn port_devices() -> Vec<(u8, String)> {
+        let mut devices = Vec::new();
+        Self::visit_port_devices(KEY_READ.0, |port_number, instance_id, _| {
+            devices.push((port_number, instance_id));
+            true
+        });
+        devices
+    }
+
+    /// Enumerate every present COM port with SetupAPI, and call `visit` with its port number,
+    /// device instance ID, and the device registry key opened with `access`. Stop as soon as
+    /// `visit` returns `false`.
+    fn visit_port_devices(access: u32, mut visit: impl FnMut(u8, String, HKEY) -> bool) {
+        unsafe {
+            let device_info = SetupDiGetClassDevsW(
+                &GUID_DEVCLASS_PORTS,
+                PWSTR::default(),
+                HWND::default(),
+                DIGCF_PRESENT,
+            );
+            if device_info == INVALID_HANDLE_VALUE.0 {
+                return;
+            }
+
+            let mut device_data = SP_DEVINFO_DATA {
+                cbSize: mem::size_of::<SP_DEVINFO_DATA>() as u32,
+                ..Default::default()
+            };
+            let mut index = 0;
+            while SetupDiEnumDeviceInfo(device_info, index, &mut device_data).as_bool() {
+                index += 1;
+
+                let mut instance_id = [0_u16; 256];
+                let mut instance_len = 0_u32;
+                if !SetupDiGetDeviceInstanceIdW(
+                    device_info,
+                    &device_data,
+                    PWSTR(instance_id.as_mut_ptr()),
+                    instance_id.len() as u32,
+                    &mut instance_len,
+                )
+                .as_bool()
+                {
+                    continue;
+                }
+
+                let key = SetupDiOpenDevRegKey(
+                    device_info,
+                    &device_data,
+                    DICS_FLAG_GLOBAL,
+                    0,
+                    DIREG_DEV,
+                    access,
+                );
+                if key.0 == INVALID_HANDLE_VALUE.0 {
+                    continue;
+                }
+
+                let mut data = [0_u16; 256];
+                let mut data_len = mem::size_of_val(&data) as u32;
+                let mut value_type = 0_u32;
+                let result = RegQueryValueExW(
+                    key,
+                    "PortName",
+                    ptr::null_mut(),
+                    &mut value_type,
+                    data.as_mut_ptr() as *mut u8,
+                    &mut data_len,
+                );
+
+                let mut visiting = true;
+                if ERROR_SUCCESS == result && value_type == REG_SZ.0 {
+                    let data_len = data_len as usize / mem::size_of::<u16>();
+                    let port_name = String::from_utf16_lossy(&data[..data_len]);
+                    if let Some(port_number) = Self::parse_port_name(&port_name) {
+                        let instance_len = (instance_len as usize).saturating_sub(1);
+                        let instance_id = String::from_utf16_lossy(
+                            &instance_id[..instance_len.min(instance_id.len())],
+                        );
+                        visiting = visit(port_number, instance_id, key);
+                    }
+                }
+
+                RegCloseKey(key);
+                if !visiting {
+                    break;
+                }
+            }
+
+            SetupDiDestroyDeviceInfoList(device_info);
+        }
+    }
+
+    /// Set the latency timer (in milliseconds) which the FTDI driver reads from the device
+    /// registry key when it opens the port with `port_number`. It defaults to 16 ms, which
+    /// visibly delays the LEDs. Other drivers ignore it, and writing it needs administrator
+    /// rights, so this is best effort. Returns `true` if the value was written.
+    fn set_latency_timer(port_number: u8, latency_timer: u8) -> bool {
+        let mut written = false;
+        let latency_timer = u32::from(latency_timer);
+        Self::visit_port_devices(KEY_READ.0 | KEY_SET_VALUE.0, |device_port, _, key| unsafe {
+            if device_port != port_number {
+                return true;
+            }
+
+            written = ERROR_SUCCESS
+                == RegSetValueExW(
+                    key,
+                    "LatencyTimer",
+                    0,
+                    REG_DWORD.0,
+                    &latency_timer as *const u32 as *const u8,
+                    mem::size_of::<u32>() as u32,
+                );
+            false
+        });
+        written
+    }
+
+    /// Get the COM port numbers listed under [SERIALCOMM_KEY] in the registry, so we only
+    /// probe ports which actually exist and leave any other serial hardware alone. If the
+    /// registry can't be read, fall back to trying every port from COM1 - COM255.
+    fn available_ports() -> Vec<u8> {
+        let mut ports = Vec::new();
+
+        unsafe {
+            let mut key = HKEY::default();
+            if ERROR_SUCCESS
+                == RegOpenKeyExW(HKEY_LOCAL_MACHINE, SERIALCOMM_KEY, 0, KEY_READ, &mut key)
+            {
+                let mut name = [0_u16; 256];
+                let mut data = [0_u16; 256];
+                for index in 0.. {
+                    let mut name_len = name.len() as u32;
+                    let mut data_len = mem::size_of_val(&data) as u32;
+                    let mut value_type = 0_u32;
+                    if ERROR_SUCCESS
+                        != RegEnumValueW(
+                            key,
+                            index,
+                            PWSTR(name.as_mut_ptr()),
+                            &mut name_len,
+                            ptr::null_mut(),
+                            &mut value_type,
+                            data.as_mut_ptr() as *mut u8,
+                            &mut data_len,
+                        )
+                    {
+                        // This is usually ERROR_NO_MORE_ITEMS after the last value.
+                        break;
+                    }
+
+                    if value_type != REG_SZ.0 {
+                        continue;
+                    }
+
+                    let data_len = data_len as usize / mem::size_of::<u16>();
+                    let port_name = String::from_utf16_lossy(&data[..data_len]);
+                    if let Some(port_number) = Self::parse_port_name(&port_name) {
+                        ports.push(port_number);
+                    }
+                }
+
+                RegCloseKey(key);
+            }
+        }
+
+        if ports.is_empty() {
+            (1_u8..=255).collect()
+        } else {
+            ports.sort_unstable();
+            ports.dedup();
+            ports
+        }
+    }
+
+    /// Parse the port number from a COM port name like `COM3` or `\\.\COM12`, ignoring any
+    /// trailing nulls.
+    pub(crate) fn parse_port_name(port_name: &str) -> Option<u8> {
+        let port_name = port_name.trim_end_matches('\0').to_ascii_uppercase();
+        let port_name = port_name.strip_prefix(r"\\.\").unwrap_or(&port_name);
+        match port_name.strip_prefix("COM")?.parse::<u8>() {
+            Ok(0) | Err(_) => None,
+            Ok(port_number) => Some(port_number),
+        }
+    }
+
+    /// Set the DTR or RTS control bits at `shift` in the [DCB] `bitfield` to assert or
+    /// suppress the line, or leave them alone if it's not configured.
+    fn line_control(bitfield: u32, shift: u32, enabled: Option<bool>) -> u32 {
+        match enabled {
+            Some(enabled) => {
+                (bitfield & !(LINE_CONTROL_MASK << shift)) | (u32::from(enabled) << shift)
+            }
+            None => bitfield,
+        }
+    }
+
+    /// Get the device path to open the COM port with `port_number`. `CreateFileW` only
+    /// recognizes the bare `COM1` through `COM9` names, so they all need the `\\.\` prefix.
+    fn port_path(port_number: u8) -> String {
+        format!(r"\\.\COM{port_number}")
+    }
+
+    /// Try to open the port at `port_path` and save the [HANDLE] and [DCB] configuration struct
+    /// for later. The configuration is saved so we can restore the original settings when
+    /// closing the COM port if it's not a match.
+    fn get_port(&self, port_path: &str, read_test: bool) -> (HANDLE, DCB) {
+        let desired_access = if read_test {
+            FILE_ACCESS_FLAGS(GENERIC_READ)
+        } else if self.output.flow_control {
+            // We also need to read the ACK after each frame.
+            FILE_ACCESS_FLAGS(GENERIC_READ | GENERIC_WRITE)
         } else {
-            (FILE_ACCESS_FLAGS(GENERIC_WRITE), FILE_ATTRIBUTE_NORMAL)
+            FILE_ACCESS_FLAGS(GENERIC_WRITE)
         };
         unsafe {
             let mut port_handle = CreateFileW(
-                port_name,
+                port_path,
                 desired_access,
                 Default::default(),
                 ptr::null(),
                 OPEN_EXISTING,
-                flags_and_attributes,
+                FILE_FLAG_OVERLAPPED,
                 HANDLE::default(),
             );
             let mut configuration = DCB {
@@ -274,16 +1463,34 @@ impl<'a> SerialPort<'a> {
 
             if INVALID_HANDLE_VALUE != port_handle {
                 if GetCommState(port_handle, &mut configuration).as_bool() {
-                    let reconfigured = DCB {
-                        BaudRate: CBR_115200,
+                    let mut reconfigured = DCB {
+                        BaudRate: self.baud_rate,
                         ByteSize: 8,
                         StopBits: ONESTOPBIT,
                         Parity: NOPARITY,
                         ..configuration
                     };
+                    reconfigured._bitfield = Self::line_control(
+                        reconfigured._bitfield,
+                        DTR_CONTROL_SHIFT,
+                        self.output.dtr,
+                    );
+                    reconfigured._bitfield = Self::line_control(
+                        reconfigured._bitfield,
+                        RTS_CONTROL_SHIFT,
+                        self.output.rts,
+                    );
+                    let read_timeout = if read_test {
+                        self.parameters.timeout
+                    } else {
+                        self.output.read_timeout.unwrap_or(self.parameters.timeout)
+                    };
                     let timeouts = COMMTIMEOUTS {
-                        ReadTotalTimeoutConstant: self.parameters.timeout,
-                        WriteTotalTimeoutConstant: self.parameters.get_delay(),
+                        ReadTotalTimeoutConstant: read_timeout,
+                        WriteTotalTimeoutConstant: self
+                            .output
+                            .write_timeout
+                            .unwrap_or_else(|| self.parameters.get_delay()),
                         ..Default::default()
                     };
 
@@ -308,5 +1515,123 @@ impl<'a> SerialPort<'a> {
 impl<'a> Drop for SerialPort<'a> {
     fn drop(&mut self) {
         self.close();
+
+        if INVALID_HANDLE_VALUE != self.write_event {
+            unsafe {
+                CloseHandle(self.write_event);
+            }
+            self.write_event = INVALID_HANDLE_VALUE;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_port_names() {
+        assert_eq!(SerialPort::parse_port_name("COM3"), Some(3));
+        assert_eq!(SerialPort::parse_port_name("com12\0"), Some(12));
+        assert_eq!(SerialPort::parse_port_name(r"\\.\COM12"), Some(12));
+        assert_eq!(SerialPort::parse_port_name(r"\\?\USB#VID_2341"), None);
+        assert_eq!(SerialPort::parse_port_name("COM0"), None);
+        assert_eq!(SerialPort::parse_port_name("COM256"), None);
+        assert_eq!(SerialPort::parse_port_name("LPT1"), None);
+    }
+
+    #[test]
+    fn reconnect_backoff() {
+        assert_eq!(SerialPort::backoff_delay(0, 3, 50, 1000), 0);
+        assert_eq!(SerialPort::backoff_delay(2, 3, 50, 1000), 0);
+        assert_eq!(SerialPort::backoff_delay(3, 3, 50, 1000), 50);
+        assert_eq!(SerialPort::backoff_delay(4, 3, 50, 1000), 100);
+        assert_eq!(SerialPort::backoff_delay(6, 3, 50, 1000), 400);
+        assert_eq!(SerialPort::backoff_delay(8, 3, 50, 1000), 1000);
+        assert_eq!(SerialPort::backoff_delay(100, 3, 50, 1000), 1000);
+        assert_eq!(SerialPort::backoff_delay(0, 0, 50, 1000), 50);
+    }
+
+    #[test]
+    fn dtr_rts_control() {
+        // fBinary and DTR_CONTROL_HANDSHAKE
+        let bitfield = 0b10_0001;
+        assert_eq!(
+            SerialPort::line_control(bitfield, DTR_CONTROL_SHIFT, None),
+            bitfield
+        );
+        assert_eq!(
+            SerialPort::line_control(bitfield, DTR_CONTROL_SHIFT, Some(true)),
+            0b01_0001
+        );
+        assert_eq!(
+            SerialPort::line_control(bitfield, DTR_CONTROL_SHIFT, Some(false)),
+            0b00_0001
+        );
+        assert_eq!(
+            SerialPort::line_control(bitfield, RTS_CONTROL_SHIFT, Some(true)),
+            0b01_0000_0010_0001
+        );
+    }
+
+    #[test]
+    fn probe_baud_rates() {
+        assert_eq!(SerialPort::baud_rates(500_000, false), vec![500_000]);
+        assert_eq!(
+            SerialPort::baud_rates(500_000, true),
+            vec![500_000, 230_400, 115_200]
+        );
+        assert_eq!(SerialPort::baud_rates(115_200, true), vec![115_200]);
+    }
+
+    #[test]
+    fn excluded_devices() {
+        let exclude_devices = vec![String::from(r"usb\vid_2341&pid_0043")];
+        assert!(SerialPort::is_excluded_device(
+            r"USB\VID_2341&PID_0043\85734323",
+            &exclude_devices
+        ));
+        assert!(!SerialPort::is_excluded_device(
+            r"USB\VID_1A86&PID_7523\5&1B4A0C3&0&2",
+            &exclude_devices
+        ));
+        assert!(!SerialPort::is_excluded_device(
+            r"USB\VID_2341&PID_0043\85734323",
+            &[]
+        ));
+    }
+
+    #[test]
+    fn negotiated_baud_rates() {
+        let baud_rates = [1_000_000, 500_000, 115_200];
+        assert_eq!(
+            SerialPort::negotiated_baud_rates(&baud_rates, None),
+            vec![1_000_000, 500_000, 115_200]
+        );
+        assert_eq!(
+            SerialPort::negotiated_baud_rates(&baud_rates, Some(500_000)),
+            vec![500_000, 1_000_000, 115_200]
+        );
+        assert_eq!(
+            SerialPort::negotiated_baud_rates(&baud_rates, Some(230_400)),
+            vec![1_000_000, 500_000, 115_200]
+        );
+    }
+
+    #[test]
+    fn parse_port_state() {
+        let state: PortState =
+            serde_json::from_str(r#"{ "portNumber": 4, "deviceId": "USB\\VID_2341&PID_0043\\1" }"#)
+                .expect("parse the PortState");
+        assert_eq!(state.port_number, 4);
+        assert_eq!(
+            state.device_id.as_deref(),
+            Some("USB\\VID_2341&PID_0043\\1")
+        );
+        assert!(state.baud_rate.is_none());
+
+        let json = serde_json::to_string(&state).expect("serialize the PortState");
+        let round_trip: PortState = serde_json::from_str(&json).expect("parse the PortState");
+        assert_eq!(round_trip, state);
     }
 }
@@ -0,0 +1,303 @@
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    health::HealthSender,
+    output_sink::OutputSink,
+    pixel_buffer::PixelBuffer,
+    screen_samples::ScreenSamples,
+    serial_port::{PortDiscovery, SerialPort},
+    settings::Settings,
+    stats::SerialStats,
+};
+
+/// How often the [SerialThread] checks for a [SerialPort] to finish writing while it has a
+/// queued frame with [crate::settings::BusyPolicy::Latest].
+const SERIAL_POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Messages from the [crate::update_timer::UpdateTimer] worker thread to the [SerialThread].
+enum SerialMessage {
+    /// Open each of the [SerialPort] outputs which isn't open yet, with or without
+    /// `reconnect`, and `reply` with `true` if any of them are open.
+    Open {
+        reconnect: bool,
+        reply: mpsc::Sender<bool>,
+    },
+
+    /// Send one rendered [PixelBuffer] to each of the [SerialPort] outputs, after changing the
+    /// WLED `brightness` if it's set. Also count the frames which were `dropped` since the last
+    /// one because the channel was full.
+    Frame {
+        buffers: Vec<PixelBuffer>,
+        brightness: Option<u8>,
+        dropped: usize,
+    },
+
+    /// Fade out and reset the LED strip(s), close all of the [SerialPort] outputs, and exit.
+    Stop,
+}
+
+/// Handle to the thread which owns all of the [SerialPort] outputs and does all of the serial
+/// I/O, so a USB latency spike never holds up the worker thread. The frames are handed over
+/// in a channel with a single slot. If the [SerialThread] is still busy with the last frame
+/// when the next one is ready, the new frame is dropped.
+pub struct SerialThread {
+    /// Configuration parameters in a [Settings] struct, which are shared with the thread.
+    parameters: Arc<Settings>,
+
+    /// Brightness for any WLED outputs, if it was changed at runtime.
+    brightness: Arc<Mutex<Option<u8>>>,
+
+    /// Send [SerialMessage] messages to the [SerialThread].
+    tx: mpsc::SyncSender<SerialMessage>,
+
+    /// The [JoinHandle<()>] for the [SerialThread], until it's stopped.
+    thread: Option<JoinHandle<()>>,
+
+    /// Frames which were dropped because the channel was full since the last one we sent.
+    dropped: usize,
+}
+
+impl SerialThread {
+    /// Start the [SerialThread] for all of the [crate::settings::SerialOutput] outputs in the
+    /// [Settings] in `parameters`. It sends the WLED `brightness` with every frame, and keeps
+    /// the total [SerialStats] for all of the [SerialPort] outputs updated in `stats`. Each
+    /// [SerialPort] reports when it's opened or lost to `health`.
+    pub fn start(
+        parameters: Arc<Settings>,
+        brightness: Arc<Mutex<Option<u8>>>,
+        stats: Arc<Mutex<SerialStats>>,
+        health: HealthSender,
+    ) -> Self {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let thread_parameters = parameters.clone();
+        let thread = thread::spawn(move || {
+            let parameters = thread_parameters;
+            let mut dropped_frames = 0;
+            let mut ports: Vec<SerialPort> = (0..parameters.serial_outputs.len())
+                .map(|index| SerialPort::new(&parameters, index, health.clone()))
+                .collect();
+            let mut discovery = PortDiscovery::new(parameters.clone());
+            let mut last_frames: Vec<PixelBuffer> = parameters
+                .serial_outputs
+                .iter()
+                .map(PixelBuffer::new_serial_buffer)
+                .collect();
+
+            loop {
+                let message = if ports.iter().any(|port| port.has_queued_frame()) {
+                    // Keep checking for the previous write to finish so the queued frame
+                    // goes out as soon as the port is free, instead of with the next frame.
+                    match rx.recv_timeout(SERIAL_POLL_INTERVAL) {
+                        Ok(message) => message,
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            for port in ports.iter_mut() {
+                                port.poll();
+                            }
+                            continue;
+                        }
+                        Err(mpsc::RecvTimeoutError::Disconnected) => SerialMessage::Stop,
+                    }
+                } else {
+                    rx.recv().unwrap_or(SerialMessage::Stop)
+                };
+
+                let stopped = match message {
+                    SerialMessage::Open { reconnect, reply } => {
+                        let opened = SerialPort::open_all(&mut ports, reconnect, &mut discovery);
+
+                        // The worker thread may have stopped waiting for the reply.
+                        let _ = reply.send(opened);
+                        false
+                    }
+                    SerialMessage::Frame {
+                        buffers,
+                        brightness,
+                        dropped,
+                    } => {
+                        dropped_frames += dropped;
+
+                        // Pick up any Arduino which the PortDiscovery found while we were
+                        // already driving the other outputs.
+                        if discovery.is_running() {
+                            SerialPort::open_discovered(&mut ports, &mut discovery);
+                        }
+
+                        if let Some(brightness) = brightness {
+                            for port in ports.iter_mut() {
+                                port.set_brightness(brightness);
+                            }
+                        }
+
+                        for (buffer, port) in buffers.iter().zip(ports.iter_mut()) {
+                            if port.reconnect() {
+                                port.send(buffer);
+                            }
+                        }
+
+                        last_frames = buffers;
+                        false
+                    }
+                    SerialMessage::Stop => {
+                        Self::fade_out(&parameters, &mut ports, &mut last_frames);
+                        for port in ports.iter_mut() {
+                            port.close();
+                        }
+                        true
+                    }
+                };
+
+                let mut total = ports
+                    .iter()
+                    .fold(SerialStats::default(), |total, port| total + port.stats());
+                total.dropped += dropped_frames;
+                *stats.lock().expect("lock serial stats") = total;
+
+                if stopped {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            parameters,
+            brightness,
+            tx,
+            thread: Some(thread),
+            dropped: 0,
+        }
+    }
+
+    /// Open each of the [SerialPort] outputs which isn't open yet, and wait for the
+    /// [SerialThread] to finish. See [SerialPort::open_all] for `reconnect`. Returns `true` if
+    /// any of them are open.
+    fn open_ports(&self, reconnect: bool) -> bool {
+        let (reply, rx) = mpsc::channel();
+        self.tx
+            .send(SerialMessage::Open { reconnect, reply })
+            .is_ok()
+            && rx.recv().unwrap_or(false)
+    }
+
+    /// Fade out and reset the LED strip(s), close all of the [SerialPort] outputs, and wait
+    /// for the [SerialThread] to exit.
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            if self.tx.send(SerialMessage::Stop).is_ok() {
+                thread.join().expect("join serial thread");
+            }
+        }
+    }
+
+    /// Fade the `last_frames` out to black over a few frames, waiting for each one to finish
+    /// so none of them are dropped. Then reset the LED strip(s), waiting for the last frame so
+    /// this one isn't dropped, and for this one to finish before closing.
+    fn fade_out(parameters: &Settings, ports: &mut [SerialPort], last_frames: &mut [PixelBuffer]) {
+        let delay = parameters.get_delay().max(1);
+        let fade_frames = parameters.fade_out / delay;
+        if fade_frames > 1 && ports.iter().any(|port| port.is_open()) {
+            let from: Vec<Vec<u8>> = last_frames
+                .iter()
+                .map(|last_frame| last_frame.data().to_vec())
+                .collect();
+            for frame in 1..fade_frames {
+                let level = 255 - (255 * frame / fade_frames) as u8;
+                for ((last_frame, from), port) in last_frames
+                    .iter_mut()
+                    .zip(from.iter())
+                    .zip(ports.iter_mut())
+                {
+                    last_frame.fade(from, level);
+                    if port.is_open() {
+                        port.flush();
+                        port.send(last_frame);
+                    }
+                }
+                thread::sleep(Duration::from_millis(u64::from(delay)));
+            }
+        }
+
+        for (last_frame, port) in last_frames.iter_mut().zip(ports.iter_mut()) {
+            last_frame.clear();
+            port.flush();
+            port.send(last_frame);
+            port.wled_power(false);
+            port.flush();
+        }
+    }
+}
+
+impl OutputSink for SerialThread {
+    fn open(&mut self) -> bool {
+        self.open_ports(false)
+    }
+
+    /// Forget where we last found each closed [SerialPort] and look for it again, in case it
+    /// was re-plugged and assigned a different COM port.
+    fn reconnect(&mut self) -> bool {
+        self.open_ports(true)
+    }
+
+    /// Render a [PixelBuffer] for each [crate::settings::SerialOutput], balanced with its
+    /// [crate::settings::WhitePoint] if it has one.
+    fn render(&self, samples: &ScreenSamples) -> Vec<PixelBuffer> {
+        self.parameters
+            .serial_outputs
+            .iter()
+            .map(|output| {
+                let mut serial_buffer = PixelBuffer::new_serial_buffer(output);
+                if let Some(white_point) = output.white_point {
+                    serial_buffer.set_white_point(white_point);
+                }
+                samples.render_serial(output, &mut serial_buffer);
+                serial_buffer
+            })
+            .collect()
+    }
+
+    /// Hand the rendered `pixels` to the [SerialThread], along with the WLED brightness if
+    /// it's set. If the [SerialThread] hasn't picked up the last frame yet, drop this one
+    /// instead of waiting for it.
+    fn send(&mut self, pixels: Vec<PixelBuffer>) -> bool {
+        let brightness = *self.brightness.lock().expect("lock brightness");
+        match self.tx.try_send(SerialMessage::Frame {
+            buffers: pixels,
+            brightness,
+            dropped: self.dropped,
+        }) {
+            Ok(()) => {
+                self.dropped = 0;
+                true
+            }
+            Err(mpsc::TrySendError::Full(_)) => {
+                self.dropped += 1;
+                true
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    fn close(&mut self) {
+        self.stop();
+    }
+
+    /// The [SerialThread] reopens the [SerialPort] outputs on its own, so it's ready for the
+    /// next frame as long as it's running, even if none of them are open.
+    fn healthy(&self) -> bool {
+        self.thread.is_some()
+    }
+
+    /// Get the `serial_latency` from the [Settings].
+    fn latency(&self) -> Duration {
+        Duration::from_millis(u64::from(self.parameters.serial_latency))
+    }
+}
+
+impl Drop for SerialThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
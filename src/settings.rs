@@ -1,8 +1,12 @@
+use std::io;
+
 use regex::Regex;
 
 use serde::Deserialize;
 use serde_json::Result;
 
+use crate::{gamma_correction::GammaLookup, serial_port::SerialPort};
+
 /// This struct contains the 2D coordinates corresponding to each pixel in the
 /// LED strand, in the order that they're connected (i.e. the first element
 /// here belongs to the first LED in the strand, second element is the second
@@ -31,6 +35,72 @@ impl From<JsonLedPosition> for LedPosition {
     }
 }
 
+/// A rectangle in the grid units of a [DisplayConfiguration] which should be excluded
+/// from sampling, e.g. a subtitle band or a chat overlay. The coordinates may be
+/// fractional, and `{ 0, 0 }` is the top-left corner of the display. Sample pixels
+/// which fall inside the region are dropped from the average for each LED.
+#[derive(Debug)]
+pub struct ExclusionRegion {
+    pub left: f64,
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+}
+
+impl ExclusionRegion {
+    /// Test if a point in grid units falls inside the region.
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.left && x < self.right && y >= self.top && y < self.bottom
+    }
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonExclusionRegion {
+    pub left: f64,
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+}
+
+impl From<JsonExclusionRegion> for ExclusionRegion {
+    fn from(json: JsonExclusionRegion) -> Self {
+        Self {
+            left: json.left,
+            top: json.top,
+            right: json.right,
+            bottom: json.bottom,
+        }
+    }
+}
+
+/// Instead of sampling a whole display, follow the first visible application window which
+/// matches all of the optional criteria. The `processName` is compared with the file name
+/// of the executable (e.g. `vlc.exe`) ignoring case, and the `title` is a regular expression
+/// which is matched against the window title.
+#[derive(Debug)]
+pub struct WindowConfiguration {
+    pub process_name: Option<String>,
+    pub title: Option<String>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonWindowConfiguration {
+    pub processName: Option<String>,
+    pub title: Option<String>,
+}
+
+impl From<JsonWindowConfiguration> for WindowConfiguration {
+    fn from(json: JsonWindowConfiguration) -> Self {
+        Self {
+            process_name: json.processName,
+            title: json.title,
+        }
+    }
+}
+
 /// This struct contains details for each display that the software will
 /// process. The horizontalCount is the number LEDs accross the top of the
 /// AdaLight board, and the verticalCount is the number of LEDs up and down
@@ -42,12 +112,39 @@ impl From<JsonLedPosition> for LedPosition {
 /// displays this might require some trial and error to figure out the precise
 /// order relative to your setup. To leave a gap in the list and include another
 /// display after that, just include an entry for the skipped display with
-/// `{ 0, 0 }` for the horizontalCount and verticalCount.
+/// `{ 0, 0 }` for the horizontalCount and verticalCount. If the display has a
+/// [WindowConfiguration], the grid is mapped to the client area of that window
+/// instead of the display in the same position. Any [ExclusionRegion] rectangles
+/// are masked out of the sample blocks. The optional sampleDepth is a percentage of
+/// the distance from each edge to the center of the display which the blocks for
+/// LEDs along that edge should cover, instead of a single grid cell. With angledCorners,
+/// the blocks for the LEDs in each corner only sample a diagonal wedge pointing towards
+/// the center of the display, which is closer to the region of the wall they light.
+/// Setting temporalSamples averages each LED over that many captured frames before
+/// fading, which reduces shimmer from dithered or noisy content but adds some latency.
+/// With followForeground, the grid is mapped to a borderless or fullscreen foreground
+/// window centered on the display, e.g. a game running at a lower resolution.
+/// Setting colorProfile transforms every sample pixel from the display's active ICC
+/// profile to sRGB, so displays which are calibrated differently produce matching
+/// LED colors, at the cost of some extra work per pixel. The optional overlap is a percentage
+/// of a grid cell which each block extends into its neighbors along the edge, so adjacent
+/// LEDs share some of the screen and transitions along the strip are smoother. Setting
+/// enabled to false turns the LEDs for a display off without removing it from the list, so
+/// the display indices don't change. It can be turned back on at runtime.
 #[derive(Debug)]
 pub struct DisplayConfiguration {
     pub horizontal_count: usize,
     pub vertical_count: usize,
     pub positions: Vec<LedPosition>,
+    pub window: Option<WindowConfiguration>,
+    pub exclusions: Vec<ExclusionRegion>,
+    pub sample_depth: Option<f64>,
+    pub angled_corners: bool,
+    pub temporal_samples: usize,
+    pub follow_foreground: bool,
+    pub color_profile: bool,
+    pub overlap: Option<f64>,
+    pub enabled: bool,
 }
 
 #[doc(hidden)]
@@ -57,6 +154,16 @@ struct JsonDisplayConfiguration {
     pub horizontalCount: usize,
     pub verticalCount: usize,
     pub positions: Vec<JsonLedPosition>,
+    pub window: Option<JsonWindowConfiguration>,
+    #[serde(default)]
+    pub exclusions: Vec<JsonExclusionRegion>,
+    pub sampleDepth: Option<f64>,
+    pub angledCorners: Option<bool>,
+    pub temporalSamples: Option<usize>,
+    pub followForeground: Option<bool>,
+    pub colorProfile: Option<bool>,
+    pub overlap: Option<f64>,
+    pub enabled: Option<bool>,
 }
 
 impl From<JsonDisplayConfiguration> for DisplayConfiguration {
@@ -69,6 +176,457 @@ impl From<JsonDisplayConfiguration> for DisplayConfiguration {
                 .into_iter()
                 .map(|position| position.into())
                 .collect(),
+            window: json.window.map(|window| window.into()),
+            exclusions: json
+                .exclusions
+                .into_iter()
+                .map(|exclusion| exclusion.into())
+                .collect(),
+            sample_depth: json.sampleDepth,
+            angled_corners: json.angledCorners.unwrap_or(false),
+            temporal_samples: json.temporalSamples.unwrap_or(1),
+            follow_foreground: json.followForeground.unwrap_or(false),
+            color_profile: json.colorProfile.unwrap_or(false),
+            overlap: json.overlap,
+            enabled: json.enabled.unwrap_or(true),
+        }
+    }
+}
+
+/// Automatically detect uniform black bars around the picture (letterbox or pillarbox)
+/// and map the sample blocks to the active picture area instead. A pixel is considered
+/// black if none of its channels are brighter than the `threshold`. Detection runs every
+/// `interval` frames, and a new picture area must be detected `debounce` times in a row
+/// before the sample blocks move.
+#[derive(Debug)]
+pub struct LetterboxConfiguration {
+    pub threshold: u8,
+    pub debounce: u32,
+    pub interval: u32,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonLetterboxConfiguration {
+    pub threshold: u8,
+    pub debounce: u32,
+    pub interval: u32,
+}
+
+impl From<JsonLetterboxConfiguration> for LetterboxConfiguration {
+    fn from(json: JsonLetterboxConfiguration) -> Self {
+        Self {
+            threshold: json.threshold,
+            debounce: json.debounce,
+            interval: json.interval,
+        }
+    }
+}
+
+/// Turn the LEDs off completely, ignoring the minimum brightness, once every sample block
+/// has been black for `frames` frames in a row, e.g. when the screen is blanked or a
+/// projector is warming up. A sample block is considered black if none of its channels
+/// are brighter than the `threshold`.
+#[derive(Debug)]
+pub struct BlackFrameConfiguration {
+    pub threshold: u8,
+    pub frames: u32,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonBlackFrameConfiguration {
+    pub threshold: u8,
+    pub frames: u32,
+}
+
+impl From<JsonBlackFrameConfiguration> for BlackFrameConfiguration {
+    fn from(json: JsonBlackFrameConfiguration) -> Self {
+        Self {
+            threshold: json.threshold,
+            frames: json.frames,
+        }
+    }
+}
+
+/// Normalize the LED brightness towards a target by tracking the rolling average luminance
+/// of every sample block, so dark scenes still light up the wall and bright desktops are
+/// toned down. The `strength` between 0 (off) and 1 (fully normalized) sets how much of the
+/// difference is corrected, and the `time_constant` in seconds sets how quickly the rolling
+/// average follows a change in the scene.
+#[derive(Debug)]
+pub struct AutoExposureConfiguration {
+    pub strength: f64,
+    pub time_constant: f64,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonAutoExposureConfiguration {
+    pub strength: Option<f64>,
+    pub timeConstant: Option<f64>,
+}
+
+impl From<JsonAutoExposureConfiguration> for AutoExposureConfiguration {
+    fn from(json: JsonAutoExposureConfiguration) -> Self {
+        Self {
+            strength: json.strength.unwrap_or(0.5),
+            time_constant: json.timeConstant.unwrap_or(3.0),
+        }
+    }
+}
+
+/// Brightness ceiling and floor for a contiguous range of `ledCount` LEDs starting at
+/// `firstLed`, e.g. to dim the LEDs behind a desk lamp and keep a ceiling wash brighter. The
+/// brightest channel of each LED color is kept between `minBrightness` (default 0) and
+/// `maxBrightness` (default 255) by scaling all of the channels, so the hue doesn't change.
+/// It's applied to every output before gamma correction and the power limit, and black LEDs
+/// stay black. [Settings::from_str] rejects a zone which is empty, goes past the last LED, or
+/// has a `minBrightness` above its `maxBrightness`.
+#[derive(Debug)]
+pub struct BrightnessZone {
+    pub first_led: usize,
+    pub led_count: usize,
+    pub min_brightness: u8,
+    pub max_brightness: u8,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonBrightnessZone {
+    pub firstLed: usize,
+    pub ledCount: usize,
+    pub minBrightness: Option<u8>,
+    pub maxBrightness: Option<u8>,
+}
+
+impl TryFrom<JsonBrightnessZone> for BrightnessZone {
+    type Error = io::Error;
+
+    fn try_from(json: JsonBrightnessZone) -> io::Result<Self> {
+        let zone = Self {
+            first_led: json.firstLed,
+            led_count: json.ledCount,
+            min_brightness: json.minBrightness.unwrap_or(0),
+            max_brightness: json.maxBrightness.unwrap_or(255),
+        };
+        if zone.led_count == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("brightness zone at LED {} is empty", zone.first_led),
+            ));
+        }
+        if zone.min_brightness > zone.max_brightness {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "brightness zone at LED {} has minBrightness {} above maxBrightness {}",
+                    zone.first_led, zone.min_brightness, zone.max_brightness
+                ),
+            ));
+        }
+        Ok(zone)
+    }
+}
+
+/// Limit the estimated current draw of the LEDs, e.g. to protect a supply which injects power
+/// along the strip during bright white scenes. Each channel draws up to `milliamps_per_channel`
+/// (default 20) at full brightness after gamma correction, and if the whole frame adds up to
+/// more than `max_milliamps`, it's dimmed to fit. It only brightens again once the frame fits
+/// under the limit with the `hysteresis` fraction (default 0.1) to spare, easing back up with
+/// the `release` time constant in seconds (default 1).
+#[derive(Debug)]
+pub struct PowerLimitConfiguration {
+    pub milliamps_per_channel: f64,
+    pub max_milliamps: f64,
+    pub hysteresis: f64,
+    pub release: f64,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonPowerLimitConfiguration {
+    pub milliampsPerChannel: Option<f64>,
+    pub maxMilliamps: f64,
+    pub hysteresis: Option<f64>,
+    pub release: Option<f64>,
+}
+
+impl From<JsonPowerLimitConfiguration> for PowerLimitConfiguration {
+    fn from(json: JsonPowerLimitConfiguration) -> Self {
+        Self {
+            milliamps_per_channel: json.milliampsPerChannel.unwrap_or(20.0),
+            max_milliamps: json.maxMilliamps,
+            hysteresis: json.hysteresis.unwrap_or(0.1),
+            release: json.release.unwrap_or(1.0),
+        }
+    }
+}
+
+/// Gamma curve for one color channel of the LEDs. Each channel value is scaled to
+/// `0.0..=1.0`, raised to the `exponent`, and scaled back up to the `max` value, which lowers
+/// the white point of a channel that's brighter than the others on a given strip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GammaCurve {
+    pub exponent: f64,
+    pub max: u8,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonGammaCurve {
+    pub exponent: Option<f64>,
+    pub max: Option<u8>,
+}
+
+/// Gamma correction for the LEDs, with a separate [GammaCurve] for each channel. The
+/// `exponent` (default 2.8) applies to any channel which doesn't set its own, and the `max`
+/// values default to 255 for red, 240 for green, and 220 for blue, which suits most WS2801
+/// and WS2812 strips. Other chemistries may need a different balance. If the `table` is set,
+/// it's the path to a measured lookup table in a CSV or JSON file, which replaces the curves,
+/// see [crate::gamma_correction::GammaLookup::load].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GammaConfiguration {
+    pub red: GammaCurve,
+    pub green: GammaCurve,
+    pub blue: GammaCurve,
+    pub table: Option<String>,
+}
+
+impl Default for GammaConfiguration {
+    fn default() -> Self {
+        JsonGammaConfiguration {
+            exponent: None,
+            red: None,
+            green: None,
+            blue: None,
+            table: None,
+        }
+        .into()
+    }
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonGammaConfiguration {
+    pub exponent: Option<f64>,
+    pub red: Option<JsonGammaCurve>,
+    pub green: Option<JsonGammaCurve>,
+    pub blue: Option<JsonGammaCurve>,
+    pub table: Option<String>,
+}
+
+impl From<JsonGammaConfiguration> for GammaConfiguration {
+    fn from(json: JsonGammaConfiguration) -> Self {
+        let exponent = json.exponent.unwrap_or(2.8);
+        let curve = |curve: Option<JsonGammaCurve>, max| GammaCurve {
+            exponent: curve
+                .as_ref()
+                .and_then(|curve| curve.exponent)
+                .unwrap_or(exponent),
+            max: curve.and_then(|curve| curve.max).unwrap_or(max),
+        };
+        Self {
+            red: curve(json.red, 255),
+            green: curve(json.green, 240),
+            blue: curve(json.blue, 220),
+            table: json.table,
+        }
+    }
+}
+
+/// Adjust the saturation and value of each LED color in HSV after it's averaged, which makes
+/// bias lighting look more vivid without clipping any of the channels. The `saturation` gain
+/// (default 1) multiplies the saturation up to full saturation, and the `value_gamma` (default
+/// 1) is an exponent applied to the value, where less than 1 brightens the darker colors.
+#[derive(Debug)]
+pub struct HsvConfiguration {
+    pub saturation: f64,
+    pub value_gamma: f64,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonHsvConfiguration {
+    pub saturation: Option<f64>,
+    pub valueGamma: Option<f64>,
+}
+
+impl From<JsonHsvConfiguration> for HsvConfiguration {
+    fn from(json: JsonHsvConfiguration) -> Self {
+        Self {
+            saturation: json.saturation.unwrap_or(1.0),
+            value_gamma: json.valueGamma.unwrap_or(1.0),
+        }
+    }
+}
+
+/// What to show on the LEDs while DXGI masks out protected (DRM) content, which would
+/// otherwise be sampled as solid black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtectedContentFallback {
+    /// Keep showing the last colors sampled before the content was masked.
+    Hold,
+
+    /// Fade to the minimum brightness.
+    MinBrightness,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonProtectedContentFallback {
+    Hold,
+    MinBrightness,
+}
+
+impl From<JsonProtectedContentFallback> for ProtectedContentFallback {
+    fn from(json: JsonProtectedContentFallback) -> Self {
+        match json {
+            JsonProtectedContentFallback::Hold => Self::Hold,
+            JsonProtectedContentFallback::MinBrightness => Self::MinBrightness,
+        }
+    }
+}
+
+/// How the sample pixels are distributed within each sample block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleDistribution {
+    /// A regular grid, with each sample at the center of its cell.
+    Grid,
+
+    /// A random position within each cell of the grid, which breaks up aliasing with fine
+    /// regular patterns like thin scrolling text.
+    Jittered,
+
+    /// Like `Jittered`, but neighboring samples are kept at least 3/4 of a cell apart, so the
+    /// samples don't clump together.
+    Poisson,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonSampleDistribution {
+    Grid,
+    Jittered,
+    Poisson,
+}
+
+impl From<JsonSampleDistribution> for SampleDistribution {
+    fn from(json: JsonSampleDistribution) -> Self {
+        match json {
+            JsonSampleDistribution::Grid => Self::Grid,
+            JsonSampleDistribution::Jittered => Self::Jittered,
+            JsonSampleDistribution::Poisson => Self::Poisson,
+        }
+    }
+}
+
+/// The color space which the `fade` blends the previous LED colors with the new samples in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FadeSpace {
+    /// Mix the raw RGB channels, which desaturates the colors in the middle of a transition.
+    Rgb,
+
+    /// Mix the lightness and opponent color axes in OKLab, which looks perceptually even.
+    Oklab,
+
+    /// Mix the saturation and value, and turn the hue the short way around the color wheel.
+    Hsv,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonFadeSpace {
+    Rgb,
+    Oklab,
+    Hsv,
+}
+
+impl From<JsonFadeSpace> for FadeSpace {
+    fn from(json: JsonFadeSpace) -> Self {
+        match json {
+            JsonFadeSpace::Rgb => Self::Rgb,
+            JsonFadeSpace::Oklab => Self::Oklab,
+            JsonFadeSpace::Hsv => Self::Hsv,
+        }
+    }
+}
+
+/// Synthetic patterns which can be generated instead of capturing the displays, for testing
+/// the sampling and output code without a particular display setup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPattern {
+    /// Cycle through solid red, green, blue, white and black.
+    SolidColors,
+
+    /// A rotating rainbow gradient across the width of the frame.
+    Gradient,
+
+    /// Bars moving across and down over a black background.
+    MovingBars,
+}
+
+impl TestPattern {
+    /// Look up a [TestPattern] by the same name used in the configuration file, e.g. on the
+    /// command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "solidColors" => Some(Self::SolidColors),
+            "gradient" => Some(Self::Gradient),
+            "movingBars" => Some(Self::MovingBars),
+            _ => None,
+        }
+    }
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonTestPattern {
+    SolidColors,
+    Gradient,
+    MovingBars,
+}
+
+impl From<JsonTestPattern> for TestPattern {
+    fn from(json: JsonTestPattern) -> Self {
+        match json {
+            JsonTestPattern::SolidColors => Self::SolidColors,
+            JsonTestPattern::Gradient => Self::Gradient,
+            JsonTestPattern::MovingBars => Self::MovingBars,
+        }
+    }
+}
+
+/// Read frames from a file instead of capturing the displays. The `path` can be a PNG image,
+/// a directory of PNG images which are read in sorted order, or a video file which is
+/// decoded with `ffmpeg`. If `repeat` is true (the default), the frames start over from the
+/// beginning after the last one.
+#[derive(Debug)]
+pub struct FileSourceConfiguration {
+    pub path: String,
+    pub repeat: bool,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonFileSourceConfiguration {
+    pub path: String,
+    pub repeat: Option<bool>,
+}
+
+impl From<JsonFileSourceConfiguration> for FileSourceConfiguration {
+    fn from(json: JsonFileSourceConfiguration) -> Self {
+        Self {
+            path: json.path,
+            repeat: json.repeat.unwrap_or(true),
         }
     }
 }
@@ -173,7 +731,9 @@ impl From<JsonOpcPixelRange> for OpcPixelRange {
 
 /// Each channel can have multiple ranges. They cannot overlap, but if they
 /// don't cover the whole range of pixels on the channel we'll just send smaller
-/// buffers and we won't set the pixels on the remainder.
+/// buffers and we won't set the pixels on the remainder. The `channel` defaults to 0, which
+/// the OPC spec defines as a broadcast to every channel on the server, so one buffer drives
+/// all of the outputs on a Fadecandy.
 #[derive(Debug)]
 pub struct OpcChannel {
     pub channel: u8,
@@ -195,12 +755,17 @@ impl OpcChannel {
     pub fn get_total_pixel_count(&self) -> usize {
         self.total_pixel_count
     }
+
+    /// Test if this is the broadcast channel 0, which the server shows on every channel.
+    pub fn is_broadcast(&self) -> bool {
+        self.channel == 0
+    }
 }
 
 impl From<JsonOpcChannel> for OpcChannel {
     fn from(json: JsonOpcChannel) -> Self {
         let mut channel = Self {
-            channel: json.channel,
+            channel: json.channel.unwrap_or(0),
             pixels: json.pixels.into_iter().map(|pixel| pixel.into()).collect(),
             #[cfg(test)]
             total_sample_count: 0,
@@ -223,74 +788,1443 @@ impl From<JsonOpcChannel> for OpcChannel {
 #[derive(Deserialize)]
 #[allow(non_snake_case)]
 struct JsonOpcChannel {
-    pub channel: u8,
+    pub channel: Option<u8>,
     pub pixels: Vec<JsonOpcPixelRange>,
 }
 
-/// OPC server configuration includes the hostname, port (as a string for getaddrinfo)
-/// and a collection of sub-channels and pixel ranges mapped to portions of the AdaLight
-/// display.
-#[derive(Debug)]
-pub struct OpcServer {
-    pub host: String,
-    pub port: String,
-    pub alpha_channel: bool,
-    pub channels: Vec<OpcChannel>,
+/// Optional checksum appended to each serial frame after the pixel data, which needs a
+/// matching version of the Adalight sketch. It lets the sketch throw away frames which were
+/// corrupted by an electrical glitch instead of showing wildly wrong colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialChecksum {
+    /// The original Adalight protocol, without a checksum.
+    None,
+
+    /// One byte CRC-8 (polynomial `0x07`, initial value 0) of the pixel data.
+    Crc8,
+
+    /// Two byte Fletcher-16 checksum of the pixel data, with the high byte first.
+    Fletcher16,
 }
 
 #[doc(hidden)]
 #[derive(Deserialize)]
-#[allow(non_snake_case)]
-struct JsonOpcServer {
-    pub host: String,
-    pub port: String,
-    pub alphaChannel: bool,
-    pub channels: Vec<JsonOpcChannel>,
+#[serde(rename_all = "camelCase")]
+enum JsonSerialChecksum {
+    None,
+    Crc8,
+    Fletcher16,
 }
 
-impl From<JsonOpcServer> for OpcServer {
-    fn from(json: JsonOpcServer) -> Self {
-        Self {
-            host: json.host,
-            port: json.port,
-            alpha_channel: json.alphaChannel,
-            channels: json
-                .channels
-                .into_iter()
-                .map(|channel| channel.into())
-                .collect(),
+impl From<JsonSerialChecksum> for SerialChecksum {
+    fn from(json: JsonSerialChecksum) -> Self {
+        match json {
+            JsonSerialChecksum::None => Self::None,
+            JsonSerialChecksum::Crc8 => Self::Crc8,
+            JsonSerialChecksum::Fletcher16 => Self::Fletcher16,
         }
     }
 }
 
-/// JSON doesn't allow comments, and neither does [serde_json], but the C++ version
-/// used the [cpprestsdk](https://github.com/microsoft/cpprestsdk) parser which ignores
-/// them. So, to maintain backwards compatibility (and preserve the comments in the
-/// sample), strip them on input while deserializing the settings.
-fn strip_comments(json: &str) -> String {
-    #[derive(Debug)]
-    enum State {
-        Parsed,
-        QuotedString,
-        CommentBlock,
-    }
+/// What to do with a new frame when the previous write to the serial port hasn't finished
+/// yet. Either way the frame is counted as dropped in the stats, and the worker thread is
+/// never blocked waiting for the port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusyPolicy {
+    /// Drop the new frame, and send the next one which is ready after the port is free.
+    Drop,
+
+    /// Hold on to the new frame, replacing any other frame which is already waiting, and
+    /// send it as soon as the port is free.
+    Latest,
+}
 
-    let mut state = State::Parsed;
-    let mut output = Vec::new();
-    let start_token = Regex::new(r#"(?:"|(?:/[/*]))"#).expect("build regex");
-    let quoted = Regex::new(r#"(?m)^(?:[^"]|(?:\\"))*"#).expect("build regex");
-    let end_block = Regex::new(r#"(?:\*/)"#).expect("build regex");
-    let empty_line = Regex::new(r#"(?m)^\s*$"#).expect("build regex");
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonBusyPolicy {
+    Drop,
+    Latest,
+}
 
-    for mut line in json.lines() {
-        let mut content = String::new();
+impl From<JsonBusyPolicy> for BusyPolicy {
+    fn from(json: JsonBusyPolicy) -> Self {
+        match json {
+            JsonBusyPolicy::Drop => Self::Drop,
+            JsonBusyPolicy::Latest => Self::Latest,
+        }
+    }
+}
 
-        loop {
-            if line.is_empty() {
-                break;
-            }
+/// How quickly to reopen a [SerialOutput] after a write fails, e.g. when a USB serial adapter
+/// glitches. The first `retries` attempts happen right away with the next frame, and after that
+/// the delay between attempts starts at `delay` milliseconds and doubles each time, up to the
+/// `throttleTimer` interval. The defaults are 3 retries and a 50 ms delay. The same backoff,
+/// plus some jitter, is used to reconnect to an [OpcServer] which dropped the connection, and
+/// without the jitter to reopen any other network output after a send fails.
+#[derive(Debug)]
+pub struct ReconnectConfiguration {
+    pub retries: u32,
+    pub delay: u32,
+}
 
-            match state {
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonReconnectConfiguration {
+    pub retries: Option<u32>,
+    pub delay: Option<u32>,
+}
+
+impl From<JsonReconnectConfiguration> for ReconnectConfiguration {
+    fn from(json: JsonReconnectConfiguration) -> Self {
+        Self {
+            retries: json.retries.unwrap_or(3),
+            delay: json.delay.unwrap_or(50),
+        }
+    }
+}
+
+/// How many bytes of the [SerialHeader] hold the LED count. The count is always sent as one
+/// less than the number of LEDs, most significant byte first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderLedCount {
+    /// A single byte, for sketches which drive at most 256 LEDs.
+    Byte,
+
+    /// Two bytes, which is what the stock LEDstream sketch expects.
+    Word,
+
+    /// Three bytes, for forks which drive more LEDs than fit in a 16-bit count.
+    Extended,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonHeaderLedCount {
+    Byte,
+    Word,
+    Extended,
+}
+
+impl From<JsonHeaderLedCount> for HeaderLedCount {
+    fn from(json: JsonHeaderLedCount) -> Self {
+        match json {
+            JsonHeaderLedCount::Byte => Self::Byte,
+            JsonHeaderLedCount::Word => Self::Word,
+            JsonHeaderLedCount::Extended => Self::Extended,
+        }
+    }
+}
+
+/// Header at the start of each frame on a [SerialOutput], for sketches which don't expect the
+/// stock Adalight header. It starts with the `magic` word, which defaults to `"Ada"`, followed
+/// by the LED count in the [HeaderLedCount] format, defaulting to [HeaderLedCount::Word], and
+/// a check byte which is the XOR of the LED count bytes and `0x55`.
+#[derive(Debug)]
+pub struct SerialHeader {
+    pub magic: String,
+    pub led_count: HeaderLedCount,
+}
+
+impl Default for SerialHeader {
+    fn default() -> Self {
+        Self {
+            magic: String::from("Ada"),
+            led_count: HeaderLedCount::Word,
+        }
+    }
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonSerialHeader {
+    pub magic: Option<String>,
+    pub ledCount: Option<JsonHeaderLedCount>,
+}
+
+impl From<JsonSerialHeader> for SerialHeader {
+    fn from(json: JsonSerialHeader) -> Self {
+        let default = Self::default();
+        Self {
+            magic: json.magic.unwrap_or(default.magic),
+            led_count: json
+                .ledCount
+                .map_or(default.led_count, |led_count| led_count.into()),
+        }
+    }
+}
+
+/// Order of the color channels for each LED in a [RawConfiguration] frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonByteOrder {
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+impl From<JsonByteOrder> for ByteOrder {
+    fn from(json: JsonByteOrder) -> Self {
+        match json {
+            JsonByteOrder::Rgb => Self::Rgb,
+            JsonByteOrder::Rbg => Self::Rbg,
+            JsonByteOrder::Grb => Self::Grb,
+            JsonByteOrder::Gbr => Self::Gbr,
+            JsonByteOrder::Brg => Self::Brg,
+            JsonByteOrder::Bgr => Self::Bgr,
+        }
+    }
+}
+
+/// Raw binary frames for a [SerialOutput] which is connected to a serial-to-SPI bridge instead
+/// of an Adalight sketch, e.g. for APA102 or SK9822 strips. Each frame starts with the fixed
+/// `header` bytes, followed by each LED with an optional `led_prefix` byte (like the `0xE0`
+/// plus global brightness byte for APA102) and the color channels in the [ByteOrder], and ends
+/// with the fixed `footer` bytes. The header and footer default to empty, and the [ByteOrder]
+/// defaults to [ByteOrder::Rgb].
+#[derive(Debug)]
+pub struct RawConfiguration {
+    pub header: Vec<u8>,
+    pub byte_order: ByteOrder,
+    pub led_prefix: Option<u8>,
+    pub footer: Vec<u8>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonRawConfiguration {
+    pub header: Option<Vec<u8>>,
+    pub byteOrder: Option<JsonByteOrder>,
+    pub ledPrefix: Option<u8>,
+    pub footer: Option<Vec<u8>>,
+}
+
+impl From<JsonRawConfiguration> for RawConfiguration {
+    fn from(json: JsonRawConfiguration) -> Self {
+        Self {
+            header: json.header.unwrap_or_default(),
+            byte_order: json
+                .byteOrder
+                .map_or(ByteOrder::Rgb, |byte_order| byte_order.into()),
+            led_prefix: json.ledPrefix,
+            footer: json.footer.unwrap_or_default(),
+        }
+    }
+}
+
+/// The COM `port` for a [SerialOutput] can be set to a port number, a port name like `COM12` or
+/// `\\.\COM12`, or the full device path of a USB serial adapter.
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonSerialPort {
+    Number(u8),
+    Name(String),
+}
+
+/// Extra control for a [WLED](https://kno.wled.ge/) device on a [SerialOutput]. WLED accepts
+/// the same pixel stream as the Adalight sketch, and also JSON commands on the serial port. With
+/// `power`, it's turned on when we connect and off when we stop. The optional `brightness` is
+/// sent to WLED instead of scaling the pixels, and it can also be changed at runtime.
+#[derive(Debug)]
+pub struct WledConfiguration {
+    pub power: bool,
+    pub brightness: Option<u8>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonWledConfiguration {
+    pub power: Option<bool>,
+    pub brightness: Option<u8>,
+}
+
+impl From<JsonWledConfiguration> for WledConfiguration {
+    fn from(json: JsonWledConfiguration) -> Self {
+        Self {
+            power: json.power.unwrap_or(true),
+            brightness: json.brightness,
+        }
+    }
+}
+
+/// White balance gains for one output, which scale the red, green and blue channels (each
+/// defaults to 1) after the colors are sampled, e.g. so a cool white SK6812 strip and a warm
+/// WS2812 strip driven from the same samples look the same. Each gain is clamped to `0..=1`,
+/// so the white point can only be pulled down towards the dimmest channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhitePoint {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonWhitePoint {
+    pub red: Option<f64>,
+    pub green: Option<f64>,
+    pub blue: Option<f64>,
+}
+
+impl From<JsonWhitePoint> for WhitePoint {
+    fn from(json: JsonWhitePoint) -> Self {
+        let gain = |gain: Option<f64>| gain.unwrap_or(1.0).clamp(0.0, 1.0);
+        Self {
+            red: gain(json.red),
+            green: gain(json.green),
+            blue: gain(json.blue),
+        }
+    }
+}
+
+/// Serial output configuration for one Arduino running the Adalight sketch, which drives a
+/// contiguous range of `ledCount` LEDs starting at `firstLed` in the order that they appear
+/// in the configured displays. If there's more than one Arduino attached, the optional COM
+/// `port` number pins each of them to a specific port instead of scanning for the next one.
+/// The `port` can also be set to the `device_path` of a USB serial adapter in the config, which
+/// keeps working if Windows assigns it a different COM port number.
+/// Setting [WledConfiguration] for the output switches to WLED mode. WLED doesn't send the
+/// Adalight heartbeat, so it needs a `port` as well. With flowControl, we wait for the sketch
+/// to acknowledge each frame with an ASCII ACK (`0x06`) byte before sending the next one, so
+/// a small Arduino's UART buffer doesn't overflow and tear frames on long strips. The
+/// [SerialChecksum] defaults to [SerialChecksum::None]. Setting dtr or rts to `true` asserts
+/// that line when we open the port, and `false` keeps it low, e.g. so a board which resets
+/// when DTR toggles doesn't black out the LEDs for a few seconds every time we reconnect. By
+/// default they're left the way the driver had them. The [BusyPolicy] defaults to
+/// [BusyPolicy::Drop], and the [SerialHeader] defaults to the stock Adalight header. The
+/// `read_timeout` and `write_timeout` (in milliseconds) default to the `timeout` and the delay
+/// between frames. If the `latency_timer` is set, we also write it to the registry for an FTDI
+/// USB serial adapter before opening it, instead of the driver's 16 ms default. If the
+/// `baud_rates` list is set, we try the handshake at each of them in order while scanning for
+/// the Arduino instead of the global `baud_rate`, and lock in the first one that works. Setting
+/// [RawConfiguration] sends raw binary frames instead of the Adalight protocol, which replaces
+/// the [SerialHeader] and ignores the [SerialChecksum]. A raw output doesn't send the
+/// heartbeat either, so like WLED it needs a `port`. The optional [WhitePoint] balances the
+/// colors for this strip.
+#[derive(Debug)]
+pub struct SerialOutput {
+    pub first_led: usize,
+    pub led_count: usize,
+    pub port: Option<u8>,
+    pub device_path: Option<String>,
+    pub wled: Option<WledConfiguration>,
+    pub flow_control: bool,
+    pub checksum: SerialChecksum,
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+    pub busy_policy: BusyPolicy,
+    pub header: SerialHeader,
+    pub read_timeout: Option<u32>,
+    pub write_timeout: Option<u32>,
+    pub latency_timer: Option<u8>,
+    pub raw: Option<RawConfiguration>,
+    pub baud_rates: Vec<u32>,
+    pub white_point: Option<WhitePoint>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonSerialOutput {
+    pub firstLed: usize,
+    pub ledCount: usize,
+    pub port: Option<JsonSerialPort>,
+    pub wled: Option<JsonWledConfiguration>,
+    pub flowControl: Option<bool>,
+    pub checksum: Option<JsonSerialChecksum>,
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+    pub busyPolicy: Option<JsonBusyPolicy>,
+    pub header: Option<JsonSerialHeader>,
+    pub readTimeout: Option<u32>,
+    pub writeTimeout: Option<u32>,
+    pub latencyTimer: Option<u8>,
+    pub raw: Option<JsonRawConfiguration>,
+    pub baudRates: Option<Vec<u32>>,
+    pub whitePoint: Option<JsonWhitePoint>,
+}
+
+impl From<JsonSerialOutput> for SerialOutput {
+    fn from(json: JsonSerialOutput) -> Self {
+        let (port, device_path) = match json.port {
+            Some(JsonSerialPort::Number(port)) => (Some(port), None),
+            Some(JsonSerialPort::Name(name)) => match SerialPort::parse_port_name(&name) {
+                Some(port) => (Some(port), None),
+                None => (None, Some(name)),
+            },
+            None => (None, None),
+        };
+
+        Self {
+            first_led: json.firstLed,
+            led_count: json.ledCount,
+            port,
+            device_path,
+            wled: json.wled.map(|wled| wled.into()),
+            flow_control: json.flowControl.unwrap_or(false),
+            checksum: json
+                .checksum
+                .map_or(SerialChecksum::None, |checksum| checksum.into()),
+            dtr: json.dtr,
+            rts: json.rts,
+            busy_policy: json
+                .busyPolicy
+                .map_or(BusyPolicy::Drop, |policy| policy.into()),
+            header: json.header.map(|header| header.into()).unwrap_or_default(),
+            read_timeout: json.readTimeout,
+            write_timeout: json.writeTimeout,
+            latency_timer: json.latencyTimer,
+            raw: json.raw.map(|raw| raw.into()),
+            baud_rates: json.baudRates.unwrap_or_default(),
+            white_point: json.whitePoint.map(|white_point| white_point.into()),
+        }
+    }
+}
+
+/// An OPC system exclusive (command 255) message, e.g. to push the Fadecandy color correction
+/// (system ID `0x0001`) or firmware configuration to an [OpcServer]. The `data` follows the
+/// system ID in the message, so for the Fadecandy it starts with the 2 byte command ID. The
+/// channel defaults to 0, which is a broadcast to every channel on the server.
+#[derive(Debug)]
+pub struct OpcSysex {
+    pub channel: u8,
+    pub system_id: u16,
+    pub data: Vec<u8>,
+}
+
+/// The `data` for an [OpcSysex] can be set to an array of bytes, or to a string which is sent
+/// as UTF-8, e.g. the JSON for the Fadecandy color correction after the command ID.
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonOpcSysexData {
+    Bytes(Vec<u8>),
+    Text(String),
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonOpcSysex {
+    pub channel: Option<u8>,
+    pub systemId: u16,
+    pub data: Option<Vec<JsonOpcSysexData>>,
+}
+
+impl From<JsonOpcSysex> for OpcSysex {
+    fn from(json: JsonOpcSysex) -> Self {
+        Self {
+            channel: json.channel.unwrap_or(0),
+            system_id: json.systemId,
+            data: json
+                .data
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|data| match data {
+                    JsonOpcSysexData::Bytes(bytes) => bytes,
+                    JsonOpcSysexData::Text(text) => text.into_bytes(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Another `host` and `port` (as a string for getaddrinfo) for an [OpcServer], which it fails
+/// over to if the primary host is unreachable.
+#[derive(Debug)]
+pub struct OpcHost {
+    pub host: String,
+    pub port: String,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonOpcHost {
+    pub host: String,
+    pub port: String,
+}
+
+impl From<JsonOpcHost> for OpcHost {
+    fn from(json: JsonOpcHost) -> Self {
+        Self {
+            host: json.host,
+            port: json.port,
+        }
+    }
+}
+
+/// Type of proxy server in a [ProxyConfiguration].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    /// A SOCKS5 proxy, optionally with username and password authentication.
+    Socks5,
+
+    /// An HTTP proxy which supports the `CONNECT` method, optionally with basic authentication.
+    Http,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonProxyProtocol {
+    Socks5,
+    Http,
+}
+
+impl From<JsonProxyProtocol> for ProxyProtocol {
+    fn from(json: JsonProxyProtocol) -> Self {
+        match json {
+            JsonProxyProtocol::Socks5 => Self::Socks5,
+            JsonProxyProtocol::Http => Self::Http,
+        }
+    }
+}
+
+/// A proxy server (e.g. on a jump host) which an [OpcServer] connects through, at the `host`
+/// and `port` (as a string for getaddrinfo). The proxy resolves the OPC server's host name, so
+/// it can be a name on the remote network. The `username` and `password` are only sent if
+/// they're both set.
+#[derive(Debug)]
+pub struct ProxyConfiguration {
+    pub protocol: ProxyProtocol,
+    pub host: String,
+    pub port: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonProxyConfiguration {
+    pub protocol: JsonProxyProtocol,
+    pub host: String,
+    pub port: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl From<JsonProxyConfiguration> for ProxyConfiguration {
+    fn from(json: JsonProxyConfiguration) -> Self {
+        Self {
+            protocol: json.protocol.into(),
+            host: json.host,
+            port: json.port,
+            username: json.username,
+            password: json.password,
+        }
+    }
+}
+
+/// OPC server configuration includes the hostname, port (as a string for getaddrinfo)
+/// and a collection of sub-channels and pixel ranges mapped to portions of the AdaLight
+/// display. We give up on each attempt to connect after `connect_timeout` milliseconds,
+/// which defaults to 1000. The `startup_commands` are sent each time the connection opens,
+/// and the `shutdown_commands` are sent before it closes, see [OpcSysex]. If `fps_max` is set
+/// below the [Settings] `fps_max`, the server only gets that many of the frames per second.
+/// If writing a frame takes longer than `write_timeout` milliseconds (default 1000), or the
+/// TCP keepalive probes which start after `keep_alive` milliseconds (default 5000, 0 to
+/// disable them) go unanswered, the connection is treated as lost and reopened. If
+/// `batch_channels` is set, all of the channels in each frame are written together instead of
+/// one at a time, which saves syscalls and packets for servers with a lot of channels. If the
+/// primary host is unreachable, each of the `failover` hosts is tried in priority order, and
+/// while it's connected to one of them it tries to fail back to a higher priority host every
+/// `failback_interval` milliseconds (default 30000, 0 to stay on the failover host). The
+/// `latency` (in milliseconds, default 0) is how long the server takes to show a frame, and the
+/// faster outputs are held back to match the slowest one. With `adaptive_rate` (the default),
+/// the server gets fewer frames while writing them takes longer than the time between frames,
+/// e.g. on a flaky Wi-Fi link, and it goes back to the full frame rate once the link recovers.
+/// If the `proxy` is set, every connection (including the `failover` hosts) goes through it,
+/// see [ProxyConfiguration]. The optional [WhitePoint] balances the colors for every channel
+/// on the server.
+#[derive(Debug)]
+pub struct OpcServer {
+    pub host: String,
+    pub port: String,
+    pub alpha_channel: bool,
+    pub connect_timeout: u32,
+    pub write_timeout: u32,
+    pub keep_alive: u32,
+    pub fps_max: Option<u32>,
+    pub latency: u32,
+    pub adaptive_rate: bool,
+    pub batch_channels: bool,
+    pub failover: Vec<OpcHost>,
+    pub failback_interval: u32,
+    pub proxy: Option<ProxyConfiguration>,
+    pub white_point: Option<WhitePoint>,
+    pub startup_commands: Vec<OpcSysex>,
+    pub shutdown_commands: Vec<OpcSysex>,
+    pub channels: Vec<OpcChannel>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonOpcServer {
+    pub host: String,
+    pub port: String,
+    pub alphaChannel: bool,
+    pub connectTimeout: Option<u32>,
+    pub writeTimeout: Option<u32>,
+    pub keepAlive: Option<u32>,
+    pub fpsMax: Option<u32>,
+    pub latency: Option<u32>,
+    pub adaptiveRate: Option<bool>,
+    pub batchChannels: Option<bool>,
+    pub failover: Option<Vec<JsonOpcHost>>,
+    pub failbackInterval: Option<u32>,
+    pub proxy: Option<JsonProxyConfiguration>,
+    pub whitePoint: Option<JsonWhitePoint>,
+    pub startupCommands: Option<Vec<JsonOpcSysex>>,
+    pub shutdownCommands: Option<Vec<JsonOpcSysex>>,
+    pub channels: Vec<JsonOpcChannel>,
+}
+
+impl From<JsonOpcServer> for OpcServer {
+    fn from(json: JsonOpcServer) -> Self {
+        Self {
+            host: json.host,
+            port: json.port,
+            alpha_channel: json.alphaChannel,
+            connect_timeout: json.connectTimeout.unwrap_or(1000),
+            write_timeout: json.writeTimeout.unwrap_or(1000),
+            keep_alive: json.keepAlive.unwrap_or(5000),
+            fps_max: json.fpsMax,
+            latency: json.latency.unwrap_or_default(),
+            adaptive_rate: json.adaptiveRate.unwrap_or(true),
+            batch_channels: json.batchChannels.unwrap_or(false),
+            failover: json
+                .failover
+                .unwrap_or_default()
+                .into_iter()
+                .map(|host| host.into())
+                .collect(),
+            failback_interval: json.failbackInterval.unwrap_or(30000),
+            proxy: json.proxy.map(|proxy| proxy.into()),
+            white_point: json.whitePoint.map(|white_point| white_point.into()),
+            startup_commands: json
+                .startupCommands
+                .unwrap_or_default()
+                .into_iter()
+                .map(|command| command.into())
+                .collect(),
+            shutdown_commands: json
+                .shutdownCommands
+                .unwrap_or_default()
+                .into_iter()
+                .map(|command| command.into())
+                .collect(),
+            channels: {
+                // Send the broadcast channel first, so any specific channels which follow it
+                // in the same frame override it on the server.
+                let mut channels: Vec<OpcChannel> = json
+                    .channels
+                    .into_iter()
+                    .map(|channel| channel.into())
+                    .collect();
+                channels.sort_by_key(|channel| !channel.is_broadcast());
+                channels
+            },
+        }
+    }
+}
+
+/// Which of the WLED UDP realtime protocols to use for a [WledServer].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WledProtocol {
+    /// Each LED is sent with its index, which only works for up to 255 LEDs.
+    Warls,
+
+    /// The LEDs are sent in order, starting with the first one, for up to 490 LEDs.
+    Drgb,
+
+    /// The LEDs are sent in order, starting at the index in each packet, so longer strips
+    /// can be split across several packets of up to 489 LEDs.
+    Dnrgb,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonWledProtocol {
+    Warls,
+    Drgb,
+    Dnrgb,
+}
+
+impl From<JsonWledProtocol> for WledProtocol {
+    fn from(json: JsonWledProtocol) -> Self {
+        match json {
+            JsonWledProtocol::Warls => Self::Warls,
+            JsonWledProtocol::Drgb => Self::Drgb,
+            JsonWledProtocol::Dnrgb => Self::Dnrgb,
+        }
+    }
+}
+
+/// A [WLED](https://kno.wled.ge/) controller on the network, which is driven directly with
+/// one of the UDP realtime protocols instead of the Adalight serial protocol. It shows a
+/// contiguous range of `ledCount` LEDs starting at `firstLed`, like a [SerialOutput]. The
+/// `port` defaults to 21324. If the [WledProtocol] isn't set, we use [WledProtocol::Drgb] for
+/// up to 490 LEDs and [WledProtocol::Dnrgb] for more than that. WLED goes back to its own
+/// effects if it doesn't get a packet for `timeout` seconds, which defaults to 2. The
+/// `latency` (in milliseconds, default 0) works like the [OpcServer] `latency`.
+#[derive(Debug)]
+pub struct WledServer {
+    pub host: String,
+    pub port: u16,
+    pub protocol: Option<WledProtocol>,
+    pub timeout: u8,
+    pub latency: u32,
+    pub first_led: usize,
+    pub led_count: usize,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonWledServer {
+    pub host: String,
+    pub port: Option<u16>,
+    pub protocol: Option<JsonWledProtocol>,
+    pub timeout: Option<u8>,
+    pub latency: Option<u32>,
+    pub firstLed: usize,
+    pub ledCount: usize,
+}
+
+impl From<JsonWledServer> for WledServer {
+    fn from(json: JsonWledServer) -> Self {
+        Self {
+            host: json.host,
+            port: json.port.unwrap_or(21324),
+            protocol: json.protocol.map(|protocol| protocol.into()),
+            timeout: json.timeout.unwrap_or(2),
+            latency: json.latency.unwrap_or_default(),
+            first_led: json.firstLed,
+            led_count: json.ledCount,
+        }
+    }
+}
+
+/// An E1.31 (sACN) output, which maps a contiguous range of `ledCount` LEDs starting at
+/// `firstLed` onto DMX universes with 170 RGB pixels each, starting at `universe` (default 1).
+/// The packets are sent with the `priority` (default 100) to the multicast address for each
+/// universe, unless a unicast `destination` host is set. The `latency` (in milliseconds,
+/// default 0) works like the [OpcServer] `latency`.
+#[derive(Debug)]
+pub struct SacnOutput {
+    pub destination: Option<String>,
+    pub universe: u16,
+    pub priority: u8,
+    pub latency: u32,
+    pub first_led: usize,
+    pub led_count: usize,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonSacnOutput {
+    pub destination: Option<String>,
+    pub universe: Option<u16>,
+    pub priority: Option<u8>,
+    pub latency: Option<u32>,
+    pub firstLed: usize,
+    pub ledCount: usize,
+}
+
+impl From<JsonSacnOutput> for SacnOutput {
+    fn from(json: JsonSacnOutput) -> Self {
+        Self {
+            destination: json.destination,
+            universe: json.universe.unwrap_or(1),
+            priority: json.priority.unwrap_or(100),
+            latency: json.latency.unwrap_or_default(),
+            first_led: json.firstLed,
+            led_count: json.ledCount,
+        }
+    }
+}
+
+/// Color channels for each LED in a [UdpOutput] packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Red, green, and blue.
+    Rgb,
+
+    /// Red, green, blue, and white, where the white channel takes over the part of the color
+    /// which all 3 of the others share.
+    Rgbw,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonPixelFormat {
+    Rgb,
+    Rgbw,
+}
+
+impl From<JsonPixelFormat> for PixelFormat {
+    fn from(json: JsonPixelFormat) -> Self {
+        match json {
+            JsonPixelFormat::Rgb => Self::Rgb,
+            JsonPixelFormat::Rgbw => Self::Rgbw,
+        }
+    }
+}
+
+/// A catch-all UDP output for home-made firmware with its own framing. It sends a contiguous
+/// range of `ledCount` LEDs starting at `firstLed` to the `host` and `port`, in packets which
+/// start with the fixed `header` bytes (default empty) followed by the LEDs in the
+/// [PixelFormat] (default [PixelFormat::Rgb]). If the LEDs don't fit in `max_payload` bytes
+/// (default 1440) after the header, they're split into several packets which each start with
+/// the same header, and they're sent in order. The `latency` (in milliseconds, default 0)
+/// works like the [OpcServer] `latency`.
+#[derive(Debug)]
+pub struct UdpOutput {
+    pub host: String,
+    pub port: u16,
+    pub header: Vec<u8>,
+    pub pixel_format: PixelFormat,
+    pub max_payload: usize,
+    pub latency: u32,
+    pub first_led: usize,
+    pub led_count: usize,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonUdpOutput {
+    pub host: String,
+    pub port: u16,
+    pub header: Option<Vec<u8>>,
+    pub pixelFormat: Option<JsonPixelFormat>,
+    pub maxPayload: Option<usize>,
+    pub latency: Option<u32>,
+    pub firstLed: usize,
+    pub ledCount: usize,
+}
+
+impl From<JsonUdpOutput> for UdpOutput {
+    fn from(json: JsonUdpOutput) -> Self {
+        Self {
+            host: json.host,
+            port: json.port,
+            header: json.header.unwrap_or_default(),
+            pixel_format: json
+                .pixelFormat
+                .map_or(PixelFormat::Rgb, |pixel_format| pixel_format.into()),
+            max_payload: json.maxPayload.unwrap_or(1440),
+            latency: json.latency.unwrap_or_default(),
+            first_led: json.firstLed,
+            led_count: json.ledCount,
+        }
+    }
+}
+
+/// A Hyperion or HyperHDR server, which gets an image of the LED colors on one of the
+/// `displays` (default 0) as an external source over its flatbuffers interface on `port`
+/// (default 19400). The image is registered with the `origin` (default "AdaLight") and
+/// `priority` (default 150), which Hyperion expects to be between 100 and 199. We give up on
+/// each attempt to connect after `connect_timeout` milliseconds (default 1000).
+#[derive(Debug, Clone)]
+pub struct HyperionServer {
+    pub host: String,
+    pub port: u16,
+    pub priority: i32,
+    pub origin: String,
+    pub display: usize,
+    pub connect_timeout: u32,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonHyperionServer {
+    pub host: String,
+    pub port: Option<u16>,
+    pub priority: Option<i32>,
+    pub origin: Option<String>,
+    pub display: Option<usize>,
+    pub connectTimeout: Option<u32>,
+}
+
+impl From<JsonHyperionServer> for HyperionServer {
+    fn from(json: JsonHyperionServer) -> Self {
+        Self {
+            host: json.host,
+            port: json.port.unwrap_or(19400),
+            priority: json.priority.unwrap_or(150),
+            origin: json.origin.unwrap_or_else(|| String::from("AdaLight")),
+            display: json.display.unwrap_or_default(),
+            connect_timeout: json.connectTimeout.unwrap_or(1000),
+        }
+    }
+}
+
+/// A lamp in a Philips Hue entertainment area, which shows the average color of the `leds`.
+/// The LED indices are in the same order as the displays, like the `firstLed` of a
+/// [SerialOutput].
+#[derive(Debug)]
+#[cfg_attr(not(feature = "hue"), allow(dead_code))]
+pub struct HueLight {
+    pub id: u16,
+    pub leds: Vec<usize>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonHueLight {
+    pub id: u16,
+    pub leds: Vec<usize>,
+}
+
+impl From<JsonHueLight> for HueLight {
+    fn from(json: JsonHueLight) -> Self {
+        Self {
+            id: json.id,
+            leds: json.leds,
+        }
+    }
+}
+
+/// A Philips Hue bridge, which streams colors to the `lights` in the entertainment area
+/// `group` with the Hue Entertainment API. The `username` and hex encoded `client_key` come
+/// from registering an application with `generateclientkey` on the bridge. It's only available
+/// if AdaLight was built with the `hue` feature, and [Settings::from_str] returns an error if
+/// one is configured without it.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "hue"), allow(dead_code))]
+pub struct HueBridge {
+    pub host: String,
+    pub username: String,
+    pub client_key: String,
+    pub group: u32,
+    pub lights: Vec<HueLight>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonHueBridge {
+    pub host: String,
+    pub username: String,
+    pub clientKey: String,
+    pub group: u32,
+    pub lights: Vec<JsonHueLight>,
+}
+
+impl From<JsonHueBridge> for HueBridge {
+    fn from(json: JsonHueBridge) -> Self {
+        Self {
+            host: json.host,
+            username: json.username,
+            client_key: json.clientKey,
+            group: json.group,
+            lights: json.lights.into_iter().map(|light| light.into()).collect(),
+        }
+    }
+}
+
+/// A panel on a Nanoleaf controller, which shows the average color of the `leds`, like a
+/// [HueLight].
+#[derive(Debug)]
+pub struct NanoleafPanel {
+    pub id: u16,
+    pub leds: Vec<usize>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonNanoleafPanel {
+    pub id: u16,
+    pub leds: Vec<usize>,
+}
+
+impl From<JsonNanoleafPanel> for NanoleafPanel {
+    fn from(json: JsonNanoleafPanel) -> Self {
+        Self {
+            id: json.id,
+            leds: json.leds,
+        }
+    }
+}
+
+/// A Nanoleaf controller, which streams colors to the `panels` with the external control
+/// (version 2) UDP protocol. The `token` comes from pairing with the OpenAPI on `port`
+/// (default 16021), and each panel fades to the next color over `transition` tenths of a
+/// second (default 1).
+#[derive(Debug)]
+pub struct NanoleafController {
+    pub host: String,
+    pub port: u16,
+    pub token: String,
+    pub transition: u16,
+    pub panels: Vec<NanoleafPanel>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonNanoleafController {
+    pub host: String,
+    pub port: Option<u16>,
+    pub token: String,
+    pub transition: Option<u16>,
+    pub panels: Vec<JsonNanoleafPanel>,
+}
+
+impl From<JsonNanoleafController> for NanoleafController {
+    fn from(json: JsonNanoleafController) -> Self {
+        Self {
+            host: json.host,
+            port: json.port.unwrap_or(16021),
+            token: json.token,
+            transition: json.transition.unwrap_or(1),
+            panels: json.panels.into_iter().map(|panel| panel.into()).collect(),
+        }
+    }
+}
+
+/// Types of Razer peripherals which the Chroma SDK can set to a single color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChromaDeviceType {
+    Keyboard,
+    Mouse,
+    Mousepad,
+    Headset,
+    Keypad,
+    Chromalink,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonChromaDeviceType {
+    Keyboard,
+    Mouse,
+    Mousepad,
+    Headset,
+    Keypad,
+    Chromalink,
+}
+
+impl From<JsonChromaDeviceType> for ChromaDeviceType {
+    fn from(json: JsonChromaDeviceType) -> Self {
+        match json {
+            JsonChromaDeviceType::Keyboard => Self::Keyboard,
+            JsonChromaDeviceType::Mouse => Self::Mouse,
+            JsonChromaDeviceType::Mousepad => Self::Mousepad,
+            JsonChromaDeviceType::Headset => Self::Headset,
+            JsonChromaDeviceType::Keypad => Self::Keypad,
+            JsonChromaDeviceType::Chromalink => Self::Chromalink,
+        }
+    }
+}
+
+/// A type of Razer peripheral, which shows the average color of the `leds`, like a [HueLight].
+#[derive(Debug)]
+pub struct ChromaDevice {
+    pub device: ChromaDeviceType,
+    pub leds: Vec<usize>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonChromaDevice {
+    pub device: JsonChromaDeviceType,
+    pub leds: Vec<usize>,
+}
+
+impl From<JsonChromaDevice> for ChromaDevice {
+    fn from(json: JsonChromaDevice) -> Self {
+        Self {
+            device: json.device.into(),
+            leds: json.leds,
+        }
+    }
+}
+
+/// Mirror some of the LED colors to Razer peripherals with the Chroma SDK REST API on this PC.
+/// Each HTTP request takes a lot longer than a serial or UDP write, so the `devices` are only
+/// updated every `interval` milliseconds (default 100) on a separate thread.
+#[derive(Debug)]
+pub struct ChromaConfiguration {
+    pub interval: u32,
+    pub devices: Vec<ChromaDevice>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonChromaConfiguration {
+    pub interval: Option<u32>,
+    pub devices: Vec<JsonChromaDevice>,
+}
+
+impl From<JsonChromaConfiguration> for ChromaConfiguration {
+    fn from(json: JsonChromaConfiguration) -> Self {
+        Self {
+            interval: json.interval.unwrap_or(100),
+            devices: json
+                .devices
+                .into_iter()
+                .map(|device| device.into())
+                .collect(),
+        }
+    }
+}
+
+/// Connection to an MQTT broker, which gets the availability, state, brightness, frame rate,
+/// and average color under the `topic` prefix (default "adalight") every `interval`
+/// milliseconds (default 1000). Turning the LEDs on and off and setting the brightness from
+/// the broker uses the `{topic}/set` and `{topic}/brightness/set` command topics. The `port`
+/// defaults to 1883, the `client_id` to "adalight", and the `keep_alive` to 30 seconds. MQTT
+/// 3.1.1 only sends a `password` along with a `username`, so [Settings::from_str] rejects a
+/// password without one.
+#[derive(Debug, Clone)]
+pub struct MqttConfiguration {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic: String,
+    pub interval: u32,
+    pub keep_alive: u16,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct JsonMqttConfiguration {
+    pub host: String,
+    pub port: Option<u16>,
+    pub clientId: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic: Option<String>,
+    pub interval: Option<u32>,
+    pub keepAlive: Option<u16>,
+}
+
+impl From<JsonMqttConfiguration> for MqttConfiguration {
+    fn from(json: JsonMqttConfiguration) -> Self {
+        Self {
+            host: json.host,
+            port: json.port.unwrap_or(1883),
+            client_id: json.clientId.unwrap_or_else(|| String::from("adalight")),
+            username: json.username,
+            password: json.password,
+            topic: json.topic.unwrap_or_else(|| String::from("adalight")),
+            interval: json.interval.unwrap_or(1000),
+            keep_alive: json.keepAlive.unwrap_or(30),
+        }
+    }
+}
+
+/// A named preset which the [RestConfiguration] API can switch to. Each entry in `displays`
+/// and `servers` enables or disables the configured display or OPC server at the same index,
+/// and any which aren't listed stay the way they are. The `brightness` applies to any WLED
+/// serial outputs, if it's set.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub brightness: Option<u8>,
+    pub displays: Vec<bool>,
+    pub servers: Vec<bool>,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonProfile {
+    pub name: String,
+    pub brightness: Option<u8>,
+    pub displays: Option<Vec<bool>>,
+    pub servers: Option<Vec<bool>>,
+}
+
+impl From<JsonProfile> for Profile {
+    fn from(json: JsonProfile) -> Self {
+        Self {
+            name: json.name,
+            brightness: json.brightness,
+            displays: json.displays.unwrap_or_default(),
+            servers: json.servers.unwrap_or_default(),
+        }
+    }
+}
+
+/// Serve a small REST API on the `bind` address (default `127.0.0.1:8421`) to turn the LEDs on
+/// and off, change the brightness, switch to one of the [Profile] presets, and get the current
+/// status, e.g. from Stream Deck buttons or scripts.
+#[derive(Debug, Clone)]
+pub struct RestConfiguration {
+    pub bind: String,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonRestConfiguration {
+    pub bind: Option<String>,
+}
+
+impl From<JsonRestConfiguration> for RestConfiguration {
+    fn from(json: JsonRestConfiguration) -> Self {
+        Self {
+            bind: json.bind.unwrap_or_else(|| String::from("127.0.0.1:8421")),
+        }
+    }
+}
+
+/// Serve the gRPC `Control` service from `proto/adalight.proto` on the `bind` address (default
+/// `127.0.0.1:50051`). It's only available if AdaLight was built with the `grpc` feature, and
+/// [Settings::from_str] returns an error if it's configured without it.
+#[derive(Debug, Clone)]
+pub struct GrpcConfiguration {
+    pub bind: String,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonGrpcConfiguration {
+    pub bind: Option<String>,
+}
+
+impl From<JsonGrpcConfiguration> for GrpcConfiguration {
+    fn from(json: JsonGrpcConfiguration) -> Self {
+        Self {
+            bind: json.bind.unwrap_or_else(|| String::from("127.0.0.1:50051")),
+        }
+    }
+}
+
+/// Stream the LED colors from the [crate::preview::Preview] to WebSocket clients on the `bind`
+/// address (default `127.0.0.1:8422`), at most `fps` times per second (default 10), so a
+/// browser page can show what the LEDs are showing while debugging the LED positions.
+#[derive(Debug, Clone)]
+pub struct PreviewStreamConfiguration {
+    pub bind: String,
+    pub fps: u32,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonPreviewStreamConfiguration {
+    pub bind: Option<String>,
+    pub fps: Option<u32>,
+}
+
+impl From<JsonPreviewStreamConfiguration> for PreviewStreamConfiguration {
+    fn from(json: JsonPreviewStreamConfiguration) -> Self {
+        Self {
+            bind: json.bind.unwrap_or_else(|| String::from("127.0.0.1:8422")),
+            fps: json.fps.unwrap_or(10).max(1),
+        }
+    }
+}
+
+/// Listen for OPC clients on `port` (default 7890), e.g. another instance on an HTPC, and show
+/// the pixels they send on the LEDs in order instead of the screen colors. Only messages for
+/// `channel` are used, unless it's 0 (the default) which accepts all of them, and messages for
+/// channel 0 are always used. If we don't get a frame for `timeout` milliseconds (default
+/// 1000), the LEDs go back to the screen colors.
+#[derive(Debug)]
+pub struct OpcReceiverConfiguration {
+    pub port: u16,
+    pub channel: u8,
+    pub timeout: u32,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonOpcReceiverConfiguration {
+    pub port: Option<u16>,
+    pub channel: Option<u8>,
+    pub timeout: Option<u32>,
+}
+
+impl From<JsonOpcReceiverConfiguration> for OpcReceiverConfiguration {
+    fn from(json: JsonOpcReceiverConfiguration) -> Self {
+        Self {
+            port: json.port.unwrap_or(7890),
+            channel: json.channel.unwrap_or(0),
+            timeout: json.timeout.unwrap_or(1000),
+        }
+    }
+}
+
+/// What to do with the OPC servers and WLED controllers which were found with mDNS discovery,
+/// see [DiscoveryConfiguration].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscoveryMode {
+    /// Only report whether each configured host was discovered.
+    Validate,
+
+    /// Also replace each configured host which matches a discovered instance name or host name
+    /// with its address, and the port for an OPC server.
+    Resolve,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonDiscoveryMode {
+    Validate,
+    Resolve,
+}
+
+impl From<JsonDiscoveryMode> for DiscoveryMode {
+    fn from(json: JsonDiscoveryMode) -> Self {
+        match json {
+            JsonDiscoveryMode::Validate => Self::Validate,
+            JsonDiscoveryMode::Resolve => Self::Resolve,
+        }
+    }
+}
+
+/// Browse for OPC servers, WLED controllers, and DDP receivers with mDNS at startup. We wait
+/// `timeout` milliseconds (default 2000) for the responses, and then check the configured hosts
+/// with the [DiscoveryMode] (default [DiscoveryMode::Validate]). What was found is written to
+/// `AdaLight.discovery.json` next to the settings file.
+#[derive(Debug)]
+pub struct DiscoveryConfiguration {
+    pub mode: DiscoveryMode,
+    pub timeout: u32,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonDiscoveryConfiguration {
+    pub mode: Option<JsonDiscoveryMode>,
+    pub timeout: Option<u32>,
+}
+
+impl From<JsonDiscoveryConfiguration> for DiscoveryConfiguration {
+    fn from(json: JsonDiscoveryConfiguration) -> Self {
+        Self {
+            mode: json
+                .mode
+                .map_or(DiscoveryMode::Validate, |mode| mode.into()),
+            timeout: json.timeout.unwrap_or(2000),
+        }
+    }
+}
+
+/// Whether this instance drives the frame timing for the others, see [SyncConfiguration].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Broadcast the frame timing and brightness every time the timer fires.
+    Leader,
+
+    /// Start each frame when the leader's packet arrives, and use its brightness.
+    Follower,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum JsonSyncMode {
+    Leader,
+    Follower,
+}
+
+impl From<JsonSyncMode> for SyncMode {
+    fn from(json: JsonSyncMode) -> Self {
+        match json {
+            JsonSyncMode::Leader => Self::Leader,
+            JsonSyncMode::Follower => Self::Follower,
+        }
+    }
+}
+
+/// Keep several instances (e.g. two PCs driving the strips behind adjacent monitors) in
+/// lockstep over UDP. The [SyncMode::Leader] broadcasts a packet to the `address` (default
+/// `255.255.255.255`) and `port` (default 21330) every time its timer fires, and each
+/// [SyncMode::Follower] listening on the same `port` starts a frame as soon as one arrives from
+/// the same `group` (default 0). If a follower doesn't hear from the leader for `timeout`
+/// milliseconds (default 1000), it falls back to its own frame rate.
+#[derive(Debug, Clone)]
+pub struct SyncConfiguration {
+    pub mode: SyncMode,
+    pub address: String,
+    pub port: u16,
+    pub group: u8,
+    pub timeout: u32,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+struct JsonSyncConfiguration {
+    pub mode: JsonSyncMode,
+    pub address: Option<String>,
+    pub port: Option<u16>,
+    pub group: Option<u8>,
+    pub timeout: Option<u32>,
+}
+
+impl From<JsonSyncConfiguration> for SyncConfiguration {
+    fn from(json: JsonSyncConfiguration) -> Self {
+        Self {
+            mode: json.mode.into(),
+            address: json
+                .address
+                .unwrap_or_else(|| String::from("255.255.255.255")),
+            port: json.port.unwrap_or(21330),
+            group: json.group.unwrap_or_default(),
+            timeout: json.timeout.unwrap_or(1000),
+        }
+    }
+}
+
+/// JSON doesn't allow comments, and neither does [serde_json], but the C++ version
+/// used the [cpprestsdk](https://github.com/microsoft/cpprestsdk) parser which ignores
+/// them. So, to maintain backwards compatibility (and preserve the comments in the
+/// sample), strip them on input while deserializing the settings.
+fn strip_comments(json: &str) -> String {
+    #[derive(Debug)]
+    enum State {
+        Parsed,
+        QuotedString,
+        CommentBlock,
+    }
+
+    let mut state = State::Parsed;
+    let mut output = Vec::new();
+    let start_token = Regex::new(r#"(?:"|(?:/[/*]))"#).expect("build regex");
+    let quoted = Regex::new(r#"(?m)^(?:[^"]|(?:\\"))*"#).expect("build regex");
+    let end_block = Regex::new(r#"(?:\*/)"#).expect("build regex");
+    let empty_line = Regex::new(r#"(?m)^\s*$"#).expect("build regex");
+
+    for mut line in json.lines() {
+        let mut content = String::new();
+
+        loop {
+            if line.is_empty() {
+                break;
+            }
+
+            match state {
                 State::Parsed => match start_token.find(line) {
                     Some(mat) if mat.as_str() == r#"""# => {
                         let start_quote = mat.end();
@@ -358,6 +2292,12 @@ pub struct Settings {
     /// or set to 0 to disable this feature.
     pub min_brightness: u8,
 
+    /// Black level threshold; if the brightest channel of a sampled color is below this, the
+    /// LED fades to true black instead of getting boosted to the `min_brightness`, so dark
+    /// scenes don't glow gray. Anything brighter still gets the boost. Defaults to 0, which
+    /// disables this feature.
+    pub black_level: u8,
+
     /// LED transition speed; it's sometimes distracting if LEDs instantaneously
     /// track screen contents (such as during bright flashing sequences), so this
     /// feature enables a gradual fade to each new LED state. Higher numbers yield
@@ -365,10 +2305,47 @@ pub struct Settings {
     /// (immediate transition of all LEDs).
     pub fade: f64,
 
+    /// The [FadeSpace] which the `fade` blends the colors in, defaults to [FadeSpace::Rgb].
+    pub fade_space: FadeSpace,
+
+    /// Time (in milliseconds) to fade the LEDs out to black when the update timer stops,
+    /// e.g. when the session is locked or the program exits, defaults to 500. Set to 0 to
+    /// turn them off immediately.
+    pub fade_out: u32,
+
     /// Serial device timeout (in milliseconds), for locating Arduino device
     /// running the corresponding LEDstream code.
     pub timeout: u32,
 
+    /// Serial baud rate which the Arduino sketch is configured for, defaults to 115200. At
+    /// 115200 baud the strip tops out around 150 LEDs at 30 FPS, so longer strips need one of
+    /// the faster rates: 230400, 500000, 1000000, or 2000000.
+    pub baud_rate: u32,
+
+    /// If the Arduino doesn't answer at the `baud_rate`, keep trying each of the slower
+    /// supported rates down to 115200 until the handshake succeeds.
+    pub probe_baud_rate: bool,
+
+    /// COM port numbers which the scan for the Arduino never opens, e.g. for a 3D printer or a
+    /// UPS. Ports pinned in a [SerialOutput] are still opened.
+    pub exclude_ports: Vec<u8>,
+
+    /// Device instance IDs (e.g. `USB\VID_2341&PID_0043`), or prefixes of them, for USB serial
+    /// adapters which the scan for the Arduino never opens, whichever COM port they're on.
+    pub exclude_devices: Vec<String>,
+
+    /// How quickly to reopen a serial port or a network output after a write fails, see
+    /// [ReconnectConfiguration].
+    pub reconnect: ReconnectConfiguration,
+
+    /// Set of Arduinos which each drive a range of the LEDs, defaults to a single
+    /// [SerialOutput] driving all of them.
+    pub serial_outputs: Vec<SerialOutput>,
+
+    /// How long (in milliseconds) the [SerialOutput] LEDs take to show a frame, like the
+    /// [OpcServer] `latency`. This defaults to 0, since they're usually the fastest output.
+    pub serial_latency: u32,
+
     /// Cap the refresh rate at 30 FPS. If the update takes longer the FPS
     /// will actually be lower.
     pub fps_max: u32,
@@ -378,13 +2355,137 @@ pub struct Settings {
     /// the display, but it will take longer to resume sampling again.
     pub throttle_timer: u32,
 
+    /// Optional timeout (in milliseconds) to wait for a new frame from each display. This
+    /// defaults to the delay between frames for `fps_max`, but it can be tuned separately
+    /// to trade capture latency against how long we block waiting for the desktop to change.
+    pub capture_timeout: Option<u32>,
+
     /// Set of displays that should be sampled to drive the LED display.
     pub displays: Vec<DisplayConfiguration>,
 
+    /// Convert the samples to linear light before averaging them and back to sRGB afterwards.
+    /// Averaging the gamma encoded values directly biases the result towards darker colors.
+    pub linear_averaging: bool,
+
+    /// Gamma correction for the LEDs, see [GammaConfiguration].
+    pub gamma: GammaConfiguration,
+
+    /// Optional saturation and value adjustment for each LED color, see [HsvConfiguration].
+    pub hsv: Option<HsvConfiguration>,
+
+    /// Optional 3x3 matrix which corrects each LED color before gamma correction, e.g. to
+    /// compensate for crosstalk between the channels or LED primaries which don't match the
+    /// monitor. Each row is the contribution of the sampled red, green and blue channels to
+    /// the red, green or blue LED channel, so the identity matrix doesn't change anything.
+    pub color_matrix: Option<[[f64; 3]; 3]>,
+
+    /// Carry the rounding error of each LED channel over to the next frame when the colors are
+    /// quantized to 8 bits, so slow fades at low brightness don't step between the levels.
+    pub temporal_dithering: bool,
+
+    /// Carry the rounding error of each LED channel over to the next LED in order, so long runs
+    /// of nearly identical colors don't show bands after gamma correction. If both kinds of
+    /// dithering are enabled, the error is split between the next LED and the next frame.
+    pub spatial_dithering: bool,
+
+    /// Optional letterbox and pillarbox detection, which keeps the edge LEDs tracking the
+    /// picture instead of the black bars around it.
+    pub letterbox: Option<LetterboxConfiguration>,
+
+    /// Optional black frame detection, which turns the LEDs off when the screen goes dark.
+    pub black_frame: Option<BlackFrameConfiguration>,
+
+    /// Optional automatic exposure compensation, which brightens dark scenes and dims bright
+    /// ones before applying the minimum brightness.
+    pub auto_exposure: Option<AutoExposureConfiguration>,
+
+    /// Optional limit on the estimated current draw of the LEDs, see [PowerLimitConfiguration].
+    pub power_limit: Option<PowerLimitConfiguration>,
+
+    /// Set of [BrightnessZone] ranges which override the brightness of some of the LEDs when
+    /// they're rendered. If more than one of them covers an LED, the first one wins.
+    pub brightness_zones: Vec<BrightnessZone>,
+
+    /// What to show while protected content is masked out of the desktop image, defaults
+    /// to [ProtectedContentFallback::Hold].
+    pub protected_content: ProtectedContentFallback,
+
+    /// How the sample pixels are distributed within each sample block, defaults to
+    /// [SampleDistribution::Grid]. The random patterns are generated once for each LED.
+    pub sample_distribution: SampleDistribution,
+
+    /// Generate frames with a [TestPattern] instead of capturing the displays. This can also
+    /// be enabled with the `--test-source [pattern]` command line argument.
+    pub test_source: Option<TestPattern>,
+
+    /// Read frames from a file with a [FileSourceConfiguration] instead of capturing the
+    /// displays. This can also be enabled with the `--file-source <path>` command line argument.
+    pub file_source: Option<FileSourceConfiguration>,
+
+    /// Downscale each frame once to a small buffer with 16x16 pixels per grid cell and sample
+    /// the LEDs from that, instead of reading all over a large texture. This is much friendlier
+    /// to the CPU cache on 4K and 8K displays.
+    pub downscale: bool,
+
+    /// Copy only the rows of each duplicated frame which the sample blocks read from to a
+    /// much smaller `staging` texture, instead of copying the whole frame from the GPU.
+    pub partial_copy: bool,
+
     /// Set of OPC (Open Pixel Controller) servers and channels which should also be
     /// driven by the display samples.
     pub servers: Vec<OpcServer>,
 
+    /// Set of WLED controllers which should also be driven over UDP, see [WledServer].
+    pub wled_servers: Vec<WledServer>,
+
+    /// Set of E1.31 (sACN) outputs which should also be driven, see [SacnOutput].
+    pub sacn_outputs: Vec<SacnOutput>,
+
+    /// Set of raw UDP outputs with custom framing which should also be driven, see
+    /// [UdpOutput].
+    pub udp_outputs: Vec<UdpOutput>,
+
+    /// Set of Hyperion or HyperHDR servers which should also get the LED colors, see
+    /// [HyperionServer].
+    pub hyperion_servers: Vec<HyperionServer>,
+
+    /// Set of Philips Hue bridges which should also stream to their lamps, see [HueBridge].
+    pub hue_bridges: Vec<HueBridge>,
+
+    /// Set of Nanoleaf controllers which should also stream to their panels, see
+    /// [NanoleafController].
+    pub nanoleaf_controllers: Vec<NanoleafController>,
+
+    /// Optional Razer peripherals which should also mirror some of the LEDs, see
+    /// [ChromaConfiguration].
+    pub chroma: Option<ChromaConfiguration>,
+
+    /// Optional MQTT broker for home automation, see [MqttConfiguration].
+    pub mqtt: Option<MqttConfiguration>,
+
+    /// Optional mDNS discovery of the network outputs, see [DiscoveryConfiguration].
+    pub discovery: Option<DiscoveryConfiguration>,
+
+    /// Optionally accept frames from OPC clients, see [OpcReceiverConfiguration].
+    pub opc_receiver: Option<OpcReceiverConfiguration>,
+
+    /// Optional local REST API to control the LEDs, see [RestConfiguration].
+    pub rest: Option<RestConfiguration>,
+
+    /// Set of presets which the REST API can switch to, see [Profile].
+    pub profiles: Vec<Profile>,
+
+    /// Optional gRPC control service, see [GrpcConfiguration].
+    pub grpc: Option<GrpcConfiguration>,
+
+    /// Optionally keep the frame timing in sync with other instances, see
+    /// [SyncConfiguration].
+    pub sync: Option<SyncConfiguration>,
+
+    /// Optionally stream the LED colors to WebSocket clients, see
+    /// [PreviewStreamConfiguration].
+    pub preview_stream: Option<PreviewStreamConfiguration>,
+
     #[doc(hidden)]
     min_brightness_color: u32,
     #[doc(hidden)]
@@ -397,11 +2498,69 @@ pub struct Settings {
 
 impl Settings {
     /// Strip any JSON comments for backwards compatibility and parse the settings
-    /// from a configuration file.
+    /// from a configuration file. If the [GammaConfiguration] sets a `table`, it must load.
     pub fn from_str(json: &str) -> Result<Self> {
         let json = strip_comments(json);
-        let json: JsonSettings = serde_json::from_str(&json)?;
-        Ok(json.into())
+        let mut json: JsonSettings = serde_json::from_str(&json)?;
+        let brightness_zones = json
+            .brightnessZones
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(BrightnessZone::try_from)
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(serde_json::Error::io)?;
+        let mut settings: Self = json.into();
+        if let Some(zone) = brightness_zones
+            .iter()
+            .find(|zone| zone.first_led + zone.led_count > settings.total_led_count)
+        {
+            return Err(serde_json::Error::io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "brightness zone at LED {} with {} LEDs is past the last of {} LEDs",
+                    zone.first_led, zone.led_count, settings.total_led_count
+                ),
+            )));
+        }
+        settings.brightness_zones = brightness_zones;
+        if let Some(path) = &settings.gamma.table {
+            GammaLookup::load(path).map_err(|error| {
+                serde_json::Error::io(io::Error::new(
+                    error.kind(),
+                    format!("failed to load the gamma table {}: {}", path, error),
+                ))
+            })?;
+        }
+        if let Some(MqttConfiguration {
+            username: None,
+            password: Some(_),
+            ..
+        }) = &settings.mqtt
+        {
+            return Err(serde_json::Error::io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "MQTT needs a username to go with the password",
+            )));
+        }
+        #[cfg(not(feature = "hue"))]
+        if !settings.hue_bridges.is_empty() {
+            return Err(serde_json::Error::io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Hue bridges are configured, but this build does not include the hue feature",
+            )));
+        }
+        #[cfg(not(feature = "grpc"))]
+        if let Some(grpc) = &settings.grpc {
+            return Err(serde_json::Error::io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "gRPC is configured on {}, but this build does not include the grpc feature",
+                    grpc.bind
+                ),
+            )));
+        }
+        Ok(settings)
     }
 
     /// Determine the color of an LED at its minimum brightness.
@@ -423,6 +2582,11 @@ impl Settings {
     pub fn get_delay(&self) -> u32 {
         self.delay
     }
+
+    /// Get the timeout in milliseconds to wait for a new frame, which defaults to `get_delay`.
+    pub fn get_capture_timeout(&self) -> u32 {
+        self.capture_timeout.unwrap_or(self.delay)
+    }
 }
 
 #[doc(hidden)]
@@ -430,32 +2594,182 @@ impl Settings {
 #[allow(non_snake_case)]
 struct JsonSettings {
     pub minBrightness: u8,
+    pub blackLevel: Option<u8>,
     pub fade: f64,
+    pub fadeSpace: Option<JsonFadeSpace>,
+    pub fadeOut: Option<u32>,
     pub timeout: u32,
+    pub baudRate: Option<u32>,
+    pub probeBaudRate: Option<bool>,
+    pub excludePorts: Option<Vec<JsonSerialPort>>,
+    pub reconnect: Option<JsonReconnectConfiguration>,
+    pub serialOutputs: Option<Vec<JsonSerialOutput>>,
+    pub serialLatency: Option<u32>,
     pub fpsMax: u32,
     pub throttleTimer: u32,
+    pub captureTimeout: Option<u32>,
     pub displays: Vec<JsonDisplayConfiguration>,
+    pub linearAveraging: Option<bool>,
+    pub gamma: Option<JsonGammaConfiguration>,
+    pub hsv: Option<JsonHsvConfiguration>,
+    pub colorMatrix: Option<[[f64; 3]; 3]>,
+    pub temporalDithering: Option<bool>,
+    pub spatialDithering: Option<bool>,
+    pub letterbox: Option<JsonLetterboxConfiguration>,
+    pub blackFrame: Option<JsonBlackFrameConfiguration>,
+    pub autoExposure: Option<JsonAutoExposureConfiguration>,
+    pub powerLimit: Option<JsonPowerLimitConfiguration>,
+    pub brightnessZones: Option<Vec<JsonBrightnessZone>>,
+    pub protectedContent: Option<JsonProtectedContentFallback>,
+    pub sampleDistribution: Option<JsonSampleDistribution>,
+    pub testSource: Option<JsonTestPattern>,
+    pub fileSource: Option<JsonFileSourceConfiguration>,
+    pub downscale: Option<bool>,
+    pub partialCopy: Option<bool>,
     pub servers: Vec<JsonOpcServer>,
+    pub wledServers: Option<Vec<JsonWledServer>>,
+    pub sacnOutputs: Option<Vec<JsonSacnOutput>>,
+    pub udpOutputs: Option<Vec<JsonUdpOutput>>,
+    pub hyperionServers: Option<Vec<JsonHyperionServer>>,
+    pub hueBridges: Option<Vec<JsonHueBridge>>,
+    pub nanoleafControllers: Option<Vec<JsonNanoleafController>>,
+    pub chroma: Option<JsonChromaConfiguration>,
+    pub mqtt: Option<JsonMqttConfiguration>,
+    pub discovery: Option<JsonDiscoveryConfiguration>,
+    pub opcReceiver: Option<JsonOpcReceiverConfiguration>,
+    pub rest: Option<JsonRestConfiguration>,
+    pub profiles: Option<Vec<JsonProfile>>,
+    pub grpc: Option<JsonGrpcConfiguration>,
+    pub sync: Option<JsonSyncConfiguration>,
+    pub previewStream: Option<JsonPreviewStreamConfiguration>,
 }
 
 impl From<JsonSettings> for Settings {
     fn from(json: JsonSettings) -> Self {
+        let serial_outputs = json
+            .serialOutputs
+            .map(|outputs| outputs.into_iter().map(|output| output.into()).collect());
+        let mut exclude_ports = Vec::new();
+        let mut exclude_devices = Vec::new();
+        for port in json.excludePorts.unwrap_or_default() {
+            match port {
+                JsonSerialPort::Number(port) => exclude_ports.push(port),
+                JsonSerialPort::Name(name) => match SerialPort::parse_port_name(&name) {
+                    Some(port) => exclude_ports.push(port),
+                    None => exclude_devices.push(name),
+                },
+            }
+        }
+
         let mut settings = Self {
             min_brightness: json.minBrightness,
+            black_level: json.blackLevel.unwrap_or_default(),
             fade: json.fade,
+            fade_space: json
+                .fadeSpace
+                .map_or(FadeSpace::Rgb, |fade_space| fade_space.into()),
+            fade_out: json.fadeOut.unwrap_or(500),
             timeout: json.timeout,
+            baud_rate: json.baudRate.unwrap_or(115200),
+            probe_baud_rate: json.probeBaudRate.unwrap_or(false),
+            exclude_ports,
+            exclude_devices,
+            reconnect: json
+                .reconnect
+                .unwrap_or(JsonReconnectConfiguration {
+                    retries: None,
+                    delay: None,
+                })
+                .into(),
+            serial_outputs: Vec::new(),
+            serial_latency: json.serialLatency.unwrap_or_default(),
             fps_max: json.fpsMax,
             throttle_timer: json.throttleTimer,
+            capture_timeout: json.captureTimeout,
             displays: json
                 .displays
                 .into_iter()
                 .map(|display| display.into())
                 .collect(),
+            linear_averaging: json.linearAveraging.unwrap_or(false),
+            gamma: json
+                .gamma
+                .map_or_else(GammaConfiguration::default, |gamma| gamma.into()),
+            hsv: json.hsv.map(|hsv| hsv.into()),
+            color_matrix: json.colorMatrix,
+            temporal_dithering: json.temporalDithering.unwrap_or(false),
+            spatial_dithering: json.spatialDithering.unwrap_or(false),
+            letterbox: json.letterbox.map(|letterbox| letterbox.into()),
+            black_frame: json.blackFrame.map(|black_frame| black_frame.into()),
+            auto_exposure: json.autoExposure.map(|auto_exposure| auto_exposure.into()),
+            power_limit: json.powerLimit.map(|power_limit| power_limit.into()),
+            // Settings::from_str validates these with TryFrom.
+            brightness_zones: Vec::new(),
+            protected_content: json
+                .protectedContent
+                .map_or(ProtectedContentFallback::Hold, |fallback| fallback.into()),
+            sample_distribution: json
+                .sampleDistribution
+                .map_or(SampleDistribution::Grid, |distribution| distribution.into()),
+            test_source: json.testSource.map(|pattern| pattern.into()),
+            file_source: json.fileSource.map(|file_source| file_source.into()),
+            downscale: json.downscale.unwrap_or(false),
+            partial_copy: json.partialCopy.unwrap_or(false),
             servers: json
                 .servers
                 .into_iter()
                 .map(|server| server.into())
                 .collect(),
+            wled_servers: json
+                .wledServers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|server| server.into())
+                .collect(),
+            sacn_outputs: json
+                .sacnOutputs
+                .unwrap_or_default()
+                .into_iter()
+                .map(|output| output.into())
+                .collect(),
+            udp_outputs: json
+                .udpOutputs
+                .unwrap_or_default()
+                .into_iter()
+                .map(|output| output.into())
+                .collect(),
+            hyperion_servers: json
+                .hyperionServers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|server| server.into())
+                .collect(),
+            hue_bridges: json
+                .hueBridges
+                .unwrap_or_default()
+                .into_iter()
+                .map(|bridge| bridge.into())
+                .collect(),
+            nanoleaf_controllers: json
+                .nanoleafControllers
+                .unwrap_or_default()
+                .into_iter()
+                .map(|controller| controller.into())
+                .collect(),
+            chroma: json.chroma.map(|chroma| chroma.into()),
+            mqtt: json.mqtt.map(|mqtt| mqtt.into()),
+            discovery: json.discovery.map(|discovery| discovery.into()),
+            opc_receiver: json.opcReceiver.map(|receiver| receiver.into()),
+            rest: json.rest.map(|rest| rest.into()),
+            profiles: json
+                .profiles
+                .unwrap_or_default()
+                .into_iter()
+                .map(|profile| profile.into())
+                .collect(),
+            grpc: json.grpc.map(|grpc| grpc.into()),
+            sync: json.sync.map(|sync| sync.into()),
+            preview_stream: json.previewStream.map(|stream| stream.into()),
             min_brightness_color: 0,
             total_led_count: 0,
             weight: 0.0,
@@ -472,6 +2786,28 @@ impl From<JsonSettings> for Settings {
             settings.total_led_count += display.positions.len();
         }
 
+        settings.serial_outputs = serial_outputs.unwrap_or_else(|| {
+            vec![SerialOutput {
+                first_led: 0,
+                led_count: settings.total_led_count,
+                port: None,
+                device_path: None,
+                wled: None,
+                flow_control: false,
+                checksum: SerialChecksum::None,
+                dtr: None,
+                rts: None,
+                busy_policy: BusyPolicy::Drop,
+                header: SerialHeader::default(),
+                read_timeout: None,
+                write_timeout: None,
+                latency_timer: None,
+                raw: None,
+                baud_rates: Vec::new(),
+                white_point: None,
+            }]
+        });
+
         settings.weight = 1.0 - settings.fade;
         settings.delay = 1000 / settings.fps_max;
 
@@ -512,6 +2848,422 @@ mod test {
         assert_eq!(display_configuration.horizontal_count, 10);
         assert_eq!(display_configuration.vertical_count, 5);
         assert_eq!(display_configuration.positions.len(), 24);
+        assert!(display_configuration.window.is_none());
+        assert!(display_configuration.exclusions.is_empty());
+        assert!(display_configuration.sample_depth.is_none());
+        assert!(!display_configuration.angled_corners);
+        assert_eq!(display_configuration.temporal_samples, 1);
+        assert!(!display_configuration.follow_foreground);
+        assert!(!display_configuration.color_profile);
+        assert!(display_configuration.overlap.is_none());
+        assert!(display_configuration.enabled);
+    }
+
+    #[test]
+    fn parse_window_configuration() {
+        let display_configuration: JsonDisplayConfiguration = serde_json::from_str(
+            r#"
+{
+    "horizontalCount": 2,
+    "verticalCount": 2,
+    "positions": [ { "x": 0, "y": 0 }, { "x": 1, "y": 0 } ],
+    "window": { "processName": "vlc.exe", "title": " - VLC media player$" }
+}"#,
+        )
+        .expect("parse the JsonDisplayConfiguration");
+        let display_configuration: DisplayConfiguration = display_configuration.into();
+        let window = display_configuration.window.expect("window configuration");
+        assert_eq!(window.process_name.as_deref(), Some("vlc.exe"));
+        assert_eq!(window.title.as_deref(), Some(" - VLC media player$"));
+    }
+
+    #[test]
+    fn parse_exclusion_regions() {
+        let display_configuration: JsonDisplayConfiguration = serde_json::from_str(
+            r#"
+{
+    "horizontalCount": 10,
+    "verticalCount": 5,
+    "positions": [ { "x": 0, "y": 4 }, { "x": 9, "y": 0 } ],
+    "exclusions": [
+        { "left": 2, "top": 4.25, "right": 8, "bottom": 5 },
+        { "left": 7.5, "top": 0, "right": 10, "bottom": 3 }
+    ]
+}"#,
+        )
+        .expect("parse the JsonDisplayConfiguration");
+        let display_configuration: DisplayConfiguration = display_configuration.into();
+        assert_eq!(display_configuration.exclusions.len(), 2);
+        let subtitles = &display_configuration.exclusions[0];
+        assert!(subtitles.contains(5.0, 4.5));
+        assert!(!subtitles.contains(5.0, 4.0));
+        assert!(!subtitles.contains(8.0, 4.5));
+        let overlay = &display_configuration.exclusions[1];
+        assert!(overlay.contains(9.5, 0.0));
+        assert!(!overlay.contains(9.5, 3.0));
+    }
+
+    #[test]
+    fn parse_sampling_options() {
+        let display_configuration: JsonDisplayConfiguration = serde_json::from_str(
+            r#"
+{
+    "horizontalCount": 10,
+    "verticalCount": 5,
+    "positions": [ { "x": 0, "y": 4 } ],
+    "sampleDepth": 25,
+    "angledCorners": true,
+    "temporalSamples": 4,
+    "followForeground": true,
+    "colorProfile": true,
+    "overlap": 50,
+    "enabled": false
+}"#,
+        )
+        .expect("parse the JsonDisplayConfiguration");
+        let display_configuration: DisplayConfiguration = display_configuration.into();
+        assert_eq!(display_configuration.sample_depth, Some(25.0));
+        assert!(display_configuration.angled_corners);
+        assert_eq!(display_configuration.temporal_samples, 4);
+        assert!(display_configuration.follow_foreground);
+        assert!(display_configuration.color_profile);
+        assert_eq!(display_configuration.overlap, Some(50.0));
+        assert!(!display_configuration.enabled);
+    }
+
+    #[test]
+    fn parse_letterbox_configuration() {
+        let letterbox: JsonLetterboxConfiguration =
+            serde_json::from_str(r#"{ "threshold": 16, "debounce": 3, "interval": 10 }"#)
+                .expect("parse the JsonLetterboxConfiguration");
+        let letterbox: LetterboxConfiguration = letterbox.into();
+        assert_eq!(letterbox.threshold, 16);
+        assert_eq!(letterbox.debounce, 3);
+        assert_eq!(letterbox.interval, 10);
+    }
+
+    #[test]
+    fn parse_file_source_configuration() {
+        let file_source: JsonFileSourceConfiguration =
+            serde_json::from_str(r#"{ "path": "frames" }"#)
+                .expect("parse the JsonFileSourceConfiguration");
+        let file_source: FileSourceConfiguration = file_source.into();
+        assert_eq!(file_source.path, "frames");
+        assert!(file_source.repeat);
+    }
+
+    #[test]
+    fn parse_black_frame_configuration() {
+        let black_frame: JsonBlackFrameConfiguration =
+            serde_json::from_str(r#"{ "threshold": 8, "frames": 90 }"#)
+                .expect("parse the JsonBlackFrameConfiguration");
+        let black_frame: BlackFrameConfiguration = black_frame.into();
+        assert_eq!(black_frame.threshold, 8);
+        assert_eq!(black_frame.frames, 90);
+    }
+
+    #[test]
+    fn parse_gamma_configuration() {
+        let gamma: JsonGammaConfiguration = serde_json::from_str(
+            r#"{ "exponent": 2.2, "green": { "max": 255 }, "blue": { "exponent": 2.5, "max": 200 } }"#,
+        )
+        .expect("parse the JsonGammaConfiguration");
+        let gamma: GammaConfiguration = gamma.into();
+        assert_eq!(
+            gamma.red,
+            GammaCurve {
+                exponent: 2.2,
+                max: 255
+            }
+        );
+        assert_eq!(
+            gamma.green,
+            GammaCurve {
+                exponent: 2.2,
+                max: 255
+            }
+        );
+        assert_eq!(
+            gamma.blue,
+            GammaCurve {
+                exponent: 2.5,
+                max: 200
+            }
+        );
+
+        let gamma: JsonGammaConfiguration =
+            serde_json::from_str(r#"{}"#).expect("parse the JsonGammaConfiguration");
+        let gamma: GammaConfiguration = gamma.into();
+        assert_eq!(gamma.red.exponent, 2.8);
+        assert_eq!(gamma.blue.max, 220);
+        assert!(gamma.table.is_none());
+
+        let gamma: JsonGammaConfiguration = serde_json::from_str(r#"{ "table": "measured.csv" }"#)
+            .expect("parse the JsonGammaConfiguration");
+        let gamma: GammaConfiguration = gamma.into();
+        assert_eq!(gamma.table.as_deref(), Some("measured.csv"));
+    }
+
+    #[test]
+    fn parse_hsv_configuration() {
+        let hsv: JsonHsvConfiguration =
+            serde_json::from_str(r#"{ "saturation": 1.25, "valueGamma": 0.8 }"#)
+                .expect("parse the JsonHsvConfiguration");
+        let hsv: HsvConfiguration = hsv.into();
+        assert_eq!(hsv.saturation, 1.25);
+        assert_eq!(hsv.value_gamma, 0.8);
+
+        let hsv: JsonHsvConfiguration =
+            serde_json::from_str(r#"{}"#).expect("parse the JsonHsvConfiguration");
+        let hsv: HsvConfiguration = hsv.into();
+        assert_eq!(hsv.saturation, 1.0);
+        assert_eq!(hsv.value_gamma, 1.0);
+    }
+
+    #[test]
+    fn parse_auto_exposure_configuration() {
+        let auto_exposure: JsonAutoExposureConfiguration =
+            serde_json::from_str(r#"{ "strength": 0.75, "timeConstant": 5 }"#)
+                .expect("parse the JsonAutoExposureConfiguration");
+        let auto_exposure: AutoExposureConfiguration = auto_exposure.into();
+        assert_eq!(auto_exposure.strength, 0.75);
+        assert_eq!(auto_exposure.time_constant, 5.0);
+
+        let auto_exposure: JsonAutoExposureConfiguration =
+            serde_json::from_str(r#"{}"#).expect("parse the JsonAutoExposureConfiguration");
+        let auto_exposure: AutoExposureConfiguration = auto_exposure.into();
+        assert_eq!(auto_exposure.strength, 0.5);
+        assert_eq!(auto_exposure.time_constant, 3.0);
+    }
+
+    #[test]
+    fn parse_protected_content_fallback() {
+        let fallback: JsonProtectedContentFallback =
+            serde_json::from_str(r#""minBrightness""#).expect("parse the fallback");
+        let fallback: ProtectedContentFallback = fallback.into();
+        assert_eq!(fallback, ProtectedContentFallback::MinBrightness);
+    }
+
+    #[test]
+    fn parse_sample_distribution() {
+        let distribution: JsonSampleDistribution =
+            serde_json::from_str(r#""poisson""#).expect("parse the distribution");
+        let distribution: SampleDistribution = distribution.into();
+        assert_eq!(distribution, SampleDistribution::Poisson);
+    }
+
+    #[test]
+    fn parse_power_limit_configuration() {
+        let power_limit: JsonPowerLimitConfiguration = serde_json::from_str(
+            r#"{ "milliampsPerChannel": 15, "maxMilliamps": 8000, "hysteresis": 0.2, "release": 2 }"#,
+        )
+        .expect("parse the JsonPowerLimitConfiguration");
+        let power_limit: PowerLimitConfiguration = power_limit.into();
+        assert_eq!(power_limit.milliamps_per_channel, 15.0);
+        assert_eq!(power_limit.max_milliamps, 8000.0);
+        assert_eq!(power_limit.hysteresis, 0.2);
+        assert_eq!(power_limit.release, 2.0);
+
+        let power_limit: JsonPowerLimitConfiguration =
+            serde_json::from_str(r#"{ "maxMilliamps": 4000 }"#)
+                .expect("parse the JsonPowerLimitConfiguration");
+        let power_limit: PowerLimitConfiguration = power_limit.into();
+        assert_eq!(power_limit.milliamps_per_channel, 20.0);
+        assert_eq!(power_limit.max_milliamps, 4000.0);
+        assert_eq!(power_limit.hysteresis, 0.1);
+        assert_eq!(power_limit.release, 1.0);
+    }
+
+    #[test]
+    fn parse_brightness_zone() {
+        let zone: JsonBrightnessZone = serde_json::from_str(
+            r#"{ "firstLed": 10, "ledCount": 20, "minBrightness": 16, "maxBrightness": 96 }"#,
+        )
+        .expect("parse the JsonBrightnessZone");
+        let zone = BrightnessZone::try_from(zone).expect("convert the BrightnessZone");
+        assert_eq!(zone.first_led, 10);
+        assert_eq!(zone.led_count, 20);
+        assert_eq!(zone.min_brightness, 16);
+        assert_eq!(zone.max_brightness, 96);
+
+        let zone: JsonBrightnessZone = serde_json::from_str(r#"{ "firstLed": 0, "ledCount": 5 }"#)
+            .expect("parse the JsonBrightnessZone");
+        let zone = BrightnessZone::try_from(zone).expect("convert the BrightnessZone");
+        assert_eq!(zone.min_brightness, 0);
+        assert_eq!(zone.max_brightness, 255);
+
+        let zone: JsonBrightnessZone = serde_json::from_str(
+            r#"{ "firstLed": 0, "ledCount": 5, "minBrightness": 128, "maxBrightness": 64 }"#,
+        )
+        .expect("parse the JsonBrightnessZone");
+        assert!(BrightnessZone::try_from(zone).is_err());
+
+        let zone: JsonBrightnessZone = serde_json::from_str(r#"{ "firstLed": 3, "ledCount": 0 }"#)
+            .expect("parse the JsonBrightnessZone");
+        assert!(BrightnessZone::try_from(zone).is_err());
+
+        assert!(serde_json::from_str::<JsonBrightnessZone>(
+            r#"{ "firstLed": 0, "ledCount": 5, "maxBrightness": 256 }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_fade_space() {
+        let fade_space: JsonFadeSpace =
+            serde_json::from_str(r#""oklab""#).expect("parse the fade space");
+        let fade_space: FadeSpace = fade_space.into();
+        assert_eq!(fade_space, FadeSpace::Oklab);
+    }
+
+    #[test]
+    fn test_pattern_names() {
+        assert_eq!(
+            TestPattern::from_name("solidColors"),
+            Some(TestPattern::SolidColors)
+        );
+        assert_eq!(
+            TestPattern::from_name("gradient"),
+            Some(TestPattern::Gradient)
+        );
+        assert_eq!(
+            TestPattern::from_name("movingBars"),
+            Some(TestPattern::MovingBars)
+        );
+        assert_eq!(TestPattern::from_name("plaid"), None);
+    }
+
+    #[test]
+    fn parse_serial_output() {
+        let serial_output: JsonSerialOutput = serde_json::from_str(
+            r#"{ "firstLed": 50, "ledCount": 100, "port": 4, "flowControl": true, "checksum": "crc8", "dtr": false, "busyPolicy": "latest" }"#,
+        )
+        .expect("parse the JsonSerialOutput");
+        let serial_output: SerialOutput = serial_output.into();
+        assert_eq!(serial_output.first_led, 50);
+        assert_eq!(serial_output.led_count, 100);
+        assert_eq!(serial_output.port, Some(4));
+        assert!(serial_output.device_path.is_none());
+        assert!(serial_output.wled.is_none());
+        assert!(serial_output.flow_control);
+        assert_eq!(serial_output.checksum, SerialChecksum::Crc8);
+        assert_eq!(serial_output.dtr, Some(false));
+        assert!(serial_output.rts.is_none());
+        assert_eq!(serial_output.busy_policy, BusyPolicy::Latest);
+        assert_eq!(serial_output.header.magic, "Ada");
+        assert_eq!(serial_output.header.led_count, HeaderLedCount::Word);
+    }
+
+    #[test]
+    fn parse_serial_latency() {
+        let serial_output: JsonSerialOutput = serde_json::from_str(
+            r#"{ "firstLed": 0, "ledCount": 60, "writeTimeout": 10, "latencyTimer": 1 }"#,
+        )
+        .expect("parse the JsonSerialOutput");
+        let serial_output: SerialOutput = serial_output.into();
+        assert!(serial_output.read_timeout.is_none());
+        assert_eq!(serial_output.write_timeout, Some(10));
+        assert_eq!(serial_output.latency_timer, Some(1));
+    }
+
+    #[test]
+    fn parse_serial_baud_rates() {
+        let serial_output: JsonSerialOutput = serde_json::from_str(
+            r#"{ "firstLed": 0, "ledCount": 60, "baudRates": [1000000, 115200] }"#,
+        )
+        .expect("parse the JsonSerialOutput");
+        let serial_output: SerialOutput = serial_output.into();
+        assert_eq!(serial_output.baud_rates, [1_000_000, 115_200]);
+    }
+
+    #[test]
+    fn parse_serial_raw() {
+        let serial_output: JsonSerialOutput = serde_json::from_str(
+            r#"{
+                "firstLed": 0,
+                "ledCount": 60,
+                "port": 4,
+                "raw": {
+                    "header": [0, 0, 0, 0],
+                    "byteOrder": "bgr",
+                    "ledPrefix": 255,
+                    "footer": [255, 255, 255, 255]
+                }
+            }"#,
+        )
+        .expect("parse the JsonSerialOutput");
+        let serial_output: SerialOutput = serial_output.into();
+        let raw = serial_output.raw.expect("raw configuration");
+        assert_eq!(raw.header, [0, 0, 0, 0]);
+        assert_eq!(raw.byte_order, ByteOrder::Bgr);
+        assert_eq!(raw.led_prefix, Some(0xFF));
+        assert_eq!(raw.footer, [0xFF, 0xFF, 0xFF, 0xFF]);
+
+        let raw: JsonRawConfiguration =
+            serde_json::from_str("{}").expect("parse the JsonRawConfiguration");
+        let raw: RawConfiguration = raw.into();
+        assert!(raw.header.is_empty());
+        assert_eq!(raw.byte_order, ByteOrder::Rgb);
+        assert!(raw.led_prefix.is_none());
+        assert!(raw.footer.is_empty());
+    }
+
+    #[test]
+    fn parse_reconnect_configuration() {
+        let reconnect: JsonReconnectConfiguration = serde_json::from_str(r#"{ "retries": 5 }"#)
+            .expect("parse the JsonReconnectConfiguration");
+        let reconnect: ReconnectConfiguration = reconnect.into();
+        assert_eq!(reconnect.retries, 5);
+        assert_eq!(reconnect.delay, 50);
+    }
+
+    #[test]
+    fn parse_serial_port_names() {
+        let serial_output: JsonSerialOutput =
+            serde_json::from_str(r#"{ "firstLed": 0, "ledCount": 60, "port": "\\\\.\\COM12" }"#)
+                .expect("parse the JsonSerialOutput");
+        let serial_output: SerialOutput = serial_output.into();
+        assert_eq!(serial_output.port, Some(12));
+        assert!(serial_output.device_path.is_none());
+
+        let serial_output: JsonSerialOutput = serde_json::from_str(
+            r#"{ "firstLed": 0, "ledCount": 60, "port": "\\\\?\\USB#VID_2341&PID_0043#85734323" }"#,
+        )
+        .expect("parse the JsonSerialOutput");
+        let serial_output: SerialOutput = serial_output.into();
+        assert!(serial_output.port.is_none());
+        assert_eq!(
+            serial_output.device_path.as_deref(),
+            Some(r"\\?\USB#VID_2341&PID_0043#85734323")
+        );
+    }
+
+    #[test]
+    fn parse_serial_header() {
+        let serial_header: JsonSerialHeader =
+            serde_json::from_str(r#"{ "magic": "Awa", "ledCount": "extended" }"#)
+                .expect("parse the JsonSerialHeader");
+        let serial_header: SerialHeader = serial_header.into();
+        assert_eq!(serial_header.magic, "Awa");
+        assert_eq!(serial_header.led_count, HeaderLedCount::Extended);
+
+        let serial_header: JsonSerialHeader =
+            serde_json::from_str(r#"{ "ledCount": "byte" }"#).expect("parse the JsonSerialHeader");
+        let serial_header: SerialHeader = serial_header.into();
+        assert_eq!(serial_header.magic, "Ada");
+        assert_eq!(serial_header.led_count, HeaderLedCount::Byte);
+    }
+
+    #[test]
+    fn parse_wled_configuration() {
+        let serial_output: JsonSerialOutput = serde_json::from_str(
+            r#"{ "firstLed": 0, "ledCount": 60, "port": 5, "wled": { "brightness": 128 } }"#,
+        )
+        .expect("parse the JsonSerialOutput");
+        let serial_output: SerialOutput = serial_output.into();
+        let wled = serial_output.wled.expect("parse the WledConfiguration");
+        assert!(wled.power);
+        assert_eq!(wled.brightness, Some(128));
     }
 
     #[test]
@@ -583,6 +3335,15 @@ mod test {
     "host": "192.168.1.14",
     "port": "80",
     "alphaChannel": false,
+    "connectTimeout": 250,
+    "writeTimeout": 500,
+    "fpsMax": 20,
+    "latency": 80,
+    "batchChannels": true,
+    "failover": [
+        { "host": "backup.local", "port": "7890" },
+        { "host": "fe80::2", "port": "7890" }
+    ],
 
     "channels": [
         {
@@ -615,9 +3376,505 @@ mod test {
         assert_eq!(&opc_server.host, "192.168.1.14");
         assert_eq!(&opc_server.port, "80");
         assert!(!opc_server.alpha_channel);
+        assert_eq!(opc_server.connect_timeout, 250);
+        assert_eq!(opc_server.write_timeout, 500);
+        assert_eq!(opc_server.keep_alive, 5000);
+        assert_eq!(opc_server.fps_max, Some(20));
+        assert_eq!(opc_server.latency, 80);
+        assert!(opc_server.adaptive_rate);
+        assert!(opc_server.batch_channels);
+        assert_eq!(opc_server.failover.len(), 2);
+        assert_eq!(&opc_server.failover[0].host, "backup.local");
+        assert_eq!(&opc_server.failover[1].port, "7890");
+        assert_eq!(opc_server.failback_interval, 30000);
+        assert!(opc_server.proxy.is_none());
+        assert!(opc_server.startup_commands.is_empty());
+        assert!(opc_server.shutdown_commands.is_empty());
         assert_eq!(opc_server.channels.len(), 1);
     }
 
+    #[test]
+    fn parse_opc_proxy() {
+        let opc_server: JsonOpcServer = serde_json::from_str(
+            r#"
+{
+    "host": "fadecandy.lan",
+    "port": "7890",
+    "alphaChannel": false,
+    "proxy": { "protocol": "socks5", "host": "jump.example.com", "port": "1080", "username": "led", "password": "secret" },
+    "channels": []
+}"#,
+        )
+        .expect("parse the JsonOpcServer");
+        let opc_server: OpcServer = opc_server.into();
+        let proxy = opc_server.proxy.expect("parse the proxy");
+        assert_eq!(proxy.protocol, ProxyProtocol::Socks5);
+        assert_eq!(proxy.host, "jump.example.com");
+        assert_eq!(proxy.port, "1080");
+        assert_eq!(proxy.username.as_deref(), Some("led"));
+        assert_eq!(proxy.password.as_deref(), Some("secret"));
+
+        let proxy: JsonProxyConfiguration =
+            serde_json::from_str(r#"{ "protocol": "http", "host": "10.0.0.1", "port": "3128" }"#)
+                .expect("parse the JsonProxyConfiguration");
+        let proxy: ProxyConfiguration = proxy.into();
+        assert_eq!(proxy.protocol, ProxyProtocol::Http);
+        assert!(proxy.username.is_none());
+    }
+
+    #[test]
+    fn parse_white_point() {
+        let serial_output: JsonSerialOutput = serde_json::from_str(
+            r#"{ "firstLed": 0, "ledCount": 10, "whitePoint": { "green": 0.9, "blue": 1.5 } }"#,
+        )
+        .expect("parse the JsonSerialOutput");
+        let serial_output: SerialOutput = serial_output.into();
+        assert_eq!(
+            serial_output.white_point,
+            Some(WhitePoint {
+                red: 1.0,
+                green: 0.9,
+                blue: 1.0
+            })
+        );
+
+        let opc_server: JsonOpcServer = serde_json::from_str(
+            r#"
+{
+    "host": "fadecandy.lan",
+    "port": "7890",
+    "alphaChannel": false,
+    "whitePoint": { "red": 0.8 },
+    "channels": []
+}"#,
+        )
+        .expect("parse the JsonOpcServer");
+        let opc_server: OpcServer = opc_server.into();
+        assert_eq!(
+            opc_server.white_point.map(|white_point| white_point.red),
+            Some(0.8)
+        );
+    }
+
+    #[test]
+    fn parse_opc_broadcast_channel() {
+        let opc_server: JsonOpcServer = serde_json::from_str(
+            r#"
+{
+    "host": "fadecandy.local",
+    "port": "7890",
+    "alphaChannel": false,
+    "channels": [
+        { "channel": 2, "pixels": [ { "pixelCount": 4, "displayIndex": [] } ] },
+        { "pixels": [ { "pixelCount": 64, "displayIndex": [] } ] }
+    ]
+}"#,
+        )
+        .expect("parse the JsonOpcServer");
+        let opc_server: OpcServer = opc_server.into();
+        assert_eq!(opc_server.channels.len(), 2);
+        assert!(opc_server.channels[0].is_broadcast());
+        assert_eq!(opc_server.channels[0].get_total_pixel_count(), 64);
+        assert_eq!(opc_server.channels[1].channel, 2);
+        assert!(!opc_server.channels[1].is_broadcast());
+    }
+
+    #[test]
+    fn parse_opc_sysex() {
+        let opc_server: JsonOpcServer = serde_json::from_str(
+            r#"
+{
+    "host": "fadecandy.local",
+    "port": "7890",
+    "alphaChannel": false,
+    "startupCommands": [
+        { "systemId": 1, "data": [ [ 0, 2 ], [ 3 ] ] },
+        { "channel": 1, "systemId": 1, "data": [ [ 0, 1 ], "{\"gamma\":2.5}" ] }
+    ],
+    "shutdownCommands": [
+        { "systemId": 1, "data": [ [ 0, 2, 0 ] ] }
+    ],
+    "channels": []
+}"#,
+        )
+        .expect("parse the JsonOpcServer");
+        let opc_server: OpcServer = opc_server.into();
+        assert_eq!(opc_server.startup_commands.len(), 2);
+        assert_eq!(opc_server.startup_commands[0].channel, 0);
+        assert_eq!(opc_server.startup_commands[0].system_id, 1);
+        assert_eq!(opc_server.startup_commands[0].data, [0, 2, 3]);
+        assert_eq!(opc_server.startup_commands[1].channel, 1);
+        assert_eq!(
+            opc_server.startup_commands[1].data,
+            b"\x00\x01{\"gamma\":2.5}"
+        );
+        assert_eq!(opc_server.shutdown_commands.len(), 1);
+        assert_eq!(opc_server.shutdown_commands[0].data, [0, 2, 0]);
+    }
+
+    #[test]
+    fn parse_wled_server() {
+        let wled_server: JsonWledServer = serde_json::from_str(
+            r#"{ "host": "wled.local", "protocol": "warls", "firstLed": 12, "ledCount": 60 }"#,
+        )
+        .expect("parse the JsonWledServer");
+        let wled_server: WledServer = wled_server.into();
+        assert_eq!(&wled_server.host, "wled.local");
+        assert_eq!(wled_server.port, 21324);
+        assert_eq!(wled_server.protocol, Some(WledProtocol::Warls));
+        assert_eq!(wled_server.timeout, 2);
+        assert_eq!(wled_server.latency, 0);
+        assert_eq!(wled_server.first_led, 12);
+        assert_eq!(wled_server.led_count, 60);
+    }
+
+    #[test]
+    fn parse_sacn_output() {
+        let sacn_output: JsonSacnOutput =
+            serde_json::from_str(r#"{ "firstLed": 0, "ledCount": 300, "priority": 150 }"#)
+                .expect("parse the JsonSacnOutput");
+        let sacn_output: SacnOutput = sacn_output.into();
+        assert!(sacn_output.destination.is_none());
+        assert_eq!(sacn_output.universe, 1);
+        assert_eq!(sacn_output.priority, 150);
+        assert_eq!(sacn_output.led_count, 300);
+
+        let sacn_output: JsonSacnOutput = serde_json::from_str(
+            r#"{ "destination": "192.168.1.20", "universe": 7, "firstLed": 0, "ledCount": 60 }"#,
+        )
+        .expect("parse the JsonSacnOutput");
+        let sacn_output: SacnOutput = sacn_output.into();
+        assert_eq!(sacn_output.destination.as_deref(), Some("192.168.1.20"));
+        assert_eq!(sacn_output.universe, 7);
+        assert_eq!(sacn_output.priority, 100);
+    }
+
+    #[test]
+    fn parse_udp_output() {
+        let udp_output: JsonUdpOutput = serde_json::from_str(
+            r#"{ "host": "esp-strip.local", "port": 7777, "firstLed": 0, "ledCount": 60 }"#,
+        )
+        .expect("parse the JsonUdpOutput");
+        let udp_output: UdpOutput = udp_output.into();
+        assert_eq!(udp_output.host, "esp-strip.local");
+        assert_eq!(udp_output.port, 7777);
+        assert!(udp_output.header.is_empty());
+        assert_eq!(udp_output.pixel_format, PixelFormat::Rgb);
+        assert_eq!(udp_output.max_payload, 1440);
+        assert_eq!(udp_output.latency, 0);
+
+        let udp_output: JsonUdpOutput = serde_json::from_str(
+            r#"{ "host": "192.168.1.30", "port": 7777, "header": [65, 76, 1], "pixelFormat": "rgbw", "maxPayload": 512, "firstLed": 60, "ledCount": 144 }"#,
+        )
+        .expect("parse the JsonUdpOutput");
+        let udp_output: UdpOutput = udp_output.into();
+        assert_eq!(udp_output.header, [65, 76, 1]);
+        assert_eq!(udp_output.pixel_format, PixelFormat::Rgbw);
+        assert_eq!(udp_output.max_payload, 512);
+        assert_eq!(udp_output.first_led, 60);
+    }
+
+    #[test]
+    fn parse_hyperion_server() {
+        let hyperion_server: JsonHyperionServer =
+            serde_json::from_str(r#"{ "host": "hyperion.local" }"#)
+                .expect("parse the JsonHyperionServer");
+        let hyperion_server: HyperionServer = hyperion_server.into();
+        assert_eq!(hyperion_server.host, "hyperion.local");
+        assert_eq!(hyperion_server.port, 19400);
+        assert_eq!(hyperion_server.priority, 150);
+        assert_eq!(hyperion_server.origin, "AdaLight");
+        assert_eq!(hyperion_server.display, 0);
+        assert_eq!(hyperion_server.connect_timeout, 1000);
+
+        let hyperion_server: JsonHyperionServer = serde_json::from_str(
+            r#"{ "host": "192.168.1.30", "priority": 120, "origin": "Desk", "display": 1 }"#,
+        )
+        .expect("parse the JsonHyperionServer");
+        let hyperion_server: HyperionServer = hyperion_server.into();
+        assert_eq!(hyperion_server.priority, 120);
+        assert_eq!(hyperion_server.origin, "Desk");
+        assert_eq!(hyperion_server.display, 1);
+    }
+
+    #[test]
+    fn parse_hue_bridge() {
+        let hue_bridge: JsonHueBridge = serde_json::from_str(
+            r#"{
+    "host": "192.168.1.40",
+    "username": "abcdef",
+    "clientKey": "00112233445566778899AABBCCDDEEFF",
+    "group": 5,
+    "lights": [ { "id": 3, "leds": [ 0, 1, 2 ] }, { "id": 7, "leds": [ 20 ] } ]
+}"#,
+        )
+        .expect("parse the JsonHueBridge");
+        let hue_bridge: HueBridge = hue_bridge.into();
+        assert_eq!(hue_bridge.host, "192.168.1.40");
+        assert_eq!(hue_bridge.username, "abcdef");
+        assert_eq!(hue_bridge.client_key, "00112233445566778899AABBCCDDEEFF");
+        assert_eq!(hue_bridge.group, 5);
+        assert_eq!(hue_bridge.lights.len(), 2);
+        assert_eq!(hue_bridge.lights[0].id, 3);
+        assert_eq!(hue_bridge.lights[0].leds, vec![0, 1, 2]);
+        assert_eq!(hue_bridge.lights[1].leds, vec![20]);
+    }
+
+    #[test]
+    fn parse_nanoleaf_controller() {
+        let nanoleaf_controller: JsonNanoleafController = serde_json::from_str(
+            r#"{ "host": "nanoleaf.local", "token": "abc", "panels": [ { "id": 1234, "leds": [ 4 ] } ] }"#,
+        )
+        .expect("parse the JsonNanoleafController");
+        let nanoleaf_controller: NanoleafController = nanoleaf_controller.into();
+        assert_eq!(nanoleaf_controller.host, "nanoleaf.local");
+        assert_eq!(nanoleaf_controller.port, 16021);
+        assert_eq!(nanoleaf_controller.token, "abc");
+        assert_eq!(nanoleaf_controller.transition, 1);
+        assert_eq!(nanoleaf_controller.panels.len(), 1);
+        assert_eq!(nanoleaf_controller.panels[0].id, 1234);
+        assert_eq!(nanoleaf_controller.panels[0].leds, vec![4]);
+    }
+
+    #[test]
+    fn parse_chroma_configuration() {
+        let chroma: JsonChromaConfiguration = serde_json::from_str(
+            r#"{ "devices": [ { "device": "keyboard", "leds": [ 0, 1 ] }, { "device": "mousepad", "leds": [ 5 ] } ] }"#,
+        )
+        .expect("parse the JsonChromaConfiguration");
+        let chroma: ChromaConfiguration = chroma.into();
+        assert_eq!(chroma.interval, 100);
+        assert_eq!(chroma.devices.len(), 2);
+        assert_eq!(chroma.devices[0].device, ChromaDeviceType::Keyboard);
+        assert_eq!(chroma.devices[0].leds, vec![0, 1]);
+        assert_eq!(chroma.devices[1].device, ChromaDeviceType::Mousepad);
+    }
+
+    #[test]
+    fn parse_mqtt_configuration() {
+        let mqtt: JsonMqttConfiguration = serde_json::from_str(r#"{ "host": "broker.local" }"#)
+            .expect("parse the JsonMqttConfiguration");
+        let mqtt: MqttConfiguration = mqtt.into();
+        assert_eq!(mqtt.host, "broker.local");
+        assert_eq!(mqtt.port, 1883);
+        assert_eq!(mqtt.client_id, "adalight");
+        assert!(mqtt.username.is_none());
+        assert!(mqtt.password.is_none());
+        assert_eq!(mqtt.topic, "adalight");
+        assert_eq!(mqtt.interval, 1000);
+        assert_eq!(mqtt.keep_alive, 30);
+
+        let mqtt: JsonMqttConfiguration = serde_json::from_str(
+            r#"{ "host": "broker.local", "username": "lights", "password": "secret", "topic": "home/tv" }"#,
+        )
+        .expect("parse the JsonMqttConfiguration");
+        let mqtt: MqttConfiguration = mqtt.into();
+        assert_eq!(mqtt.username.as_deref(), Some("lights"));
+        assert_eq!(mqtt.password.as_deref(), Some("secret"));
+        assert_eq!(mqtt.topic, "home/tv");
+    }
+
+    #[test]
+    fn parse_rest_configuration() {
+        let rest: JsonRestConfiguration =
+            serde_json::from_str("{}").expect("parse the JsonRestConfiguration");
+        let rest: RestConfiguration = rest.into();
+        assert_eq!(rest.bind, "127.0.0.1:8421");
+
+        let rest: JsonRestConfiguration = serde_json::from_str(r#"{ "bind": "0.0.0.0:8080" }"#)
+            .expect("parse the JsonRestConfiguration");
+        let rest: RestConfiguration = rest.into();
+        assert_eq!(rest.bind, "0.0.0.0:8080");
+    }
+
+    #[test]
+    fn parse_profile() {
+        let profile: JsonProfile = serde_json::from_str(
+            r#"{ "name": "movie", "brightness": 128, "displays": [ true, false ] }"#,
+        )
+        .expect("parse the JsonProfile");
+        let profile: Profile = profile.into();
+        assert_eq!(profile.name, "movie");
+        assert_eq!(profile.brightness, Some(128));
+        assert_eq!(profile.displays, [true, false]);
+        assert!(profile.servers.is_empty());
+    }
+
+    #[test]
+    fn parse_grpc_configuration() {
+        let grpc: JsonGrpcConfiguration =
+            serde_json::from_str("{}").expect("parse the JsonGrpcConfiguration");
+        let grpc: GrpcConfiguration = grpc.into();
+        assert_eq!(grpc.bind, "127.0.0.1:50051");
+
+        let grpc: JsonGrpcConfiguration = serde_json::from_str(r#"{ "bind": "0.0.0.0:50052" }"#)
+            .expect("parse the JsonGrpcConfiguration");
+        let grpc: GrpcConfiguration = grpc.into();
+        assert_eq!(grpc.bind, "0.0.0.0:50052");
+    }
+
+    #[test]
+    fn parse_sync_configuration() {
+        let sync: JsonSyncConfiguration = serde_json::from_str(r#"{ "mode": "leader" }"#)
+            .expect("parse the JsonSyncConfiguration");
+        let sync: SyncConfiguration = sync.into();
+        assert_eq!(sync.mode, SyncMode::Leader);
+        assert_eq!(sync.address, "255.255.255.255");
+        assert_eq!(sync.port, 21330);
+        assert_eq!(sync.group, 0);
+        assert_eq!(sync.timeout, 1000);
+
+        let sync: JsonSyncConfiguration = serde_json::from_str(
+            r#"{ "mode": "follower", "port": 21331, "group": 2, "timeout": 250 }"#,
+        )
+        .expect("parse the JsonSyncConfiguration");
+        let sync: SyncConfiguration = sync.into();
+        assert_eq!(sync.mode, SyncMode::Follower);
+        assert_eq!(sync.port, 21331);
+        assert_eq!(sync.group, 2);
+        assert_eq!(sync.timeout, 250);
+
+        assert!(serde_json::from_str::<JsonSyncConfiguration>("{}").is_err());
+    }
+
+    #[test]
+    fn parse_preview_stream_configuration() {
+        let stream: JsonPreviewStreamConfiguration =
+            serde_json::from_str("{}").expect("parse the JsonPreviewStreamConfiguration");
+        let stream: PreviewStreamConfiguration = stream.into();
+        assert_eq!(stream.bind, "127.0.0.1:8422");
+        assert_eq!(stream.fps, 10);
+
+        let stream: JsonPreviewStreamConfiguration =
+            serde_json::from_str(r#"{ "bind": "0.0.0.0:9000", "fps": 0 }"#)
+                .expect("parse the JsonPreviewStreamConfiguration");
+        let stream: PreviewStreamConfiguration = stream.into();
+        assert_eq!(stream.bind, "0.0.0.0:9000");
+        assert_eq!(stream.fps, 1);
+    }
+
+    #[test]
+    fn parse_opc_receiver_configuration() {
+        let receiver: JsonOpcReceiverConfiguration =
+            serde_json::from_str("{}").expect("parse the JsonOpcReceiverConfiguration");
+        let receiver: OpcReceiverConfiguration = receiver.into();
+        assert_eq!(receiver.port, 7890);
+        assert_eq!(receiver.channel, 0);
+        assert_eq!(receiver.timeout, 1000);
+
+        let receiver: JsonOpcReceiverConfiguration =
+            serde_json::from_str(r#"{ "port": 7891, "channel": 2, "timeout": 250 }"#)
+                .expect("parse the JsonOpcReceiverConfiguration");
+        let receiver: OpcReceiverConfiguration = receiver.into();
+        assert_eq!(receiver.port, 7891);
+        assert_eq!(receiver.channel, 2);
+        assert_eq!(receiver.timeout, 250);
+    }
+
+    #[test]
+    fn parse_discovery_configuration() {
+        let discovery: JsonDiscoveryConfiguration =
+            serde_json::from_str("{}").expect("parse the JsonDiscoveryConfiguration");
+        let discovery: DiscoveryConfiguration = discovery.into();
+        assert_eq!(discovery.mode, DiscoveryMode::Validate);
+        assert_eq!(discovery.timeout, 2000);
+
+        let discovery: JsonDiscoveryConfiguration =
+            serde_json::from_str(r#"{ "mode": "resolve", "timeout": 500 }"#)
+                .expect("parse the JsonDiscoveryConfiguration");
+        let discovery: DiscoveryConfiguration = discovery.into();
+        assert_eq!(discovery.mode, DiscoveryMode::Resolve);
+        assert_eq!(discovery.timeout, 500);
+    }
+
+    #[cfg(not(feature = "grpc"))]
+    #[test]
+    fn reject_grpc_without_feature() {
+        let error = Settings::from_str(
+            r#"{
+                "minBrightness": 0,
+                "fade": 0,
+                "timeout": 5000,
+                "fpsMax": 30,
+                "throttleTimer": 3000,
+                "displays": [],
+                "servers": [],
+                "grpc": { "bind": "127.0.0.1:50051" }
+            }"#,
+        )
+        .expect_err("reject the gRPC configuration");
+        assert!(error.to_string().contains("grpc feature"));
+    }
+
+    #[cfg(not(feature = "hue"))]
+    #[test]
+    fn reject_hue_without_feature() {
+        let error = Settings::from_str(
+            r#"{
+                "minBrightness": 0,
+                "fade": 0,
+                "timeout": 5000,
+                "fpsMax": 30,
+                "throttleTimer": 3000,
+                "displays": [],
+                "servers": [],
+                "hueBridges": [
+                    {
+                        "host": "192.168.1.40",
+                        "username": "abcdef",
+                        "clientKey": "00112233445566778899AABBCCDDEEFF",
+                        "group": 5,
+                        "lights": []
+                    }
+                ]
+            }"#,
+        )
+        .expect_err("reject the Hue bridge");
+        assert!(error.to_string().contains("hue feature"));
+    }
+
+    #[test]
+    fn reject_mqtt_password_without_username() {
+        let error = Settings::from_str(
+            r#"{
+                "minBrightness": 0,
+                "fade": 0,
+                "timeout": 5000,
+                "fpsMax": 30,
+                "throttleTimer": 3000,
+                "displays": [],
+                "servers": [],
+                "mqtt": { "host": "broker.local", "password": "secret" }
+            }"#,
+        )
+        .expect_err("reject the MQTT configuration");
+        assert!(error.to_string().contains("username"));
+    }
+
+    #[test]
+    fn reject_brightness_zone_past_last_led() {
+        let error = Settings::from_str(
+            r#"{
+                "minBrightness": 0,
+                "fade": 0,
+                "timeout": 5000,
+                "fpsMax": 30,
+                "throttleTimer": 3000,
+                "displays": [
+                    {
+                        "horizontalCount": 2,
+                        "verticalCount": 1,
+                        "positions": [ { "x": 0, "y": 0 }, { "x": 1, "y": 0 } ]
+                    }
+                ],
+                "servers": [],
+                "brightnessZones": [ { "firstLed": 1, "ledCount": 2 } ]
+            }"#,
+        )
+        .expect_err("reject the brightness zone");
+        assert!(error.to_string().contains("past the last"));
+    }
+
     #[test]
     fn parse_settings() {
         let settings = Settings::from_str(r#"
@@ -757,15 +4014,81 @@ mod test {
 }"#,
         ).expect("parse the sample");
         assert_eq!(settings.min_brightness, 64);
+        assert_eq!(settings.black_level, 0);
         assert_eq!(settings.fade, 0.0);
+        assert_eq!(settings.fade_space, FadeSpace::Rgb);
+        assert_eq!(settings.fade_out, 500);
         assert_eq!(settings.timeout, 5000);
+        assert_eq!(settings.baud_rate, 115200);
+        assert!(!settings.probe_baud_rate);
+        assert!(settings.exclude_ports.is_empty());
+        assert!(settings.exclude_devices.is_empty());
+        assert_eq!(settings.reconnect.retries, 3);
+        assert_eq!(settings.reconnect.delay, 50);
+        assert_eq!(settings.serial_outputs.len(), 1);
+        assert_eq!(settings.serial_outputs[0].first_led, 0);
+        assert_eq!(settings.serial_outputs[0].led_count, 24);
+        assert!(settings.serial_outputs[0].port.is_none());
+        assert!(settings.serial_outputs[0].device_path.is_none());
+        assert!(settings.serial_outputs[0].wled.is_none());
+        assert!(!settings.serial_outputs[0].flow_control);
+        assert_eq!(settings.serial_outputs[0].checksum, SerialChecksum::None);
+        assert!(settings.serial_outputs[0].dtr.is_none());
+        assert!(settings.serial_outputs[0].rts.is_none());
+        assert_eq!(settings.serial_outputs[0].busy_policy, BusyPolicy::Drop);
+        assert_eq!(settings.serial_outputs[0].header.magic, "Ada");
+        assert_eq!(
+            settings.serial_outputs[0].header.led_count,
+            HeaderLedCount::Word
+        );
+        assert!(settings.serial_outputs[0].read_timeout.is_none());
+        assert!(settings.serial_outputs[0].write_timeout.is_none());
+        assert!(settings.serial_outputs[0].latency_timer.is_none());
+        assert!(settings.serial_outputs[0].raw.is_none());
+        assert!(settings.serial_outputs[0].baud_rates.is_empty());
+        assert_eq!(settings.serial_latency, 0);
         assert_eq!(settings.fps_max, 30);
         assert_eq!(settings.throttle_timer, 3000);
         assert_eq!(settings.displays.len(), 1);
+        assert!(!settings.linear_averaging);
+        assert_eq!(settings.gamma, GammaConfiguration::default());
+        assert_eq!(settings.gamma.green.max, 240);
+        assert!(settings.hsv.is_none());
+        assert!(settings.color_matrix.is_none());
+        assert!(!settings.temporal_dithering);
+        assert!(!settings.spatial_dithering);
+        assert!(settings.letterbox.is_none());
+        assert!(settings.black_frame.is_none());
+        assert!(settings.auto_exposure.is_none());
+        assert!(settings.power_limit.is_none());
+        assert!(settings.brightness_zones.is_empty());
+        assert_eq!(settings.protected_content, ProtectedContentFallback::Hold);
+        assert_eq!(settings.sample_distribution, SampleDistribution::Grid);
+        assert!(settings.test_source.is_none());
+        assert!(settings.file_source.is_none());
+        assert!(!settings.downscale);
+        assert!(!settings.partial_copy);
         assert_eq!(settings.servers.len(), 1);
+        assert!(settings.wled_servers.is_empty());
+        assert!(settings.sacn_outputs.is_empty());
+        assert!(settings.udp_outputs.is_empty());
+        assert!(settings.hyperion_servers.is_empty());
+        assert!(settings.hue_bridges.is_empty());
+        assert!(settings.nanoleaf_controllers.is_empty());
+        assert!(settings.chroma.is_none());
+        assert!(settings.mqtt.is_none());
+        assert!(settings.discovery.is_none());
+        assert!(settings.opc_receiver.is_none());
+        assert!(settings.rest.is_none());
+        assert!(settings.grpc.is_none());
+        assert!(settings.sync.is_none());
+        assert!(settings.preview_stream.is_none());
+        assert!(settings.profiles.is_empty());
         assert_eq!(settings.get_min_brightness_color(), 0x151515FF);
         assert_eq!(settings.get_total_led_count(), 24);
         assert_eq!(settings.get_weight(), 1.0);
         assert_eq!(settings.get_delay(), 33);
+        assert!(settings.capture_timeout.is_none());
+        assert_eq!(settings.get_capture_timeout(), 33);
     }
 }
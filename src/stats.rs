@@ -0,0 +1,159 @@
+use std::{fmt, ops::Add, time::Duration};
+
+/// Statistics for the serial link, which each [crate::serial_port::SerialPort] tracks for
+/// itself, so flicker can be traced to either the capture or the serial port.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SerialStats {
+    /// Number of bytes written to the serial port, including the headers.
+    pub bytes_written: u64,
+
+    /// Number of writes which completed.
+    pub writes: usize,
+
+    /// Total time between starting and completing the `writes`. The completion of a write is
+    /// noticed when the next frame is sent, so this is an upper bound.
+    pub write_time: Duration,
+
+    /// Number of times writing to an open serial port failed.
+    pub failures: usize,
+
+    /// Number of frames which weren't sent to the serial port because the previous write
+    /// was still in progress or hadn't been acknowledged yet.
+    pub dropped: usize,
+
+    /// Number of times the serial port was opened again after it was closed.
+    pub reconnects: usize,
+}
+
+impl SerialStats {
+    /// Count a write which completed in `elapsed` time.
+    pub fn record_write(&mut self, elapsed: Duration) {
+        self.writes += 1;
+        self.write_time += elapsed;
+    }
+
+    /// Get the average time it took to complete each of the `writes`.
+    pub fn average_write_time(&self) -> Duration {
+        match u32::try_from(self.writes) {
+            Ok(0) => Duration::ZERO,
+            Ok(writes) => self.write_time / writes,
+            Err(_) => self.write_time.div_f64(self.writes as f64),
+        }
+    }
+}
+
+impl Add for SerialStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            bytes_written: self.bytes_written + other.bytes_written,
+            writes: self.writes + other.writes,
+            write_time: self.write_time + other.write_time,
+            failures: self.failures + other.failures,
+            dropped: self.dropped + other.dropped,
+            reconnects: self.reconnects + other.reconnects,
+        }
+    }
+}
+
+/// Runtime statistics collected by the worker thread in [crate::update_timer::UpdateTimer],
+/// so they can be reported without attaching a debugger.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// Number of frames which were sampled successfully.
+    pub frames_captured: usize,
+
+    /// Number of timer intervals where the displays could not be sampled, e.g. because the
+    /// resources were lost or we're throttled.
+    pub frames_skipped: usize,
+
+    /// Total time spent sampling the `frames_captured`.
+    pub sample_time: Duration,
+
+    /// The effective frame rate since the resources were last created.
+    pub frame_rate: f64,
+
+    /// The [SerialStats] for all of the serial ports.
+    pub serial: SerialStats,
+
+    /// Number of times sending a frame to a healthy [crate::output_sink::OutputSink] failed.
+    pub output_failures: usize,
+
+    /// Average RGBA color of all of the LEDs in the last frame.
+    pub average_color: u32,
+}
+
+impl Stats {
+    /// Count a frame which was sampled successfully in `elapsed` time.
+    pub fn record_sample(&mut self, elapsed: Duration) {
+        self.frames_captured += 1;
+        self.sample_time += elapsed;
+    }
+
+    /// Get the average time it took to sample each of the `frames_captured`.
+    pub fn average_sample_time(&self) -> Duration {
+        match u32::try_from(self.frames_captured) {
+            Ok(0) => Duration::ZERO,
+            Ok(frames) => self.sample_time / frames,
+            Err(_) => self.sample_time.div_f64(self.frames_captured as f64),
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Frames Captured: {}, Frames Skipped: {}, Average Sample Time: {:?}, Frame Rate: {:.1}, Serial Bytes Written: {}, Average Serial Write Time: {:?}, Serial Failures: {}, Serial Frames Dropped: {}, Serial Reconnects: {}, Output Failures: {}",
+            self.frames_captured,
+            self.frames_skipped,
+            self.average_sample_time(),
+            self.frame_rate,
+            self.serial.bytes_written,
+            self.serial.average_write_time(),
+            self.serial.failures,
+            self.serial.dropped,
+            self.serial.reconnects,
+            self.output_failures
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn average_sample_time() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.average_sample_time(), Duration::ZERO);
+        stats.record_sample(Duration::from_millis(4));
+        stats.record_sample(Duration::from_millis(8));
+        assert_eq!(stats.frames_captured, 2);
+        assert_eq!(stats.average_sample_time(), Duration::from_millis(6));
+    }
+
+    #[test]
+    fn total_serial_stats() {
+        let mut first = SerialStats {
+            bytes_written: 100,
+            failures: 1,
+            ..Default::default()
+        };
+        first.record_write(Duration::from_millis(2));
+        let mut second = SerialStats {
+            bytes_written: 50,
+            reconnects: 2,
+            ..Default::default()
+        };
+        second.record_write(Duration::from_millis(4));
+
+        let total = first + second;
+        assert_eq!(total.bytes_written, 150);
+        assert_eq!(total.failures, 1);
+        assert_eq!(total.reconnects, 2);
+        assert_eq!(total.writes, 2);
+        assert_eq!(total.average_write_time(), Duration::from_millis(3));
+    }
+}
@@ -0,0 +1,189 @@
+use std::mem;
+
+use windows::{core::Result, Win32::Foundation::SIZE};
+
+use crate::{
+    capture_source::{CaptureSource, MappedSurface, SurfaceFormat},
+    settings::TestPattern,
+};
+
+/// Width in pixels of the generated test frames.
+const TEST_WIDTH: i32 = 320;
+
+/// Height in pixels of the generated test frames.
+const TEST_HEIGHT: i32 = 180;
+
+/// Number of frames to show each color with [TestPattern::SolidColors], which is 1 second
+/// at the default 30 FPS.
+const SOLID_COLOR_FRAMES: usize = 30;
+
+/// Sequence of colors shown by [TestPattern::SolidColors].
+const SOLID_COLORS: [(u8, u8, u8); 5] = [
+    (255, 0, 0),
+    (0, 255, 0),
+    (0, 0, 255),
+    (255, 255, 255),
+    (0, 0, 0),
+];
+
+/// Generate synthetic BGRA frames with a [TestPattern] instead of capturing the desktop, so
+/// the sampling and output code can run without DXGI or a particular display setup.
+pub struct TestSource {
+    /// The [TestPattern] to generate.
+    pattern: TestPattern,
+
+    /// The BGRA pixels of the last frame, in rows that are `TEST_WIDTH * 4` bytes long.
+    pixels: Vec<u8>,
+
+    /// Number of frames generated so far, which drives the animation.
+    frame: usize,
+}
+
+impl TestSource {
+    /// Create a new [TestSource] for the [TestPattern].
+    pub fn new(pattern: TestPattern) -> Self {
+        Self {
+            pattern,
+            pixels: vec![0; (TEST_WIDTH * TEST_HEIGHT) as usize * mem::size_of::<u32>()],
+            frame: 0,
+        }
+    }
+
+    /// Generate the next frame and return the BGRA pixels along with the row pitch in bytes.
+    pub fn next_frame(&mut self) -> (&[u8], usize) {
+        let (width, height) = (TEST_WIDTH as usize, TEST_HEIGHT as usize);
+        let pitch = Self::pitch();
+        let (pattern, frame) = (self.pattern, self.frame);
+
+        for (y, row) in self.pixels.chunks_exact_mut(pitch).enumerate() {
+            for (x, pixel) in row.chunks_exact_mut(mem::size_of::<u32>()).enumerate() {
+                let (r, g, b) = Self::color(pattern, frame, x, y, width, height);
+                pixel.copy_from_slice(&[b, g, r, 0xFF]);
+            }
+        }
+
+        self.frame += 1;
+
+        (&self.pixels, pitch)
+    }
+
+    /// Get the row pitch in bytes of the generated frames.
+    fn pitch() -> usize {
+        TEST_WIDTH as usize * mem::size_of::<u32>()
+    }
+
+    /// Calculate the color of the pixel at `x` and `y` in a `frame` of the [TestPattern].
+    fn color(
+        pattern: TestPattern,
+        frame: usize,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> (u8, u8, u8) {
+        match pattern {
+            TestPattern::SolidColors => {
+                SOLID_COLORS[(frame / SOLID_COLOR_FRAMES) % SOLID_COLORS.len()]
+            }
+            TestPattern::Gradient => {
+                // Sweep the whole hue circle across the width and rotate it over time.
+                Self::hue((x * 360 / width + frame * 4) % 360)
+            }
+            TestPattern::MovingBars => {
+                // A white bar moves across and a red bar moves down over a black background.
+                let bar_x = (frame * 4) % width;
+                let bar_y = (frame * 2) % height;
+
+                if x >= bar_x && x < bar_x + width / 10 {
+                    (255, 255, 255)
+                } else if y >= bar_y && y < bar_y + height / 10 {
+                    (255, 0, 0)
+                } else {
+                    (0, 0, 0)
+                }
+            }
+        }
+    }
+
+    /// Convert a `hue` in degrees to a fully saturated RGB color.
+    fn hue(hue: usize) -> (u8, u8, u8) {
+        let rising = ((hue % 60) * 255 / 60) as u8;
+        let falling = 255 - rising;
+
+        match hue / 60 {
+            0 => (255, rising, 0),
+            1 => (falling, 255, 0),
+            2 => (0, 255, rising),
+            3 => (0, falling, 255),
+            4 => (rising, 0, 255),
+            _ => (255, 0, falling),
+        }
+    }
+}
+
+impl CaptureSource for TestSource {
+    /// Generate the next frame, which is always new.
+    fn acquire_frame(&mut self) -> Result<bool> {
+        self.next_frame();
+        Ok(true)
+    }
+
+    /// Get the size of the generated frames.
+    fn bounds(&self) -> SIZE {
+        SIZE {
+            cx: TEST_WIDTH,
+            cy: TEST_HEIGHT,
+        }
+    }
+
+    /// Read the BGRA pixels of the last generated frame.
+    fn read_pixels(&self) -> Result<MappedSurface<'_>> {
+        Ok(MappedSurface {
+            pixels: &self.pixels,
+            pitch: Self::pitch(),
+            format: SurfaceFormat::Bgra8,
+            rows: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pixel(pixels: &[u8], pitch: usize, x: usize, y: usize) -> (u8, u8, u8) {
+        let offset = y * pitch + x * mem::size_of::<u32>();
+        (pixels[offset + 2], pixels[offset + 1], pixels[offset])
+    }
+
+    #[test]
+    fn solid_colors() {
+        let mut source = TestSource::new(TestPattern::SolidColors);
+        let (pixels, pitch) = source.next_frame();
+        assert_eq!(pitch, TEST_WIDTH as usize * 4);
+        assert_eq!(pixel(pixels, pitch, 0, 0), (255, 0, 0));
+        assert_eq!(pixel(pixels, pitch, 319, 179), (255, 0, 0));
+
+        for _ in 1..SOLID_COLOR_FRAMES {
+            source.next_frame();
+        }
+        let (pixels, pitch) = source.next_frame();
+        assert_eq!(pixel(pixels, pitch, 160, 90), (0, 255, 0));
+    }
+
+    #[test]
+    fn gradient_hues() {
+        assert_eq!(TestSource::hue(0), (255, 0, 0));
+        assert_eq!(TestSource::hue(120), (0, 255, 0));
+        assert_eq!(TestSource::hue(240), (0, 0, 255));
+    }
+
+    #[test]
+    fn moving_bars() {
+        let mut source = TestSource::new(TestPattern::MovingBars);
+        let (pixels, pitch) = source.next_frame();
+        assert_eq!(pixel(pixels, pitch, 0, 90), (255, 255, 255));
+        assert_eq!(pixel(pixels, pitch, 160, 0), (255, 0, 0));
+        assert_eq!(pixel(pixels, pitch, 160, 90), (0, 0, 0));
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, UdpSocket},
+    time::Duration,
+};
+
+use crate::{
+    output_sink::OutputSink,
+    pixel_buffer::PixelBuffer,
+    screen_samples::ScreenSamples,
+    settings::{PixelFormat, UdpOutput},
+};
+
+/// Representation of a [UdpOutput] driven with raw UDP packets in its own framing.
+pub struct UdpConnection<'a> {
+    output: &'a UdpOutput,
+    socket: Option<UdpSocket>,
+}
+
+impl<'a> UdpConnection<'a> {
+    /// Allocate a new [UdpConnection] without opening the socket.
+    pub fn new(output: &'a UdpOutput) -> Self {
+        Self {
+            output,
+            socket: None,
+        }
+    }
+
+    /// Split `led_count` LEDs into the `(start, led_count)` of each packet, so the LEDs in each
+    /// one fit in `max_payload` bytes. Every packet gets at least one LED.
+    fn packets(
+        pixel_format: PixelFormat,
+        max_payload: usize,
+        led_count: usize,
+    ) -> Vec<(usize, usize)> {
+        let led_size = match pixel_format {
+            PixelFormat::Rgb => 3,
+            PixelFormat::Rgbw => 4,
+        };
+        let max_leds = (max_payload / led_size).max(1);
+        (0..led_count)
+            .step_by(max_leds)
+            .map(|start| (start, (led_count - start).min(max_leds)))
+            .collect()
+    }
+}
+
+impl<'a> OutputSink for UdpConnection<'a> {
+    /// Open a non-blocking UDP socket to the [UdpOutput] if it isn't open yet.
+    fn open(&mut self) -> bool {
+        if self.socket.is_none() {
+            self.socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+                .and_then(|socket| {
+                    socket.connect((self.output.host.as_str(), self.output.port))?;
+                    socket.set_nonblocking(true)?;
+                    Ok(socket)
+                })
+                .ok();
+        }
+
+        self.healthy()
+    }
+
+    /// Render a packet with the `header` for each range of LEDs which fits in the
+    /// `max_payload`.
+    fn render(&self, samples: &ScreenSamples) -> Vec<PixelBuffer> {
+        Self::packets(
+            self.output.pixel_format,
+            self.output.max_payload,
+            self.output.led_count,
+        )
+        .into_iter()
+        .map(|(start, led_count)| {
+            let mut pixels = PixelBuffer::new_udp_buffer(
+                &self.output.header,
+                self.output.pixel_format,
+                led_count,
+            );
+            samples.render_range(self.output.first_led + start, led_count, &mut pixels);
+            pixels
+        })
+        .collect()
+    }
+
+    /// Send each of the packets to the [UdpOutput] in order. If the socket buffer is full, the
+    /// rest of the frame is dropped instead of waiting.
+    fn send(&mut self, pixels: Vec<PixelBuffer>) -> bool {
+        let result = match self.socket.as_ref() {
+            Some(socket) => pixels
+                .iter()
+                .try_for_each(|pixels| socket.send(pixels.data()).map(|_| ())),
+            None => return false,
+        };
+
+        match result {
+            Ok(()) => true,
+            Err(error) if error.kind() == ErrorKind::WouldBlock => true,
+            Err(_) => {
+                self.close();
+                false
+            }
+        }
+    }
+
+    /// Close the socket.
+    fn close(&mut self) {
+        self.socket = None;
+    }
+
+    /// Test if the socket to the [UdpOutput] is open.
+    fn healthy(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Get the `latency` of the [UdpOutput].
+    fn latency(&self) -> Duration {
+        Duration::from_millis(u64::from(self.output.latency))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_packets() {
+        assert_eq!(
+            UdpConnection::packets(PixelFormat::Rgb, 1440, 300),
+            vec![(0, 300)]
+        );
+        assert_eq!(
+            UdpConnection::packets(PixelFormat::Rgbw, 1440, 1000),
+            vec![(0, 360), (360, 360), (720, 280)]
+        );
+        assert_eq!(
+            UdpConnection::packets(PixelFormat::Rgb, 10, 7),
+            vec![(0, 3), (3, 3), (6, 1)]
+        );
+        assert_eq!(
+            UdpConnection::packets(PixelFormat::Rgbw, 0, 2),
+            vec![(0, 1), (1, 1)]
+        );
+    }
+}
@@ -4,9 +4,26 @@ use std::{
     time::{Duration, Instant},
 };
 
+#[cfg(feature = "hue")]
+use crate::hue::HueConnection;
 use crate::{
-    gamma_correction::GammaLookup, opc_pool::OpcPool, pixel_buffer::PixelBuffer,
-    screen_samples::ScreenSamples, serial_port::SerialPort, settings::Settings,
+    chroma::ChromaConnection,
+    gamma_correction::GammaLookup,
+    health::{HealthEvent, HealthSender},
+    hyperion::HyperionConnection,
+    instance_sync::{SyncFollower, SyncLeader},
+    nanoleaf::NanoleafConnection,
+    opc_pool::{OpcPool, ServerSwitches},
+    opc_receiver::OpcReceiver,
+    output_sink::{FrameDelay, FrameLimiter, OutputSink, ReconnectBackoff},
+    preview::Preview,
+    sacn::SacnSender,
+    screen_samples::ScreenSamples,
+    serial_thread::SerialThread,
+    settings::{Profile, Settings, SyncConfiguration, SyncMode},
+    stats::{SerialStats, Stats},
+    udp_output::UdpConnection,
+    wled_udp::WledConnection,
 };
 
 /// The [TimerThread] runs in a loop firing [TimerEvent] messages over an [std::sync::mpsc]
@@ -15,6 +32,13 @@ enum TimerEvent {
     /// The [TimerThread] interval event fired.
     Fired,
 
+    /// The display topology changed, so the [WorkerThread] needs to re-enumerate the outputs.
+    DisplaysChanged,
+
+    /// A serial port was plugged in, so the [WorkerThread] should try to reconnect to the
+    /// Arduino if it lost the [SerialPort].
+    SerialPortArrived,
+
     /// The [TimerThread] is stopping.
     Stopped,
 }
@@ -43,11 +67,26 @@ struct TimerThread {
     /// This is the time between intervals required to hit the [crate::settings::Settings]
     /// `fps_max` frame rate (`1000 / fps_max`).
     delay: u32,
+
+    /// Optionally keep the intervals in sync with other instances, see [SyncConfiguration].
+    sync: Option<SyncConfiguration>,
+
+    /// Global brightness for every output, shared with the [WorkerThread]. A leader sends it to
+    /// the followers, and a follower sets it to whatever the leader sent.
+    brightness: Arc<Mutex<Option<u8>>>,
+
+    /// Report when the screen capture is throttled or resumed.
+    health: HealthSender,
 }
 
 impl TimerThread {
     /// Allocate a new, unstarted [TimerThread] struct.
-    pub fn new(parameters: &Settings, tx: mpsc::Sender<TimerEvent>) -> Self {
+    pub fn new(
+        parameters: &Settings,
+        tx: mpsc::Sender<TimerEvent>,
+        brightness: Arc<Mutex<Option<u8>>>,
+        health: HealthSender,
+    ) -> Self {
         Self {
             tx,
             thread: None,
@@ -55,19 +94,31 @@ impl TimerThread {
             stopped: false,
             throttle_timer: parameters.throttle_timer,
             delay: parameters.get_delay(),
+            sync: parameters.sync.clone(),
+            brightness,
+            health,
         }
     }
 
     /// Start the [TimerThread] in `timer`, and pass it the [WorkerThread] [JoinHandle<()>]
-    /// in `worker` to let the [TimerThread] join that thread when stopping.
+    /// in `worker` to let the [TimerThread] join that thread when stopping. If it's a
+    /// [SyncMode::Follower], each interval ends as soon as the leader's packet arrives instead.
     pub fn start(timer: Arc<Mutex<TimerThread>>, worker: Arc<Mutex<Option<JoinHandle<()>>>>) {
         let clone = timer.clone();
         let mut timer = timer.lock().expect("lock timer");
         timer.stopped = false;
+        let sync = timer.sync.clone();
+        let brightness = timer.brightness.clone();
         timer.thread = Some(thread::spawn(move || {
+            let (mut leader, mut follower) = match sync.as_ref() {
+                Some(sync) if sync.mode == SyncMode::Leader => (SyncLeader::new(sync), None),
+                Some(sync) => (None, SyncFollower::new(sync)),
+                None => (None, None),
+            };
+
             loop {
                 let start_loop = Instant::now();
-                let delay = {
+                let (delay, throttled) = {
                     let timer = clone.lock().expect("lock timer thread");
 
                     if timer.stopped {
@@ -81,11 +132,30 @@ impl TimerThread {
                     timer.tx.send(TimerEvent::Fired).expect("send fired event");
 
                     if timer.throttled {
-                        timer.throttle_timer
+                        (timer.throttle_timer, true)
                     } else {
-                        timer.delay
+                        (timer.delay, false)
                     }
                 };
+
+                if throttled {
+                    // Let the followers fall back to their own timers until we resume.
+                } else if let Some(leader) = leader.as_mut() {
+                    leader.send(*brightness.lock().expect("lock brightness"));
+                } else if let Some(follower) = follower.as_mut() {
+                    // Wait up to the timeout for the leader while we're following it, otherwise
+                    // keep our own frame rate, but fire early if the leader comes back.
+                    let wait = if follower.following(start_loop) {
+                        follower.timeout()
+                    } else {
+                        Duration::from_millis(u64::from(delay))
+                    };
+                    if let Some(leader_brightness) = follower.wait(start_loop + wait) {
+                        *brightness.lock().expect("lock brightness") = Some(leader_brightness);
+                    }
+                    continue;
+                }
+
                 let next_loop = start_loop + Duration::from_millis(u64::from(delay));
                 let start_sleep = Instant::now();
                 if next_loop > start_sleep {
@@ -118,28 +188,83 @@ impl TimerThread {
     }
 
     /// Throttle the [TimerThread] in `timer` when the session is locked or
-    /// detached from the console, or when there are no listeners.
+    /// detached from the console, or when there are no listeners. Reports a
+    /// [HealthEvent::CaptureThrottled] if it wasn't already throttled.
     pub fn throttle(timer: Arc<Mutex<TimerThread>>) -> bool {
         let mut timer = timer.lock().expect("lock timer");
         let throttled = timer.throttled;
         timer.throttled = true;
-        !throttled && !timer.stopped
+        let changed = !throttled && !timer.stopped;
+        if changed {
+            timer.health.send(HealthEvent::CaptureThrottled);
+        }
+        changed
     }
 
     /// Resume the throttled [TimerThread] in `timer` when the session is unlocked
-    /// or reattaches to the console and there are listeners.
+    /// or reattaches to the console and there are listeners. Reports a
+    /// [HealthEvent::CaptureResumed] if it was throttled.
     pub fn resume(timer: Arc<Mutex<TimerThread>>) -> bool {
         let mut timer = timer.lock().expect("lock timer");
         let throttled = timer.throttled;
         timer.throttled = false;
-        throttled && !timer.stopped
+        let changed = throttled && !timer.stopped;
+        if changed {
+            timer.health.send(HealthEvent::CaptureResumed);
+        }
+        changed
+    }
+
+    /// Tell the [WorkerThread] that monitors were added or removed while the [TimerThread]
+    /// in `timer` is running.
+    pub fn refresh_displays(timer: Arc<Mutex<TimerThread>>) -> bool {
+        let timer = timer.lock().expect("lock timer");
+        !timer.stopped
+            && timer.thread.is_some()
+            && timer.tx.send(TimerEvent::DisplaysChanged).is_ok()
+    }
+
+    /// Tell the [WorkerThread] that a serial port was plugged in while the [TimerThread]
+    /// in `timer` is running.
+    pub fn serial_port_arrived(timer: Arc<Mutex<TimerThread>>) -> bool {
+        let timer = timer.lock().expect("lock timer");
+        !timer.stopped
+            && timer.thread.is_some()
+            && timer.tx.send(TimerEvent::SerialPortArrived).is_ok()
+    }
+}
+
+/// Whether anyone is observing the [Preview], and the last one the [WorkerThread] collected.
+#[derive(Default)]
+struct PreviewState {
+    /// True if the [WorkerThread] should collect a [Preview] after every frame.
+    enabled: bool,
+
+    /// True if the [Preview] should include a [crate::preview::Thumbnail] of each display.
+    thumbnails: bool,
+
+    /// The [Preview] from the last frame, if `enabled`.
+    latest: Option<Preview>,
+}
+
+/// Shared handle to the [PreviewState], which lets another thread like the
+/// [crate::preview_stream::PreviewStream] pick up the latest [Preview] without going through
+/// the [UpdateTimer].
+#[derive(Clone)]
+pub struct PreviewHandle(Arc<Mutex<PreviewState>>);
+
+impl PreviewHandle {
+    /// Get the [Preview] from the last frame, if a frame has been sampled.
+    pub fn latest(&self) -> Option<Preview> {
+        self.0.lock().expect("lock preview").latest.clone()
     }
 }
 
 /// The state and a [JoinHandle<()>] for the [WorkerThread].
 struct WorkerThread {
-    /// Configuration parameters in a [crate::settings::Settings] struct.
-    parameters: Settings,
+    /// Configuration parameters in a [crate::settings::Settings] struct, which are shared with
+    /// the [SerialThread].
+    parameters: Arc<Settings>,
 
     /// The [mpsc::Receiver<TimerEvent>] to receive [TimerEvent] messages from the [TimerThread].
     rx: mpsc::Receiver<TimerEvent>,
@@ -147,15 +272,56 @@ struct WorkerThread {
     /// The [Option<JoinHandle<()>>] for the [WorkerThread], used to join the thread when the
     /// [TimerThread] is stopped.
     thread: Arc<Mutex<Option<JoinHandle<()>>>>,
+
+    /// The [Stats] which the [WorkerThread] updates after every [TimerEvent::Fired] event.
+    /// This is shared with the [UpdateTimer] separately, since the [WorkerThread] keeps
+    /// itself locked while it's running.
+    stats: Arc<Mutex<Stats>>,
+
+    /// The [PreviewState] which the [WorkerThread] updates after every [TimerEvent::Fired]
+    /// event if the [Preview] is enabled. This is also shared with the [UpdateTimer].
+    preview: Arc<Mutex<PreviewState>>,
+
+    /// Whether each configured display is enabled, which the [UpdateTimer] can change at
+    /// runtime. The [WorkerThread] passes it to the [ScreenSamples] before every frame.
+    enabled_displays: Arc<Mutex<Vec<bool>>>,
+
+    /// Global brightness for every output, if the [UpdateTimer] changed it at runtime. The
+    /// [WorkerThread] passes it to the [ScreenSamples] and each [SerialPort] before every frame.
+    brightness: Arc<Mutex<Option<u8>>>,
+
+    /// The [ServerSwitches] which the [UpdateTimer] can use to pause and resume each OPC
+    /// server at runtime. The [WorkerThread] passes them to the [OpcPool].
+    server_switches: Arc<ServerSwitches>,
+
+    /// Report when the outputs connect or disconnect. The [WorkerThread] passes it to the
+    /// [SerialThread] and the [OpcPool].
+    health: HealthSender,
 }
 
 impl WorkerThread {
     /// Allocate a new, unstarted [WorkerThread] struct.
-    pub fn new(parameters: Settings, rx: mpsc::Receiver<TimerEvent>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        parameters: Settings,
+        rx: mpsc::Receiver<TimerEvent>,
+        stats: Arc<Mutex<Stats>>,
+        preview: Arc<Mutex<PreviewState>>,
+        enabled_displays: Arc<Mutex<Vec<bool>>>,
+        brightness: Arc<Mutex<Option<u8>>>,
+        server_switches: Arc<ServerSwitches>,
+        health: HealthSender,
+    ) -> Self {
         Self {
-            parameters,
+            parameters: Arc::new(parameters),
             rx,
             thread: Arc::new(Mutex::new(None)),
+            stats,
+            preview,
+            enabled_displays,
+            brightness,
+            server_switches,
+            health,
         }
     }
 
@@ -172,57 +338,241 @@ impl WorkerThread {
         if thread.is_none() {
             *thread = Some(thread::spawn(move || {
                 let worker = clone.lock().expect("lock worker thread");
-                let gamma = GammaLookup::new();
+                let gamma = GammaLookup::new(&worker.parameters.gamma);
                 let mut samples = ScreenSamples::new(&worker.parameters, &gamma);
-                let mut serial_buffer = PixelBuffer::new_serial_buffer(&worker.parameters);
-                let mut port = SerialPort::new(&worker.parameters);
-                let mut pool = OpcPool::new(&worker.parameters);
+                let mut stats = *worker.stats.lock().expect("lock stats");
+                let serial_stats = stats.serial;
+                let shared_serial_stats = Arc::new(Mutex::new(SerialStats::default()));
+                let mut serial = SerialThread::start(
+                    worker.parameters.clone(),
+                    worker.brightness.clone(),
+                    shared_serial_stats.clone(),
+                    worker.health.clone(),
+                );
+                let receiver = worker
+                    .parameters
+                    .opc_receiver
+                    .as_ref()
+                    .and_then(OpcReceiver::start);
+                let mut pool = OpcPool::new(
+                    &worker.parameters,
+                    &worker.server_switches,
+                    worker.health.clone(),
+                );
+                let mut sinks: Vec<&mut dyn OutputSink> = vec![&mut serial];
+                sinks.extend(pool.sinks());
+                let mut wled_connections: Vec<WledConnection> = worker
+                    .parameters
+                    .wled_servers
+                    .iter()
+                    .map(WledConnection::new)
+                    .collect();
+                sinks.extend(
+                    wled_connections
+                        .iter_mut()
+                        .map(|connection| connection as &mut dyn OutputSink),
+                );
+                let mut sacn_senders: Vec<SacnSender> = worker
+                    .parameters
+                    .sacn_outputs
+                    .iter()
+                    .map(SacnSender::new)
+                    .collect();
+                sinks.extend(
+                    sacn_senders
+                        .iter_mut()
+                        .map(|sender| sender as &mut dyn OutputSink),
+                );
+                let mut udp_connections: Vec<UdpConnection> = worker
+                    .parameters
+                    .udp_outputs
+                    .iter()
+                    .map(UdpConnection::new)
+                    .collect();
+                sinks.extend(
+                    udp_connections
+                        .iter_mut()
+                        .map(|connection| connection as &mut dyn OutputSink),
+                );
+                let mut hyperion_connections: Vec<HyperionConnection> = worker
+                    .parameters
+                    .hyperion_servers
+                    .iter()
+                    .map(|server| HyperionConnection::new(server, &worker.parameters))
+                    .collect();
+                sinks.extend(
+                    hyperion_connections
+                        .iter_mut()
+                        .map(|connection| connection as &mut dyn OutputSink),
+                );
+                #[cfg(feature = "hue")]
+                let mut hue_connections: Vec<HueConnection> = worker
+                    .parameters
+                    .hue_bridges
+                    .iter()
+                    .map(HueConnection::new)
+                    .collect();
+                #[cfg(feature = "hue")]
+                sinks.extend(
+                    hue_connections
+                        .iter_mut()
+                        .map(|connection| connection as &mut dyn OutputSink),
+                );
+                let mut nanoleaf_connections: Vec<NanoleafConnection> = worker
+                    .parameters
+                    .nanoleaf_controllers
+                    .iter()
+                    .map(NanoleafConnection::new)
+                    .collect();
+                sinks.extend(
+                    nanoleaf_connections
+                        .iter_mut()
+                        .map(|connection| connection as &mut dyn OutputSink),
+                );
+                let mut chroma_connections: Vec<ChromaConnection> = worker
+                    .parameters
+                    .chroma
+                    .iter()
+                    .map(ChromaConnection::new)
+                    .collect();
+                sinks.extend(
+                    chroma_connections
+                        .iter_mut()
+                        .map(|connection| connection as &mut dyn OutputSink),
+                );
+                let mut limiters: Vec<FrameLimiter> =
+                    sinks.iter().map(|_| FrameLimiter::default()).collect();
+                let mut backoffs: Vec<ReconnectBackoff> =
+                    sinks.iter().map(|_| ReconnectBackoff::default()).collect();
+                let max_latency = sinks
+                    .iter()
+                    .map(|sink| sink.latency())
+                    .max()
+                    .unwrap_or_default();
+                let mut delays: Vec<FrameDelay> = sinks
+                    .iter()
+                    .map(|sink| FrameDelay::new(&**sink, max_latency))
+                    .collect();
+                let mut content_protected = false;
 
                 loop {
                     match worker.rx.recv().expect("receive timer event") {
                         TimerEvent::Fired => {
                             if samples.is_empty() {
-                                let port_opened = port.open();
-                                let pool_opened = pool.open();
+                                // Try to open all of them, even if one of them is already open.
+                                let mut opened = false;
+                                for sink in sinks.iter_mut() {
+                                    opened |= sink.open();
+                                }
 
-                                if (port_opened || pool_opened)
-                                    && samples.create_resources().is_ok()
-                                {
+                                if opened && samples.create_resources().is_ok() {
                                     TimerThread::resume(timer.clone());
-                                } else if TimerThread::throttle(timer.clone()) {
-                                    serial_buffer.clear();
+                                } else {
+                                    TimerThread::throttle(timer.clone());
                                 }
                             }
 
-                            let _ = samples.take_samples();
+                            samples.set_enabled_displays(
+                                &worker
+                                    .enabled_displays
+                                    .lock()
+                                    .expect("lock enabled displays"),
+                            );
+                            samples.set_brightness(
+                                *worker.brightness.lock().expect("lock brightness"),
+                            );
+
+                            let preview_enabled = {
+                                let preview = worker.preview.lock().expect("lock preview");
+                                samples
+                                    .set_preview_thumbnails(preview.enabled && preview.thumbnails);
+                                preview.enabled
+                            };
+
+                            let start_sample = Instant::now();
+                            match samples.take_samples() {
+                                Ok(()) => stats.record_sample(start_sample.elapsed()),
+                                Err(_) => stats.frames_skipped += 1,
+                            }
+
+                            // Show the latest frame from an OPC client instead of the screen.
+                            if let Some(colors) = receiver.as_ref().and_then(OpcReceiver::latest) {
+                                samples.set_colors(&colors);
+                            }
+
+                            if samples.is_content_protected() != content_protected {
+                                content_protected = !content_protected;
+                                worker.health.send(if content_protected {
+                                    HealthEvent::ContentProtected
+                                } else {
+                                    HealthEvent::ContentUnprotected
+                                });
+                            }
+
+                            // Update the LED strip(s) and send the frames to the server(s),
+                            // skipping any which are limited to a lower frame rate, and holding
+                            // back any which are faster than the slowest one. Any which closed
+                            // after a failed send are reopened with a backoff.
+                            let now = Instant::now();
+                            for (((sink, limiter), delay), backoff) in sinks
+                                .iter_mut()
+                                .zip(limiters.iter_mut())
+                                .zip(delays.iter_mut())
+                                .zip(backoffs.iter_mut())
+                            {
+                                let ready = backoff.ready(
+                                    &mut **sink,
+                                    now,
+                                    &worker.parameters.reconnect,
+                                    worker.parameters.throttle_timer,
+                                );
+                                if ready && limiter.ready(now, sink.frame_interval()) {
+                                    let pixels = sink.render(&samples);
+                                    if let Some(pixels) = delay.delay(now, pixels) {
+                                        if !sink.send(pixels) {
+                                            stats.output_failures += 1;
+                                        }
+                                    }
+                                }
+                            }
 
-                            // Update the LED strip.
-                            samples.render_serial(&mut serial_buffer);
-                            port.send(&serial_buffer);
+                            stats.serial = serial_stats
+                                + *shared_serial_stats.lock().expect("lock serial stats");
 
-                            // Send the OPC frames to the server(s).
-                            for (i, server) in worker.parameters.servers.iter().enumerate() {
-                                for channel in server.channels.iter() {
-                                    let mut pixels = if server.alpha_channel {
-                                        PixelBuffer::new_bob_buffer(channel)
-                                    } else {
-                                        PixelBuffer::new_opc_buffer(channel)
-                                    };
+                            stats.frame_rate = samples.frame_rate();
+                            stats.average_color = samples.average_color();
+                            *worker.stats.lock().expect("lock stats") = stats;
 
-                                    samples.render_channel(channel, &mut pixels);
-                                    pool.send(i, &pixels);
+                            if preview_enabled {
+                                let mut preview = worker.preview.lock().expect("lock preview");
+                                if preview.enabled {
+                                    preview.latest = Some(samples.preview());
                                 }
                             }
                         }
+                        TimerEvent::DisplaysChanged => {
+                            // The next timer event will re-create the resources and match the
+                            // configured displays against the new set of outputs.
+                            samples.free_all_resources();
+                        }
+                        TimerEvent::SerialPortArrived => {
+                            // Don't wait for the next send failure and throttle cycle, look
+                            // for the Arduino again right away in case it was re-plugged.
+                            let mut opened = false;
+                            for sink in sinks.iter_mut() {
+                                opened |= sink.reconnect();
+                            }
+                            if opened {
+                                TimerThread::resume(timer.clone());
+                            }
+                        }
                         TimerEvent::Stopped => {
-                            // Reset the LED strip
-                            serial_buffer.clear();
-                            port.send(&serial_buffer);
-
-                            // Free resources anytime the update timer stops completely.
-                            samples.free_resources();
-                            port.close();
-                            pool.close();
+                            // Free resources anytime the update timer stops completely. This
+                            // also fades out and resets the LED strip(s) before closing them.
+                            samples.free_all_resources();
+                            for sink in sinks.iter_mut() {
+                                sink.close();
+                            }
 
                             break;
                         }
@@ -242,15 +592,75 @@ pub struct UpdateTimer {
 
     /// The [WorkerThread] instance.
     worker: Arc<Mutex<WorkerThread>>,
+
+    /// The [Stats] collected by the [WorkerThread].
+    stats: Arc<Mutex<Stats>>,
+
+    /// The [PreviewState] shared with the [WorkerThread].
+    preview: Arc<Mutex<PreviewState>>,
+
+    /// Whether each configured display is enabled, shared with the [WorkerThread].
+    enabled_displays: Arc<Mutex<Vec<bool>>>,
+
+    /// Global brightness for every output, shared with the [WorkerThread].
+    brightness: Arc<Mutex<Option<u8>>>,
+
+    /// The [ServerSwitches] for each OPC server, shared with the [WorkerThread].
+    server_switches: Arc<ServerSwitches>,
+
+    /// The [Profile] presets from the [Settings].
+    profiles: Vec<Profile>,
+
+    /// Index of the last [Profile] which was applied, if any.
+    active_profile: Mutex<Option<usize>>,
+
+    /// The receiving end of the [HealthEvent] channel, until someone takes it.
+    health: Mutex<Option<mpsc::Receiver<HealthEvent>>>,
 }
 
 impl UpdateTimer {
     /// Allocate an unstarted [UpdateTimer] using the [Settings] in `parameters`.
     pub fn new(parameters: Settings) -> Self {
         let (tx, rx) = mpsc::channel();
+        let stats = Arc::new(Mutex::new(Stats::default()));
+        let preview = Arc::new(Mutex::new(PreviewState::default()));
+        let enabled_displays = Arc::new(Mutex::new(
+            parameters
+                .displays
+                .iter()
+                .map(|display| display.enabled)
+                .collect(),
+        ));
+        let brightness = Arc::new(Mutex::new(None));
+        let server_switches = Arc::new(ServerSwitches::new(&parameters));
+        let profiles = parameters.profiles.clone();
+        let (health_tx, health_rx) = mpsc::channel();
+        let health = HealthSender::new(health_tx);
         Self {
-            timer: Arc::new(Mutex::new(TimerThread::new(&parameters, tx))),
-            worker: Arc::new(Mutex::new(WorkerThread::new(parameters, rx))),
+            timer: Arc::new(Mutex::new(TimerThread::new(
+                &parameters,
+                tx,
+                brightness.clone(),
+                health.clone(),
+            ))),
+            worker: Arc::new(Mutex::new(WorkerThread::new(
+                parameters,
+                rx,
+                stats.clone(),
+                preview.clone(),
+                enabled_displays.clone(),
+                brightness.clone(),
+                server_switches.clone(),
+                health,
+            ))),
+            stats,
+            preview,
+            enabled_displays,
+            brightness,
+            server_switches,
+            profiles,
+            active_profile: Mutex::new(None),
+            health: Mutex::new(Some(health_rx)),
         }
     }
 
@@ -275,4 +685,114 @@ impl UpdateTimer {
     pub fn resume(&self) -> bool {
         TimerThread::resume(self.timer.clone())
     }
+
+    /// Re-enumerate the displays after a monitor is plugged in or removed.
+    pub fn refresh_displays(&self) -> bool {
+        TimerThread::refresh_displays(self.timer.clone())
+    }
+
+    /// Reconnect to the Arduino after a serial port is plugged in.
+    pub fn serial_port_arrived(&self) -> bool {
+        TimerThread::serial_port_arrived(self.timer.clone())
+    }
+
+    /// Take the receiving end of the [HealthEvent] channel, which reports when the outputs
+    /// connect or disconnect and when the screen capture is throttled or resumed. There's only
+    /// one receiver, so this returns `None` after the first call.
+    pub fn health_events(&self) -> Option<mpsc::Receiver<HealthEvent>> {
+        self.health.lock().expect("lock health events").take()
+    }
+
+    /// Get a snapshot of the [Stats] collected by the [WorkerThread].
+    pub fn stats(&self) -> Stats {
+        *self.stats.lock().expect("lock stats")
+    }
+
+    /// Start collecting a [Preview] after every frame, optionally including a low resolution
+    /// [crate::preview::Thumbnail] of each display. Once thumbnails are requested, they stay
+    /// enabled for every observer.
+    pub fn enable_preview(&self, thumbnails: bool) {
+        let mut preview = self.preview.lock().expect("lock preview");
+        preview.enabled = true;
+        preview.thumbnails |= thumbnails;
+    }
+
+    /// Start collecting a [Preview] after every frame, and get a [PreviewHandle] which another
+    /// thread can use to observe it.
+    pub fn preview_handle(&self) -> PreviewHandle {
+        self.enable_preview(false);
+        PreviewHandle(self.preview.clone())
+    }
+
+    /// Enable or disable sampling the configured display at `display_index`, e.g. when a TV is
+    /// turned off but the monitor next to it is still on. The LEDs for a disabled display are
+    /// turned off, and the display indices of the rest don't change. Returns `false` if there
+    /// is no configured display at that index.
+    pub fn set_display_enabled(&self, display_index: usize, enabled: bool) -> bool {
+        let mut enabled_displays = self.enabled_displays.lock().expect("lock enabled displays");
+        match enabled_displays.get_mut(display_index) {
+            Some(display) => {
+                *display = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pause or resume the configured OPC server at `server_index`, e.g. when the controller in
+    /// another room is powered down. A paused server is disconnected and doesn't try to
+    /// reconnect until it's resumed, and the rest of the outputs keep running. Returns `false`
+    /// if there is no configured server at that index.
+    pub fn set_server_enabled(&self, server_index: usize, enabled: bool) -> bool {
+        self.server_switches.set_enabled(server_index, enabled)
+    }
+
+    /// Change the global brightness at runtime. Every output is dimmed by scaling the colors
+    /// at the end of each frame, and WLED serial outputs also get it as the device brightness.
+    pub fn set_brightness(&self, brightness: u8) {
+        *self.brightness.lock().expect("lock brightness") = Some(brightness);
+    }
+
+    /// Get the global brightness, if it was changed at runtime.
+    pub fn brightness(&self) -> Option<u8> {
+        *self.brightness.lock().expect("lock brightness")
+    }
+
+    /// Get the name of each of the [Profile] presets, in order.
+    pub fn profile_names(&self) -> Vec<String> {
+        self.profiles
+            .iter()
+            .map(|profile| profile.name.clone())
+            .collect()
+    }
+
+    /// Apply the [Profile] at `profile_index`, which enables or disables the displays and OPC
+    /// servers it lists, and changes the brightness if it's set. Returns `false` if there is no
+    /// profile at that index.
+    pub fn apply_profile(&self, profile_index: usize) -> bool {
+        let profile = match self.profiles.get(profile_index) {
+            Some(profile) => profile,
+            None => return false,
+        };
+
+        for (display_index, enabled) in profile.displays.iter().enumerate() {
+            self.set_display_enabled(display_index, *enabled);
+        }
+
+        for (server_index, enabled) in profile.servers.iter().enumerate() {
+            self.set_server_enabled(server_index, *enabled);
+        }
+
+        if let Some(brightness) = profile.brightness {
+            self.set_brightness(brightness);
+        }
+
+        *self.active_profile.lock().expect("lock active profile") = Some(profile_index);
+        true
+    }
+
+    /// Get the index of the last [Profile] which was applied, if any.
+    pub fn active_profile(&self) -> Option<usize> {
+        *self.active_profile.lock().expect("lock active profile")
+    }
 }
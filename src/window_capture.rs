@@ -0,0 +1,360 @@
+use std::{path::Path, ptr, slice};
+
+use regex::Regex;
+use windows::{
+    core::{factory, Interface, Result},
+    Graphics::{
+        Capture::{Direct3D11CaptureFramePool, GraphicsCaptureItem, GraphicsCaptureSession},
+        DirectX::{Direct3D11::IDirect3DDevice, DirectXPixelFormat},
+        SizeInt32,
+    },
+    Win32::{
+        Foundation::{
+            CloseHandle, BOOL, E_FAIL, E_INVALIDARG, HINSTANCE, HWND, LPARAM, PWSTR, SIZE,
+        },
+        Graphics::{
+            Direct3D::D3D_DRIVER_TYPE_HARDWARE,
+            Direct3D11::{
+                D3D11CreateDevice, ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D,
+                D3D11_BIND_FLAG, D3D11_CPU_ACCESS_READ, D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                D3D11_MAP_READ, D3D11_RESOURCE_MISC_FLAG, D3D11_SDK_VERSION, D3D11_TEXTURE2D_DESC,
+                D3D11_USAGE_STAGING,
+            },
+            Dxgi::IDXGIDevice,
+        },
+        System::{
+            Threading::{
+                OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+                PROCESS_QUERY_LIMITED_INFORMATION,
+            },
+            WinRT::{
+                Direct3D11::{CreateDirect3D11DeviceFromDXGIDevice, IDirect3DDxgiInterfaceAccess},
+                Graphics::Capture::IGraphicsCaptureItemInterop,
+                RoInitialize, RO_INIT_MULTITHREADED,
+            },
+        },
+        UI::WindowsAndMessaging::{
+            EnumWindows, GetWindowTextW, GetWindowThreadProcessId, IsWindow, IsWindowVisible,
+        },
+    },
+};
+
+use crate::{
+    capture_source::{CaptureSource, MappedSurface, SurfaceFormat},
+    settings::WindowConfiguration,
+};
+
+/// Maximum length of a window title or executable path that we'll try to match.
+const MAX_NAME_LENGTH: usize = 1024;
+
+/// State passed through [EnumWindows] to [WindowSearch::enum_windows_proc] while looking
+/// for a window which matches a [WindowConfiguration].
+struct WindowSearch<'a> {
+    process_name: Option<&'a str>,
+    title: Option<Regex>,
+    found: Option<HWND>,
+}
+
+impl<'a> WindowSearch<'a> {
+    /// Find the first visible top-level window matching the [WindowConfiguration].
+    pub fn find(window: &'a WindowConfiguration) -> Result<Option<HWND>> {
+        let title = match window.title.as_deref() {
+            Some(title) => Some(Regex::new(title).map_err(|_| E_INVALIDARG)?),
+            None => None,
+        };
+        let mut search = Self {
+            process_name: window.process_name.as_deref(),
+            title,
+            found: None,
+        };
+
+        if search.process_name.is_none() && search.title.is_none() {
+            E_INVALIDARG.ok()?;
+        }
+
+        unsafe {
+            EnumWindows(
+                Some(Self::enum_windows_proc),
+                LPARAM(&mut search as *mut Self as isize),
+            );
+        }
+
+        Ok(search.found)
+    }
+
+    /// Test if the window in `h_wnd` matches all of the criteria.
+    fn is_match(&self, h_wnd: HWND) -> bool {
+        if let Some(title) = &self.title {
+            let mut buffer = [0_u16; MAX_NAME_LENGTH];
+            let length =
+                unsafe { GetWindowTextW(h_wnd, PWSTR(buffer.as_mut_ptr()), buffer.len() as i32) };
+            let window_title = String::from_utf16_lossy(&buffer[..length.max(0) as usize]);
+
+            if !title.is_match(&window_title) {
+                return false;
+            }
+        }
+
+        if let Some(process_name) = self.process_name {
+            match Self::get_process_name(h_wnd) {
+                Some(name) if name.eq_ignore_ascii_case(process_name) => (),
+                _ => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Get the file name of the executable which owns the window in `h_wnd`.
+    fn get_process_name(h_wnd: HWND) -> Option<String> {
+        unsafe {
+            let mut process_id = 0_u32;
+            GetWindowThreadProcessId(h_wnd, &mut process_id);
+            let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id);
+            if process.is_invalid() {
+                return None;
+            }
+
+            let mut buffer = [0_u16; MAX_NAME_LENGTH];
+            let mut length = buffer.len() as u32;
+            let queried = QueryFullProcessImageNameW(
+                process,
+                PROCESS_NAME_WIN32,
+                PWSTR(buffer.as_mut_ptr()),
+                &mut length,
+            )
+            .as_bool();
+            CloseHandle(process);
+
+            if !queried {
+                return None;
+            }
+
+            let path = String::from_utf16_lossy(&buffer[..length as usize]);
+            Path::new(&path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        }
+    }
+
+    /// Implement the `WNDENUMPROC` callback for [EnumWindows].
+    unsafe extern "system" fn enum_windows_proc(h_wnd: HWND, l_param: LPARAM) -> BOOL {
+        let search = &mut *(l_param.0 as *mut Self);
+
+        if IsWindowVisible(h_wnd).as_bool() && search.is_match(h_wnd) {
+            // Stop enumerating once we find a match.
+            search.found = Some(h_wnd);
+            return false.into();
+        }
+
+        true.into()
+    }
+}
+
+/// Capture the contents of a single application window with the Windows.Graphics.Capture
+/// APIs and copy each new frame to a `staging` texture we can map and sample.
+pub struct WindowCapture {
+    /// The [HWND] of the window we are following.
+    h_wnd: HWND,
+
+    /// The [ID3D11Device] interface which owns the `staging` texture.
+    device: ID3D11Device,
+
+    /// The [ID3D11DeviceContext] interface.
+    context: ID3D11DeviceContext,
+
+    /// The WinRT [IDirect3DDevice] wrapper around `device` used by the `frame_pool`.
+    direct3d_device: IDirect3DDevice,
+
+    /// The [GraphicsCaptureItem] for the window, which we just need to keep alive once set.
+    _item: GraphicsCaptureItem,
+
+    /// The [Direct3D11CaptureFramePool] which receives new frames from the `session`.
+    frame_pool: Direct3D11CaptureFramePool,
+
+    /// The [GraphicsCaptureSession] which is capturing the window.
+    session: GraphicsCaptureSession,
+
+    /// Optional [ID3D11Texture2D] interface containing the last frame copied from the GPU.
+    staging: Option<ID3D11Texture2D>,
+
+    /// The size of the `staging` texture in pixels.
+    staging_size: SIZE,
+
+    /// The size of the window contents in the last frame.
+    content_size: SizeInt32,
+}
+
+impl WindowCapture {
+    /// Find a window matching the [WindowConfiguration] and start capturing it with a new
+    /// D3D11 device on the default adapter.
+    pub fn new(window: &WindowConfiguration) -> Result<Self> {
+        let h_wnd = match WindowSearch::find(window)? {
+            Some(h_wnd) => h_wnd,
+            None => return Err(E_FAIL.into()),
+        };
+
+        unsafe {
+            // The frame pool is free-threaded, so it doesn't matter if WinRT was already
+            // initialized on this thread.
+            let _ = RoInitialize(RO_INIT_MULTITHREADED);
+
+            let mut device = None;
+            let mut context = None;
+            D3D11CreateDevice(
+                None,
+                D3D_DRIVER_TYPE_HARDWARE,
+                HINSTANCE::default(),
+                D3D11_CREATE_DEVICE_BGRA_SUPPORT,
+                ptr::null(),
+                0,
+                D3D11_SDK_VERSION,
+                &mut device,
+                ptr::null_mut(),
+                &mut context,
+            )?;
+            let (device, context) = match (device, context) {
+                (Some(device), Some(context)) => (device, context),
+                _ => return Err(E_FAIL.into()),
+            };
+            let dxgi_device: IDXGIDevice = device.cast()?;
+            let direct3d_device: IDirect3DDevice =
+                CreateDirect3D11DeviceFromDXGIDevice(&dxgi_device)?.cast()?;
+            let interop = factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
+            let item: GraphicsCaptureItem = interop.CreateForWindow(h_wnd)?;
+            let content_size = item.Size()?;
+            let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
+                &direct3d_device,
+                DirectXPixelFormat::B8G8R8A8UIntNormalized,
+                1,
+                content_size,
+            )?;
+            let session = frame_pool.CreateCaptureSession(&item)?;
+            session.StartCapture()?;
+
+            Ok(Self {
+                h_wnd,
+                device,
+                context,
+                direct3d_device,
+                _item: item,
+                frame_pool,
+                session,
+                staging: None,
+                staging_size: SIZE::default(),
+                content_size,
+            })
+        }
+    }
+}
+
+impl CaptureSource for WindowCapture {
+    /// Copy the next frame to the `staging` texture. Returns `true` if there was a new frame,
+    /// or an [Err] value if the window was closed.
+    fn acquire_frame(&mut self) -> Result<bool> {
+        unsafe {
+            if !IsWindow(self.h_wnd).as_bool() {
+                E_FAIL.ok()?;
+            }
+
+            let frame = match self.frame_pool.TryGetNextFrame() {
+                Ok(frame) => frame,
+                Err(_) => return Ok(false),
+            };
+
+            // If the window was resized, the frame pool needs to be recreated to match.
+            let content_size = frame.ContentSize()?;
+            if content_size != self.content_size {
+                self.content_size = content_size;
+                self.frame_pool.Recreate(
+                    &self.direct3d_device,
+                    DirectXPixelFormat::B8G8R8A8UIntNormalized,
+                    1,
+                    content_size,
+                )?;
+            }
+
+            let access: IDirect3DDxgiInterfaceAccess = frame.Surface()?.cast()?;
+            let texture: ID3D11Texture2D = access.GetInterface()?;
+            let mut description = D3D11_TEXTURE2D_DESC::default();
+            texture.GetDesc(&mut description);
+
+            if self.staging.is_none()
+                || self.staging_size.cx != description.Width as i32
+                || self.staging_size.cy != description.Height as i32
+            {
+                let staging_description = D3D11_TEXTURE2D_DESC {
+                    MipLevels: 1,
+                    ArraySize: 1,
+                    Usage: D3D11_USAGE_STAGING,
+                    BindFlags: D3D11_BIND_FLAG(0),
+                    CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+                    MiscFlags: D3D11_RESOURCE_MISC_FLAG(0),
+                    ..description
+                };
+                self.staging = Some(
+                    self.device
+                        .CreateTexture2D(&staging_description, ptr::null())?,
+                );
+                self.staging_size = SIZE {
+                    cx: description.Width as i32,
+                    cy: description.Height as i32,
+                };
+            }
+
+            if let Some(staging) = &self.staging {
+                self.context.CopyResource(staging, &texture);
+            }
+
+            let _ = frame.Close();
+        }
+
+        Ok(true)
+    }
+
+    /// Get the size of the window contents which can be sampled from the `staging` texture.
+    fn bounds(&self) -> SIZE {
+        SIZE {
+            cx: self.content_size.Width.min(self.staging_size.cx),
+            cy: self.content_size.Height.min(self.staging_size.cy),
+        }
+    }
+
+    /// Map the `staging` texture and return the BGRA pixels. Call `release_pixels` when
+    /// finished with the pixels.
+    fn read_pixels(&self) -> Result<MappedSurface<'_>> {
+        match &self.staging {
+            Some(staging) => unsafe {
+                let map = self.context.Map(staging, 0, D3D11_MAP_READ, 0)?;
+                let pitch = map.RowPitch as usize;
+                let pixels = slice::from_raw_parts(
+                    map.pData as *const u8,
+                    pitch * self.staging_size.cy as usize,
+                );
+                Ok(MappedSurface {
+                    pixels,
+                    pitch,
+                    format: SurfaceFormat::Bgra8,
+                    rows: None,
+                })
+            },
+            None => Err(E_FAIL.into()),
+        }
+    }
+
+    /// Unmap the `staging` texture after a successful call to `read_pixels`.
+    fn release_pixels(&self) {
+        if let Some(staging) = &self.staging {
+            unsafe {
+                self.context.Unmap(staging, 0);
+            }
+        }
+    }
+}
+
+impl Drop for WindowCapture {
+    fn drop(&mut self) {
+        let _ = self.session.Close();
+        let _ = self.frame_pool.Close();
+    }
+}
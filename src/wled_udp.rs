@@ -0,0 +1,154 @@
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, UdpSocket},
+    time::Duration,
+};
+
+use crate::{
+    output_sink::OutputSink,
+    pixel_buffer::PixelBuffer,
+    screen_samples::ScreenSamples,
+    settings::{WledProtocol, WledServer},
+};
+
+/// Most LEDs which fit in a single packet of each [WledProtocol].
+fn max_packet_leds(protocol: WledProtocol) -> usize {
+    match protocol {
+        WledProtocol::Warls => 255,
+        WledProtocol::Drgb => 490,
+        WledProtocol::Dnrgb => 489,
+    }
+}
+
+/// Representation of a [WledServer] driven with one of the WLED UDP realtime protocols.
+pub struct WledConnection<'a> {
+    server: &'a WledServer,
+    socket: Option<UdpSocket>,
+}
+
+impl<'a> WledConnection<'a> {
+    /// Allocate a new [WledConnection] without opening the socket.
+    pub fn new(server: &'a WledServer) -> Self {
+        Self {
+            server,
+            socket: None,
+        }
+    }
+
+    /// Get the [WledProtocol] for the [WledServer], picking one which fits the `led_count` if
+    /// it isn't configured.
+    fn protocol(&self) -> WledProtocol {
+        self.server.protocol.unwrap_or(
+            if self.server.led_count <= max_packet_leds(WledProtocol::Drgb) {
+                WledProtocol::Drgb
+            } else {
+                WledProtocol::Dnrgb
+            },
+        )
+    }
+
+    /// Split `led_count` LEDs into the `(start, led_count)` of each packet for the `protocol`.
+    /// Only [WledProtocol::Dnrgb] can start at an offset, so the others are limited to the LEDs
+    /// which fit in a single packet.
+    fn packets(protocol: WledProtocol, led_count: usize) -> Vec<(usize, usize)> {
+        let max_leds = max_packet_leds(protocol);
+        match protocol {
+            WledProtocol::Dnrgb => (0..led_count)
+                .step_by(max_leds)
+                .map(|start| (start, (led_count - start).min(max_leds)))
+                .collect(),
+            WledProtocol::Warls | WledProtocol::Drgb => vec![(0, led_count.min(max_leds))],
+        }
+    }
+}
+
+impl<'a> OutputSink for WledConnection<'a> {
+    /// Open a non-blocking UDP socket to the [WledServer] if it isn't open yet.
+    fn open(&mut self) -> bool {
+        if self.socket.is_none() {
+            self.socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+                .and_then(|socket| {
+                    socket.connect((self.server.host.as_str(), self.server.port))?;
+                    socket.set_nonblocking(true)?;
+                    Ok(socket)
+                })
+                .ok();
+        }
+
+        self.healthy()
+    }
+
+    /// Render a packet for each range of LEDs in the [WledServer].
+    fn render(&self, samples: &ScreenSamples) -> Vec<PixelBuffer> {
+        let protocol = self.protocol();
+        Self::packets(protocol, self.server.led_count)
+            .into_iter()
+            .map(|(start, led_count)| {
+                let mut pixels =
+                    PixelBuffer::new_wled_buffer(protocol, self.server.timeout, start, led_count);
+                samples.render_range(self.server.first_led + start, led_count, &mut pixels);
+                pixels
+            })
+            .collect()
+    }
+
+    /// Send each of the packets to the [WledServer]. If the socket buffer is full, the rest
+    /// of the frame is dropped instead of waiting.
+    fn send(&mut self, pixels: Vec<PixelBuffer>) -> bool {
+        let result = match self.socket.as_ref() {
+            Some(socket) => pixels
+                .iter()
+                .try_for_each(|pixels| socket.send(pixels.data()).map(|_| ())),
+            None => return false,
+        };
+
+        match result {
+            Ok(()) => true,
+            Err(error) if error.kind() == ErrorKind::WouldBlock => true,
+            Err(_) => {
+                self.close();
+                false
+            }
+        }
+    }
+
+    /// Close the socket.
+    fn close(&mut self) {
+        self.socket = None;
+    }
+
+    /// Test if the socket to the [WledServer] is open.
+    fn healthy(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Get the `latency` of the [WledServer].
+    fn latency(&self) -> Duration {
+        Duration::from_millis(u64::from(self.server.latency))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_packets() {
+        assert_eq!(
+            WledConnection::packets(WledProtocol::Drgb, 300),
+            vec![(0, 300)]
+        );
+        assert_eq!(
+            WledConnection::packets(WledProtocol::Warls, 300),
+            vec![(0, 255)]
+        );
+        assert_eq!(
+            WledConnection::packets(WledProtocol::Dnrgb, 1000),
+            vec![(0, 489), (489, 489), (978, 22)]
+        );
+        assert_eq!(
+            WledConnection::packets(WledProtocol::Dnrgb, 489),
+            vec![(0, 489)]
+        );
+    }
+}